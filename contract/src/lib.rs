@@ -13,6 +13,9 @@ use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
+/// Maximum number of groups accepted by a single `batch_get_group_key_id` or `prepare_groups`
+pub const MAX_BATCH_GROUP_IDS: usize = 100;
+
 /// Get CKD master seed from OutLayer runtime
 /// OutLayer provides a unique, hardware-derived seed via the OUTLAYER_CKD_SEED env var
 /// This seed is different for each execution and tied to the TEE hardware
@@ -48,6 +51,14 @@ pub enum Request {
         group_id: String,
         account_id: String,
     },
+    BatchGetGroupKeyId {
+        group_ids: Vec<String>,
+    },
+    /// Membership and key_id for several groups in one call
+    PrepareGroups {
+        account_id: String,
+        group_ids: Vec<String>,
+    },
     WrapKey {
         group_id: String,
         account_id: String,
@@ -57,6 +68,9 @@ pub enum Request {
         group_id: String,
         account_id: String,
         wrapped_key_b64: String,
+        /// If set, must equal the group's current key_id
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expected_key_id: Option<String>,
     },
     Encrypt {
         group_id: String,
@@ -67,6 +81,26 @@ pub enum Request {
         group_id: String,
         account_id: String,
         ciphertext_b64: String,
+        /// If set, must equal the group's current key_id
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expected_key_id: Option<String>,
+        /// Return invalid UTF-8 lossily (U+FFFD) instead of `plaintext_utf8: null`
+        #[serde(default)]
+        lossy_utf8: bool,
+    },
+    /// Encrypt into the stored `enc:AES256:<key_id>:<ciphertext_b64>` format
+    EncryptTagged {
+        group_id: String,
+        account_id: String,
+        plaintext_b64: String,
+    },
+    /// Decrypt a stored `enc:AES256:<key_id>:<ciphertext_b64>` value
+    DecryptTagged {
+        group_id: String,
+        account_id: String,
+        tagged_value: String,
+        #[serde(default)]
+        lossy_utf8: bool,
     },
     VerifyMembership {
         group_id: String,
@@ -82,6 +116,10 @@ pub enum Request {
         account_id: String,
         items: Vec<DecryptItem>,
     },
+    /// Diagnostic: derive the group key and wrap/unwrap an internal key with it
+    SelfTest {
+        group_id: String,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -112,6 +150,14 @@ pub struct GroupKeyIdResponse {
     pub algorithm: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PreparedGroup {
+    pub group_id: String,
+    pub is_member: bool,
+    /// Public, so returned for non-members too
+    pub key_id: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WrapKeyResponse {
     pub wrapped_key_b64: String,
@@ -130,10 +176,25 @@ pub struct EncryptResponse {
     pub key_id: String,
 }
 
+/// Whether decrypted bytes were valid UTF-8
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaintextEncoding {
+    Utf8,
+    Binary,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaggedValueResponse {
+    pub tagged_value: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DecryptResponse {
     pub plaintext_b64: String,
+    /// `None` for binary plaintext unless `lossy_utf8` was requested
     pub plaintext_utf8: Option<String>,
+    pub encoding: PlaintextEncoding,
     pub key_id: String,
 }
 
@@ -171,6 +232,11 @@ pub struct BatchDecryptItemResult {
     pub error: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SelfTestResponse {
+    pub ok: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ErrorResponse {
     pub error: String,
@@ -190,6 +256,43 @@ pub fn derive_group_key(master_seed: &[u8], group_id: &str) -> [u8; 32] {
     key
 }
 
+/// Throwaway key for `self_test`. The domain tag differs from the one in
+/// [`derive_group_key`], so no group id can ever derive the same key.
+fn derive_self_test_key(master_seed: &[u8], group_id: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(master_seed);
+    hasher.update(group_id.as_bytes());
+    hasher.update(b"fastkv_self_test_key_v1");
+
+    let result = hasher.finalize();
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&result);
+    key
+}
+
+/// Prefix of encrypted values as stored in FastKV
+pub const ENCRYPTED_VALUE_PREFIX: &str = "enc:AES256:";
+
+/// `enc:AES256:<key_id>:<ciphertext_b64>`, the value format FastKV indexes as encrypted
+pub fn format_tagged_value(key_id: &str, ciphertext_b64: &str) -> String {
+    format!("{ENCRYPTED_VALUE_PREFIX}{key_id}:{ciphertext_b64}")
+}
+
+/// Split `enc:AES256:<key_id>:<ciphertext_b64>` (optionally JSON-quoted, as
+/// the indexer accepts it) into `(key_id, ciphertext_b64)`.
+pub fn parse_tagged_value(value: &str) -> Result<(&str, &str), String> {
+    let rest = value
+        .trim_matches('"')
+        .strip_prefix(ENCRYPTED_VALUE_PREFIX)
+        .ok_or_else(|| format!("Tagged value must start with {ENCRYPTED_VALUE_PREFIX}"))?;
+    match rest.split_once(':') {
+        Some((key_id, ciphertext_b64)) if !key_id.is_empty() && !ciphertext_b64.is_empty() => {
+            Ok((key_id, ciphertext_b64))
+        }
+        _ => Err("Tagged value must be enc:AES256:<key_id>:<ciphertext_b64>".to_string()),
+    }
+}
+
 /// Generate key ID from group_id (deterministic)
 pub fn key_id_for_group(group_id: &str) -> String {
     let mut hasher = Sha256::new();
@@ -198,13 +301,65 @@ pub fn key_id_for_group(group_id: &str) -> String {
     hex::encode(&hasher.finalize()[..8])
 }
 
-/// Generate attestation hash (in real OutLayer, this comes from Intel TDX)
+/// Produces the `attestation_hash` binding a response to its request.
+/// A real OutLayer deployment injects an Intel TDX quote provider through
+/// [`execute_with`]; [`Sha256Attestation`] is the mock used otherwise.
+pub trait AttestationProvider {
+    fn attest(&self, input: &str, output: &str) -> String;
+}
+
+pub const DEFAULT_ATTESTATION_PREFIX: &str = "outlayer_attestation_v1";
+/// Bytes of the SHA-256 digest kept by default (hex-encoded to 32 chars)
+pub const DEFAULT_ATTESTATION_BYTES: usize = 16;
+
+/// Mock attestation: hex of the first `length` bytes of
+/// SHA-256(prefix || input || output). Deterministic and NOT bound to a
+/// real TEE quote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sha256Attestation {
+    pub prefix: String,
+    /// Clamped to 1..=32 when hashing
+    pub length: usize,
+}
+
+impl Default for Sha256Attestation {
+    fn default() -> Self {
+        Self {
+            prefix: DEFAULT_ATTESTATION_PREFIX.to_string(),
+            length: DEFAULT_ATTESTATION_BYTES,
+        }
+    }
+}
+
+impl Sha256Attestation {
+    /// Read `OUTLAYER_ATTESTATION_PREFIX` and `OUTLAYER_ATTESTATION_BYTES`
+    /// (1-32), falling back to the defaults when unset or invalid.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            prefix: std::env::var("OUTLAYER_ATTESTATION_PREFIX").unwrap_or(default.prefix),
+            length: std::env::var("OUTLAYER_ATTESTATION_BYTES")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .filter(|n| (1..=32).contains(n))
+                .unwrap_or(default.length),
+        }
+    }
+}
+
+impl AttestationProvider for Sha256Attestation {
+    fn attest(&self, input: &str, output: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.prefix.as_bytes());
+        hasher.update(input.as_bytes());
+        hasher.update(output.as_bytes());
+        hex::encode(&hasher.finalize()[..self.length.clamp(1, 32)])
+    }
+}
+
+/// Generate attestation hash with the default mock provider
 pub fn generate_attestation(input: &str, output: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(b"outlayer_attestation_v1");
-    hasher.update(input.as_bytes());
-    hasher.update(output.as_bytes());
-    hex::encode(&hasher.finalize()[..16])
+    Sha256Attestation::default().attest(input, output)
 }
 
 /// Encrypt data with AES-256-GCM
@@ -252,28 +407,118 @@ pub fn decrypt(ciphertext: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, String> {
         .map_err(|e| format!("Decryption failed: {}", e))
 }
 
+/// Decode a base64 AES-256 key, rejecting anything that isn't 32 bytes
+fn decode_key_b64(key_b64: &str) -> Result<[u8; 32], String> {
+    let key = BASE64
+        .decode(key_b64)
+        .map_err(|e| format!("Invalid base64 key: {e}"))?;
+    key.as_slice()
+        .try_into()
+        .map_err(|_| format!("Key must be 32 bytes, got {}", key.len()))
+}
+
+/// Encrypt base64 plaintext with a base64 group key (e.g. exported via `GetKey`)
+/// Returns the same IV + ciphertext layout as `encrypt`
+pub fn encrypt_b64(plaintext_b64: &str, key_b64: &str) -> Result<Vec<u8>, String> {
+    let key = decode_key_b64(key_b64)?;
+    let plaintext = BASE64
+        .decode(plaintext_b64)
+        .map_err(|e| format!("Invalid base64 plaintext: {e}"))?;
+    encrypt(&plaintext, &key)
+}
+
+/// Decrypt a base64 ciphertext with a base64 group key, without contacting the TEE
+pub fn decrypt_b64(ciphertext_b64: &str, key_b64: &str) -> Result<Vec<u8>, String> {
+    let key = decode_key_b64(key_b64)?;
+    let ciphertext = BASE64
+        .decode(ciphertext_b64)
+        .map_err(|e| format!("Invalid base64 ciphertext: {e}"))?;
+    decrypt(&ciphertext, &key)
+}
+
+/// Compare equal-length byte slices without short-circuiting on the first mismatch
+fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn ct_starts_with(haystack: &str, prefix: &str) -> bool {
+    haystack.len() >= prefix.len() && ct_eq(&haystack.as_bytes()[..prefix.len()], prefix.as_bytes())
+}
+
+fn ct_ends_with(haystack: &str, suffix: &str) -> bool {
+    haystack.len() >= suffix.len()
+        && ct_eq(&haystack.as_bytes()[haystack.len() - suffix.len()..], suffix.as_bytes())
+}
+
+/// Evaluate every membership rule (mock implementation)
+/// All rules always run so timing doesn't reveal which one matched
+fn membership_rules(group_id: &str, account_id: &str) -> [bool; 3] {
+    // Mock: everyone is a member of their own groups
+    // Real implementation: query contextual.near is_member view
+    [
+        ct_starts_with(group_id, account_id.split('.').next().unwrap_or("")),
+        ct_ends_with(account_id, ".near"),
+        ct_ends_with(account_id, ".testnet"),
+    ]
+}
+
 /// Check membership (mock implementation)
 /// In production, this would query contextual.near contract
 pub fn check_membership(group_id: &str, account_id: &str) -> bool {
-    // Mock: everyone is a member of their own groups
-    // Real implementation: query contextual.near is_member view
-    group_id.starts_with(&account_id.split('.').next().unwrap_or(""))
-        || account_id.ends_with(".near")
-        || account_id.ends_with(".testnet")
+    membership_rules(group_id, account_id)
+        .iter()
+        .fold(0u8, |acc, &matched| acc | matched as u8)
+        == 1
+}
+
+/// Derive the group key and gate it on membership
+/// The key is derived on both paths so a 403 costs the same as a success
+fn member_group_key(group_id: &str, account_id: &str) -> Option<[u8; 32]> {
+    let key = derive_group_key(&get_ckd_master_seed(), group_id);
+    let is_member = check_membership(group_id, account_id);
+    std::hint::black_box(&key);
+    is_member.then_some(key)
+}
+
+/// Reject a caller-supplied key_id that doesn't match the group's current key_id
+fn check_expected_key_id(group_id: &str, expected_key_id: Option<&str>) -> Result<(), String> {
+    match expected_key_id {
+        Some(expected) => {
+            let actual = key_id_for_group(group_id);
+            if expected == actual {
+                Ok(())
+            } else {
+                Err(format!("key_id mismatch: expected {expected}, group has {actual}"))
+            }
+        }
+        None => Ok(()),
+    }
 }
 
 /// Main entry point for OutLayer WASM execution
 pub fn execute(input: &str) -> String {
+    execute_with(input, &Sha256Attestation::from_env())
+}
+
+/// [`execute`] with an explicit attestation provider
+pub fn execute_with(input: &str, attestation: &dyn AttestationProvider) -> String {
     let request: Result<Request, _> = serde_json::from_str(input);
 
     match request {
         Ok(req) => match req {
             Request::GetKey { group_id, account_id } => {
-                handle_get_key(&group_id, &account_id)
+                handle_get_key(&group_id, &account_id, attestation)
             }
             Request::GetGroupKeyId { group_id, account_id } => {
                 handle_get_group_key_id(&group_id, &account_id)
             }
+            Request::BatchGetGroupKeyId { group_ids } => handle_batch_get_group_key_id(&group_ids),
+            Request::PrepareGroups { account_id, group_ids } => {
+                handle_prepare_groups(&account_id, &group_ids)
+            }
             Request::WrapKey {
                 group_id,
                 account_id,
@@ -283,7 +528,8 @@ pub fn execute(input: &str) -> String {
                 group_id,
                 account_id,
                 wrapped_key_b64,
-            } => handle_unwrap_key(&group_id, &account_id, &wrapped_key_b64),
+                expected_key_id,
+            } => handle_unwrap_key(&group_id, &account_id, &wrapped_key_b64, expected_key_id.as_deref()),
             Request::Encrypt {
                 group_id,
                 account_id,
@@ -293,7 +539,26 @@ pub fn execute(input: &str) -> String {
                 group_id,
                 account_id,
                 ciphertext_b64,
-            } => handle_decrypt(&group_id, &account_id, &ciphertext_b64),
+                expected_key_id,
+                lossy_utf8,
+            } => handle_decrypt(
+                &group_id,
+                &account_id,
+                &ciphertext_b64,
+                expected_key_id.as_deref(),
+                lossy_utf8,
+            ),
+            Request::EncryptTagged {
+                group_id,
+                account_id,
+                plaintext_b64,
+            } => handle_encrypt_tagged(&group_id, &account_id, &plaintext_b64),
+            Request::DecryptTagged {
+                group_id,
+                account_id,
+                tagged_value,
+                lossy_utf8,
+            } => handle_decrypt_tagged(&group_id, &account_id, &tagged_value, lossy_utf8),
             Request::VerifyMembership {
                 group_id,
                 account_id,
@@ -308,25 +573,29 @@ pub fn execute(input: &str) -> String {
                 account_id,
                 items,
             } => handle_batch_decrypt(&group_id, &account_id, &items),
+            Request::SelfTest { group_id } => handle_self_test(&group_id),
         },
         Err(e) => error_response(&format!("Invalid request: {}", e), 400),
     }
 }
 
-fn handle_get_key(group_id: &str, account_id: &str) -> String {
+fn handle_get_key(
+    group_id: &str,
+    account_id: &str,
+    attestation: &dyn AttestationProvider,
+) -> String {
     // Check membership first
-    if !check_membership(group_id, account_id) {
-        return error_response("Not a group member", 403);
-    }
-
-    let key = derive_group_key(&get_ckd_master_seed(), group_id);
+    let key = match member_group_key(group_id, account_id) {
+        Some(k) => k,
+        None => return error_response("Not a group member", 403),
+    };
     let key_id = key_id_for_group(group_id);
 
     let response = KeyResponse {
         key_b64: BASE64.encode(key),
         key_id,
         group_id: group_id.to_string(),
-        attestation_hash: generate_attestation(
+        attestation_hash: attestation.attest(
             &format!("get_key:{}:{}", group_id, account_id),
             &BASE64.encode(key),
         ),
@@ -337,22 +606,66 @@ fn handle_get_key(group_id: &str, account_id: &str) -> String {
 
 fn handle_get_group_key_id(group_id: &str, _account_id: &str) -> String {
     // No membership check needed - key_id is public info
-    let key_id = key_id_for_group(group_id);
+    let response = group_key_id_response(group_id);
 
-    let response = GroupKeyIdResponse {
-        key_id,
+    serde_json::to_string(&response).unwrap_or_else(|e| error_response(&e.to_string(), 500))
+}
+
+fn group_key_id_response(group_id: &str) -> GroupKeyIdResponse {
+    GroupKeyIdResponse {
+        key_id: key_id_for_group(group_id),
         group_id: group_id.to_string(),
         algorithm: "AES-256-GCM".to_string(),
-    };
+    }
+}
+
+fn check_group_count(group_ids: &[String]) -> Result<(), String> {
+    if group_ids.len() > MAX_BATCH_GROUP_IDS {
+        return Err(error_response(
+            &format!("Too many group_ids: {} (max {MAX_BATCH_GROUP_IDS})", group_ids.len()),
+            400,
+        ));
+    }
+    Ok(())
+}
+
+fn handle_batch_get_group_key_id(group_ids: &[String]) -> String {
+    // No membership check needed - key_id is public info
+    if let Err(error) = check_group_count(group_ids) {
+        return error;
+    }
+
+    let response: Vec<GroupKeyIdResponse> = group_ids
+        .iter()
+        .map(|group_id| group_key_id_response(group_id))
+        .collect();
+
+    serde_json::to_string(&response).unwrap_or_else(|e| error_response(&e.to_string(), 500))
+}
+
+fn handle_prepare_groups(account_id: &str, group_ids: &[String]) -> String {
+    if let Err(error) = check_group_count(group_ids) {
+        return error;
+    }
+
+    let response: Vec<PreparedGroup> = group_ids
+        .iter()
+        .map(|group_id| PreparedGroup {
+            group_id: group_id.clone(),
+            is_member: check_membership(group_id, account_id),
+            key_id: key_id_for_group(group_id),
+        })
+        .collect();
 
     serde_json::to_string(&response).unwrap_or_else(|e| error_response(&e.to_string(), 500))
 }
 
 fn handle_wrap_key(group_id: &str, account_id: &str, plaintext_key_b64: &str) -> String {
     // Check membership - only members can wrap keys
-    if !check_membership(group_id, account_id) {
-        return error_response("Not a group member", 403);
-    }
+    let group_key = match member_group_key(group_id, account_id) {
+        Some(k) => k,
+        None => return error_response("Not a group member", 403),
+    };
 
     // Decode the plaintext key (client's ephemeral encryption key)
     let plaintext_key = match BASE64.decode(plaintext_key_b64) {
@@ -365,9 +678,6 @@ fn handle_wrap_key(group_id: &str, account_id: &str, plaintext_key_b64: &str) ->
         return error_response("Key must be 32 bytes", 400);
     }
 
-    // Get group key
-    let group_key = derive_group_key(&get_ckd_master_seed(), group_id);
-
     // Wrap the plaintext key (encrypt with group key)
     let wrapped_key = match encrypt(&plaintext_key, &group_key) {
         Ok(w) => w,
@@ -382,11 +692,17 @@ fn handle_wrap_key(group_id: &str, account_id: &str, plaintext_key_b64: &str) ->
     serde_json::to_string(&response).unwrap_or_else(|e| error_response(&e.to_string(), 500))
 }
 
-fn handle_unwrap_key(group_id: &str, account_id: &str, wrapped_key_b64: &str) -> String {
+fn handle_unwrap_key(
+    group_id: &str,
+    account_id: &str,
+    wrapped_key_b64: &str,
+    expected_key_id: Option<&str>,
+) -> String {
     // Check membership - only members can unwrap keys
-    if !check_membership(group_id, account_id) {
-        return error_response("Not a group member", 403);
-    }
+    let group_key = match member_group_key(group_id, account_id) {
+        Some(k) => k,
+        None => return error_response("Not a group member", 403),
+    };
 
     // Decode the wrapped key
     let wrapped_key = match BASE64.decode(wrapped_key_b64) {
@@ -394,8 +710,9 @@ fn handle_unwrap_key(group_id: &str, account_id: &str, wrapped_key_b64: &str) ->
         Err(e) => return error_response(&format!("Invalid base64 wrapped key: {}", e), 400),
     };
 
-    // Get group key
-    let group_key = derive_group_key(&get_ckd_master_seed(), group_id);
+    if let Err(e) = check_expected_key_id(group_id, expected_key_id) {
+        return error_response(&e, 409);
+    }
 
     // Unwrap (decrypt with group key)
     let plaintext_key = match decrypt(&wrapped_key, &group_key) {
@@ -412,57 +729,114 @@ fn handle_unwrap_key(group_id: &str, account_id: &str, wrapped_key_b64: &str) ->
 }
 
 fn handle_encrypt(group_id: &str, account_id: &str, plaintext_b64: &str) -> String {
-    if !check_membership(group_id, account_id) {
-        return error_response("Not a group member", 403);
+    match encrypt_for_member(group_id, account_id, plaintext_b64) {
+        Ok(response) => {
+            serde_json::to_string(&response).unwrap_or_else(|e| error_response(&e.to_string(), 500))
+        }
+        Err(error) => error,
     }
+}
 
-    let plaintext = match BASE64.decode(plaintext_b64) {
-        Ok(p) => p,
-        Err(e) => return error_response(&format!("Invalid base64 plaintext: {}", e), 400),
-    };
+fn handle_encrypt_tagged(group_id: &str, account_id: &str, plaintext_b64: &str) -> String {
+    match encrypt_for_member(group_id, account_id, plaintext_b64) {
+        Ok(encrypted) => {
+            let response = TaggedValueResponse {
+                tagged_value: format_tagged_value(&encrypted.key_id, &encrypted.ciphertext_b64),
+            };
+            serde_json::to_string(&response).unwrap_or_else(|e| error_response(&e.to_string(), 500))
+        }
+        Err(error) => error,
+    }
+}
 
-    let key = derive_group_key(&get_ckd_master_seed(), group_id);
-    let ciphertext = match encrypt(&plaintext, &key) {
-        Ok(c) => c,
-        Err(e) => return error_response(&e, 500),
-    };
+/// Shared by `encrypt` and `encrypt_tagged`; the error is a ready-made error response.
+fn encrypt_for_member(
+    group_id: &str,
+    account_id: &str,
+    plaintext_b64: &str,
+) -> Result<EncryptResponse, String> {
+    let key = member_group_key(group_id, account_id)
+        .ok_or_else(|| error_response("Not a group member", 403))?;
+
+    let plaintext = BASE64
+        .decode(plaintext_b64)
+        .map_err(|e| error_response(&format!("Invalid base64 plaintext: {e}"), 400))?;
+
+    let ciphertext = encrypt(&plaintext, &key).map_err(|e| error_response(&e, 500))?;
 
-    let response = EncryptResponse {
+    Ok(EncryptResponse {
         ciphertext_b64: BASE64.encode(&ciphertext),
         key_id: key_id_for_group(group_id),
-    };
-
-    serde_json::to_string(&response).unwrap_or_else(|e| error_response(&e.to_string(), 500))
+    })
 }
 
-fn handle_decrypt(group_id: &str, account_id: &str, ciphertext_b64: &str) -> String {
-    if !check_membership(group_id, account_id) {
-        return error_response("Not a group member", 403);
-    }
+fn handle_decrypt(
+    group_id: &str,
+    account_id: &str,
+    ciphertext_b64: &str,
+    expected_key_id: Option<&str>,
+    lossy_utf8: bool,
+) -> String {
+    let key = match member_group_key(group_id, account_id) {
+        Some(k) => k,
+        None => return error_response("Not a group member", 403),
+    };
 
     let ciphertext = match BASE64.decode(ciphertext_b64) {
         Ok(c) => c,
         Err(e) => return error_response(&format!("Invalid base64 ciphertext: {}", e), 400),
     };
 
-    let key = derive_group_key(&get_ckd_master_seed(), group_id);
+    if let Err(e) = check_expected_key_id(group_id, expected_key_id) {
+        return error_response(&e, 409);
+    }
+
     let plaintext = match decrypt(&ciphertext, &key) {
         Ok(p) => p,
         Err(e) => return error_response(&e, 500),
     };
 
     let plaintext_b64 = BASE64.encode(&plaintext);
-    let plaintext_utf8 = String::from_utf8(plaintext.clone()).ok();
+    let (plaintext_utf8, encoding) = plaintext_text(plaintext, lossy_utf8);
 
     let response = DecryptResponse {
         plaintext_b64,
         plaintext_utf8,
+        encoding,
         key_id: key_id_for_group(group_id),
     };
 
     serde_json::to_string(&response).unwrap_or_else(|e| error_response(&e.to_string(), 500))
 }
 
+/// The tag's key_id is checked like `expected_key_id`, so a value written
+/// under another group's key fails with 409 rather than a decryption error.
+fn handle_decrypt_tagged(
+    group_id: &str,
+    account_id: &str,
+    tagged_value: &str,
+    lossy_utf8: bool,
+) -> String {
+    match parse_tagged_value(tagged_value) {
+        Ok((key_id, ciphertext_b64)) => {
+            handle_decrypt(group_id, account_id, ciphertext_b64, Some(key_id), lossy_utf8)
+        }
+        Err(e) => error_response(&e, 400),
+    }
+}
+
+/// Text form of decrypted bytes. Invalid UTF-8 is reported as binary and
+/// only converted (with replacement chars) when `lossy` is set.
+fn plaintext_text(plaintext: Vec<u8>, lossy: bool) -> (Option<String>, PlaintextEncoding) {
+    match String::from_utf8(plaintext) {
+        Ok(text) => (Some(text), PlaintextEncoding::Utf8),
+        Err(e) => {
+            let text = lossy.then(|| String::from_utf8_lossy(e.as_bytes()).into_owned());
+            (text, PlaintextEncoding::Binary)
+        }
+    }
+}
+
 fn handle_verify_membership(group_id: &str, account_id: &str) -> String {
     let is_member = check_membership(group_id, account_id);
 
@@ -476,11 +850,11 @@ fn handle_verify_membership(group_id: &str, account_id: &str) -> String {
 }
 
 fn handle_batch_encrypt(group_id: &str, account_id: &str, items: &[EncryptItem]) -> String {
-    if !check_membership(group_id, account_id) {
-        return error_response("Not a group member", 403);
-    }
+    let key = match member_group_key(group_id, account_id) {
+        Some(k) => k,
+        None => return error_response("Not a group member", 403),
+    };
 
-    let key = derive_group_key(&get_ckd_master_seed(), group_id);
     let key_id = key_id_for_group(group_id);
 
     let results: Vec<BatchEncryptItemResult> = items
@@ -513,11 +887,11 @@ fn handle_batch_encrypt(group_id: &str, account_id: &str, items: &[EncryptItem])
 }
 
 fn handle_batch_decrypt(group_id: &str, account_id: &str, items: &[DecryptItem]) -> String {
-    if !check_membership(group_id, account_id) {
-        return error_response("Not a group member", 403);
-    }
+    let key = match member_group_key(group_id, account_id) {
+        Some(k) => k,
+        None => return error_response("Not a group member", 403),
+    };
 
-    let key = derive_group_key(&get_ckd_master_seed(), group_id);
     let key_id = key_id_for_group(group_id);
 
     let results: Vec<BatchDecryptItemResult> = items
@@ -556,6 +930,34 @@ fn handle_batch_decrypt(group_id: &str, account_id: &str, items: &[DecryptItem])
     serde_json::to_string(&response).unwrap_or_else(|e| error_response(&e.to_string(), 500))
 }
 
+fn handle_self_test(group_id: &str) -> String {
+    let master_seed = get_ckd_master_seed();
+    let group_key = derive_group_key(&master_seed, group_id);
+    // Not random (there is no RNG dependency) but never leaves this function
+    let ephemeral_key = derive_self_test_key(&master_seed, group_id);
+
+    match wrap_unwrap_roundtrip(&group_key, &ephemeral_key, decrypt) {
+        Ok(()) => serde_json::to_string(&SelfTestResponse { ok: true })
+            .unwrap_or_else(|e| error_response(&e.to_string(), 500)),
+        Err(e) => error_response(&format!("Self-test failed: {e}"), 500),
+    }
+}
+
+/// Wrap `key` with `group_key`, unwrap it with `unwrap` and compare.
+/// Errors never include key material.
+fn wrap_unwrap_roundtrip(
+    group_key: &[u8; 32],
+    key: &[u8; 32],
+    unwrap: impl Fn(&[u8], &[u8; 32]) -> Result<Vec<u8>, String>,
+) -> Result<(), String> {
+    let wrapped = encrypt(key, group_key)?;
+    let unwrapped = unwrap(&wrapped, group_key)?;
+    if !ct_eq(&unwrapped, key) {
+        return Err("unwrapped key does not match".to_string());
+    }
+    Ok(())
+}
+
 fn error_response(message: &str, code: u32) -> String {
     serde_json::to_string(&ErrorResponse {
         error: message.to_string(),
@@ -630,6 +1032,28 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_encrypt_decrypt_b64_roundtrip() {
+        let key_b64 = BASE64.encode(derive_group_key(b"master", "alice.near/data"));
+        let plaintext = b"Offline value";
+
+        let ciphertext = encrypt_b64(&BASE64.encode(plaintext), &key_b64).expect("Encryption failed");
+        let decrypted = decrypt_b64(&BASE64.encode(&ciphertext), &key_b64).expect("Decryption failed");
+
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn test_decrypt_b64_rejects_short_key() {
+        let key_b64 = BASE64.encode([7u8; 31]);
+        let ciphertext_b64 = BASE64.encode([0u8; 40]);
+
+        let err = decrypt_b64(&ciphertext_b64, &key_b64).unwrap_err();
+        assert_eq!(err, "Key must be 32 bytes, got 31");
+        let err = encrypt_b64(&BASE64.encode(b"x"), &key_b64).unwrap_err();
+        assert_eq!(err, "Key must be 32 bytes, got 31");
+    }
+
     #[test]
     fn test_execute_get_key() {
         let input = r#"{"action":"get_key","group_id":"alice.near/private","account_id":"alice.near"}"#;
@@ -662,6 +1086,8 @@ mod tests {
             group_id: "alice.near/data".to_string(),
             account_id: "alice.near".to_string(),
             ciphertext_b64: encrypt_resp.ciphertext_b64,
+            expected_key_id: None,
+            lossy_utf8: false,
         })
         .unwrap();
 
@@ -674,6 +1100,92 @@ mod tests {
         assert_eq!(decrypted, plaintext);
     }
 
+    fn encrypt_for(group_id: &str, plaintext: &[u8]) -> String {
+        let key = derive_group_key(&get_ckd_master_seed(), group_id);
+        BASE64.encode(encrypt(plaintext, &key).unwrap())
+    }
+
+    #[test]
+    fn test_decrypt_with_matching_key_id() {
+        let input = serde_json::to_string(&Request::Decrypt {
+            group_id: "alice.near/data".to_string(),
+            account_id: "alice.near".to_string(),
+            ciphertext_b64: encrypt_for("alice.near/data", b"hi"),
+            expected_key_id: Some(key_id_for_group("alice.near/data")),
+            lossy_utf8: false,
+        })
+        .unwrap();
+
+        let response: DecryptResponse = serde_json::from_str(&execute(&input)).expect("Decrypt failed");
+        assert_eq!(response.plaintext_utf8.as_deref(), Some("hi"));
+    }
+
+    fn decrypt_text(plaintext: &[u8], lossy_utf8: bool) -> DecryptResponse {
+        let input = serde_json::to_string(&Request::Decrypt {
+            group_id: "alice.near/data".to_string(),
+            account_id: "alice.near".to_string(),
+            ciphertext_b64: encrypt_for("alice.near/data", plaintext),
+            expected_key_id: None,
+            lossy_utf8,
+        })
+        .unwrap();
+        serde_json::from_str(&execute(&input)).expect("Decrypt failed")
+    }
+
+    #[test]
+    fn test_decrypt_reports_plaintext_encoding() {
+        let utf8 = decrypt_text("héllo".as_bytes(), false);
+        assert_eq!(utf8.encoding, PlaintextEncoding::Utf8);
+        assert_eq!(utf8.plaintext_utf8.as_deref(), Some("héllo"));
+
+        let binary = decrypt_text(&[0x66, 0xff, 0x6f], false);
+        assert_eq!(binary.encoding, PlaintextEncoding::Binary);
+        assert_eq!(binary.plaintext_utf8, None);
+        assert_eq!(BASE64.decode(&binary.plaintext_b64).unwrap(), [0x66, 0xff, 0x6f]);
+
+        // Empty plaintext is valid UTF-8, distinct from binary
+        let empty = decrypt_text(b"", false);
+        assert_eq!(empty.encoding, PlaintextEncoding::Utf8);
+        assert_eq!(empty.plaintext_utf8.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_decrypt_lossy_utf8() {
+        let lossy = decrypt_text(&[0x66, 0xff, 0x6f], true);
+        assert_eq!(lossy.encoding, PlaintextEncoding::Binary);
+        assert_eq!(lossy.plaintext_utf8.as_deref(), Some("f\u{FFFD}o"));
+
+        let json = serde_json::to_value(&lossy).unwrap();
+        assert_eq!(json["encoding"], "binary");
+        assert_eq!(decrypt_text(b"ok", true).plaintext_utf8.as_deref(), Some("ok"));
+    }
+
+    #[test]
+    fn test_key_id_mismatch_is_distinct_error() {
+        let other_key_id = key_id_for_group("alice.near/other");
+        let decrypt_input = serde_json::to_string(&Request::Decrypt {
+            group_id: "alice.near/data".to_string(),
+            account_id: "alice.near".to_string(),
+            ciphertext_b64: encrypt_for("alice.near/other", b"hi"),
+            expected_key_id: Some(other_key_id.clone()),
+            lossy_utf8: false,
+        })
+        .unwrap();
+        let unwrap_input = serde_json::to_string(&Request::UnwrapKey {
+            group_id: "alice.near/data".to_string(),
+            account_id: "alice.near".to_string(),
+            wrapped_key_b64: encrypt_for("alice.near/other", &[1u8; 32]),
+            expected_key_id: Some(other_key_id),
+        })
+        .unwrap();
+
+        for input in [decrypt_input, unwrap_input] {
+            let response: ErrorResponse = serde_json::from_str(&execute(&input)).unwrap();
+            assert_eq!(response.code, 409);
+            assert!(response.error.starts_with("key_id mismatch"), "{}", response.error);
+        }
+    }
+
     #[test]
     fn test_membership_check() {
         // Mock: .near and .testnet accounts are members
@@ -684,6 +1196,85 @@ mod tests {
         assert!(check_membership("alice/private", "alice.near"));
     }
 
+    #[test]
+    fn test_membership_evaluates_all_rules() {
+        // The prefix rule matches first, but the suffix rules still run
+        assert_eq!(membership_rules("alice/private", "alice.near"), [true, true, false]);
+        assert_eq!(membership_rules("alice/private", "alice.testnet"), [true, false, true]);
+        assert_eq!(membership_rules("bob/private", "alice.eth"), [false, false, false]);
+        assert!(!check_membership("bob/private", "alice.eth"));
+    }
+
+    #[test]
+    fn test_non_member_gets_403_without_key() {
+        assert!(member_group_key("bob/private", "alice.eth").is_none());
+        assert_eq!(
+            member_group_key("alice/private", "alice.near"),
+            Some(derive_group_key(&get_ckd_master_seed(), "alice/private"))
+        );
+
+        let input = r#"{"action":"get_key","group_id":"bob/private","account_id":"alice.eth"}"#;
+        let response: ErrorResponse = serde_json::from_str(&execute(input)).unwrap();
+        assert_eq!(response.code, 403);
+    }
+
+    #[test]
+    fn test_batch_get_group_key_id_preserves_order() {
+        let group_ids = vec![
+            "carol.near/c".to_string(),
+            "alice.near/a".to_string(),
+            "bob.near/b".to_string(),
+        ];
+        let input = serde_json::to_string(&Request::BatchGetGroupKeyId { group_ids: group_ids.clone() }).unwrap();
+
+        let response: Vec<GroupKeyIdResponse> =
+            serde_json::from_str(&execute(&input)).expect("Invalid response");
+
+        let returned: Vec<&str> = response.iter().map(|r| r.group_id.as_str()).collect();
+        assert_eq!(returned, group_ids);
+        for item in &response {
+            assert_eq!(item.key_id, key_id_for_group(&item.group_id));
+        }
+    }
+
+    #[test]
+    fn test_batch_get_group_key_id_rejects_over_cap() {
+        let group_ids = (0..=MAX_BATCH_GROUP_IDS).map(|i| format!("g{i}")).collect();
+        let input = serde_json::to_string(&Request::BatchGetGroupKeyId { group_ids }).unwrap();
+
+        let response: ErrorResponse = serde_json::from_str(&execute(&input)).expect("Invalid response");
+        assert_eq!(response.code, 400);
+        assert!(response.error.starts_with("Too many group_ids"));
+    }
+
+    #[test]
+    fn test_prepare_groups_reports_membership_and_key_id() {
+        let group_ids = vec!["bob/private".to_string(), "alice/private".to_string()];
+        let input = serde_json::to_string(&Request::PrepareGroups {
+            account_id: "alice.eth".to_string(),
+            group_ids: group_ids.clone(),
+        })
+        .unwrap();
+
+        let response: Vec<PreparedGroup> =
+            serde_json::from_str(&execute(&input)).expect("Invalid response");
+        let summary: Vec<(&str, bool)> =
+            response.iter().map(|g| (g.group_id.as_str(), g.is_member)).collect();
+        assert_eq!(summary, [("bob/private", false), ("alice/private", true)]);
+        for group in &response {
+            assert_eq!(group.key_id, key_id_for_group(&group.group_id));
+        }
+
+        let group_ids = (0..=MAX_BATCH_GROUP_IDS).map(|i| format!("g{i}")).collect();
+        let input = serde_json::to_string(&Request::PrepareGroups {
+            account_id: "alice.near".to_string(),
+            group_ids,
+        })
+        .unwrap();
+        let response: ErrorResponse = serde_json::from_str(&execute(&input)).unwrap();
+        assert_eq!(response.code, 400);
+    }
+
     #[test]
     fn test_key_id_deterministic() {
         let id1 = key_id_for_group("alice.near/data");
@@ -704,4 +1295,143 @@ mod tests {
         assert_ne!(att1, att3);
         assert_eq!(att1.len(), 32); // 16 bytes hex
     }
+
+    #[test]
+    fn test_sha256_attestation_config() {
+        let default = Sha256Attestation::default();
+        assert_eq!(default.attest("in", "out"), generate_attestation("in", "out"));
+
+        let long = Sha256Attestation { length: 32, ..Sha256Attestation::default() };
+        let full = long.attest("in", "out");
+        assert_eq!(full.len(), 64);
+        assert!(full.starts_with(&default.attest("in", "out")));
+
+        let other_prefix = Sha256Attestation { prefix: "v2".to_string(), length: 16 };
+        assert_ne!(other_prefix.attest("in", "out"), default.attest("in", "out"));
+        // Out-of-range lengths are clamped to the digest size
+        assert_eq!(Sha256Attestation { length: 99, ..default }.attest("in", "out"), full);
+    }
+
+    #[test]
+    fn test_parse_tagged_value() {
+        assert_eq!(parse_tagged_value("enc:AES256:abc123:Zm9v"), Ok(("abc123", "Zm9v")));
+        assert_eq!(parse_tagged_value("\"enc:AES256:abc123:Zm9v\""), Ok(("abc123", "Zm9v")));
+        for malformed in [
+            "Zm9v",
+            "enc:AES128:abc123:Zm9v",
+            "enc:AES256:abc123",
+            "enc:AES256::Zm9v",
+            "enc:AES256:abc123:",
+        ] {
+            assert!(parse_tagged_value(malformed).is_err(), "{malformed}");
+        }
+    }
+
+    fn decrypt_tagged(group_id: &str, tagged_value: String) -> String {
+        execute(
+            &serde_json::to_string(&Request::DecryptTagged {
+                group_id: group_id.to_string(),
+                account_id: "alice.near".to_string(),
+                tagged_value,
+                lossy_utf8: false,
+            })
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_decrypt_tagged_value() {
+        let key_id = key_id_for_group("alice.near/data");
+        let tagged = format!("enc:AES256:{key_id}:{}", encrypt_for("alice.near/data", b"hi"));
+        let response: DecryptResponse =
+            serde_json::from_str(&decrypt_tagged("alice.near/data", tagged.clone())).expect("Decrypt failed");
+        assert_eq!(response.plaintext_utf8.as_deref(), Some("hi"));
+        assert_eq!(response.key_id, key_id);
+
+        // Tagged for another group
+        let response: ErrorResponse =
+            serde_json::from_str(&decrypt_tagged("alice.near/other", tagged)).unwrap();
+        assert_eq!(response.code, 409);
+
+        let response: ErrorResponse =
+            serde_json::from_str(&decrypt_tagged("alice.near/data", format!("enc:AES256:{key_id}"))).unwrap();
+        assert_eq!(response.code, 400);
+    }
+
+    #[test]
+    fn test_encrypt_tagged_roundtrip() {
+        let input = serde_json::to_string(&Request::EncryptTagged {
+            group_id: "alice.near/data".to_string(),
+            account_id: "alice.near".to_string(),
+            plaintext_b64: BASE64.encode("tagged"),
+        })
+        .unwrap();
+        let response: TaggedValueResponse =
+            serde_json::from_str(&execute(&input)).expect("EncryptTagged failed");
+
+        let (key_id, _) = parse_tagged_value(&response.tagged_value).unwrap();
+        assert_eq!(key_id, key_id_for_group("alice.near/data"));
+        let decrypted: DecryptResponse =
+            serde_json::from_str(&decrypt_tagged("alice.near/data", response.tagged_value)).unwrap();
+        assert_eq!(decrypted.plaintext_utf8.as_deref(), Some("tagged"));
+    }
+
+    #[test]
+    fn test_self_test_ok() {
+        let input = r#"{"action":"self_test","group_id":"alice.near/private"}"#;
+        let output = execute(input);
+        let response: SelfTestResponse = serde_json::from_str(&output).expect("Self-test failed");
+        assert!(response.ok);
+        assert_eq!(output, r#"{"ok":true}"#);
+    }
+
+    #[test]
+    fn test_self_test_key_is_not_a_group_key() {
+        let key = derive_self_test_key(b"master", "alice.near/private");
+        assert_ne!(key, derive_group_key(b"master", "alice.near/private"));
+        assert_ne!(key, derive_group_key(b"master", "alice.near/private/self_test"));
+    }
+
+    #[test]
+    fn test_self_test_surfaces_crypto_failures() {
+        let group_key = derive_group_key(b"master", "alice.near/private");
+        let key = [9u8; 32];
+        assert_eq!(wrap_unwrap_roundtrip(&group_key, &key, decrypt), Ok(()));
+
+        // Tampered ciphertext fails authentication
+        let tampered = |wrapped: &[u8], k: &[u8; 32]| {
+            let mut wrapped = wrapped.to_vec();
+            wrapped[20] ^= 1;
+            decrypt(&wrapped, k)
+        };
+        let err = wrap_unwrap_roundtrip(&group_key, &key, tampered).unwrap_err();
+        assert!(err.starts_with("Decryption failed"), "{err}");
+
+        let wrong_key = |_: &[u8], _: &[u8; 32]| Ok(vec![0u8; 32]);
+        let err = wrap_unwrap_roundtrip(&group_key, &key, wrong_key).unwrap_err();
+        assert_eq!(err, "unwrapped key does not match");
+    }
+
+    struct FixedQuote;
+
+    impl AttestationProvider for FixedQuote {
+        fn attest(&self, input: &str, _output: &str) -> String {
+            format!("tdx-quote:{input}")
+        }
+    }
+
+    #[test]
+    fn test_injected_attestation_provider_is_used() {
+        let input = r#"{"action":"get_key","group_id":"alice.near/private","account_id":"alice.near"}"#;
+
+        let response: KeyResponse = serde_json::from_str(&execute_with(input, &FixedQuote)).unwrap();
+        assert_eq!(response.attestation_hash, "tdx-quote:get_key:alice.near/private:alice.near");
+
+        let mock: KeyResponse =
+            serde_json::from_str(&execute_with(input, &Sha256Attestation::default())).unwrap();
+        assert_eq!(
+            mock.attestation_hash,
+            generate_attestation("get_key:alice.near/private:alice.near", &mock.key_b64)
+        );
+    }
 }
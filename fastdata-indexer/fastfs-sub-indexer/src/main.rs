@@ -8,6 +8,7 @@ use crate::scylla_types::{
 use dotenvy::dotenv;
 use fastnear_primitives::near_indexer_primitives::types::BlockHeight;
 use fastnear_primitives::types::ChainId;
+use redis_db::UNIVERSAL_SUFFIX;
 use scylla::statement::prepared::PreparedStatement;
 use scylladb::{retry_with_delays, ScyllaDb};
 use std::env;
@@ -38,7 +39,7 @@ async fn main() {
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("fastfs-sub-indexer=info,scylladb=info,suffix-fetcher=info")),
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("fastfs-sub-indexer=info,scylladb=info,suffix-fetcher=info,indexer-health=info")),
         )
         .init();
 
@@ -84,6 +85,24 @@ async fn main() {
     })
     .expect("Error setting Ctrl+C handler");
 
+    if let Some(port) = indexer_health::port_from_env() {
+        let (scylladb, redis_db, is_running) = (scylladb.clone(), fetcher.get_redis_db(), is_running.clone());
+        let server = indexer_health::start(port, move || {
+            let (scylladb, redis_db, is_running) = (scylladb.clone(), redis_db.clone(), is_running.clone());
+            async move {
+                let last_processed_block = scylladb.get_last_processed_block_height(INDEXER_ID).await?;
+                let head = redis_db.get_last_processed_block_height(UNIVERSAL_SUFFIX).await?;
+                Ok(indexer_health::HealthStatus {
+                    last_processed_block,
+                    lag: indexer_health::lag(head, last_processed_block),
+                    is_running: is_running.load(Ordering::SeqCst),
+                })
+            }
+        })
+        .expect("Can't start health endpoint");
+        tokio::spawn(server);
+    }
+
     tracing::info!(target: PROJECT_ID,
         "Starting {:?} {} fetcher from height {} with indexer ID {}",
         SUFFIX,
@@ -98,6 +117,10 @@ async fn main() {
             suffix: SUFFIX.to_string(),
             start_block_height: Some(start_block_height),
             sleep_duration: Duration::from_millis(500),
+            replay: None,
+            head_suffix: None,
+            max_concurrency: 1,
+            checkpoint_key: None,
         },
         sender,
         is_running.clone(),
@@ -205,7 +228,7 @@ async fn main() {
                     };
                 }
             }
-            SuffixFetcherUpdate::EndOfRange(block_height) => {
+            SuffixFetcherUpdate::EndOfRange { block_height, .. } => {
                 tracing::info!(target: PROJECT_ID, "Saving last processed block height: {}", block_height);
 
                 // Retry checkpoint write with delays
@@ -260,6 +283,11 @@ async fn main() {
                     break;
                 }
             }
+            SuffixFetcherUpdate::Error { message, .. } => {
+                tracing::error!(target: PROJECT_ID, "Suffix fetcher failed, shutting down: {}", message);
+                is_running.store(false, Ordering::SeqCst);
+                break;
+            }
         };
     }
 
@@ -0,0 +1,54 @@
+//! `CHAIN_ID` parsing shared by the indexers and fastkv-server.
+//!
+//! Each binary still converts the result into its own `ChainId`; this only
+//! replaces the bare "Invalid chain id" panic with an error that says what to
+//! set instead.
+
+use std::env;
+
+/// Every chain id the indexers and fastkv-server accept.
+pub const CHAIN_IDS: [&str; 2] = ["mainnet", "testnet"];
+
+/// Validate a `CHAIN_ID` value (trimmed, case-insensitive) and return its
+/// canonical form. The error lists the accepted values.
+pub fn parse_chain_id(value: &str) -> Result<&'static str, String> {
+    let normalized = value.trim().to_ascii_lowercase();
+    CHAIN_IDS
+        .iter()
+        .find(|id| **id == normalized)
+        .copied()
+        .ok_or_else(|| {
+            format!(
+                "Invalid CHAIN_ID {value:?}: expected one of {}",
+                CHAIN_IDS.join(", ")
+            )
+        })
+}
+
+/// Read and validate `CHAIN_ID`.
+pub fn chain_id_from_env() -> Result<&'static str, String> {
+    match env::var("CHAIN_ID") {
+        Ok(value) => parse_chain_id(&value),
+        Err(_) => Err(format!(
+            "CHAIN_ID is required: set it to one of {}",
+            CHAIN_IDS.join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chain_id() {
+        assert_eq!(parse_chain_id("mainnet"), Ok("mainnet"));
+        assert_eq!(parse_chain_id(" Testnet\n"), Ok("testnet"));
+
+        let err = parse_chain_id("mainet").unwrap_err();
+        assert_eq!(err, r#"Invalid CHAIN_ID "mainet": expected one of mainnet, testnet"#);
+        for id in CHAIN_IDS {
+            assert!(parse_chain_id("").unwrap_err().contains(id));
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Redis key formats shared by the indexers, the suffix fetcher and
+//! fastkv-server. Change a format here and nowhere else.
+
+/// `checkpoint:{chain_id}:{suffix}` — last processed block height per indexer/suffix.
+pub fn checkpoint(chain_id: &str, suffix: &str) -> String {
+    format!("checkpoint:{chain_id}:{suffix}")
+}
+
+/// `fastdata:{chain_id}:{suffix}:{block_height}:{receipt_id}` — raw FastData JSON.
+pub fn fastdata(chain_id: &str, suffix: &str, block_height: u64, receipt_id: &str) -> String {
+    format!("fastdata:{chain_id}:{suffix}:{block_height}:{receipt_id}")
+}
+
+/// SCAN pattern matching every [`fastdata`] key for a suffix.
+pub fn fastdata_pattern(chain_id: &str, suffix: &str) -> String {
+    format!("fastdata:{chain_id}:{suffix}:*")
+}
+
+/// Block height embedded in a [`fastdata`] key, if the key is well-formed.
+pub fn fastdata_block_height(key: &str) -> Option<u64> {
+    let mut parts = key.splitn(5, ':');
+    match (parts.next(), parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some("fastdata"), Some(_), Some(_), Some(height), Some(_)) => height.parse().ok(),
+        _ => None,
+    }
+}
+
+/// `kv:{predecessor_id}:{current_account_id}:{key}` — current value.
+pub fn kv(predecessor_id: &str, current_account_id: &str, key: &str) -> String {
+    format!("kv:{predecessor_id}:{current_account_id}:{key}")
+}
+
+/// `kv:{predecessor_id}:{current_account_id}:` — prefix of every [`kv`] key
+/// written by one account to one contract.
+pub fn kv_prefix(predecessor_id: &str, current_account_id: &str) -> String {
+    format!("kv:{predecessor_id}:{current_account_id}:")
+}
+
+/// `history:{predecessor_id}:{current_account_id}:{key}` — zset of values by block height.
+pub fn history(predecessor_id: &str, current_account_id: &str, key: &str) -> String {
+    format!("history:{predecessor_id}:{current_account_id}:{key}")
+}
+
+/// `receipt:{receipt_id}` — set of stored entry JSON written by a receipt.
+pub fn receipt(receipt_id: &str) -> String {
+    format!("receipt:{receipt_id}")
+}
+
+/// `tx:{tx_hash}` — set of stored entry JSON written under a transaction.
+pub fn tx(tx_hash: &str) -> String {
+    format!("tx:{tx_hash}")
+}
+
+/// `accounts:{current_account_id}` — set of accounts that wrote to a contract.
+pub fn accounts(current_account_id: &str) -> String {
+    format!("accounts:{current_account_id}")
+}
+
+/// `contracts:{predecessor_id}` — set of contracts an account wrote to.
+pub fn contracts(predecessor_id: &str) -> String {
+    format!("contracts:{predecessor_id}")
+}
+
+/// `edges:{edge_type}:{target}` — set of accounts whose current value at
+/// `{edge_type}/{target}` is live (see [`crate::edge`]).
+pub fn edges(edge_type: &str, target: &str) -> String {
+    format!("edges:{edge_type}:{target}")
+}
+
+/// `changes:{chain_id}:{current_account_id}` — pub/sub channel for KV changes.
+pub fn changes(chain_id: &str, current_account_id: &str) -> String {
+    format!("changes:{chain_id}:{current_account_id}")
+}
+
+/// `meta:{chain_id}` — latest block height written by the main-indexer.
+pub fn meta(chain_id: &str) -> String {
+    format!("meta:{chain_id}")
+}
+
+/// Patterns of every key namespaced by chain id, for startup logs: a wrong
+/// `CHAIN_ID` shows up as a wrong prefix.
+pub fn chain_prefixes(chain_id: &str) -> [String; 4] {
+    [
+        checkpoint(chain_id, "*"),
+        fastdata_pattern(chain_id, "*"),
+        meta(chain_id),
+        changes(chain_id, "*"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_formats() {
+        assert_eq!(checkpoint("mainnet", "kv-sub-indexer"), "checkpoint:mainnet:kv-sub-indexer");
+        assert_eq!(fastdata("mainnet", "kv", 150, "rcpt"), "fastdata:mainnet:kv:150:rcpt");
+        assert_eq!(fastdata_pattern("mainnet", "kv"), "fastdata:mainnet:kv:*");
+        assert_eq!(kv("alice.near", "social.near", "profile/name"), "kv:alice.near:social.near:profile/name");
+        assert_eq!(kv_prefix("alice.near", "social.near"), "kv:alice.near:social.near:");
+        assert!(kv("alice.near", "social.near", "k").starts_with(&kv_prefix("alice.near", "social.near")));
+        assert_eq!(history("alice.near", "social.near", "profile/name"), "history:alice.near:social.near:profile/name");
+        assert_eq!(receipt("rcpt"), "receipt:rcpt");
+        assert_eq!(tx("hash"), "tx:hash");
+        assert_eq!(accounts("social.near"), "accounts:social.near");
+        assert_eq!(contracts("alice.near"), "contracts:alice.near");
+        assert_eq!(edges("graph/follow", "bob.near"), "edges:graph/follow:bob.near");
+        assert_eq!(changes("mainnet", "social.near"), "changes:mainnet:social.near");
+        assert_eq!(meta("mainnet"), "meta:mainnet");
+        assert_eq!(
+            chain_prefixes("testnet"),
+            ["checkpoint:testnet:*", "fastdata:testnet:*:*", "meta:testnet", "changes:testnet:*"]
+        );
+    }
+
+    #[test]
+    fn test_fastdata_block_height() {
+        assert_eq!(fastdata_block_height(&fastdata("mainnet", "kv", 150, "rcpt")), Some(150));
+        assert_eq!(fastdata_block_height("fastdata:mainnet:kv:bad:rcpt"), None);
+        assert_eq!(fastdata_block_height("fastdata:mainnet:kv:150"), None);
+        assert_eq!(fastdata_block_height("kv:mainnet:kv:150:rcpt"), None);
+    }
+}
@@ -0,0 +1,185 @@
+//! Formats shared by everything that reads or writes FastKV data in Redis:
+//! the kv-sub-indexer writes them, fastkv-server reads them back. Keeping a
+//! single definition here is what stops the two sides from drifting.
+
+pub mod chain;
+pub mod keys;
+
+pub use chain::{chain_id_from_env, parse_chain_id, CHAIN_IDS};
+
+use serde::{Deserialize, Serialize};
+
+/// Largest `receipt_index` that [`compute_order_id`] can encode.
+pub const MAX_ORDER_RECEIPT_INDEX: u32 = 99_999;
+/// Largest `action_index` that [`compute_order_id`] can encode.
+pub const MAX_ORDER_ACTION_INDEX: u32 = 999;
+
+/// Position of an action within its block, as a single sortable integer:
+/// `(shard_id * 100_000 + receipt_index) * 1_000 + action_index`.
+///
+/// Fails instead of wrapping when an index is out of range, since an
+/// overflowed id would collide with another action's.
+pub fn compute_order_id(shard_id: u32, receipt_index: u32, action_index: u32) -> Result<u64, String> {
+    if receipt_index > MAX_ORDER_RECEIPT_INDEX || action_index > MAX_ORDER_ACTION_INDEX {
+        return Err(format!(
+            "order_id encoding overflow: receipt_index={receipt_index}, action_index={action_index} (max receipt_index={MAX_ORDER_RECEIPT_INDEX}, max action_index={MAX_ORDER_ACTION_INDEX})"
+        ));
+    }
+    Ok((shard_id as u64 * 100_000 + receipt_index as u64) * 1_000 + action_index as u64)
+}
+
+/// Graph edge encoded by a key of 2+ `/`-separated segments, as in the
+/// Scylla `kv_edges` table: `(edge_type, target)`, where the target is the
+/// last segment and the edge type everything before it. The source is the
+/// writer, so `graph/follow/bob.near` written by alice is a follow of bob.
+pub fn edge(key: &str) -> Option<(&str, &str)> {
+    key.rsplit_once('/')
+        .filter(|(edge_type, target)| !edge_type.is_empty() && !target.is_empty())
+}
+
+/// What a stored value holds, detected once at ingestion so readers can serve
+/// it with the right hint instead of re-parsing. Serialized lowercase.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    /// Any non-string JSON value (object, array, number, bool, null).
+    #[default]
+    Json,
+    /// A JSON string holding text.
+    Text,
+    /// A JSON string holding base64-encoded bytes that are not text.
+    Binary,
+}
+
+/// JSON stored at `kv:*`, in `history:*` zsets and in the `receipt:*`/`tx:*`
+/// index sets. Field names and types are a wire contract: `tx_hash` is always
+/// a string, empty when unknown, and `deleted` is an optional tombstone marker
+/// that overrides the `"null"` value heuristic when present. `content_type`
+/// is only written at `kv:*`: zset and set members are deduplicated by their
+/// JSON, so they keep the original field set, and older `kv:*` values lack it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredKvEntry {
+    pub predecessor_id: String,
+    pub current_account_id: String,
+    pub key: String,
+    pub value: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub receipt_id: String,
+    #[serde(default)]
+    pub tx_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<ContentType>,
+}
+
+impl StoredKvEntry {
+    /// Marker to store for an entry: only written when the `"null"` heuristic
+    /// would get it wrong, so ordinary entries keep the original wire shape.
+    pub fn deletion_marker(is_deleted: bool, value: &str) -> Option<bool> {
+        (is_deleted != (value == "null")).then_some(is_deleted)
+    }
+
+    /// Whether this entry is a deletion: the explicit marker when present,
+    /// otherwise a stored value of `"null"`.
+    pub fn is_deleted(&self) -> bool {
+        self.deleted.unwrap_or(self.value == "null")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pinned wire format. Both the indexer's and the server's redis_db tests
+    /// round-trip this exact string.
+    const STORED_KV_FIXTURE: &str = r#"{"predecessor_id":"alice.near","current_account_id":"contract.near","key":"profile/name","value":"\"Alice\"","block_height":42,"block_timestamp":7,"receipt_id":"r","tx_hash":""}"#;
+
+    fn fixture_entry() -> StoredKvEntry {
+        StoredKvEntry {
+            predecessor_id: "alice.near".to_string(),
+            current_account_id: "contract.near".to_string(),
+            key: "profile/name".to_string(),
+            value: "\"Alice\"".to_string(),
+            block_height: 42,
+            block_timestamp: 7,
+            receipt_id: "r".to_string(),
+            tx_hash: String::new(),
+            deleted: None,
+            content_type: None,
+        }
+    }
+
+    #[test]
+    fn test_stored_kv_entry_wire_format() {
+        assert_eq!(serde_json::to_string(&fixture_entry()).unwrap(), STORED_KV_FIXTURE);
+        let read: StoredKvEntry = serde_json::from_str(STORED_KV_FIXTURE).unwrap();
+        assert_eq!(read, fixture_entry());
+    }
+
+    #[test]
+    fn test_stored_kv_entry_optional_fields() {
+        let json = r#"{"predecessor_id":"a.near","current_account_id":"c.near","key":"k","value":"null","block_height":1,"block_timestamp":0,"receipt_id":"r"}"#;
+        let stored: StoredKvEntry = serde_json::from_str(json).unwrap();
+        assert_eq!(stored.tx_hash, "");
+        assert_eq!(stored.deleted, None);
+        assert!(stored.is_deleted());
+
+        let kept_null = StoredKvEntry { deleted: Some(false), ..stored };
+        assert!(!kept_null.is_deleted());
+        assert!(serde_json::to_string(&kept_null).unwrap().ends_with(r#""deleted":false}"#));
+    }
+
+    #[test]
+    fn test_stored_kv_entry_content_type() {
+        let stored = StoredKvEntry { content_type: Some(ContentType::Binary), ..fixture_entry() };
+        let json = serde_json::to_string(&stored).unwrap();
+        assert!(json.ends_with(r#""content_type":"binary"}"#), "{json}");
+        assert_eq!(serde_json::from_str::<StoredKvEntry>(&json).unwrap(), stored);
+    }
+
+    #[test]
+    fn test_deletion_marker_round_trips() {
+        for (is_deleted, value) in [(true, "null"), (false, "\"null\""), (false, "null"), (true, "")] {
+            let stored = StoredKvEntry {
+                value: value.to_string(),
+                deleted: StoredKvEntry::deletion_marker(is_deleted, value),
+                ..fixture_entry()
+            };
+            assert_eq!(stored.is_deleted(), is_deleted, "{value}");
+        }
+        assert_eq!(StoredKvEntry::deletion_marker(true, "null"), None);
+        assert_eq!(StoredKvEntry::deletion_marker(false, "\"null\""), None);
+    }
+
+    #[test]
+    fn test_compute_order_id() {
+        assert_eq!(compute_order_id(0, 0, 0), Ok(0));
+        assert_eq!(compute_order_id(0, 0, 7), Ok(7));
+        assert_eq!(compute_order_id(0, 1, 0), Ok(1_000));
+        assert_eq!(compute_order_id(3, 12, 5), Ok(300_012_005));
+        assert_eq!(
+            compute_order_id(1, MAX_ORDER_RECEIPT_INDEX, MAX_ORDER_ACTION_INDEX),
+            Ok(199_999_999)
+        );
+        // Ordered by (shard, receipt, action)
+        assert!(compute_order_id(0, 1, 0).unwrap() > compute_order_id(0, 0, 999).unwrap());
+        assert!(compute_order_id(1, 0, 0).unwrap() > compute_order_id(0, 99_999, 999).unwrap());
+    }
+
+    #[test]
+    fn test_edge() {
+        assert_eq!(edge("graph/follow/bob.near"), Some(("graph/follow", "bob.near")));
+        assert_eq!(edge("like/post"), Some(("like", "post")));
+        assert_eq!(edge("profile"), None);
+        assert_eq!(edge("graph/follow/"), None);
+        assert_eq!(edge("/bob.near"), None);
+    }
+
+    #[test]
+    fn test_compute_order_id_rejects_overflow() {
+        assert!(compute_order_id(0, MAX_ORDER_RECEIPT_INDEX + 1, 0).is_err());
+        assert!(compute_order_id(0, 0, MAX_ORDER_ACTION_INDEX + 1).is_err());
+    }
+}
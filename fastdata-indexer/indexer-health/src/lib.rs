@@ -0,0 +1,111 @@
+//! Liveness endpoint for container orchestrators, shared by the standalone indexers.
+//!
+//! Only started when `HEALTH_PORT` is set. `GET /health` returns 200 with the
+//! indexer's status, or 503 once the indexer has begun shutting down or its
+//! status can't be read.
+
+use actix_web::{http::StatusCode, web, App, HttpResponse, HttpServer};
+use serde::Serialize;
+use std::env;
+use std::future::Future;
+
+const PROJECT_ID: &str = "indexer-health";
+
+/// What every indexer reports.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct HealthStatus {
+    pub last_processed_block: Option<u64>,
+    /// Blocks between the head checkpoint and this indexer's checkpoint.
+    pub lag: Option<u64>,
+    pub is_running: bool,
+}
+
+/// A status served from `/health`; extra fields are serialized alongside.
+pub trait Liveness: Serialize {
+    fn is_running(&self) -> bool;
+}
+
+impl Liveness for HealthStatus {
+    fn is_running(&self) -> bool {
+        self.is_running
+    }
+}
+
+pub fn port_from_env() -> Option<u16> {
+    env::var("HEALTH_PORT")
+        .ok()
+        .map(|s| s.parse().expect("Invalid HEALTH_PORT"))
+}
+
+/// Distance from the head checkpoint; before the first checkpoint, the whole head.
+pub fn lag(head: Option<u64>, last_processed_block: Option<u64>) -> Option<u64> {
+    match (head, last_processed_block) {
+        (Some(head), Some(last)) => Some(head.saturating_sub(last)),
+        (Some(head), None) => Some(head),
+        _ => None,
+    }
+}
+
+fn respond<S: Liveness>(status: anyhow::Result<S>) -> HttpResponse {
+    match status {
+        Ok(status) if status.is_running() => HttpResponse::Ok().json(status),
+        Ok(status) => HttpResponse::build(StatusCode::SERVICE_UNAVAILABLE).json(status),
+        Err(e) => {
+            tracing::warn!(target: PROJECT_ID, "Health check failed: {:?}", e);
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
+
+/// Bind the health server, answering every request with `probe()`. The
+/// returned future runs until the process exits.
+pub fn start<P, F, S>(port: u16, probe: P) -> std::io::Result<actix_web::dev::Server>
+where
+    P: Fn() -> F + Clone + Send + 'static,
+    F: Future<Output = anyhow::Result<S>> + 'static,
+    S: Liveness + 'static,
+{
+    tracing::info!(target: PROJECT_ID, "Health endpoint listening on 0.0.0.0:{}", port);
+
+    Ok(HttpServer::new(move || {
+        let probe = probe.clone();
+        App::new().route(
+            "/health",
+            web::get().to(move || {
+                let status = probe();
+                async move { respond(status.await) }
+            }),
+        )
+    })
+    .workers(1)
+    .disable_signals()
+    .bind(("0.0.0.0", port))?
+    .run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+
+    #[test]
+    fn test_lag() {
+        assert_eq!(lag(Some(1_000), Some(990)), Some(10));
+        assert_eq!(lag(Some(1_000), Some(1_005)), Some(0));
+        assert_eq!(lag(Some(1_000), None), Some(1_000));
+        assert_eq!(lag(None, Some(990)), None);
+    }
+
+    #[tokio::test]
+    async fn test_respond_reflects_running_state() {
+        let status = |is_running| HealthStatus { last_processed_block: Some(990), lag: Some(10), is_running };
+
+        let res = respond(Ok(status(true)));
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({"last_processed_block": 990, "lag": 10, "is_running": true}));
+
+        assert_eq!(respond(Ok(status(false))).status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(respond::<HealthStatus>(Err(anyhow::anyhow!("down"))).status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,222 @@
+//! Token-gated admin interface for operational control of the indexer.
+//!
+//! Only started when `ADMIN_TOKEN` is set. Exposes:
+//! - `POST /admin/replay?block_height=N` — resets the indexer checkpoint to `N`
+//!   and rewinds the running fetcher so blocks from `N + 1` are reprocessed.
+
+use crate::{INDEXER_ID, PROJECT_ID};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use fastnear_primitives::near_indexer_primitives::types::BlockHeight;
+use redis_db::{CheckpointStore, RedisDb};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::sync::Arc;
+use suffix_fetcher::ReplayHandle;
+
+const DEFAULT_ADMIN_PORT: u16 = 9090;
+
+pub struct AdminConfig {
+    pub token: String,
+    pub port: u16,
+}
+
+impl AdminConfig {
+    /// Returns `None` when `ADMIN_TOKEN` is unset or empty (admin disabled).
+    pub fn from_env() -> Option<Self> {
+        let token = env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty())?;
+        let port = env::var("ADMIN_PORT")
+            .ok()
+            .map(|s| s.parse().expect("Invalid ADMIN_PORT"))
+            .unwrap_or(DEFAULT_ADMIN_PORT);
+        Some(Self { token, port })
+    }
+}
+
+struct AdminState {
+    redis_db: Arc<RedisDb>,
+    replay: ReplayHandle,
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct ReplayParams {
+    block_height: BlockHeight,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ReplayOutcome {
+    pub previous_block_height: Option<BlockHeight>,
+    pub block_height: BlockHeight,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    /// Replay may only move the checkpoint backwards.
+    AheadOfCheckpoint {
+        requested: BlockHeight,
+        current: Option<BlockHeight>,
+    },
+    Store(redis_db::IndexerError),
+}
+
+/// Reset the indexer checkpoint to `block_height` and ask the fetcher to
+/// resume from `block_height + 1`.
+///
+/// The check and the reset are one atomic step in the store, so a checkpoint
+/// the indexer writes concurrently is never overwritten unchecked.
+/// Safe to repeat: KV writes are last-write-wins by block height, so
+/// reprocessing an older range does not overwrite newer current values.
+pub async fn apply_replay<S: CheckpointStore>(
+    store: &S,
+    replay: &ReplayHandle,
+    block_height: BlockHeight,
+) -> Result<ReplayOutcome, ReplayError> {
+    let current = store
+        .rewind_last_processed_block_height(INDEXER_ID, block_height)
+        .await
+        .map_err(ReplayError::Store)?;
+    if current.is_none_or(|h| block_height > h) {
+        return Err(ReplayError::AheadOfCheckpoint {
+            requested: block_height,
+            current,
+        });
+    }
+    replay.request(block_height + 1);
+    Ok(ReplayOutcome {
+        previous_block_height: current,
+        block_height,
+    })
+}
+
+fn is_authorized(req: &HttpRequest, token: &str) -> bool {
+    req.headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|provided| ct_eq(provided.as_bytes(), token.as_bytes()))
+}
+
+/// Compare equal-length byte slices without short-circuiting on the first mismatch
+fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn replay_handler(
+    req: HttpRequest,
+    query: web::Query<ReplayParams>,
+    state: web::Data<AdminState>,
+) -> HttpResponse {
+    if !is_authorized(&req, &state.token) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    tracing::warn!(target: PROJECT_ID, "Admin replay requested to block height {}", query.block_height);
+
+    match apply_replay(state.redis_db.as_ref(), &state.replay, query.block_height).await {
+        Ok(outcome) => HttpResponse::Ok().json(outcome),
+        Err(ReplayError::AheadOfCheckpoint { requested, current }) => {
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!(
+                    "block_height {} is ahead of the current checkpoint {:?}",
+                    requested, current
+                ),
+            }))
+        }
+        Err(ReplayError::Store(e)) => {
+            tracing::error!(target: PROJECT_ID, "Admin replay failed: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Bind the admin server. The returned future runs until the process exits.
+pub fn start(
+    config: AdminConfig,
+    redis_db: Arc<RedisDb>,
+    replay: ReplayHandle,
+) -> std::io::Result<actix_web::dev::Server> {
+    let state = web::Data::new(AdminState {
+        redis_db,
+        replay,
+        token: config.token,
+    });
+
+    tracing::info!(target: PROJECT_ID, "Admin interface listening on 0.0.0.0:{}", config.port);
+
+    Ok(HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .route("/admin/replay", web::post().to(replay_handler))
+    })
+    .workers(1)
+    .disable_signals()
+    .bind(("0.0.0.0", config.port))?
+    .run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::tests::MemoryStore;
+    use actix_web::test::TestRequest;
+
+    #[tokio::test]
+    async fn test_apply_replay_resets_checkpoint() {
+        let store = MemoryStore::default();
+        store.set_last_processed_block_height(INDEXER_ID, 500).await.unwrap();
+        let replay = ReplayHandle::new();
+
+        let outcome = apply_replay(&store, &replay, 120).await.unwrap();
+
+        assert_eq!(outcome, ReplayOutcome { previous_block_height: Some(500), block_height: 120 });
+        assert_eq!(store.get_last_processed_block_height(INDEXER_ID).await.unwrap(), Some(120));
+        assert_eq!(replay.take(), Some(121));
+    }
+
+    #[tokio::test]
+    async fn test_apply_replay_rejects_forward_skip() {
+        let store = MemoryStore::default();
+        store.set_last_processed_block_height(INDEXER_ID, 500).await.unwrap();
+        let replay = ReplayHandle::new();
+
+        let result = apply_replay(&store, &replay, 501).await;
+
+        assert!(matches!(result, Err(ReplayError::AheadOfCheckpoint { requested: 501, current: Some(500) })));
+        assert_eq!(store.get_last_processed_block_height(INDEXER_ID).await.unwrap(), Some(500));
+        assert_eq!(*store.writes.lock().unwrap(), vec![500]);
+        assert_eq!(replay.take(), None);
+    }
+
+    #[tokio::test]
+    async fn test_apply_replay_without_checkpoint() {
+        let store = MemoryStore::default();
+        let replay = ReplayHandle::new();
+
+        assert!(apply_replay(&store, &replay, 1).await.is_err());
+        assert_eq!(replay.take(), None);
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        let ok = TestRequest::default()
+            .insert_header(("Authorization", "Bearer secret"))
+            .to_http_request();
+        assert!(is_authorized(&ok, "secret"));
+
+        let wrong = TestRequest::default()
+            .insert_header(("Authorization", "Bearer nope"))
+            .to_http_request();
+        assert!(!is_authorized(&wrong, "secret"));
+
+        let prefix = TestRequest::default()
+            .insert_header(("Authorization", "Bearer secre"))
+            .to_http_request();
+        assert!(!is_authorized(&prefix, "secret"));
+
+        let missing = TestRequest::default().to_http_request();
+        assert!(!is_authorized(&missing, "secret"));
+    }
+}
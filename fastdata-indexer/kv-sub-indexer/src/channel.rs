@@ -0,0 +1,142 @@
+//! Sizing and instrumentation of the fetcher → indexer update channel.
+//!
+//! `INDEXER_CHANNEL_CAP` (default 100) bounds how far the suffix fetcher can
+//! run ahead of the Redis writer. When the channel is full the fetcher waits in
+//! `send`, which is the intended backpressure. [`ChannelStats`] tracks how deep
+//! the queue gets and how often it was found full; it is logged every
+//! [`STATS_LOG_INTERVAL`] and reported by `/health`.
+
+use crate::PROJECT_ID;
+use serde::Serialize;
+use std::env;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+pub const DEFAULT_CHANNEL_CAP: usize = 100;
+pub const STATS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+pub fn capacity_from_env() -> usize {
+    let capacity = env::var("INDEXER_CHANNEL_CAP")
+        .ok()
+        .map(|s| s.parse().expect("Invalid INDEXER_CHANNEL_CAP"))
+        .unwrap_or(DEFAULT_CHANNEL_CAP);
+    assert!(capacity > 0, "INDEXER_CHANNEL_CAP must be greater than 0");
+    capacity
+}
+
+/// Approximate channel occupancy, as seen by the consumer.
+#[derive(Debug)]
+pub struct ChannelStats {
+    capacity: usize,
+    depth: AtomicUsize,
+    max_depth: AtomicUsize,
+    full_count: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ChannelSnapshot {
+    pub capacity: usize,
+    /// Updates queued at the last receive.
+    pub depth: usize,
+    pub max_depth: usize,
+    /// Receives that found the channel full, i.e. the fetcher was (or was
+    /// about to be) blocked on `send`.
+    pub full_count: u64,
+}
+
+impl ChannelStats {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            depth: AtomicUsize::new(0),
+            max_depth: AtomicUsize::new(0),
+            full_count: AtomicU64::new(0),
+        }
+    }
+
+    /// Record the number of updates queued just before one was taken off.
+    pub fn observe(&self, depth: usize) {
+        self.depth.store(depth, Ordering::Relaxed);
+        self.max_depth.fetch_max(depth, Ordering::Relaxed);
+        if depth >= self.capacity {
+            self.full_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> ChannelSnapshot {
+        ChannelSnapshot {
+            capacity: self.capacity,
+            depth: self.depth.load(Ordering::Relaxed),
+            max_depth: self.max_depth.load(Ordering::Relaxed),
+            full_count: self.full_count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// `receiver.recv()`, recording the queue depth in `stats`.
+pub async fn recv_observed<T>(receiver: &mut mpsc::Receiver<T>, stats: &ChannelStats) -> Option<T> {
+    let update = receiver.recv().await?;
+    stats.observe(receiver.len() + 1);
+    Some(update)
+}
+
+/// Log a snapshot every `interval`. Runs until the process exits.
+pub async fn log_periodically(stats: Arc<ChannelStats>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let s = stats.snapshot();
+        tracing::info!(
+            target: PROJECT_ID,
+            "Update channel: depth {}/{}, max depth {}, found full {} times",
+            s.depth, s.capacity, s.max_depth, s.full_count
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_small_capacity_applies_backpressure_without_dropping() {
+        const UPDATES: usize = 50;
+        let stats = ChannelStats::new(2);
+        let (sender, mut receiver) = mpsc::channel(2);
+
+        let producer = tokio::spawn(async move {
+            for i in 0..UPDATES {
+                sender.send(i).await.unwrap();
+            }
+        });
+
+        let mut received = Vec::new();
+        while let Some(i) = recv_observed(&mut receiver, &stats).await {
+            // Slow consumer: let the producer fill the channel again.
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            received.push(i);
+        }
+        producer.await.unwrap();
+
+        assert_eq!(received, (0..UPDATES).collect::<Vec<_>>());
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.capacity, 2);
+        assert_eq!(snapshot.max_depth, 2);
+        assert!(snapshot.full_count > 0, "{snapshot:?}");
+    }
+
+    #[test]
+    fn test_observe_tracks_depth() {
+        let stats = ChannelStats::new(4);
+        stats.observe(3);
+        stats.observe(4);
+        stats.observe(1);
+        assert_eq!(
+            stats.snapshot(),
+            ChannelSnapshot { capacity: 4, depth: 1, max_depth: 4, full_count: 1 }
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Throttling of checkpoint writes.
+//!
+//! Configured with `CHECKPOINT_EVERY_N_RANGES` and/or `CHECKPOINT_EVERY_MS`.
+//! A checkpoint is written once either configured threshold is reached; with
+//! neither set, every `EndOfRange` is checkpointed. Heights seen in between are
+//! kept as pending and written on shutdown.
+
+use crate::{INDEXER_ID, PROJECT_ID};
+use fastnear_primitives::near_indexer_primitives::types::BlockHeight;
+use redis_db::CheckpointStore;
+use std::env;
+use std::time::{Duration, Instant};
+
+pub struct CheckpointThrottle {
+    every_n_ranges: Option<usize>,
+    every: Option<Duration>,
+    ranges_since_write: usize,
+    last_write: Instant,
+    pending: Option<BlockHeight>,
+}
+
+impl CheckpointThrottle {
+    pub fn new(every_n_ranges: Option<usize>, every: Option<Duration>, now: Instant) -> Self {
+        Self {
+            every_n_ranges: every_n_ranges.filter(|n| *n > 0),
+            every,
+            ranges_since_write: 0,
+            last_write: now,
+            pending: None,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let every_n_ranges = env::var("CHECKPOINT_EVERY_N_RANGES")
+            .ok()
+            .map(|s| s.parse().expect("Invalid CHECKPOINT_EVERY_N_RANGES"));
+        let every = env::var("CHECKPOINT_EVERY_MS")
+            .ok()
+            .map(|s| Duration::from_millis(s.parse().expect("Invalid CHECKPOINT_EVERY_MS")));
+        Self::new(every_n_ranges, every, Instant::now())
+    }
+
+    /// Record a completed range. Returns the height to checkpoint now, if
+    /// a threshold was reached; otherwise keeps it pending.
+    pub fn record(&mut self, block_height: BlockHeight, now: Instant) -> Option<BlockHeight> {
+        self.pending = Some(block_height);
+        self.ranges_since_write += 1;
+
+        let due = match (self.every_n_ranges, self.every) {
+            (None, None) => true,
+            (n, every) => {
+                n.is_some_and(|n| self.ranges_since_write >= n)
+                    || every.is_some_and(|every| now.duration_since(self.last_write) >= every)
+            }
+        };
+        if !due {
+            return None;
+        }
+        self.ranges_since_write = 0;
+        self.last_write = now;
+        self.pending.take()
+    }
+
+    /// Drop the pending height, e.g. when its rows may not have been written.
+    pub fn discard(&mut self) {
+        self.pending = None;
+    }
+
+    pub fn take_pending(&mut self) -> Option<BlockHeight> {
+        self.pending.take()
+    }
+}
+
+/// Write any pending checkpoint. Called on shutdown so throttling never
+/// loses progress that has already been flushed.
+pub async fn flush_pending<S: CheckpointStore>(
+    store: &S,
+    throttle: &mut CheckpointThrottle,
+) -> anyhow::Result<()> {
+    if let Some(height) = throttle.take_pending() {
+        tracing::info!(target: PROJECT_ID, "Writing pending checkpoint {} on shutdown", height);
+        store.set_last_processed_block_height(INDEXER_ID, height).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// In-memory checkpoint store recording every write.
+    #[derive(Default)]
+    pub(crate) struct MemoryStore {
+        heights: Mutex<HashMap<String, u64>>,
+        pub(crate) writes: Mutex<Vec<u64>>,
+    }
+
+    impl CheckpointStore for MemoryStore {
+        async fn get_last_processed_block_height(&self, suffix: &str) -> redis_db::Result<Option<u64>> {
+            Ok(self.heights.lock().unwrap().get(suffix).copied())
+        }
+
+        async fn set_last_processed_block_height(&self, suffix: &str, height: u64) -> redis_db::Result<()> {
+            self.heights.lock().unwrap().insert(suffix.to_string(), height);
+            self.writes.lock().unwrap().push(height);
+            Ok(())
+        }
+
+        async fn rewind_last_processed_block_height(&self, suffix: &str, height: u64) -> redis_db::Result<Option<u64>> {
+            let mut heights = self.heights.lock().unwrap();
+            let previous = heights.get(suffix).copied();
+            if previous.is_some_and(|h| height <= h) {
+                heights.insert(suffix.to_string(), height);
+                self.writes.lock().unwrap().push(height);
+            }
+            Ok(previous)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_every_three_ranges_and_flush_on_shutdown() {
+        let store = MemoryStore::default();
+        let now = Instant::now();
+        let mut throttle = CheckpointThrottle::new(Some(3), None, now);
+
+        for height in 1..=7 {
+            if let Some(h) = throttle.record(height, now) {
+                store.set_last_processed_block_height(INDEXER_ID, h).await.unwrap();
+            }
+        }
+        assert_eq!(*store.writes.lock().unwrap(), vec![3, 6]);
+
+        flush_pending(&store, &mut throttle).await.unwrap();
+        assert_eq!(*store.writes.lock().unwrap(), vec![3, 6, 7]);
+        assert_eq!(store.get_last_processed_block_height(INDEXER_ID).await.unwrap(), Some(7));
+
+        // Nothing left to flush
+        flush_pending(&store, &mut throttle).await.unwrap();
+        assert_eq!(store.writes.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_every_ms() {
+        let start = Instant::now();
+        let mut throttle = CheckpointThrottle::new(None, Some(Duration::from_millis(100)), start);
+
+        assert_eq!(throttle.record(1, start + Duration::from_millis(10)), None);
+        assert_eq!(throttle.record(2, start + Duration::from_millis(100)), Some(2));
+        assert_eq!(throttle.record(3, start + Duration::from_millis(150)), None);
+        assert_eq!(throttle.take_pending(), Some(3));
+    }
+
+    #[test]
+    fn test_unthrottled_by_default() {
+        let now = Instant::now();
+        let mut throttle = CheckpointThrottle::new(None, None, now);
+        assert_eq!(throttle.record(1, now), Some(1));
+        assert_eq!(throttle.record(2, now), Some(2));
+        assert_eq!(throttle.take_pending(), None);
+    }
+
+    #[test]
+    fn test_discard_drops_pending() {
+        let now = Instant::now();
+        let mut throttle = CheckpointThrottle::new(Some(3), None, now);
+        throttle.record(1, now);
+        throttle.discard();
+        assert_eq!(throttle.take_pending(), None);
+    }
+}
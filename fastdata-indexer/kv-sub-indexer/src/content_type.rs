@@ -0,0 +1,66 @@
+//! Content type of stored values.
+//!
+//! Values are JSON, but contracts also write plain strings and base64 blobs
+//! as JSON strings. Detecting which at ingestion lets readers serve a value
+//! with an accurate hint. Detection is a heuristic: a string counts as binary
+//! only when it is canonical padded base64 of at least [`MIN_BINARY_LEN`]
+//! characters and does not decode to printable UTF-8, so short words and
+//! base64-wrapped text stay `text`.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use redis_db::ContentType;
+use serde_json::Value;
+
+/// Shortest string considered for base64 detection.
+pub const MIN_BINARY_LEN: usize = 16;
+
+pub fn detect(value: &Value) -> ContentType {
+    match value {
+        Value::String(s) if is_binary(s) => ContentType::Binary,
+        Value::String(_) => ContentType::Text,
+        _ => ContentType::Json,
+    }
+}
+
+fn is_binary(s: &str) -> bool {
+    if s.len() < MIN_BINARY_LEN || s.len() % 4 != 0 {
+        return false;
+    }
+    let Ok(bytes) = BASE64.decode(s) else {
+        return false;
+    };
+    match std::str::from_utf8(&bytes) {
+        Ok(text) => text.chars().any(|c| c.is_control() && !c.is_whitespace()),
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_detect_content_type() {
+        let png_header = BASE64.encode([0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0, 0, 0, 0x0d]);
+        for (value, expected) in [
+            (json!({"name": "Alice"}), ContentType::Json),
+            (json!([1, 2]), ContentType::Json),
+            (json!(42), ContentType::Json),
+            (json!(true), ContentType::Json),
+            (json!(null), ContentType::Json),
+            (json!("hello world"), ContentType::Text),
+            (json!(""), ContentType::Text),
+            (json!("{\"a\":1}"), ContentType::Text),
+            (json!("enc:AES256:k1:Zm9vYmFyYmF6cXV4"), ContentType::Text),
+            // Valid base64, but too short to tell from a word
+            (json!("abcd"), ContentType::Text),
+            // base64 of "hello world, again"
+            (json!("aGVsbG8gd29ybGQsIGFnYWlu"), ContentType::Text),
+            (json!(png_header), ContentType::Binary),
+            (json!("AAECAwQFBgcICQoLDA0ODw=="), ContentType::Binary),
+        ] {
+            assert_eq!(detect(&value), expected, "{value}");
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! Retry budget and dead-letter file for failed row flushes.
+//!
+//! A flush is attempted once per entry of [`FLUSH_RETRY_DELAYS_SECS`] (every write
+//! in `add_kv` is idempotent, so a partially applied batch can be replayed).
+//! Only when the budget is exhausted does the indexer stop; the batch is then
+//! written as JSON lines to `DEAD_LETTER_DIR` (default `dead-letter`) for
+//! manual inspection. The checkpoint is not advanced, so the same range is
+//! reprocessed on restart either way.
+//!
+//! Rows accumulate in a [`RowBuffer`] between `EndOfRange` updates and are
+//! flushed early once it holds [`EARLY_FLUSH_ROWS`] rows or `FLUSH_MAX_BYTES`
+//! (default 64 MiB) of keys and values, whichever comes first.
+
+use crate::PROJECT_ID;
+use redis_db::{retry_with_delays, FastDataKv};
+use std::env;
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Wait after each failed flush attempt: 4 attempts over ~7s in total.
+pub const FLUSH_RETRY_DELAYS_SECS: [u64; 4] = [0, 1, 2, 4];
+const DEFAULT_DEAD_LETTER_DIR: &str = "dead-letter";
+pub const EARLY_FLUSH_ROWS: usize = 10_000;
+pub const DEFAULT_FLUSH_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+pub fn max_bytes_from_env() -> usize {
+    let max_bytes = env::var("FLUSH_MAX_BYTES")
+        .ok()
+        .map(|s| s.parse().expect("Invalid FLUSH_MAX_BYTES"))
+        .unwrap_or(DEFAULT_FLUSH_MAX_BYTES);
+    assert!(max_bytes > 0, "FLUSH_MAX_BYTES must be greater than 0");
+    max_bytes
+}
+
+/// Rows waiting to be flushed, with their approximate size.
+#[derive(Debug)]
+pub struct RowBuffer {
+    rows: Vec<FastDataKv>,
+    bytes: usize,
+    max_rows: usize,
+    max_bytes: usize,
+}
+
+impl RowBuffer {
+    pub fn new(max_rows: usize, max_bytes: usize) -> Self {
+        Self { rows: Vec::new(), bytes: 0, max_rows, max_bytes }
+    }
+
+    pub fn extend(&mut self, rows: Vec<FastDataKv>) {
+        self.bytes += rows.iter().map(|r| r.key.len() + r.value.len()).sum::<usize>();
+        self.rows.extend(rows);
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Whether either threshold has been reached.
+    pub fn is_full(&self) -> bool {
+        self.rows.len() >= self.max_rows || self.bytes >= self.max_bytes
+    }
+
+    pub fn take(&mut self) -> Vec<FastDataKv> {
+        self.bytes = 0;
+        std::mem::take(&mut self.rows)
+    }
+}
+
+pub fn dead_letter_dir_from_env() -> PathBuf {
+    env::var("DEAD_LETTER_DIR")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| DEFAULT_DEAD_LETTER_DIR.to_string())
+        .into()
+}
+
+/// Run `flush` within the retry budget. Returns `true` on success; otherwise
+/// dead-letters `rows`, clears `is_running` and returns `false`.
+pub async fn flush_or_stop<F, Fut>(
+    delays: &[u64],
+    rows: &[FastDataKv],
+    dead_letter_dir: &Path,
+    is_running: &AtomicBool,
+    flush: F,
+) -> bool
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    let Err(e) = retry_with_delays(delays, flush).await else {
+        return true;
+    };
+    tracing::error!(target: PROJECT_ID,
+        "Failed to write data, retry budget exhausted. Shutting down to prevent data loss: {:?}", e
+    );
+    match dead_letter(dead_letter_dir, rows) {
+        Ok(Some(path)) => {
+            tracing::error!(target: PROJECT_ID, "Dead-lettered {} rows to {}", rows.len(), path.display());
+        }
+        Ok(None) => {}
+        Err(e) => {
+            tracing::error!(target: PROJECT_ID, "Failed to dead-letter {} rows: {:?}", rows.len(), e);
+        }
+    }
+    is_running.store(false, Ordering::SeqCst);
+    false
+}
+
+/// Write `rows` as JSON lines to a new file in `dir`. Returns `None` for an
+/// empty batch (e.g. a failed checkpoint-only flush).
+pub fn dead_letter(dir: &Path, rows: &[FastDataKv]) -> std::io::Result<Option<PathBuf>> {
+    let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+        return Ok(None);
+    };
+    std::fs::create_dir_all(dir)?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = dir.join(format!(
+        "kv-{}-{}-{}.jsonl",
+        first.block_height, last.block_height, millis
+    ));
+    let mut file = std::io::BufWriter::new(std::fs::File::create_new(&path)?);
+    for row in rows {
+        serde_json::to_writer(&mut file, row)?;
+        file.write_all(b"\n")?;
+    }
+    file.flush()?;
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn row(block_height: u64) -> FastDataKv {
+        FastDataKv {
+            receipt_id: "r".to_string(),
+            action_index: 0,
+            tx_hash: None,
+            signer_id: "alice.near".to_string(),
+            predecessor_id: "alice.near".to_string(),
+            current_account_id: "social.near".to_string(),
+            block_height,
+            block_timestamp: 0,
+            shard_id: 0,
+            receipt_index: 0,
+            order_id: 0,
+            key: "k".to_string(),
+            value: "1".to_string(),
+            encrypted_key_id: None,
+            is_deleted: false,
+            content_type: Default::default(),
+        }
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("kv-sub-indexer-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn large_row(block_height: u64, value_len: usize) -> FastDataKv {
+        FastDataKv { value: "x".repeat(value_len), ..row(block_height) }
+    }
+
+    #[test]
+    fn test_large_rows_flush_on_bytes_before_row_count() {
+        let mut buffer = RowBuffer::new(EARLY_FLUSH_ROWS, 1_000);
+        buffer.extend(vec![large_row(10, 400), large_row(10, 400)]);
+        assert!(!buffer.is_full());
+        buffer.extend(vec![large_row(11, 400)]);
+        assert!(buffer.is_full());
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.bytes(), 3 * 401);
+
+        let flushed = buffer.take();
+        assert_eq!(flushed.len(), 3);
+        assert_eq!((buffer.len(), buffer.bytes()), (0, 0));
+        assert!(!buffer.is_full());
+    }
+
+    #[test]
+    fn test_small_rows_flush_on_row_count() {
+        let mut buffer = RowBuffer::new(3, DEFAULT_FLUSH_MAX_BYTES);
+        buffer.extend(vec![row(1), row(2)]);
+        assert!(!buffer.is_full());
+        buffer.extend(vec![row(3)]);
+        assert!(buffer.is_full());
+    }
+
+    #[tokio::test]
+    async fn test_flush_failing_once_keeps_running() {
+        let dir = scratch_dir("flush-once");
+        let is_running = AtomicBool::new(true);
+        let attempts = AtomicUsize::new(0);
+
+        let ok = flush_or_stop(&[0, 0, 0], &[row(10)], &dir, &is_running, || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                anyhow::ensure!(attempt > 0, "transient");
+                Ok(())
+            }
+        })
+        .await;
+
+        assert!(ok);
+        assert!(is_running.load(Ordering::SeqCst));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_budget_dead_letters_and_stops() {
+        let dir = scratch_dir("flush-exhausted");
+        let is_running = AtomicBool::new(true);
+        let rows = [row(10), row(12)];
+
+        let ok = flush_or_stop(&[0, 0], &rows, &dir, &is_running, || async {
+            anyhow::bail!("redis down")
+        })
+        .await;
+
+        assert!(!ok);
+        assert!(!is_running.load(Ordering::SeqCst));
+        let files: Vec<PathBuf> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].file_name().unwrap().to_str().unwrap().starts_with("kv-10-12-"));
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        let heights: Vec<u64> = contents
+            .lines()
+            .map(|l| serde_json::from_str::<FastDataKv>(l).unwrap().block_height)
+            .collect();
+        assert_eq!(heights, vec![10, 12]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+//! Liveness endpoint for container orchestrators.
+//!
+//! Served by [`indexer_health`] when `HEALTH_PORT` is set. `GET /health`
+//! returns 200 with `{last_processed_block, lag, is_running, channel}`, or 503
+//! once the indexer has begun shutting down.
+
+use crate::channel::{ChannelSnapshot, ChannelStats};
+use crate::INDEXER_ID;
+use indexer_health::Liveness;
+use redis_db::{CheckpointStore, RedisDb};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct HealthStatus {
+    #[serde(flatten)]
+    pub indexer: indexer_health::HealthStatus,
+    /// Fetcher → indexer channel occupancy.
+    pub channel: ChannelSnapshot,
+}
+
+impl Liveness for HealthStatus {
+    fn is_running(&self) -> bool {
+        self.indexer.is_running
+    }
+}
+
+struct HealthState {
+    redis_db: Arc<RedisDb>,
+    head_suffix: String,
+    is_running: Arc<AtomicBool>,
+    channel: Arc<ChannelStats>,
+}
+
+/// Read checkpoint/head heights, the running flag and channel occupancy.
+pub async fn health_status<S: CheckpointStore>(
+    store: &S,
+    head_suffix: &str,
+    is_running: &AtomicBool,
+    channel: &ChannelStats,
+) -> anyhow::Result<HealthStatus> {
+    let last_processed_block = store.get_last_processed_block_height(INDEXER_ID).await?;
+    let head = store.get_last_processed_block_height(head_suffix).await?;
+    Ok(HealthStatus {
+        indexer: indexer_health::HealthStatus {
+            last_processed_block,
+            lag: indexer_health::lag(head, last_processed_block),
+            is_running: is_running.load(Ordering::SeqCst),
+        },
+        channel: channel.snapshot(),
+    })
+}
+
+/// Bind the health server. The returned future runs until the process exits.
+pub fn start(
+    port: u16,
+    redis_db: Arc<RedisDb>,
+    head_suffix: String,
+    is_running: Arc<AtomicBool>,
+    channel: Arc<ChannelStats>,
+) -> std::io::Result<actix_web::dev::Server> {
+    let state = Arc::new(HealthState {
+        redis_db,
+        head_suffix,
+        is_running,
+        channel,
+    });
+
+    indexer_health::start(port, move || {
+        let state = state.clone();
+        async move {
+            health_status(state.redis_db.as_ref(), &state.head_suffix, &state.is_running, &state.channel).await
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::tests::MemoryStore;
+    use redis_db::UNIVERSAL_SUFFIX;
+
+    #[tokio::test]
+    async fn test_health_reflects_checkpoint_and_running_state() {
+        let store = MemoryStore::default();
+        store.set_last_processed_block_height(UNIVERSAL_SUFFIX, 1_000).await.unwrap();
+        store.set_last_processed_block_height(INDEXER_ID, 990).await.unwrap();
+        let is_running = AtomicBool::new(true);
+        let channel = ChannelStats::new(100);
+        channel.observe(7);
+
+        let status = health_status(&store, UNIVERSAL_SUFFIX, &is_running, &channel).await.unwrap();
+        assert_eq!(
+            status,
+            HealthStatus {
+                indexer: indexer_health::HealthStatus {
+                    last_processed_block: Some(990),
+                    lag: Some(10),
+                    is_running: true,
+                },
+                channel: channel.snapshot(),
+            }
+        );
+        assert_eq!(status.channel.depth, 7);
+        assert!(status.is_running());
+        assert_eq!(serde_json::to_value(&status).unwrap()["lag"], 10);
+
+        is_running.store(false, Ordering::SeqCst);
+        store.set_last_processed_block_height(INDEXER_ID, 1_000).await.unwrap();
+        let status = health_status(&store, UNIVERSAL_SUFFIX, &is_running, &channel).await.unwrap();
+        assert_eq!(
+            status,
+            HealthStatus {
+                indexer: indexer_health::HealthStatus {
+                    last_processed_block: Some(1_000),
+                    lag: Some(0),
+                    is_running: false,
+                },
+                channel: channel.snapshot(),
+            }
+        );
+        assert!(!status.is_running());
+    }
+
+    #[tokio::test]
+    async fn test_health_before_first_checkpoint() {
+        let store = MemoryStore::default();
+        let is_running = AtomicBool::new(true);
+
+        let status = health_status(&store, UNIVERSAL_SUFFIX, &is_running, &ChannelStats::new(100))
+            .await
+            .unwrap();
+        assert_eq!(status.indexer.last_processed_block, None);
+        assert_eq!(status.indexer.lag, None);
+    }
+}
@@ -1,13 +1,20 @@
+mod admin;
+mod channel;
+mod checkpoint;
+mod content_type;
+mod flush;
+mod health;
+
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use dotenvy::dotenv;
 use fastnear_primitives::near_indexer_primitives::types::BlockHeight;
 use fastnear_primitives::types::ChainId;
-use redis_db::{FastData, FastDataKv, RedisDb};
+use redis_db::{compute_order_id, detect_encrypted, FastData, FastDataKv, RedisDb};
 use std::env;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use suffix_fetcher::{SuffixFetcher, SuffixFetcherConfig, SuffixFetcherUpdate};
+use std::time::{Duration, Instant};
+use suffix_fetcher::{ReplayHandle, SuffixFetcher, SuffixFetcherConfig, SuffixFetcherUpdate};
 use tokio::sync::mpsc;
 
 const PROJECT_ID: &str = "kv-sub-indexer";
@@ -15,6 +22,7 @@ const SUFFIX: &str = "kv";
 const INDEXER_ID: &str = "kv-sub-indexer";
 const MAX_NUM_KEYS: usize = 256;
 const MAX_KEY_LENGTH: usize = 1024;
+const FETCH_CONCURRENCY: usize = 4;
 
 fn parse_kv_entries(fastdata: &FastData) -> Vec<FastDataKv> {
     // Decode base64 data
@@ -49,7 +57,13 @@ fn parse_kv_entries(fastdata: &FastData) -> Vec<FastDataKv> {
         return vec![];
     }
 
-    let order_id = compute_order_id(fastdata);
+    let order_id = match compute_order_id(fastdata) {
+        Ok(id) => id,
+        Err(e) => {
+            tracing::warn!(target: PROJECT_ID, "Dropping Key-Value Fastdata: {}", e);
+            return vec![];
+        }
+    };
     let mut entries = Vec::new();
     for (key, value) in json_object {
         if key.len() > MAX_KEY_LENGTH {
@@ -60,6 +74,8 @@ fn parse_kv_entries(fastdata: &FastData) -> Vec<FastDataKv> {
             tracing::debug!(target: PROJECT_ID, "Skipping KV key with invalid characters");
             continue;
         }
+        // serde_json is built without `preserve_order`, so objects serialize
+        // with sorted keys and no whitespace: equal values store equal strings.
         let serialized_value = match serde_json::to_string(value) {
             Ok(s) => s,
             Err(e) => {
@@ -89,26 +105,13 @@ fn parse_kv_entries(fastdata: &FastData) -> Vec<FastDataKv> {
             key: key.clone(),
             value: serialized_value,
             encrypted_key_id,
+            is_deleted: value.is_null(),
+            content_type: content_type::detect(value),
         });
     }
     entries
 }
 
-fn compute_order_id(fastdata: &FastData) -> u64 {
-    ((fastdata.shard_id as u64) * 100_000 + fastdata.receipt_index as u64) * 1_000 + fastdata.action_index as u64
-}
-
-fn detect_encrypted(value: &str) -> Option<String> {
-    let value = value.trim_matches('"');
-    if let Some(rest) = value.strip_prefix("enc:AES256:") {
-        let parts: Vec<&str> = rest.splitn(2, ':').collect();
-        if parts.len() == 2 {
-            return Some(parts[0].to_string());
-        }
-    }
-    None
-}
-
 async fn flush_rows(
     redis_db: &RedisDb,
     rows: &[FastDataKv],
@@ -128,14 +131,18 @@ async fn main() {
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("kv-sub-indexer=info,redis_db=info,suffix-fetcher=info")),
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("kv-sub-indexer=info,redis_db=info,suffix-fetcher=info,indexer-health=info")),
         )
         .init();
 
-    let chain_id: ChainId = env::var("CHAIN_ID")
-        .expect("CHAIN_ID required")
-        .try_into()
-        .expect("Invalid chain id");
+    let chain_id: ChainId = fastkv_common::chain_id_from_env()
+        .and_then(|id| id.to_string().try_into())
+        .unwrap_or_else(|e| panic!("{e}"));
+    tracing::info!(target: PROJECT_ID,
+        "Chain id {}: Redis keys {}",
+        chain_id,
+        fastkv_common::keys::chain_prefixes(&chain_id.to_string()).join(", "),
+    );
 
     let redis_db = Arc::new(RedisDb::new(chain_id.to_string())
         .await
@@ -181,19 +188,46 @@ async fn main() {
         start_block_height,
     );
 
-    let (sender, mut receiver) = mpsc::channel(100);
-    tokio::spawn(fetcher.start(
-        SuffixFetcherConfig {
-            suffix: SUFFIX.to_string(),
-            start_block_height: Some(start_block_height),
-            sleep_duration: Duration::from_millis(500),
-        },
-        sender,
-        is_running.clone(),
-    ));
-
-    let mut rows: Vec<FastDataKv> = vec![];
-    while let Some(update) = receiver.recv().await {
+    let replay = ReplayHandle::new();
+    if let Some(admin_config) = admin::AdminConfig::from_env() {
+        let server = admin::start(admin_config, redis_db.clone(), replay.clone())
+            .expect("Can't start admin interface");
+        tokio::spawn(server);
+    }
+
+    let config = SuffixFetcherConfig {
+        suffix: SUFFIX.to_string(),
+        start_block_height: Some(start_block_height),
+        sleep_duration: Duration::from_millis(500),
+        replay: Some(replay),
+        head_suffix: env::var("HEAD_SUFFIX").ok(),
+        max_concurrency: FETCH_CONCURRENCY,
+        checkpoint_key: None,
+    };
+
+    let channel_cap = channel::capacity_from_env();
+    let channel_stats = Arc::new(channel::ChannelStats::new(channel_cap));
+    tokio::spawn(channel::log_periodically(channel_stats.clone(), channel::STATS_LOG_INTERVAL));
+
+    if let Some(port) = indexer_health::port_from_env() {
+        let server = health::start(
+            port,
+            redis_db.clone(),
+            config.head_suffix().to_string(),
+            is_running.clone(),
+            channel_stats.clone(),
+        )
+        .expect("Can't start health endpoint");
+        tokio::spawn(server);
+    }
+
+    let (sender, mut receiver) = mpsc::channel(channel_cap);
+    tokio::spawn(fetcher.start(config, sender, is_running.clone()));
+
+    let dead_letter_dir = flush::dead_letter_dir_from_env();
+    let mut throttle = checkpoint::CheckpointThrottle::from_env();
+    let mut rows = flush::RowBuffer::new(flush::EARLY_FLUSH_ROWS, flush::max_bytes_from_env());
+    while let Some(update) = channel::recv_observed(&mut receiver, &channel_stats).await {
         match update {
             SuffixFetcherUpdate::FastData(fastdata) => {
                 tracing::info!(target: PROJECT_ID, "Received fastdata: {} {} {}", fastdata.block_height, fastdata.receipt_id, fastdata.action_index);
@@ -201,28 +235,41 @@ async fn main() {
                 let new_entries = parse_kv_entries(&fastdata);
                 rows.extend(new_entries);
 
-                if rows.len() >= 10_000 {
-                    tracing::info!(target: PROJECT_ID, "Early flush at {} rows", rows.len());
-                    let current_rows = std::mem::take(&mut rows);
+                if rows.is_full() {
+                    tracing::info!(target: PROJECT_ID, "Early flush at {} rows, {} bytes", rows.len(), rows.bytes());
+                    let current_rows = rows.take();
 
-                    if let Err(e) = flush_rows(&redis_db, &current_rows, None).await {
-                        tracing::error!(target: PROJECT_ID,
-                            "Failed to write data. Shutting down to prevent data loss: {:?}", e
-                        );
-                        is_running.store(false, Ordering::SeqCst);
+                    let flushed = flush::flush_or_stop(
+                        &flush::FLUSH_RETRY_DELAYS_SECS,
+                        &current_rows,
+                        &dead_letter_dir,
+                        &is_running,
+                        || flush_rows(&redis_db, &current_rows, None),
+                    )
+                    .await;
+                    if !flushed {
+                        throttle.discard();
                         break;
                     }
                 }
             }
-            SuffixFetcherUpdate::EndOfRange(block_height) => {
-                tracing::info!(target: PROJECT_ID, "Saving last processed block height {} with {} rows", block_height, rows.len());
-                let current_rows = std::mem::take(&mut rows);
-
-                if let Err(e) = flush_rows(&redis_db, &current_rows, Some(block_height)).await {
-                    tracing::error!(target: PROJECT_ID,
-                        "Failed to write data. Shutting down to prevent data loss: {:?}", e
-                    );
-                    is_running.store(false, Ordering::SeqCst);
+            SuffixFetcherUpdate::EndOfRange { block_height, .. } => {
+                let current_rows = rows.take();
+                let checkpoint = throttle.record(block_height, Instant::now());
+                if let Some(height) = checkpoint {
+                    tracing::info!(target: PROJECT_ID, "Saving last processed block height {} with {} rows", height, current_rows.len());
+                }
+
+                let flushed = flush::flush_or_stop(
+                    &flush::FLUSH_RETRY_DELAYS_SECS,
+                    &current_rows,
+                    &dead_letter_dir,
+                    &is_running,
+                    || flush_rows(&redis_db, &current_rows, checkpoint),
+                )
+                .await;
+                if !flushed {
+                    throttle.discard();
                     break;
                 }
 
@@ -231,8 +278,85 @@ async fn main() {
                     break;
                 }
             }
+            SuffixFetcherUpdate::Error { message, .. } => {
+                tracing::error!(target: PROJECT_ID, "Suffix fetcher failed, shutting down: {}", message);
+                is_running.store(false, Ordering::SeqCst);
+                break;
+            }
         };
     }
 
+    if let Err(e) = checkpoint::flush_pending(redis_db.as_ref(), &mut throttle).await {
+        tracing::error!(target: PROJECT_ID, "Failed to write pending checkpoint: {:?}", e);
+    }
+
     tracing::info!(target: PROJECT_ID, "Successfully shut down");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fastdata(json: &str) -> FastData {
+        FastData {
+            receipt_id: "receipt".to_string(),
+            action_index: 0,
+            suffix: SUFFIX.to_string(),
+            data: BASE64.encode(json),
+            tx_hash: None,
+            signer_id: "alice.near".to_string(),
+            predecessor_id: "alice.near".to_string(),
+            current_account_id: "social.near".to_string(),
+            block_height: 100,
+            block_timestamp: 0,
+            shard_id: 0,
+            receipt_index: 0,
+        }
+    }
+
+    fn stored_values(json: &str) -> Vec<(String, String)> {
+        parse_kv_entries(&fastdata(json))
+            .into_iter()
+            .map(|e| (e.key, e.value))
+            .collect()
+    }
+
+    #[test]
+    fn test_stored_values_ignore_formatting() {
+        let compact = r#"{"profile":{"name":"Alice","tags":[{"b":2,"a":1}]},"n":1}"#;
+        let spaced = "{ \"n\": 1,\n  \"profile\": { \"tags\": [ { \"a\": 1, \"b\": 2 } ],\n    \"name\": \"Alice\" } }";
+
+        let stored = stored_values(compact);
+        assert_eq!(stored, stored_values(spaced));
+        assert_eq!(
+            stored,
+            vec![
+                ("n".to_string(), "1".to_string()),
+                ("profile".to_string(), r#"{"name":"Alice","tags":[{"a":1,"b":2}]}"#.to_string()),
+            ]
+        );
+        assert_eq!(stored_values(r#"{"k":"enc:AES256:abcd:Zm9v"}"#)[0].1, r#""enc:AES256:abcd:Zm9v""#);
+    }
+
+    #[test]
+    fn test_null_value_is_tombstone() {
+        let entries = parse_kv_entries(&fastdata(r#"{"gone":null,"kept":"null"}"#));
+        let (gone, kept) = (&entries[0], &entries[1]);
+
+        assert_eq!((gone.key.as_str(), gone.value.as_str()), ("gone", "null"));
+        assert!(gone.is_deleted);
+        assert!(redis_db::StoredKvEntry::from(gone).is_deleted());
+
+        assert_eq!((kept.key.as_str(), kept.value.as_str()), ("kept", "\"null\""));
+        assert!(!kept.is_deleted);
+        assert!(!redis_db::StoredKvEntry::from(kept).is_deleted());
+    }
+
+    #[test]
+    fn test_entries_record_content_type() {
+        use redis_db::ContentType;
+        let entries = parse_kv_entries(&fastdata(r#"{"a":{"x":1},"b":"hello","c":"AAECAwQFBgcICQoLDA0ODw=="}"#));
+        let types: Vec<_> = entries.iter().map(|e| (e.key.as_str(), e.content_type)).collect();
+        assert_eq!(types, vec![("a", ContentType::Json), ("b", ContentType::Text), ("c", ContentType::Binary)]);
+    }
+}
@@ -23,7 +23,7 @@ async fn main() {
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("neardata-fetcher=info,fastdata-indexer=info,redis_db=info")),
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("neardata-fetcher=info,fastdata-indexer=info,redis_db=info,indexer-health=info")),
         )
         .init();
 
@@ -50,9 +50,9 @@ async fn main() {
         tracing::info!(target: PROJECT_ID, "Account filtering disabled - indexing all accounts");
     }
 
-    let redis_db = RedisDb::new(chain_id.to_string())
+    let redis_db = Arc::new(RedisDb::new(chain_id.to_string())
         .await
-        .expect("Can't connect to Redis");
+        .expect("Can't connect to Redis"));
 
     redis_db.test_connection()
         .await
@@ -121,6 +121,23 @@ async fn main() {
     })
     .expect("Error setting Ctrl+C handler");
 
+    if let Some(port) = indexer_health::port_from_env() {
+        let (redis_db, is_running) = (redis_db.clone(), is_running.clone());
+        // This indexer writes the head checkpoint, so there is nothing to lag behind.
+        let server = indexer_health::start(port, move || {
+            let (redis_db, is_running) = (redis_db.clone(), is_running.clone());
+            async move {
+                Ok(indexer_health::HealthStatus {
+                    last_processed_block: redis_db.get_last_processed_block_height(UNIVERSAL_SUFFIX).await?,
+                    lag: None,
+                    is_running: is_running.load(Ordering::SeqCst),
+                })
+            }
+        })
+        .expect("Can't start health endpoint");
+        tokio::spawn(server);
+    }
+
     let block_update_interval = std::time::Duration::from_millis(
         env::var("BLOCK_UPDATE_INTERVAL_MS")
             .ok()
@@ -241,7 +258,7 @@ async fn main() {
                     .collect::<Vec<_>>()
                     .await
                     .into_iter()
-                    .collect::<anyhow::Result<()>>();
+                    .collect::<redis_db::Result<()>>();
 
                 if result.is_ok() {
                     success = true;
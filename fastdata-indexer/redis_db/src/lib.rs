@@ -1,4 +1,6 @@
-use anyhow::Result;
+pub use fastkv_common::{keys, ContentType, StoredKvEntry};
+
+use redis::aio::ConnectionManager;
 use redis::{AsyncCommands, Client as RedisClient};
 use serde::{Deserialize, Serialize};
 use std::env;
@@ -6,6 +8,25 @@ use std::sync::Arc;
 
 pub const UNIVERSAL_SUFFIX: &str = "universal";
 
+/// Errors returned by [`RedisDb`] and [`CheckpointStore`], so callers can
+/// tell a Redis outage (worth retrying) from bad data (not worth retrying).
+#[derive(Debug, thiserror::Error)]
+pub enum IndexerError {
+    /// Redis was unreachable or rejected a command.
+    #[error("Redis error: {0}")]
+    ConnectionError(#[from] redis::RedisError),
+    /// A value could not be encoded to or decoded from its stored JSON.
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+    /// A stored checkpoint is not a block height.
+    #[error("Invalid checkpoint at {key}: {value:?}")]
+    CheckpointError { key: String, value: String },
+    #[error("Invalid chain_id: {0}")]
+    InvalidChainId(String),
+}
+
+pub type Result<T, E = IndexerError> = std::result::Result<T, E>;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FastData {
     pub receipt_id: String,
@@ -38,6 +59,38 @@ pub struct FastDataKv {
     pub key: String,
     pub value: String,
     pub encrypted_key_id: Option<String>,
+    /// The write was a JSON `null`, i.e. the contract deleted the key.
+    /// Distinct from a stored string `"null"`.
+    #[serde(default)]
+    pub is_deleted: bool,
+    #[serde(default)]
+    pub content_type: ContentType,
+}
+
+/// Read/write access to per-indexer checkpoints.
+///
+/// Implemented by [`RedisDb`]; lets control paths (e.g. replay) be tested
+/// without a live Redis.
+pub trait CheckpointStore {
+    fn get_last_processed_block_height(
+        &self,
+        suffix: &str,
+    ) -> impl std::future::Future<Output = Result<Option<u64>>> + Send;
+
+    fn set_last_processed_block_height(
+        &self,
+        suffix: &str,
+        height: u64,
+    ) -> impl std::future::Future<Output = Result<()>> + Send;
+
+    /// Atomically move the checkpoint back to `height`, only if one exists at
+    /// or above it. Returns the checkpoint as it was before the call; the
+    /// rewind was applied iff that is `Some(h)` with `height <= h`.
+    fn rewind_last_processed_block_height(
+        &self,
+        suffix: &str,
+        height: u64,
+    ) -> impl std::future::Future<Output = Result<Option<u64>>> + Send;
 }
 
 pub struct RedisDb {
@@ -49,10 +102,15 @@ impl RedisDb {
     pub async fn new(chain_id: String) -> Result<Self> {
         let redis_url = env::var("REDIS_URL")
             .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
-        
+        Self::connect(&redis_url, &chain_id).await
+    }
+
+    /// Connect to `redis_url` and check it answers `PING`.
+    pub async fn connect(redis_url: &str, chain_id: &str) -> Result<Self> {
+        let chain_id = normalize_chain_id(chain_id)?;
         tracing::info!("Connecting to Redis: {}", redis_url);
         
-        let client = redis::Client::open(redis_url.as_str())?;
+        let client = redis::Client::open(redis_url)?;
         
         // Test connection
         let mut conn = client.get_multiplexed_async_connection().await?;
@@ -62,6 +120,17 @@ impl RedisDb {
         Ok(Self { client, chain_id })
     }
     
+    /// Normalized chain id used in all key formats.
+    pub fn chain_id(&self) -> &str {
+        &self.chain_id
+    }
+
+    /// Auto-reconnecting connection to the same server. Cheap to clone; all
+    /// clones share one multiplexed connection.
+    pub async fn connection_manager(&self) -> Result<ConnectionManager> {
+        Ok(ConnectionManager::new(self.client.clone()).await?)
+    }
+    
     pub async fn test_connection(&self) -> Result<()> {
         self.health_check().await
     }
@@ -72,55 +141,46 @@ impl RedisDb {
         Ok(())
     }
     
-    // Key format helpers
-    fn checkpoint_key(&self, suffix: &str) -> String {
-        format!("checkpoint:{}:{}", self.chain_id, suffix)
-    }
-    
-    fn fastdata_key(&self, suffix: &str, block_height: u64, receipt_id: &str) -> String {
-        format!("fastdata:{}:{}:{}:{}", self.chain_id, suffix, block_height, receipt_id)
-    }
-    
-    fn kv_key(&self, predecessor_id: &str, current_account_id: &str, key: &str) -> String {
-        format!("kv:{}:{}:{}", predecessor_id, current_account_id, key)
-    }
-    
-    fn kv_history_key(&self, predecessor_id: &str, current_account_id: &str, key: &str) -> String {
-        format!("history:{}:{}:{}", predecessor_id, current_account_id, key)
-    }
-    
-    fn accounts_key(&self, current_account_id: &str) -> String {
-        format!("accounts:{}", current_account_id)
-    }
-    
-    fn contracts_key(&self, predecessor_id: &str) -> String {
-        format!("contracts:{}", predecessor_id)
-    }
-    
-    fn meta_key(&self) -> String {
-        format!("meta:{}", self.chain_id)
-    }
-    
     // Checkpoint operations
     pub async fn get_last_processed_block_height(&self, suffix: &str) -> Result<Option<u64>> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let key = self.checkpoint_key(suffix);
+        let key = keys::checkpoint(&self.chain_id, suffix);
         let height: Option<String> = conn.get(&key).await?;
-        Ok(height.and_then(|h| h.parse().ok()))
+        height.map(|h| parse_checkpoint(&key, h)).transpose()
     }
     
     pub async fn set_last_processed_block_height(&self, suffix: &str, height: u64) -> Result<()> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let key = self.checkpoint_key(suffix);
+        let key = keys::checkpoint(&self.chain_id, suffix);
         conn.set(&key, height.to_string()).await?;
         Ok(())
     }
     
+    pub async fn rewind_last_processed_block_height(&self, suffix: &str, height: u64) -> Result<Option<u64>> {
+        // Compare and set in one script, so a checkpoint the indexer writes
+        // between the read and the write is never clobbered unchecked.
+        let script = redis::Script::new(
+            r"
+            local current = redis.call('GET', KEYS[1])
+            local n = tonumber(current)
+            if n and tonumber(ARGV[1]) <= n then
+                redis.call('SET', KEYS[1], ARGV[1])
+            end
+            return current
+            ",
+        );
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let key = keys::checkpoint(&self.chain_id, suffix);
+        let previous: Option<String> = script.key(&key).arg(height.to_string()).invoke_async(&mut conn).await?;
+        previous.map(|h| parse_checkpoint(&key, h)).transpose()
+    }
+    
     // FastData operations (for main-indexer)
     pub async fn add_data(&self, fastdata: &FastData) -> Result<()> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
         
-        let key = self.fastdata_key(
+        let key = keys::fastdata(
+            &self.chain_id,
             &fastdata.suffix,
             fastdata.block_height,
             &fastdata.receipt_id,
@@ -130,7 +190,7 @@ impl RedisDb {
         conn.set(&key, &json).await?;
         
         // Also update meta block height
-        let meta_key = self.meta_key();
+        let meta_key = keys::meta(&self.chain_id);
         conn.set(&meta_key, fastdata.block_height.to_string()).await?;
         
         Ok(())
@@ -141,35 +201,76 @@ impl RedisDb {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
         
         // Store current value
-        let current_key = self.kv_key(&kv.predecessor_id, &kv.current_account_id, &kv.key);
-        let stored = StoredKvEntry {
-            predecessor_id: kv.predecessor_id.clone(),
-            current_account_id: kv.current_account_id.clone(),
-            key: kv.key.clone(),
-            value: kv.value.clone(),
-            block_height: kv.block_height,
-            block_timestamp: kv.block_timestamp,
-            receipt_id: kv.receipt_id.clone(),
-            tx_hash: kv.tx_hash.clone().unwrap_or_default(),
-        };
-        let json = serde_json::to_string(&stored)?;
-        conn.set(&current_key, &json).await?;
+        let current_key = keys::kv(&kv.predecessor_id, &kv.current_account_id, &kv.key);
+        let current = serde_json::to_string(&current_entry(kv))?;
+        // History and index members carry no content type: their JSON is
+        // their identity, and it must match what earlier releases wrote.
+        let json = serde_json::to_string(&StoredKvEntry::from(kv))?;
+
+        // Last write wins by block height, so replaying an older range
+        // never clobbers a newer current value.
+        let existing: Option<String> = conn.get(&current_key).await?;
+        if should_overwrite(existing.as_deref(), kv.block_height) {
+            conn.set(&current_key, &current).await?;
+
+            // Edge sets follow the current value, so replaying an older write
+            // never undoes a newer follow or unfollow.
+            if let Some((edge_type, target)) = fastkv_common::edge(&kv.key) {
+                let edges_key = keys::edges(edge_type, target);
+                if kv.is_deleted {
+                    conn.srem(&edges_key, &kv.predecessor_id).await?;
+                } else {
+                    conn.sadd(&edges_key, &kv.predecessor_id).await?;
+                }
+            }
+
+            // Notify watchers. Best-effort: subscribers fall back to polling.
+            let channel = keys::changes(&self.chain_id, &kv.current_account_id);
+            let change = serde_json::to_string(&KvChange::from(kv))?;
+            if let Err(e) = conn.publish::<_, _, ()>(&channel, &change).await {
+                tracing::warn!("Failed to publish change on {}: {:?}", channel, e);
+            }
+        }
         
         // Store history
-        let history_key = self.kv_history_key(&kv.predecessor_id, &kv.current_account_id, &kv.key);
+        let history_key = keys::history(&kv.predecessor_id, &kv.current_account_id, &kv.key);
         conn.zadd(&history_key, &json, kv.block_height as i64).await?;
+
+        // Index by receipt for /v1/kv/by-receipt. Set of identical JSON, so replays are no-ops.
+        let receipt_key = keys::receipt(&kv.receipt_id);
+        conn.sadd(&receipt_key, &json).await?;
+
+        // Index by transaction for /v1/kv/by-tx; entries without a tx hash are not indexed.
+        if let Some(tx_key) = tx_index_key(kv) {
+            conn.sadd(&tx_key, &json).await?;
+        }
         
         // Update accounts set (who wrote to this contract)
-        let accounts_key = self.accounts_key(&kv.current_account_id);
+        let accounts_key = keys::accounts(&kv.current_account_id);
         conn.sadd(&accounts_key, &kv.predecessor_id).await?;
         
         // Update contracts set (which contracts this account wrote to)
-        let contracts_key = self.contracts_key(&kv.predecessor_id);
+        let contracts_key = keys::contracts(&kv.predecessor_id);
         conn.sadd(&contracts_key, &kv.current_account_id).await?;
         
         Ok(())
     }
     
+    /// Latest stored entry for a key, as written by [`RedisDb::add_kv`].
+    pub async fn get_kv_last(
+        &self,
+        predecessor_id: &str,
+        current_account_id: &str,
+        key: &str,
+    ) -> Result<Option<StoredKvEntry>> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let json: Option<String> = conn.get(keys::kv(predecessor_id, current_account_id, key)).await?;
+        match json {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+    
     pub async fn add_kv_batch(&self, kvs: &[FastDataKv]) -> Result<()> {
         for kv in kvs {
             self.add_kv(kv).await?;
@@ -179,34 +280,138 @@ impl RedisDb {
     
     pub async fn set_indexer_block_height(&self, height: u64) -> Result<()> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let key = self.meta_key();
+        let key = keys::meta(&self.chain_id);
         conn.set(&key, height.to_string()).await?;
         Ok(())
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct StoredKvEntry {
-    predecessor_id: String,
-    current_account_id: String,
-    key: String,
-    value: String,
-    block_height: u64,
-    block_timestamp: u64,
-    receipt_id: String,
-    tx_hash: String,
-}
-
-/// Helper function to compute order_id (same as scylladb module)
+impl CheckpointStore for RedisDb {
+    async fn get_last_processed_block_height(&self, suffix: &str) -> Result<Option<u64>> {
+        RedisDb::get_last_processed_block_height(self, suffix).await
+    }
+
+    async fn set_last_processed_block_height(&self, suffix: &str, height: u64) -> Result<()> {
+        RedisDb::set_last_processed_block_height(self, suffix, height).await
+    }
+
+    async fn rewind_last_processed_block_height(&self, suffix: &str, height: u64) -> Result<Option<u64>> {
+        RedisDb::rewind_last_processed_block_height(self, suffix, height).await
+    }
+}
+
+impl From<&FastDataKv> for StoredKvEntry {
+    fn from(kv: &FastDataKv) -> Self {
+        Self {
+            predecessor_id: kv.predecessor_id.clone(),
+            current_account_id: kv.current_account_id.clone(),
+            key: kv.key.clone(),
+            value: kv.value.clone(),
+            block_height: kv.block_height,
+            block_timestamp: kv.block_timestamp,
+            receipt_id: kv.receipt_id.clone(),
+            tx_hash: kv.tx_hash.clone().unwrap_or_default(),
+            deleted: StoredKvEntry::deletion_marker(kv.is_deleted, &kv.value),
+            content_type: None,
+        }
+    }
+}
+
+/// Entry stored at `kv:*`: the member form plus the detected content type.
+/// The current value is overwritten in place, so extra fields are safe there.
+fn current_entry(kv: &FastDataKv) -> StoredKvEntry {
+    StoredKvEntry { content_type: Some(kv.content_type), ..StoredKvEntry::from(kv) }
+}
+
+/// `tx:*` index key for an entry, or `None` when its tx hash is unknown.
+fn tx_index_key(kv: &FastDataKv) -> Option<String> {
+    kv.tx_hash
+        .as_deref()
+        .filter(|hash| !hash.is_empty())
+        .map(keys::tx)
+}
+
+/// Change notification published to `changes:{chain_id}:{current_account_id}`
+/// whenever the current value of a key is updated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KvChange {
+    pub predecessor_id: String,
+    pub key: String,
+    pub value: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+}
+
+impl From<&FastDataKv> for KvChange {
+    fn from(kv: &FastDataKv) -> Self {
+        Self {
+            predecessor_id: kv.predecessor_id.clone(),
+            key: kv.key.clone(),
+            value: kv.value.clone(),
+            block_height: kv.block_height,
+            block_timestamp: kv.block_timestamp,
+        }
+    }
+}
+
+/// Whether an incoming KV write at `block_height` should replace the stored
+/// current value. Unparseable stored entries are always replaced.
+fn should_overwrite(existing: Option<&str>, block_height: u64) -> bool {
+    match existing.and_then(|json| serde_json::from_str::<StoredKvEntry>(json).ok()) {
+        Some(stored) => stored.block_height <= block_height,
+        None => true,
+    }
+}
+
+/// Block height stored at checkpoint `key`. Garbage is an error rather than
+/// "no checkpoint", which would restart indexing from the configured start.
+fn parse_checkpoint(key: &str, value: String) -> Result<u64> {
+    value
+        .parse()
+        .map_err(|_| IndexerError::CheckpointError { key: key.to_string(), value })
+}
+
+/// Trim and lowercase a chain id, rejecting anything that is not
+/// `[a-z0-9_-]+`. Chain ids are embedded in every key, so e.g. `Mainnet `
+/// and `mainnet` must not become separate keyspaces.
+pub fn normalize_chain_id(chain_id: &str) -> Result<String> {
+    let normalized = chain_id.trim().to_ascii_lowercase();
+    if normalized.is_empty() {
+        return Err(IndexerError::InvalidChainId("chain_id must not be empty".to_string()));
+    }
+    if !normalized
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(IndexerError::InvalidChainId(format!(
+            "{chain_id:?}: only letters, digits, '-' and '_' are allowed"
+        )));
+    }
+    Ok(normalized)
+}
+
+/// Extract the key id from an encrypted value of the form
+/// `enc:AES256:<key_id>:<ciphertext>` (optionally JSON-quoted).
+pub fn detect_encrypted(value: &str) -> Option<String> {
+    let value = value.trim_matches('"');
+    if let Some(rest) = value.strip_prefix("enc:AES256:") {
+        let parts: Vec<&str> = rest.splitn(2, ':').collect();
+        if parts.len() == 2 {
+            return Some(parts[0].to_string());
+        }
+    }
+    None
+}
+
+/// Order id of a FastData action; see [`fastkv_common::compute_order_id`].
 pub fn compute_order_id(fastdata: &FastData) -> Result<u64, String> {
-    let order_id = ((fastdata.shard_id as u64) & 0xFFFF) << 48
-        | ((fastdata.receipt_index as u64) & 0xFFFF) << 32
-        | ((fastdata.action_index as u64) & 0xFFFF);
-    Ok(order_id)
+    fastkv_common::compute_order_id(fastdata.shard_id, fastdata.receipt_index, fastdata.action_index)
+        .map_err(|e| format!("{e} for receipt {}", fastdata.receipt_id))
 }
 
-/// Retry helper
-pub async fn retry_with_delays<F, Fut, T, E>(delays: &[u64], mut f: F) -> Result<T>
+/// Retry helper: one attempt per entry of `delays` (a single attempt when
+/// empty). Returns the last error unchanged so callers can classify it.
+pub async fn retry_with_delays<F, Fut, T, E>(delays: &[u64], mut f: F) -> Result<T, E>
 where
     F: FnMut() -> Fut,
     Fut: std::future::Future<Output = Result<T, E>>,
@@ -225,5 +430,213 @@ where
             }
         }
     }
-    Err(anyhow::anyhow!("Operation failed after all retries: {:?}", last_error))
+    match last_error {
+        Some(e) => Err(e),
+        None => f().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored_json(block_height: u64) -> String {
+        serde_json::to_string(&StoredKvEntry {
+            predecessor_id: "alice.near".to_string(),
+            current_account_id: "contract.near".to_string(),
+            key: "profile/name".to_string(),
+            value: "\"Alice\"".to_string(),
+            block_height,
+            block_timestamp: 0,
+            receipt_id: "r".to_string(),
+            tx_hash: String::new(),
+            deleted: None,
+            content_type: None,
+        })
+        .unwrap()
+    }
+
+    fn sample_kv() -> FastDataKv {
+        FastDataKv {
+            receipt_id: "r".to_string(),
+            action_index: 0,
+            tx_hash: None,
+            signer_id: "alice.near".to_string(),
+            predecessor_id: "alice.near".to_string(),
+            current_account_id: "contract.near".to_string(),
+            block_height: 42,
+            block_timestamp: 7,
+            shard_id: 0,
+            receipt_index: 0,
+            order_id: 0,
+            key: "profile/name".to_string(),
+            value: "\"Alice\"".to_string(),
+            encrypted_key_id: None,
+            is_deleted: false,
+            content_type: ContentType::Text,
+        }
+    }
+
+    /// Pinned wire format, as in `fastkv-common`.
+    const STORED_KV_FIXTURE: &str = r#"{"predecessor_id":"alice.near","current_account_id":"contract.near","key":"profile/name","value":"\"Alice\"","block_height":42,"block_timestamp":7,"receipt_id":"r","tx_hash":""}"#;
+
+    #[test]
+    fn test_stored_kv_entry_wire_format() {
+        let stored = StoredKvEntry::from(&sample_kv());
+        assert_eq!(serde_json::to_string(&stored).unwrap(), STORED_KV_FIXTURE);
+        let read: StoredKvEntry = serde_json::from_str(STORED_KV_FIXTURE).unwrap();
+        assert_eq!(read, stored);
+    }
+
+    #[test]
+    fn test_current_entry_adds_content_type() {
+        let current = serde_json::to_string(&current_entry(&sample_kv())).unwrap();
+        assert_eq!(current, STORED_KV_FIXTURE.replace('}', r#","content_type":"text"}"#));
+        let read: StoredKvEntry = serde_json::from_str(&current).unwrap();
+        assert_eq!(read.content_type, Some(ContentType::Text));
+    }
+
+    #[test]
+    fn test_stored_kv_entry_tombstone() {
+        let mut kv = sample_kv();
+        kv.value = "null".to_string();
+        kv.is_deleted = true;
+        let stored = StoredKvEntry::from(&kv);
+        assert!(stored.is_deleted());
+        assert_eq!(stored.deleted, None);
+
+        kv.value = "\"null\"".to_string();
+        kv.is_deleted = false;
+        assert!(!StoredKvEntry::from(&kv).is_deleted());
+    }
+
+    #[test]
+    fn test_tx_index_key_skips_missing_hash() {
+        let mut kv = sample_kv();
+        assert_eq!(tx_index_key(&kv), None);
+        kv.tx_hash = Some(String::new());
+        assert_eq!(tx_index_key(&kv), None);
+        kv.tx_hash = Some("hash".to_string());
+        assert_eq!(tx_index_key(&kv).as_deref(), Some("tx:hash"));
+    }
+
+    #[test]
+    fn test_stored_kv_entry_tx_hash() {
+        let mut kv = sample_kv();
+        kv.tx_hash = Some("hash".to_string());
+        assert_eq!(StoredKvEntry::from(&kv).tx_hash, "hash");
+    }
+
+    #[test]
+    fn test_kv_change_from_fastdata_kv() {
+        let kv = sample_kv();
+        let json = serde_json::to_value(KvChange::from(&kv)).unwrap();
+        assert_eq!(json["predecessor_id"], "alice.near");
+        assert_eq!(json["key"], "profile/name");
+        assert_eq!(json["block_height"], 42);
+    }
+
+    #[test]
+    fn test_errors_map_to_variants() {
+        let redis_error = redis::Client::open("not a redis url").unwrap_err();
+        assert!(matches!(IndexerError::from(redis_error), IndexerError::ConnectionError(_)));
+
+        let json_error = serde_json::from_str::<StoredKvEntry>("{").unwrap_err();
+        assert!(matches!(IndexerError::from(json_error), IndexerError::SerializationError(_)));
+
+        assert_eq!(parse_checkpoint("k", "42".to_string()).unwrap(), 42);
+        let err = parse_checkpoint("meta:mainnet:kv", "forty-two".to_string()).unwrap_err();
+        assert!(
+            matches!(&err, IndexerError::CheckpointError { key, value } if key == "meta:mainnet:kv" && value == "forty-two"),
+            "{err:?}"
+        );
+
+        assert!(matches!(normalize_chain_id("main net"), Err(IndexerError::InvalidChainId(_))));
+    }
+
+    #[tokio::test]
+    async fn test_retry_returns_last_error() {
+        let mut attempts = 0;
+        let result: Result<(), IndexerError> = retry_with_delays(&[0, 0], || {
+            attempts += 1;
+            let value = format!("bad-{attempts}");
+            async move { parse_checkpoint("k", value).map(drop) }
+        })
+        .await;
+        assert_eq!(attempts, 2);
+        assert!(matches!(result, Err(IndexerError::CheckpointError { value, .. }) if value == "bad-2"));
+    }
+
+    #[test]
+    fn test_normalize_chain_id() {
+        assert_eq!(normalize_chain_id("mainnet").unwrap(), "mainnet");
+        assert_eq!(normalize_chain_id("Mainnet ").unwrap(), "mainnet");
+        assert_eq!(normalize_chain_id("\tTESTNET\n").unwrap(), "testnet");
+        assert_eq!(normalize_chain_id("local-net_1").unwrap(), "local-net_1");
+    }
+
+    #[test]
+    fn test_normalize_chain_id_rejects_invalid() {
+        assert!(normalize_chain_id("").is_err());
+        assert!(normalize_chain_id("   ").is_err());
+        assert!(normalize_chain_id("main:net").is_err());
+        assert!(normalize_chain_id("main net").is_err());
+        assert!(normalize_chain_id("mainnet*").is_err());
+    }
+
+    #[test]
+    fn test_detect_encrypted() {
+        assert_eq!(detect_encrypted("\"enc:AES256:abc123:Zm9v\""), Some("abc123".to_string()));
+        assert_eq!(detect_encrypted("enc:AES256:abc123"), None);
+        assert_eq!(detect_encrypted("\"plain\""), None);
+    }
+
+    /// Values produced by the key-manager's `encrypt_tagged` are indexed as
+    /// encrypted under the group's key_id and decrypt back via `decrypt_tagged`.
+    #[test]
+    fn test_detect_encrypted_key_manager_roundtrip() {
+        let encrypted = key_manager::execute(
+            r#"{"action":"encrypt_tagged","group_id":"alice.near/data","account_id":"alice.near","plaintext_b64":"aGk="}"#,
+        );
+        let tagged: key_manager::TaggedValueResponse = serde_json::from_str(&encrypted).unwrap();
+        // Stored as a JSON string value
+        let stored = serde_json::to_string(&tagged.tagged_value).unwrap();
+        assert_eq!(
+            detect_encrypted(&stored),
+            Some(key_manager::key_id_for_group("alice.near/data"))
+        );
+
+        let request = serde_json::json!({
+            "action": "decrypt_tagged",
+            "group_id": "alice.near/data",
+            "account_id": "alice.near",
+            "tagged_value": stored,
+        });
+        let decrypted: key_manager::DecryptResponse =
+            serde_json::from_str(&key_manager::execute(&request.to_string())).unwrap();
+        assert_eq!(decrypted.plaintext_utf8.as_deref(), Some("hi"));
+    }
+
+    #[test]
+    fn test_should_overwrite_missing_entry() {
+        assert!(should_overwrite(None, 100));
+    }
+
+    #[test]
+    fn test_should_overwrite_newer_or_same_block() {
+        let json = stored_json(100);
+        assert!(should_overwrite(Some(&json), 100));
+        assert!(should_overwrite(Some(&json), 101));
+    }
+
+    #[test]
+    fn test_should_not_overwrite_with_older_block() {
+        let json = stored_json(100);
+        assert!(!should_overwrite(Some(&json), 99));
+    }
+
+    #[test]
+    fn test_should_overwrite_corrupt_entry() {
+        assert!(should_overwrite(Some("not json"), 1));
+    }
 }
@@ -1,18 +1,51 @@
-use redis_db::{FastData, RedisDb, UNIVERSAL_SUFFIX};
+use fastkv_common::keys;
+use redis_db::{CheckpointStore, FastData, RedisDb, UNIVERSAL_SUFFIX};
 
 use fastnear_primitives::near_indexer_primitives::types::BlockHeight;
+use redis::aio::ConnectionManager;
 use fastnear_primitives::types::ChainId;
+use std::collections::{HashSet, VecDeque};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::sync::mpsc;
 
 const FETCHER: &str = "suffix-fetcher";
+/// Minimum number of blocks covered by one concurrently fetched sub-range.
+const SUB_RANGE_BLOCKS: BlockHeight = 1_000;
+/// Upper bound on sub-ranges per pass; each sub-range is a full key SCAN, so
+/// very large gaps use proportionally larger sub-ranges instead.
+const MAX_SUB_RANGES: BlockHeight = 64;
+/// Cap for the backoff while the head checkpoint does not exist yet.
+const MAX_HEAD_WAIT: Duration = Duration::from_secs(30);
+/// Cap for the backoff while caught up and waiting for new blocks.
+const MAX_IDLE_WAIT: Duration = Duration::from_secs(5);
+/// Entries emitted per pass before checkpointing and yielding back to the
+/// main loop (a block is never split).
+const MAX_EMITTED_PER_PASS: usize = 10_000;
+/// Backoff before each attempt at fetching a sub-range.
+const RETRY_DELAYS_SECS: [u64; 4] = [0, 1, 2, 4];
+/// Cap on the keys remembered per sub-range for dedup across retries.
+const MAX_SEEN_KEYS: usize = 100_000;
 
+/// Update from a fetch loop. Every variant carries its suffix (for
+/// `FastData`, in the record itself) so a consumer of several suffixes can
+/// route updates and checkpoint each suffix separately.
 #[derive(Debug, Clone)]
 pub enum SuffixFetcherUpdate {
     FastData(Box<FastData>),
-    EndOfRange(BlockHeight),
+    EndOfRange { suffix: String, block_height: BlockHeight },
+    /// Unrecoverable fetch failure. Sent once, right before the fetcher stops.
+    Error { suffix: String, message: String },
+}
+
+impl SuffixFetcherUpdate {
+    pub fn suffix(&self) -> &str {
+        match self {
+            Self::FastData(fastdata) => &fastdata.suffix,
+            Self::EndOfRange { suffix, .. } | Self::Error { suffix, .. } => suffix,
+        }
+    }
 }
 
 impl From<FastData> for SuffixFetcherUpdate {
@@ -24,12 +57,120 @@ impl From<FastData> for SuffixFetcherUpdate {
 pub struct SuffixFetcher {
     pub redis_db: Arc<RedisDb>,
     pub chain_id: ChainId,
+    /// Shared by every `fetch_range` call (clones multiplex one connection).
+    conn: ConnectionManager,
 }
 
 pub struct SuffixFetcherConfig {
     pub suffix: String,
     pub start_block_height: Option<BlockHeight>,
     pub sleep_duration: Duration,
+    /// Optional handle used to rewind a running fetcher to an earlier height.
+    pub replay: Option<ReplayHandle>,
+    /// Checkpoint suffix whose height marks the head of available data.
+    /// Defaults to [`UNIVERSAL_SUFFIX`] (written by the main-indexer).
+    pub head_suffix: Option<String>,
+    /// Maximum number of sub-ranges fetched at once when catching up.
+    pub max_concurrency: usize,
+    /// Checkpoint to resume from when `start_block_height` is unset; the
+    /// consumer writes it on `EndOfRange`. Defaults to `suffix`.
+    pub checkpoint_key: Option<String>,
+}
+
+impl SuffixFetcherConfig {
+    pub fn head_suffix(&self) -> &str {
+        self.head_suffix.as_deref().unwrap_or(UNIVERSAL_SUFFIX)
+    }
+
+    pub fn checkpoint_key(&self) -> &str {
+        self.checkpoint_key.as_deref().unwrap_or(&self.suffix)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.head_suffix().is_empty() {
+            anyhow::bail!("head_suffix must not be empty");
+        }
+        if self.checkpoint_key().is_empty() {
+            anyhow::bail!("checkpoint_key must not be empty");
+        }
+        Ok(())
+    }
+}
+
+/// Validate configs hosted by one fetcher: each must be valid, and no two may
+/// share a suffix or a checkpoint key.
+pub fn validate_configs(configs: &[SuffixFetcherConfig]) -> anyhow::Result<()> {
+    if configs.is_empty() {
+        anyhow::bail!("at least one suffix must be configured");
+    }
+    let mut suffixes = std::collections::HashSet::new();
+    let mut checkpoints = std::collections::HashSet::new();
+    for config in configs {
+        config.validate()?;
+        if !suffixes.insert(config.suffix.as_str()) {
+            anyhow::bail!("suffix {:?} is configured twice", config.suffix);
+        }
+        if !checkpoints.insert(config.checkpoint_key()) {
+            anyhow::bail!("checkpoint key {:?} is shared by two suffixes", config.checkpoint_key());
+        }
+    }
+    Ok(())
+}
+
+/// Read the head block height from the configured head checkpoint.
+async fn fetch_head<S: CheckpointStore>(
+    store: &S,
+    config: &SuffixFetcherConfig,
+) -> redis_db::Result<Option<BlockHeight>> {
+    store.get_last_processed_block_height(config.head_suffix()).await
+}
+
+/// Capped exponential backoff: each [`Backoff::next_delay`] doubles the wait
+/// up to `max`, and [`Backoff::reset`] returns to `initial`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max, current: initial }
+    }
+
+    /// Delay to wait now; the following call returns twice as much (capped).
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current.min(self.max);
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+/// Shared handle to request that a running fetcher restarts from an earlier
+/// block height. The request is picked up at the start of the next range.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayHandle(Arc<Mutex<Option<BlockHeight>>>);
+
+impl ReplayHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request a rewind to `from_block_height`. A later request replaces an
+    /// earlier one that has not been picked up yet.
+    pub fn request(&self, from_block_height: BlockHeight) {
+        *self.0.lock().unwrap() = Some(from_block_height);
+    }
+
+    /// Take the pending rewind request, if any.
+    pub fn take(&self) -> Option<BlockHeight> {
+        self.0.lock().unwrap().take()
+    }
 }
 
 impl SuffixFetcher {
@@ -47,7 +188,8 @@ impl SuffixFetcher {
                 Arc::new(db)
             }
         };
-        Ok(Self { redis_db, chain_id })
+        let conn = redis_db.connection_manager().await?;
+        Ok(Self { redis_db, chain_id, conn })
     }
 
     pub fn get_redis_db(&self) -> Arc<RedisDb> {
@@ -60,196 +202,1075 @@ impl SuffixFetcher {
         sink: mpsc::Sender<SuffixFetcherUpdate>,
         is_running: Arc<AtomicBool>,
     ) {
-        let mut from_block_height = config.start_block_height.unwrap_or(0);
-        tracing::info!(target: FETCHER, "Starting suffix fetcher with suffix {:?} from {}", config.suffix, from_block_height);
-        
-        while is_running.load(Ordering::SeqCst) {
-            // Get last processed block height from Redis
-            let last_block_height = match self
-                .redis_db
-                .get_last_processed_block_height(UNIVERSAL_SUFFIX)
-                .await
-            {
-                Ok(height) => height,
-                Err(e) => {
-                    tracing::error!(
-                        target: FETCHER,
-                        "Error getting last block height: {:?}. Retrying in 1s...",
-                        e
-                    );
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                    continue;
-                }
-            };
-            
-            let Some(last_block_height) = last_block_height else {
-                tracing::info!(target: FETCHER, "No last processed block height found");
-                tokio::time::sleep(Duration::from_secs(1)).await;
-                continue;
-            };
-            
-            if from_block_height > last_block_height {
-                tracing::debug!(target: FETCHER, "Waiting for new blocks");
-                tokio::time::sleep(config.sleep_duration).await;
-                continue;
-            }
-            
-            tracing::info!(target: FETCHER, "Fetching blocks from {} to {}", from_block_height, last_block_height);
-
-            // Fetch data from Redis for the range
-            let mut range_success = false;
-            let mut range_last_block: Option<BlockHeight> = None;
-            let delays = [0, 1, 2, 4];
+        if let Err(e) = config.validate() {
+            tracing::error!(target: FETCHER, "Invalid suffix fetcher config: {:?}", e);
+            is_running.store(false, Ordering::SeqCst);
+            return;
+        }
+        run(self.redis_db.as_ref(), &self, config, sink, &is_running).await;
+    }
 
-            for (attempt, &delay_secs) in delays.iter().enumerate() {
-                let mut last_fastdata_block_height: Option<BlockHeight> = None;
+    /// Host several suffixes in one process: one independent fetch loop per
+    /// config, sharing this fetcher's Redis connection and `sink`. Each loop
+    /// resumes from its own checkpoint key. An unrecoverable failure in any
+    /// loop clears `is_running`, which stops the others too.
+    pub async fn start_all(
+        self,
+        configs: Vec<SuffixFetcherConfig>,
+        sink: mpsc::Sender<SuffixFetcherUpdate>,
+        is_running: Arc<AtomicBool>,
+    ) {
+        if let Err(e) = validate_configs(&configs) {
+            tracing::error!(target: FETCHER, "Invalid suffix fetcher config: {:?}", e);
+            is_running.store(false, Ordering::SeqCst);
+            return;
+        }
+        run_all(self.redis_db.clone(), Arc::new(self), configs, sink, is_running).await;
+    }
+}
 
-                if delay_secs > 0 {
-                    tracing::info!(target: FETCHER, "Retrying range fetch (attempt {}/{}) after {}s delay", attempt, delays.len() - 1, delay_secs);
-                    tokio::time::sleep(Duration::from_secs(delay_secs)).await;
-                }
+/// Spawn a fetch loop per config and wait for all of them to stop.
+async fn run_all<S, F>(
+    store: Arc<S>,
+    source: Arc<F>,
+    configs: Vec<SuffixFetcherConfig>,
+    sink: mpsc::Sender<SuffixFetcherUpdate>,
+    is_running: Arc<AtomicBool>,
+) where
+    S: CheckpointStore + Send + Sync + 'static,
+    F: FastDataSource + Send + 'static,
+{
+    let loops: Vec<_> = configs
+        .into_iter()
+        .map(|config| {
+            let (store, source) = (store.clone(), source.clone());
+            let (sink, is_running) = (sink.clone(), is_running.clone());
+            tokio::spawn(async move {
+                run(store.as_ref(), source.as_ref(), config, sink, &is_running).await
+            })
+        })
+        .collect();
+    for handle in loops {
+        if let Err(e) = handle.await {
+            tracing::error!(target: FETCHER, "Fetch loop panicked: {:?}", e);
+            is_running.store(false, Ordering::SeqCst);
+        }
+    }
+}
 
-                // Stream suffix data from Redis
-                let result = self.stream_suffix_data(
-                    &config.suffix,
-                    from_block_height,
-                    last_block_height,
-                    &sink,
-                    is_running.clone(),
-                    &mut last_fastdata_block_height,
-                ).await;
-
-                match result {
-                    Ok(had_data) => {
-                        range_success = true;
-                        if let Some(h) = last_fastdata_block_height {
-                            range_last_block = Some(h);
-                        }
-                        tracing::debug!(target: FETCHER, "Range scan complete, had_data={}", had_data);
-                        break;
-                    }
-                    Err(e) => {
-                        tracing::error!(target: FETCHER, "Error fetching suffix data (attempt {}): {:?}", attempt + 1, e);
-                    }
-                }
+/// Fetch loop for one suffix: reads the head from `store` and data from
+/// `source` until `is_running` is cleared or the range fails.
+async fn run<S: CheckpointStore, F: FastDataSource>(
+    store: &S,
+    source: &F,
+    config: SuffixFetcherConfig,
+    sink: mpsc::Sender<SuffixFetcherUpdate>,
+    is_running: &AtomicBool,
+) {
+    let mut from_block_height = match config.start_block_height {
+        Some(height) => height,
+        None => match store.get_last_processed_block_height(config.checkpoint_key()).await {
+            Ok(checkpoint) => checkpoint.map_or(0, |h| h + 1),
+            Err(e) => {
+                let message = format!("Failed to read checkpoint {:?}: {e}", config.checkpoint_key());
+                tracing::error!(target: FETCHER, "{}", message);
+                is_running.store(false, Ordering::SeqCst);
+                let _ = sink.send(SuffixFetcherUpdate::Error { suffix: config.suffix.clone(), message }).await;
+                return;
             }
+        },
+    };
+    tracing::info!(target: FETCHER, "Starting suffix fetcher with suffix {:?} from {} (head suffix {:?})", config.suffix, from_block_height, config.head_suffix());
+    
+    let mut head_backoff = Backoff::new(Duration::from_secs(1), MAX_HEAD_WAIT);
+    let mut idle_backoff = Backoff::new(config.sleep_duration, MAX_IDLE_WAIT.max(config.sleep_duration));
+
+    while is_running.load(Ordering::SeqCst) {
+        if let Some(replay_from) = config.replay.as_ref().and_then(|r| r.take()) {
+            tracing::info!(target: FETCHER, "Replaying suffix {:?} from {} (was at {})", config.suffix, replay_from, from_block_height);
+            from_block_height = replay_from;
+        }
 
-            if !range_success {
+        // Get last processed block height from Redis
+        let last_block_height = match fetch_head(store, &config).await {
+            Ok(height) => height,
+            Err(e) => {
                 tracing::error!(
                     target: FETCHER,
-                    "Failed to fetch range [{}, {}] after {} retries.",
-                    from_block_height, last_block_height, delays.len() - 1
+                    "Error getting last block height: {:?}. Retrying in 1s...",
+                    e
                 );
-                is_running.store(false, Ordering::SeqCst);
-                break;
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
             }
+        };
+        
+        let Some(last_block_height) = last_block_height else {
+            let delay = head_backoff.next_delay();
+            tracing::info!(target: FETCHER, "No last processed block height found. Retrying in {:?}", delay);
+            tokio::time::sleep(delay).await;
+            continue;
+        };
+        head_backoff.reset();
+        
+        if from_block_height > last_block_height {
+            let delay = idle_backoff.next_delay();
+            tracing::debug!(target: FETCHER, "Waiting for new blocks ({:?})", delay);
+            tokio::time::sleep(delay).await;
+            continue;
+        }
+        idle_backoff.reset();
+        
+        tracing::info!(target: FETCHER, "Fetching blocks from {} to {}", from_block_height, last_block_height);
 
-            // Checkpoint based on actual progress
-            if let Some(checkpoint_height) = range_last_block {
-                if sink.send(SuffixFetcherUpdate::EndOfRange(checkpoint_height))
-                    .await
-                    .is_err() {
-                    tracing::warn!(target: FETCHER, "Channel closed, stopping");
-                    break;
-                }
-                from_block_height = checkpoint_height + 1;
-            } else if is_running.load(Ordering::SeqCst) {
-                if sink.send(SuffixFetcherUpdate::EndOfRange(last_block_height))
-                    .await
-                    .is_err() {
-                    tracing::warn!(target: FETCHER, "Channel closed, stopping");
-                    break;
-                }
-                from_block_height = last_block_height + 1;
-            }
+        match process_range(
+            source,
+            &config.suffix,
+            from_block_height,
+            last_block_height,
+            config.max_concurrency,
+            &sink,
+            is_running,
+        )
+        .await
+        {
+            Some(confirmed_height) => from_block_height = confirmed_height + 1,
+            None => break,
         }
-        tracing::info!(target: FETCHER, "Stopped suffix fetcher");
     }
+    tracing::info!(target: FETCHER, "Stopped suffix fetcher for suffix {:?}", config.suffix);
+}
 
-    async fn stream_suffix_data(
+impl FastDataSource for SuffixFetcher {
+    async fn fetch_range(
         &self,
         suffix: &str,
         from_block: BlockHeight,
         to_block: BlockHeight,
-        sink: &mpsc::Sender<SuffixFetcherUpdate>,
-        is_running: Arc<AtomicBool>,
-        last_block: &mut Option<BlockHeight>,
-    ) -> anyhow::Result<bool> {
-        use redis::{AsyncCommands, Client};
-        
-        let redis_url = std::env::var("REDIS_URL")
-            .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
-        let client = Client::open(redis_url.as_str())?;
-        let mut conn = client.get_multiplexed_async_connection().await?;
-        
+        items: &mut Vec<FastData>,
+    ) -> Result<(), FetchError> {
+        let mut conn = self.conn.clone();
         // Pattern for fastdata keys: fastdata:{chain_id}:{suffix}:{block_height}:{receipt_id}
-        let pattern = format!("fastdata:{}:{}:*", self.chain_id, suffix);
-        
-        let mut cursor: u64 = 0;
-        let mut had_data = false;
+        let pattern = keys::fastdata_pattern(self.redis_db.chain_id(), suffix);
+        scan_fastdata(&mut conn, &pattern, from_block, to_block, items).await
+    }
+}
+
+/// SCAN keys matching `pattern` and read each batch's keys in `[from_block,
+/// to_block]` with a single MGET, appending them to `items` batch by batch.
+async fn scan_fastdata<C: redis::aio::ConnectionLike>(
+    conn: &mut C,
+    pattern: &str,
+    from_block: BlockHeight,
+    to_block: BlockHeight,
+    items: &mut Vec<FastData>,
+) -> Result<(), FetchError> {
+    let mut cursor: u64 = 0;
+    
+    loop {
+        let (new_cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(pattern)
+            .arg("COUNT")
+            .arg(100)
+            .query_async(conn)
+            .await?;
         
-        loop {
-            if !is_running.load(Ordering::SeqCst) {
-                return Ok(true);
-            }
-            
-            let (new_cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
-                .arg(cursor)
-                .arg("MATCH")
-                .arg(&pattern)
-                .arg("COUNT")
-                .arg(100)
-                .query_async(&mut conn)
+        // Filter by height from the key, then fetch the whole batch at once
+        let keys = keys_in_range(keys, from_block, to_block);
+        if !keys.is_empty() {
+            let values: Vec<Option<String>> = redis::cmd("MGET")
+                .arg(&keys)
+                .query_async(conn)
                 .await?;
-            
-            for key in keys {
-                tracing::info!(target: FETCHER, "Processing key: {}", key);
-                // Parse block height from key
-                let parts: Vec<&str> = key.split(':').collect();
-                if parts.len() < 5 {
-                    tracing::warn!(target: FETCHER, "Key has wrong format: {}", key);
-                    continue;
-                }
-                if let Ok(block_height) = parts[3].parse::<u64>() {
-                    tracing::info!(target: FETCHER, "Parsed block_height {} from key", block_height);
-                    if block_height < from_block || block_height > to_block {
-                        tracing::debug!(target: FETCHER, "Skipping block {} (out of range {}-{})", block_height, from_block, to_block);
-                        continue;
-                    }
-                    
-                    // Get the data
-                    let data: Option<String> = conn.get(&key).await?;
-                    if let Some(json) = data {
-                        tracing::info!(target: FETCHER, "Got fastdata JSON for key {}: {} bytes", key, json.len());
-                        match serde_json::from_str::<FastData>(&json) {
-                            Ok(fastdata) => {
-                                tracing::info!(target: FETCHER, "Successfully parsed FastData: block={} receipt={}", fastdata.block_height, fastdata.receipt_id);
-                                had_data = true;
-                                *last_block = Some(fastdata.block_height);
-                                
-                                if sink.send(fastdata.into()).await.is_err() {
-                                    tracing::warn!(target: FETCHER, "Channel closed, stopping");
-                                    return Ok(true);
-                                }
-                            }
-                            Err(e) => {
-                                tracing::error!(target: FETCHER, "Failed to parse FastData from {}: {:?}", key, e);
-                            }
-                        }
-                    } else {
-                        tracing::warn!(target: FETCHER, "No data found for key {}", key);
-                    }
-                } else {
-                    tracing::warn!(target: FETCHER, "Failed to parse block_height from key {}", key);
+            items.extend(parse_fastdata_values(&keys, values));
+        }
+        
+        cursor = new_cursor;
+        if cursor == 0 {
+            break;
+        }
+    }
+    
+    Ok(())
+}
+
+/// Keep only well-formed keys (`fastdata:{chain_id}:{suffix}:{block_height}:{receipt_id}`)
+/// whose block height lies in `[from_block, to_block]`.
+fn keys_in_range(batch: Vec<String>, from_block: BlockHeight, to_block: BlockHeight) -> Vec<String> {
+    batch.into_iter()
+        .filter(|key| match keys::fastdata_block_height(key) {
+            Some(block_height) => block_height >= from_block && block_height <= to_block,
+            None => {
+                tracing::warn!(target: FETCHER, "Key has wrong format: {}", key);
+                false
+            }
+        })
+        .collect()
+}
+
+/// Parse MGET results, paired positionally with the requested keys.
+fn parse_fastdata_values(keys: &[String], values: Vec<Option<String>>) -> Vec<FastData> {
+    keys.iter()
+        .zip(values)
+        .filter_map(|(key, value)| {
+            let Some(json) = value else {
+                tracing::warn!(target: FETCHER, "No data found for key {}", key);
+                return None;
+            };
+            match parse_fastdata_lenient(&json) {
+                Ok(fastdata) => Some(fastdata),
+                Err(e) => {
+                    tracing::error!(target: FETCHER, "Dropping FastData from {}: {}", key, e);
+                    None
                 }
             }
-            
-            cursor = new_cursor;
-            if cursor == 0 {
+        })
+        .collect()
+}
+
+/// Parse a stored FastData record, tolerating schema drift: unknown fields
+/// are ignored, optional fields default when missing, and integers may be
+/// encoded as strings. Fails only if a required field is absent or invalid.
+fn parse_fastdata_lenient(json: &str) -> Result<FastData, String> {
+    let value: serde_json::Value =
+        serde_json::from_str(json).map_err(|e| format!("invalid JSON: {e}"))?;
+    let obj = value
+        .as_object()
+        .ok_or_else(|| "expected a JSON object".to_string())?;
+
+    let string = |field: &str| -> Option<String> {
+        obj.get(field).and_then(|v| v.as_str()).map(str::to_string)
+    };
+    let number = |field: &str| -> Option<u64> {
+        match obj.get(field)? {
+            serde_json::Value::Number(n) => n.as_u64(),
+            serde_json::Value::String(s) => s.parse().ok(),
+            _ => None,
+        }
+    };
+    let required_string = |field: &str| {
+        string(field).ok_or_else(|| format!("missing required field `{field}`"))
+    };
+    let small = |field: &str| -> Result<u32, String> {
+        number(field)
+            .unwrap_or_default()
+            .try_into()
+            .map_err(|_| format!("field `{field}` out of range"))
+    };
+
+    let predecessor_id = required_string("predecessor_id")?;
+    Ok(FastData {
+        receipt_id: required_string("receipt_id")?,
+        action_index: small("action_index")?,
+        suffix: required_string("suffix")?,
+        data: required_string("data")?,
+        tx_hash: string("tx_hash"),
+        signer_id: string("signer_id").unwrap_or_else(|| predecessor_id.clone()),
+        current_account_id: required_string("current_account_id")?,
+        block_height: number("block_height")
+            .ok_or_else(|| "missing required field `block_height`".to_string())?,
+        block_timestamp: number("block_timestamp").unwrap_or_default(),
+        shard_id: small("shard_id")?,
+        receipt_index: small("receipt_index")?,
+        predecessor_id,
+    })
+}
+
+/// Source of FastData for a suffix over an inclusive block range.
+pub trait FastDataSource: Sync {
+    /// Append the range's entries to `items`. On error, `items` keeps
+    /// whatever was read before the failure.
+    fn fetch_range(
+        &self,
+        suffix: &str,
+        from_block: BlockHeight,
+        to_block: BlockHeight,
+        items: &mut Vec<FastData>,
+    ) -> impl std::future::Future<Output = Result<(), FetchError>> + Send;
+}
+
+/// Failure fetching a sub-range, classified by whether a retry can help.
+#[derive(Debug)]
+pub enum FetchError {
+    /// Connection, I/O or timeout failures; the sub-range is retried.
+    Transient(anyhow::Error),
+    /// Deterministic failures (e.g. an unexpected response shape); retrying
+    /// would fail the same way, so they surface immediately.
+    Permanent(anyhow::Error),
+}
+
+impl FetchError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Transient(_))
+    }
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Transient(e) => write!(f, "transient: {e:#}"),
+            Self::Permanent(e) => write!(f, "permanent: {e:#}"),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+impl From<redis::RedisError> for FetchError {
+    fn from(e: redis::RedisError) -> Self {
+        if e.is_io_error() || e.is_timeout() || e.is_connection_dropped() || e.is_connection_refusal() {
+            Self::Transient(e.into())
+        } else {
+            Self::Permanent(e.into())
+        }
+    }
+}
+
+/// Split `[from, to]` into consecutive sub-ranges of at most `size` blocks.
+fn split_range(from: BlockHeight, to: BlockHeight, size: BlockHeight) -> Vec<(BlockHeight, BlockHeight)> {
+    let size = size.max(1);
+    let mut ranges = Vec::new();
+    let mut start = from;
+    while start <= to {
+        let end = start.saturating_add(size - 1).min(to);
+        ranges.push((start, end));
+        if end == BlockHeight::MAX {
+            break;
+        }
+        start = end + 1;
+    }
+    ranges
+}
+
+/// `(block_height, receipt_id, action_index)` of every entry kept for one
+/// sub-range, across all of its attempts. SCAN may return a key more than
+/// once, and a retry re-runs the scan over keys an earlier attempt already
+/// read. Holds at most `cap` keys, forgetting the oldest first.
+struct SeenKeys {
+    keys: HashSet<(BlockHeight, String, u32)>,
+    order: VecDeque<(BlockHeight, String, u32)>,
+    cap: usize,
+}
+
+impl SeenKeys {
+    fn new(cap: usize) -> Self {
+        Self { keys: HashSet::new(), order: VecDeque::new(), cap }
+    }
+
+    /// Record `fastdata`'s key; false if it was already seen.
+    fn insert(&mut self, fastdata: &FastData) -> bool {
+        let key = (fastdata.block_height, fastdata.receipt_id.clone(), fastdata.action_index);
+        if !self.keys.insert(key.clone()) {
+            return false;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.cap {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// Sort into emission order.
+fn sort_for_emission(items: &mut [FastData]) {
+    items.sort_by(|a, b| {
+        (a.block_height, a.shard_id, a.receipt_index, a.action_index, &a.receipt_id)
+            .cmp(&(b.block_height, b.shard_id, b.receipt_index, b.action_index, &b.receipt_id))
+    });
+}
+
+/// Fetch a single sub-range, retrying only this sub-range on transient
+/// failure. Entries read by a failed attempt are kept, and every attempt's
+/// entries are deduplicated against [`SeenKeys`] so a retry does not yield
+/// them twice.
+async fn fetch_sub_range<S: FastDataSource>(
+    source: &S,
+    suffix: &str,
+    from_block: BlockHeight,
+    to_block: BlockHeight,
+) -> anyhow::Result<Vec<FastData>> {
+    let mut seen = SeenKeys::new(MAX_SEEN_KEYS);
+    let mut items = Vec::new();
+    let mut last_error = None;
+    for (attempt, &delay_secs) in RETRY_DELAYS_SECS.iter().enumerate() {
+        if delay_secs > 0 {
+            tracing::info!(target: FETCHER, "Retrying sub-range [{}, {}] (attempt {}/{}) after {}s delay", from_block, to_block, attempt, RETRY_DELAYS_SECS.len() - 1, delay_secs);
+            tokio::time::sleep(Duration::from_secs(delay_secs)).await;
+        }
+        let mut batch = Vec::new();
+        let result = source.fetch_range(suffix, from_block, to_block, &mut batch).await;
+        let (read, before) = (batch.len(), items.len());
+        items.extend(batch.into_iter().filter(|fastdata| seen.insert(fastdata)));
+        let dropped = read - (items.len() - before);
+        if dropped > 0 {
+            tracing::debug!(target: FETCHER, "Dropped {} duplicate FastData entries", dropped);
+        }
+        match result {
+            Ok(()) => {
+                sort_for_emission(&mut items);
+                return Ok(items);
+            }
+            Err(e) if e.is_transient() => {
+                tracing::error!(target: FETCHER, "Error fetching sub-range [{}, {}] (attempt {}): {}", from_block, to_block, attempt + 1, e);
+                last_error = Some(e);
+            }
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("sub-range [{from_block}, {to_block}] failed, not retrying")));
+            }
+        }
+    }
+    Err(last_error
+        .map(anyhow::Error::new)
+        .unwrap_or_else(|| anyhow::anyhow!("no attempts made"))
+        .context(format!("sub-range [{from_block}, {to_block}] failed after {} retries", RETRY_DELAYS_SECS.len() - 1)))
+}
+
+/// Fetch `[from, to]` as sub-ranges of at least [`SUB_RANGE_BLOCKS`], up to
+/// `max_concurrency` at a time. Results are emitted in block order, and
+/// `EndOfRange` is sent after each sub-range once every earlier sub-range
+/// has been emitted, so checkpoints only ever cover a contiguous prefix.
+///
+/// At most about [`MAX_EMITTED_PER_PASS`] entries are emitted per call; past
+/// that the pass ends at the current block and the caller resumes after it.
+///
+/// Returns the last confirmed height (`to`, or the block where the cap was
+/// hit), or `None` if stopped early because the fetcher was shut down or the
+/// channel closed.
+async fn fetch_range_concurrently<S: FastDataSource>(
+    source: &S,
+    suffix: &str,
+    from: BlockHeight,
+    to: BlockHeight,
+    max_concurrency: usize,
+    sink: &mpsc::Sender<SuffixFetcherUpdate>,
+    is_running: &AtomicBool,
+) -> anyhow::Result<Option<BlockHeight>> {
+    let span = to.saturating_sub(from).saturating_add(1);
+    let size = SUB_RANGE_BLOCKS.max(span.div_ceil(MAX_SUB_RANGES));
+    fetch_sub_ranges(source, suffix, split_range(from, to, size), max_concurrency, MAX_EMITTED_PER_PASS, sink, is_running).await
+}
+
+/// Fetch one `[from, to]` pass. On unrecoverable failure, sends
+/// [`SuffixFetcherUpdate::Error`] and stops the fetcher.
+///
+/// Returns the confirmed height, or `None` if the fetcher should stop.
+async fn process_range<S: FastDataSource>(
+    source: &S,
+    suffix: &str,
+    from: BlockHeight,
+    to: BlockHeight,
+    max_concurrency: usize,
+    sink: &mpsc::Sender<SuffixFetcherUpdate>,
+    is_running: &AtomicBool,
+) -> Option<BlockHeight> {
+    match fetch_range_concurrently(source, suffix, from, to, max_concurrency, sink, is_running).await {
+        Ok(Some(confirmed_height)) => Some(confirmed_height),
+        Ok(None) => {
+            tracing::warn!(target: FETCHER, "Stopped before range [{}, {}] completed", from, to);
+            None
+        }
+        Err(e) => {
+            let message = format!("Failed to fetch {suffix:?} range [{from}, {to}]: {e:#}");
+            tracing::error!(target: FETCHER, "{}", message);
+            is_running.store(false, Ordering::SeqCst);
+            let _ = sink.send(SuffixFetcherUpdate::Error { suffix: suffix.to_string(), message }).await;
+            None
+        }
+    }
+}
+
+async fn fetch_sub_ranges<S: FastDataSource>(
+    source: &S,
+    suffix: &str,
+    sub_ranges: Vec<(BlockHeight, BlockHeight)>,
+    max_concurrency: usize,
+    max_emitted: usize,
+    sink: &mpsc::Sender<SuffixFetcherUpdate>,
+    is_running: &AtomicBool,
+) -> anyhow::Result<Option<BlockHeight>> {
+    use futures::StreamExt;
+
+    let mut results = futures::stream::iter(sub_ranges)
+        .map(|(from_block, to_block)| async move {
+            (to_block, fetch_sub_range(source, suffix, from_block, to_block).await)
+        })
+        .buffered(max_concurrency.max(1));
+
+    let mut confirmed = None;
+    let mut emitted = 0;
+    while let Some((to_block, result)) = results.next().await {
+        let items = result?;
+        // Once the cap is hit, finish the current block so the checkpoint
+        // never splits a block, then stop this pass.
+        let mut cut_at: Option<BlockHeight> = None;
+        for fastdata in items {
+            if cut_at.is_some_and(|h| fastdata.block_height != h) {
                 break;
             }
+            let block_height = fastdata.block_height;
+            if sink.send(fastdata.into()).await.is_err() {
+                tracing::warn!(target: FETCHER, "Channel closed, stopping");
+                return Ok(None);
+            }
+            emitted += 1;
+            if cut_at.is_none() && emitted >= max_emitted {
+                cut_at = Some(block_height);
+            }
         }
-        
-        Ok(had_data)
+        let checkpoint = cut_at.unwrap_or(to_block);
+        if sink.send(SuffixFetcherUpdate::EndOfRange { suffix: suffix.to_string(), block_height: checkpoint }).await.is_err() {
+            tracing::warn!(target: FETCHER, "Channel closed, stopping");
+            return Ok(None);
+        }
+        confirmed = Some(checkpoint);
+        if !is_running.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        if cut_at.is_some() {
+            tracing::info!(target: FETCHER, "Emitted {} entries, pausing range at block {}", emitted, checkpoint);
+            break;
+        }
+    }
+    Ok(confirmed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, VecDeque};
+
+    #[derive(Default)]
+    struct MemoryStore(Mutex<HashMap<String, u64>>);
+
+    impl CheckpointStore for MemoryStore {
+        async fn get_last_processed_block_height(&self, suffix: &str) -> redis_db::Result<Option<u64>> {
+            Ok(self.0.lock().unwrap().get(suffix).copied())
+        }
+
+        async fn set_last_processed_block_height(&self, suffix: &str, height: u64) -> redis_db::Result<()> {
+            self.0.lock().unwrap().insert(suffix.to_string(), height);
+            Ok(())
+        }
+
+        async fn rewind_last_processed_block_height(&self, suffix: &str, height: u64) -> redis_db::Result<Option<u64>> {
+            let mut heights = self.0.lock().unwrap();
+            let previous = heights.get(suffix).copied();
+            if previous.is_some_and(|h| height <= h) {
+                heights.insert(suffix.to_string(), height);
+            }
+            Ok(previous)
+        }
+    }
+
+    fn config(head_suffix: Option<&str>) -> SuffixFetcherConfig {
+        SuffixFetcherConfig {
+            suffix: "kv".to_string(),
+            start_block_height: None,
+            sleep_duration: Duration::from_millis(500),
+            replay: None,
+            head_suffix: head_suffix.map(str::to_string),
+            max_concurrency: 1,
+            checkpoint_key: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_head_uses_custom_head_suffix() {
+        let store = MemoryStore::default();
+        store.set_last_processed_block_height(UNIVERSAL_SUFFIX, 100).await.unwrap();
+        store.set_last_processed_block_height("upstream", 250).await.unwrap();
+
+        assert_eq!(fetch_head(&store, &config(Some("upstream"))).await.unwrap(), Some(250));
+        assert_eq!(fetch_head(&store, &config(None)).await.unwrap(), Some(100));
+    }
+
+    #[test]
+    fn test_head_suffix_validation() {
+        assert_eq!(config(None).head_suffix(), UNIVERSAL_SUFFIX);
+        assert!(config(None).validate().is_ok());
+        assert!(config(Some("upstream")).validate().is_ok());
+        assert!(config(Some("")).validate().is_err());
+    }
+
+    #[test]
+    fn test_replay_handle_take_clears_request() {
+        let handle = ReplayHandle::new();
+        assert_eq!(handle.take(), None);
+        handle.request(42);
+        assert_eq!(handle.take(), Some(42));
+        assert_eq!(handle.take(), None);
+    }
+
+    #[test]
+    fn test_replay_handle_latest_request_wins() {
+        let handle = ReplayHandle::new();
+        let clone = handle.clone();
+        clone.request(10);
+        clone.request(5);
+        assert_eq!(handle.take(), Some(5));
+    }
+
+    struct MemorySource(Vec<FastData>);
+
+    impl FastDataSource for MemorySource {
+        async fn fetch_range(
+            &self,
+            suffix: &str,
+            from_block: BlockHeight,
+            to_block: BlockHeight,
+            items: &mut Vec<FastData>,
+        ) -> Result<(), FetchError> {
+            // Yield so concurrently fetched sub-ranges interleave.
+            tokio::task::yield_now().await;
+            items.extend(
+                self.0
+                    .iter()
+                    .filter(|d| d.suffix == suffix && d.block_height >= from_block && d.block_height <= to_block)
+                    .cloned(),
+            );
+            Ok(())
+        }
+    }
+
+    fn fastdata(block_height: BlockHeight, receipt_id: &str) -> FastData {
+        suffixed_fastdata("kv", block_height, receipt_id)
+    }
+
+    fn suffixed_fastdata(suffix: &str, block_height: BlockHeight, receipt_id: &str) -> FastData {
+        FastData {
+            receipt_id: receipt_id.to_string(),
+            action_index: 0,
+            suffix: suffix.to_string(),
+            data: String::new(),
+            tx_hash: None,
+            signer_id: "alice.near".to_string(),
+            predecessor_id: "alice.near".to_string(),
+            current_account_id: "contract.near".to_string(),
+            block_height,
+            block_timestamp: 0,
+            shard_id: 0,
+            receipt_index: 0,
+        }
+    }
+
+    #[test]
+    fn test_backoff_grows_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(3));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(3));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(3));
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_parse_fastdata_tolerates_unknown_and_missing_optional_fields() {
+        let json = r#"{
+            "receipt_id": "abc",
+            "suffix": "kv",
+            "data": "e30=",
+            "predecessor_id": "alice.near",
+            "current_account_id": "contract.near",
+            "block_height": "150",
+            "new_field_from_future_schema": {"x": 1}
+        }"#;
+        let fastdata = parse_fastdata_lenient(json).unwrap();
+        assert_eq!(fastdata.block_height, 150);
+        assert_eq!(fastdata.receipt_id, "abc");
+        assert_eq!(fastdata.signer_id, "alice.near");
+        assert_eq!(fastdata.tx_hash, None);
+        assert_eq!(fastdata.action_index, 0);
+    }
+
+    #[test]
+    fn test_parse_fastdata_round_trips_full_record() {
+        let original = fastdata(42, "r1");
+        let parsed = parse_fastdata_lenient(&serde_json::to_string(&original).unwrap()).unwrap();
+        assert_eq!(serde_json::to_value(parsed).unwrap(), serde_json::to_value(original).unwrap());
+    }
+
+    #[test]
+    fn test_parse_fastdata_missing_block_height_is_dropped() {
+        let json = r#"{
+            "receipt_id": "abc",
+            "suffix": "kv",
+            "data": "e30=",
+            "predecessor_id": "alice.near",
+            "current_account_id": "contract.near"
+        }"#;
+        let err = parse_fastdata_lenient(json).unwrap_err();
+        assert_eq!(err, "missing required field `block_height`");
+
+        let values = parse_fastdata_values(&["fastdata:mainnet:kv:1:abc".to_string()], vec![Some(json.to_string())]);
+        assert!(values.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_emit_cap_resumes_across_passes_without_dropping() {
+        let source = MemorySource(vec![
+            fastdata(100, "a"),
+            fastdata(101, "b"),
+            fastdata(101, "c"),
+            fastdata(101, "d"),
+            fastdata(105, "e"),
+            fastdata(140, "f"),
+        ]);
+        let (sender, mut receiver) = mpsc::channel(100);
+        let is_running = AtomicBool::new(true);
+
+        let (mut from, to) = (100, 159);
+        let mut passes = vec![];
+        while from <= to {
+            let confirmed = fetch_sub_ranges(&source, "kv", split_range(from, to, 1000), 1, 2, &sender, &is_running)
+                .await
+                .unwrap()
+                .unwrap();
+            passes.push(confirmed);
+            from = confirmed + 1;
+        }
+        drop(sender);
+
+        // Block 101 is emitted whole even though the cap is hit inside it
+        assert_eq!(passes, vec![101, 140, 159]);
+        let mut receipts = vec![];
+        while let Some(update) = receiver.recv().await {
+            if let SuffixFetcherUpdate::FastData(d) = update {
+                receipts.push(d.receipt_id);
+            }
+        }
+        assert_eq!(receipts, vec!["a", "b", "c", "d", "e", "f"]);
+    }
+
+    #[test]
+    fn test_split_range() {
+        assert_eq!(split_range(10, 10, 1000), vec![(10, 10)]);
+        assert_eq!(split_range(0, 2499, 1000), vec![(0, 999), (1000, 1999), (2000, 2499)]);
+        assert!(split_range(5, 4, 1000).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_sub_ranges_emit_all_and_checkpoint_in_order() {
+        let source = MemorySource(vec![
+            fastdata(120, "c"),
+            fastdata(101, "a"),
+            fastdata(150, "d"),
+            fastdata(105, "b"),
+        ]);
+        let (sender, mut receiver) = mpsc::channel(100);
+        let is_running = AtomicBool::new(true);
+
+        let confirmed = fetch_sub_ranges(&source, "kv", vec![(100, 109), (110, 159)], 2, usize::MAX, &sender, &is_running)
+            .await
+            .unwrap();
+        drop(sender);
+
+        assert_eq!(confirmed, Some(159));
+        let mut updates = vec![];
+        while let Some(update) = receiver.recv().await {
+            updates.push(match update {
+                SuffixFetcherUpdate::FastData(d) => format!("data:{}", d.receipt_id),
+                SuffixFetcherUpdate::EndOfRange { block_height, .. } => format!("end:{block_height}"),
+                SuffixFetcherUpdate::Error { message, .. } => format!("error:{message}"),
+            });
+        }
+        assert_eq!(updates, vec!["data:a", "data:b", "end:109", "data:c", "data:d", "end:159"]);
+    }
+
+    /// Reads two entries and then fails on the first call. The retry
+    /// revisits one of them (twice) and misses the other, like a SCAN that
+    /// is not snapshot-consistent.
+    struct FlakySource {
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    impl FastDataSource for FlakySource {
+        async fn fetch_range(
+            &self,
+            _suffix: &str,
+            _from_block: BlockHeight,
+            _to_block: BlockHeight,
+            items: &mut Vec<FastData>,
+        ) -> Result<(), FetchError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                items.extend([fastdata(102, "c"), fastdata(100, "a")]);
+                return Err(FetchError::Transient(anyhow::anyhow!("connection reset")));
+            }
+            items.extend([fastdata(100, "a"), fastdata(101, "b"), fastdata(100, "a")]);
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retried_scan_emits_each_key_once() {
+        let source = FlakySource { calls: Default::default() };
+        let (sender, mut receiver) = mpsc::channel(100);
+        let is_running = AtomicBool::new(true);
+
+        let confirmed = fetch_sub_ranges(&source, "kv", vec![(100, 109)], 1, usize::MAX, &sender, &is_running)
+            .await
+            .unwrap();
+        drop(sender);
+
+        assert_eq!(confirmed, Some(109));
+        assert_eq!(source.calls.load(Ordering::SeqCst), 2);
+        let mut receipts = vec![];
+        while let Some(update) = receiver.recv().await {
+            if let SuffixFetcherUpdate::FastData(d) = update {
+                receipts.push(d.receipt_id);
+            }
+        }
+        assert_eq!(receipts, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_seen_keys_are_bounded() {
+        let mut seen = SeenKeys::new(2);
+        assert!(seen.insert(&fastdata(100, "a")));
+        assert!(!seen.insert(&fastdata(100, "a")));
+        assert!(seen.insert(&fastdata(100, "b")));
+        assert!(seen.insert(&FastData { action_index: 1, ..fastdata(100, "b") }));
+        assert_eq!(seen.keys.len(), 2);
+        // The oldest key was forgotten
+        assert!(seen.insert(&fastdata(100, "a")));
+    }
+
+    /// Always fails, counting attempts.
+    struct FailingSource {
+        transient: bool,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    impl FailingSource {
+        fn new(transient: bool) -> Self {
+            Self { transient, calls: Default::default() }
+        }
+    }
+
+    impl FastDataSource for FailingSource {
+        async fn fetch_range(
+            &self,
+            _suffix: &str,
+            _from_block: BlockHeight,
+            _to_block: BlockHeight,
+            _items: &mut Vec<FastData>,
+        ) -> Result<(), FetchError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.transient {
+                Err(FetchError::Transient(anyhow::anyhow!("connection refused")))
+            } else {
+                Err(FetchError::Permanent(anyhow::anyhow!("unexpected SCAN response")))
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transient_errors_are_retried() {
+        let source = FailingSource::new(true);
+        assert!(fetch_sub_range(&source, "kv", 100, 200).await.is_err());
+        assert_eq!(source.calls.load(Ordering::SeqCst), RETRY_DELAYS_SECS.len());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_permanent_errors_are_not_retried() {
+        let source = FailingSource::new(false);
+        let err = fetch_sub_range(&source, "kv", 100, 200).await.unwrap_err();
+        assert_eq!(source.calls.load(Ordering::SeqCst), 1);
+        assert!(format!("{err:#}").contains("unexpected SCAN response"));
+    }
+
+    #[test]
+    fn test_redis_error_classification() {
+        let io: redis::RedisError = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset").into();
+        assert!(FetchError::from(io).is_transient());
+
+        let parse: redis::RedisError = (redis::ErrorKind::TypeError, "Response was of incompatible type").into();
+        assert!(!FetchError::from(parse).is_transient());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unrecoverable_range_sends_error_before_stopping() {
+        let (sender, mut receiver) = mpsc::channel(100);
+        let is_running = AtomicBool::new(true);
+
+        let confirmed = process_range(&FailingSource::new(true), "kv", 100, 200, 1, &sender, &is_running).await;
+        drop(sender);
+
+        assert_eq!(confirmed, None);
+        assert!(!is_running.load(Ordering::SeqCst));
+        match receiver.recv().await {
+            Some(SuffixFetcherUpdate::Error { message, .. }) => {
+                assert!(message.contains("[100, 200]"), "{message}");
+                assert!(message.contains("connection refused"), "{message}");
+            }
+            other => panic!("expected Error update, got {other:?}"),
+        }
+        assert!(receiver.recv().await.is_none());
+    }
+
+    /// Answers SCAN with one scripted batch per call and MGET from `store`,
+    /// recording every command.
+    struct ScriptedScan {
+        batches: VecDeque<Vec<&'static str>>,
+        store: HashMap<String, String>,
+        commands: Vec<Vec<String>>,
+    }
+
+    impl redis::aio::ConnectionLike for ScriptedScan {
+        fn req_packed_command<'a>(&'a mut self, cmd: &'a redis::Cmd) -> redis::RedisFuture<'a, redis::Value> {
+            use redis::Value;
+            let args: Vec<String> = cmd
+                .args_iter()
+                .map(|arg| match arg {
+                    redis::Arg::Simple(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+                    redis::Arg::Cursor => "0".to_string(),
+                })
+                .collect();
+            let bulk = |s: &str| Value::BulkString(s.as_bytes().to_vec());
+            let reply = match args[0].as_str() {
+                "SCAN" => {
+                    let batch = self.batches.pop_front().unwrap_or_default();
+                    let cursor = if self.batches.is_empty() { "0" } else { "1" };
+                    Value::Array(vec![bulk(cursor), Value::Array(batch.into_iter().map(bulk).collect())])
+                }
+                "MGET" => Value::Array(
+                    args[1..]
+                        .iter()
+                        .map(|key| self.store.get(key).map_or(Value::Nil, |json| bulk(json)))
+                        .collect(),
+                ),
+                other => panic!("unexpected command {other}"),
+            };
+            self.commands.push(args);
+            Box::pin(async move { Ok(reply) })
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            _cmd: &'a redis::Pipeline,
+            _offset: usize,
+            _count: usize,
+        ) -> redis::RedisFuture<'a, Vec<redis::Value>> {
+            Box::pin(async {
+                Err(redis::RedisError::from((
+                    redis::ErrorKind::ClientError,
+                    "pipelines are not used",
+                )))
+            })
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_one_mget_per_scan_batch() {
+        let store: HashMap<String, String> = [
+            ("fastdata:mainnet:kv:100:a", serde_json::to_string(&fastdata(100, "a")).unwrap()),
+            ("fastdata:mainnet:kv:105:b", serde_json::to_string(&fastdata(105, "b")).unwrap()),
+            ("fastdata:mainnet:kv:300:c", serde_json::to_string(&fastdata(300, "c")).unwrap()),
+            ("fastdata:mainnet:kv:106:d", "not json".to_string()),
+            ("fastdata:mainnet:kv:150:e", serde_json::to_string(&fastdata(150, "e")).unwrap()),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+        let mut conn = ScriptedScan {
+            batches: VecDeque::from(vec![
+                vec![
+                    "fastdata:mainnet:kv:100:a",
+                    "fastdata:mainnet:kv:300:c",
+                    "fastdata:mainnet:kv:105:b",
+                    "fastdata:mainnet:kv:106:d",
+                    "fastdata:mainnet:kv:107:missing",
+                    "fastdata:mainnet:kv:bad",
+                ],
+                // Nothing in range: no MGET for this batch
+                vec!["fastdata:mainnet:kv:300:c"],
+                vec!["fastdata:mainnet:kv:150:e"],
+            ]),
+            store,
+            commands: vec![],
+        };
+
+        let mut items = vec![];
+        scan_fastdata(&mut conn, "fastdata:mainnet:kv:*", 100, 200, &mut items).await.unwrap();
+        let receipts: Vec<&str> = items.iter().map(|d| d.receipt_id.as_str()).collect();
+        assert_eq!(receipts, vec!["a", "b", "e"]);
+
+        let commands: Vec<&str> = conn.commands.iter().map(|args| args[0].as_str()).collect();
+        assert_eq!(commands, vec!["SCAN", "MGET", "SCAN", "SCAN", "MGET"]);
+        assert_eq!(
+            conn.commands[1][1..],
+            ["fastdata:mainnet:kv:100:a", "fastdata:mainnet:kv:105:b", "fastdata:mainnet:kv:106:d", "fastdata:mainnet:kv:107:missing"]
+        );
+        assert_eq!(conn.commands[4][1..], ["fastdata:mainnet:kv:150:e"]);
+    }
+
+    #[test]
+    fn test_validate_configs_rejects_shared_suffix_or_checkpoint() {
+        let suffixed = |suffix: &str, checkpoint_key: Option<&str>| SuffixFetcherConfig {
+            suffix: suffix.to_string(),
+            checkpoint_key: checkpoint_key.map(str::to_string),
+            ..config(None)
+        };
+        assert!(validate_configs(&[]).is_err());
+        assert!(validate_configs(&[suffixed("kv", None), suffixed("fastfs", None)]).is_ok());
+        assert!(validate_configs(&[suffixed("kv", None), suffixed("kv", Some("kv2"))]).is_err());
+        assert!(validate_configs(&[suffixed("kv", None), suffixed("fastfs", Some("kv"))]).is_err());
+        assert!(validate_configs(&[suffixed("kv", Some(""))]).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_multiple_suffixes_tag_updates_and_checkpoint_independently() {
+        let store = Arc::new(MemoryStore::default());
+        store.set_last_processed_block_height(UNIVERSAL_SUFFIX, 120).await.unwrap();
+        store.set_last_processed_block_height("kv", 109).await.unwrap();
+        let source = Arc::new(MemorySource(vec![
+            suffixed_fastdata("kv", 105, "old"),
+            suffixed_fastdata("kv", 115, "k1"),
+            suffixed_fastdata("fastfs", 50, "f1"),
+        ]));
+        let configs = ["kv", "fastfs"]
+            .into_iter()
+            .map(|suffix| SuffixFetcherConfig {
+                suffix: suffix.to_string(),
+                ..config(None)
+            })
+            .collect();
+        let (sender, mut receiver) = mpsc::channel(100);
+        let is_running = Arc::new(AtomicBool::new(true));
+        let fetcher = tokio::spawn(run_all(store.clone(), source, configs, sender, is_running.clone()));
+
+        // Route updates by suffix, checkpointing each one on EndOfRange
+        let mut received: HashMap<String, Vec<String>> = HashMap::new();
+        while store.0.lock().unwrap().get("kv") != Some(&120) || store.0.lock().unwrap().get("fastfs") != Some(&120) {
+            let update = receiver.recv().await.unwrap();
+            let suffix = update.suffix().to_string();
+            match update {
+                SuffixFetcherUpdate::FastData(d) => {
+                    assert_eq!(d.suffix, suffix);
+                    received.entry(suffix).or_default().push(d.receipt_id);
+                }
+                SuffixFetcherUpdate::EndOfRange { block_height, .. } => {
+                    store.set_last_processed_block_height(&suffix, block_height).await.unwrap();
+                }
+                SuffixFetcherUpdate::Error { message, .. } => panic!("{message}"),
+            }
+        }
+        is_running.store(false, Ordering::SeqCst);
+        fetcher.await.unwrap();
+
+        // kv resumed after its checkpoint; fastfs had none and started at 0
+        assert_eq!(received["kv"], vec!["k1"]);
+        assert_eq!(received["fastfs"], vec!["f1"]);
     }
 }
@@ -0,0 +1,67 @@
+//! Source of `Instant`s for time-based state (scan throttle, response cache
+//! TTL, indexer staleness).
+//!
+//! Handlers read the time from [`AppState::clock`](crate::AppState) instead of
+//! calling `Instant::now()` directly, so tests can swap in a [`MockClock`] and
+//! step past a window without sleeping. Timers (`tokio::time::interval`, e.g.
+//! the watch heartbeat) stay on tokio's clock, which tests pause instead.
+
+#[cfg(test)]
+use std::sync::Mutex;
+#[cfg(test)]
+use std::time::Duration;
+use std::time::Instant;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<Instant>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            now: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_moves_only_when_advanced() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.now() - start, Duration::from_secs(5));
+    }
+}
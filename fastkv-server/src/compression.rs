@@ -0,0 +1,226 @@
+//! Tuning around `middleware::Compress`.
+//!
+//! Compressing a 200-byte JSON error costs more CPU than it saves on the wire,
+//! so bodies smaller than `COMPRESSION_MIN_SIZE` bytes are sent as-is.
+//! `COMPRESSION_ALGORITHMS` (e.g. `gzip,br`) restricts and orders the encodings
+//! the server is willing to use; unset keeps the client's own preference.
+//!
+//! Two layers sit around `Compress`: [`negotiate`] outside it rewrites
+//! `Accept-Encoding`, and [`skip_small`] inside it marks small bodies with
+//! `Content-Encoding: identity`, which `Compress` leaves alone and
+//! [`negotiate`] strips again on the way out.
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use std::future::Future;
+use std::sync::Arc;
+
+pub const DEFAULT_COMPRESSION_MIN_SIZE: u64 = 1024;
+
+const SUPPORTED_ALGORITHMS: &[&str] = &["br", "gzip", "deflate", "zstd"];
+
+pub struct CompressionConfig {
+    pub min_size: u64,
+    /// Server preference order; empty means "whatever the client prefers".
+    pub algorithms: Vec<String>,
+}
+
+impl CompressionConfig {
+    pub fn from_env() -> Self {
+        let min_size = std::env::var("COMPRESSION_MIN_SIZE")
+            .ok()
+            .map(|s| s.parse().expect("Invalid COMPRESSION_MIN_SIZE"))
+            .unwrap_or(DEFAULT_COMPRESSION_MIN_SIZE);
+        let algorithms = std::env::var("COMPRESSION_ALGORITHMS")
+            .ok()
+            .map(|s| parse_algorithms(&s).expect("Invalid COMPRESSION_ALGORITHMS"))
+            .unwrap_or_default();
+        Self {
+            min_size,
+            algorithms,
+        }
+    }
+}
+
+/// Parse a comma-separated list of encodings, rejecting unknown names.
+fn parse_algorithms(s: &str) -> Result<Vec<String>, String> {
+    s.split(',')
+        .map(|a| a.trim().to_ascii_lowercase())
+        .filter(|a| !a.is_empty())
+        .map(|a| {
+            if SUPPORTED_ALGORITHMS.contains(&a.as_str()) {
+                Ok(a)
+            } else {
+                Err(format!("unsupported encoding '{a}'"))
+            }
+        })
+        .collect()
+}
+
+/// Rewrite a client's `Accept-Encoding` so only `preferred` encodings remain,
+/// ranked in server order. Falls back to `identity` when nothing overlaps.
+pub fn preferred_accept_encoding(client: &str, preferred: &[String]) -> String {
+    let mut accepted = Vec::new();
+    let mut wildcard = false;
+    for item in client.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name == "*" {
+            wildcard = q > 0.0;
+        } else {
+            accepted.push((name, q));
+        }
+    }
+
+    let ranked: Vec<String> = preferred
+        .iter()
+        .filter(|alg| match accepted.iter().find(|(name, _)| name == *alg) {
+            Some((_, q)) => *q > 0.0,
+            None => wildcard,
+        })
+        .enumerate()
+        .map(|(i, alg)| format!("{alg};q={:.1}", 1.0 - 0.1 * i.min(9) as f32))
+        .collect();
+    if ranked.is_empty() {
+        "identity".to_string()
+    } else {
+        ranked.join(", ")
+    }
+}
+
+/// Outer layer: apply `COMPRESSION_ALGORITHMS` to the request and drop the
+/// `identity` marker left by [`skip_small`].
+pub fn negotiate<S, B>(
+    config: Arc<CompressionConfig>,
+    mut req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    if !config.algorithms.is_empty() {
+        let rewritten = req
+            .headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| preferred_accept_encoding(v, &config.algorithms))
+            .and_then(|v| HeaderValue::from_str(&v).ok());
+        if let Some(value) = rewritten {
+            req.headers_mut().insert(header::ACCEPT_ENCODING, value);
+        }
+    }
+
+    let fut = srv.call(req);
+    async move {
+        let mut res = fut.await?;
+        if res.headers().get(header::CONTENT_ENCODING)
+            == Some(&HeaderValue::from_static("identity"))
+        {
+            res.headers_mut().remove(header::CONTENT_ENCODING);
+        }
+        Ok(res)
+    }
+}
+
+/// Inner layer: keep `Compress` off bodies smaller than `min_size` bytes.
+/// Streams (unknown size) are always eligible for compression.
+pub fn skip_small<S, B>(
+    min_size: u64,
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let fut = srv.call(req);
+    async move {
+        let mut res = fut.await?;
+        let small = matches!(res.response().body().size(), BodySize::Sized(n) if n < min_size);
+        if small && !res.headers().contains_key(header::CONTENT_ENCODING) {
+            res.headers_mut().insert(
+                header::CONTENT_ENCODING,
+                HeaderValue::from_static("identity"),
+            );
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn algs(s: &str) -> Vec<String> {
+        parse_algorithms(s).unwrap()
+    }
+
+    #[test]
+    fn test_preferred_accept_encoding() {
+        let preferred = algs("gzip, br");
+        assert_eq!(
+            preferred_accept_encoding("br;q=1.0, gzip;q=0.8, zstd", &preferred),
+            "gzip;q=1.0, br;q=0.9"
+        );
+        assert_eq!(
+            preferred_accept_encoding("zstd, gzip;q=0", &preferred),
+            "identity"
+        );
+        assert_eq!(
+            preferred_accept_encoding("*", &preferred),
+            "gzip;q=1.0, br;q=0.9"
+        );
+        assert_eq!(
+            preferred_accept_encoding("*, br;q=0", &preferred),
+            "gzip;q=1.0"
+        );
+        assert!(parse_algorithms("gzip,lz4").is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_small_bodies_skip_compression() {
+        use actix_web::{middleware, test, web, App, HttpResponse};
+
+        let config = Arc::new(CompressionConfig {
+            min_size: 1024,
+            algorithms: algs("gzip"),
+        });
+        let min_size = config.min_size;
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| skip_small(min_size, req, srv))
+                .wrap(middleware::Compress::default())
+                .wrap_fn(move |req, srv| negotiate(config.clone(), req, srv))
+                .route(
+                    "/small",
+                    web::get().to(|| async {
+                        HttpResponse::Ok().json(serde_json::json!({ "ok": true }))
+                    }),
+                )
+                .route(
+                    "/large",
+                    web::get().to(|| async { HttpResponse::Ok().json(vec!["value"; 1000]) }),
+                ),
+        )
+        .await;
+
+        for (uri, expected) in [("/small", None), ("/large", Some("gzip"))] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .insert_header((header::ACCEPT_ENCODING, "br, gzip"))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert!(res.status().is_success());
+            let encoding = res
+                .headers()
+                .get(header::CONTENT_ENCODING)
+                .map(|v| v.to_str().unwrap().to_string());
+            assert_eq!(encoding.as_deref(), expected, "{uri}");
+        }
+    }
+}
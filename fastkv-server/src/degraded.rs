@@ -0,0 +1,145 @@
+//! Optional database features for a partially provisioned keyspace.
+//!
+//! The core KV tables are required, but the tables behind edges, timeline,
+//! account/contract listings and writer lookups are provisioned separately.
+//! When one of their statements fails to prepare, the backend starts without
+//! that feature instead of exiting: [`Degraded`] records what was disabled,
+//! and [`require`] turns a missing statement into [`FeatureUnavailable`],
+//! which endpoints report as a 503 `FEATURE_UNAVAILABLE`.
+
+// Only the ScyllaDB backend prepares statements up front.
+#![cfg_attr(not(feature = "scylla-backend"), allow(dead_code))]
+
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DbFeature {
+    Edges,
+    Timeline,
+    Accounts,
+    Writers,
+}
+
+impl fmt::Display for DbFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            DbFeature::Edges => "edges",
+            DbFeature::Timeline => "timeline",
+            DbFeature::Accounts => "accounts",
+            DbFeature::Writers => "writers",
+        })
+    }
+}
+
+/// A request needed a feature that was disabled at startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeatureUnavailable(pub DbFeature);
+
+impl fmt::Display for FeatureUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is not available on this server (its tables are not provisioned)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for FeatureUnavailable {}
+
+/// Collects statements that failed to prepare for optional features.
+#[derive(Debug, Default)]
+pub struct Degraded {
+    failures: Vec<(DbFeature, String)>,
+}
+
+impl Degraded {
+    /// Keep a prepared statement for `feature`, or record why it failed.
+    pub fn optional<T>(
+        &mut self,
+        feature: DbFeature,
+        name: &str,
+        prepared: anyhow::Result<T>,
+    ) -> Option<T> {
+        match prepared {
+            Ok(statement) => Some(statement),
+            Err(e) => {
+                self.failures.push((feature, format!("{name}: {e}")));
+                None
+            }
+        }
+    }
+
+    /// Log each disabled feature and return the set.
+    pub fn finish(self) -> HashSet<DbFeature> {
+        let mut disabled = HashSet::new();
+        for (feature, reason) in self.failures {
+            tracing::warn!(target: "fastkv-server", %feature, %reason, "Feature disabled: statement failed to prepare");
+            disabled.insert(feature);
+        }
+        disabled
+    }
+}
+
+/// The statement for `feature`, or [`FeatureUnavailable`] when it was disabled.
+pub fn require<T>(statement: &Option<T>, feature: DbFeature) -> anyhow::Result<&T> {
+    statement
+        .as_ref()
+        .ok_or_else(|| FeatureUnavailable(feature).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ApiError;
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+
+    /// Stand-in for preparing a statement against a keyspace without `kv_edges`.
+    fn prepare(cql: &str) -> anyhow::Result<String> {
+        if cql.contains("kv_edges") {
+            anyhow::bail!("unconfigured table kv_edges");
+        }
+        Ok(cql.to_string())
+    }
+
+    #[test]
+    fn test_missing_edges_table_disables_only_edges() {
+        let mut degraded = Degraded::default();
+        let get_kv = prepare("SELECT value FROM s_kv_last WHERE key = ?").unwrap();
+        let edges_list = degraded.optional(
+            DbFeature::Edges,
+            "edges_list",
+            prepare("SELECT source FROM kv_edges"),
+        );
+        let edges_count = degraded.optional(
+            DbFeature::Edges,
+            "edges_count",
+            prepare("SELECT COUNT(*) FROM kv_edges"),
+        );
+        let timeline = degraded.optional(
+            DbFeature::Timeline,
+            "timeline_asc",
+            prepare("SELECT key FROM s_kv_by_block"),
+        );
+
+        assert_eq!(degraded.finish(), HashSet::from([DbFeature::Edges]));
+        assert!(get_kv.contains("s_kv_last"));
+        assert!(require(&timeline, DbFeature::Timeline).is_ok());
+
+        for statement in [&edges_list, &edges_count] {
+            let err = require(statement, DbFeature::Edges).unwrap_err();
+            assert_eq!(
+                err.downcast_ref(),
+                Some(&FeatureUnavailable(DbFeature::Edges))
+            );
+            let api_error = ApiError::from(err);
+            assert!(matches!(api_error, ApiError::FeatureUnavailable(_)));
+            assert_eq!(
+                api_error.error_response().status(),
+                StatusCode::SERVICE_UNAVAILABLE
+            );
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! In-process Redis stand-in for handler tests.
+//!
+//! Speaks enough RESP for the commands the tested handlers and the indexer's
+//! `add_kv` issue; anything else gets an error reply so a test fails loudly
+//! instead of reading an empty value.
+//! [`FakeRedis::on_command`] runs a hook before each command is answered, e.g.
+//! to change server state mid-request.
+
+use crate::redis_db::RedisDb;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Default)]
+struct State {
+    strings: HashMap<String, String>,
+    sets: HashMap<String, BTreeSet<String>>,
+    /// Sorted sets as member -> score; nothing reads them back yet.
+    zsets: HashMap<String, HashMap<String, String>>,
+    commands: Vec<String>,
+}
+
+type Hook = Arc<dyn Fn(&[String]) + Send + Sync>;
+
+#[derive(Clone)]
+pub struct FakeRedis {
+    state: Arc<Mutex<State>>,
+    hook: Arc<Mutex<Option<Hook>>>,
+    url: String,
+}
+
+impl FakeRedis {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        let redis = Self {
+            state: Arc::default(),
+            hook: Arc::default(),
+            url,
+        };
+        let server = redis.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(server.clone().serve(stream));
+            }
+        });
+        redis
+    }
+
+    pub async fn db(&self) -> RedisDb {
+        RedisDb::connect(&self.url, "mainnet".to_string())
+            .await
+            .unwrap()
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn set(&self, key: &str, value: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .strings
+            .insert(key.to_string(), value.to_string());
+    }
+
+    /// Names of the commands answered so far, upper-cased.
+    pub fn commands(&self) -> Vec<String> {
+        self.state.lock().unwrap().commands.clone()
+    }
+
+    pub fn on_command(&self, hook: impl Fn(&[String]) + Send + Sync + 'static) {
+        *self.hook.lock().unwrap() = Some(Arc::new(hook));
+    }
+
+    async fn serve(self, stream: TcpStream) {
+        let mut stream = BufReader::new(stream);
+        while let Some(args) = read_command(&mut stream).await {
+            let hook = self.hook.lock().unwrap().clone();
+            if let Some(hook) = hook {
+                hook(&args);
+            }
+            let reply = self.reply(&args);
+            if stream.get_mut().write_all(&reply).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn reply(&self, args: &[String]) -> Vec<u8> {
+        let mut state = self.state.lock().unwrap();
+        let name = args[0].to_ascii_uppercase();
+        state.commands.push(name.clone());
+        match (name.as_str(), &args[1..]) {
+            ("PING", _) => b"+PONG\r\n".to_vec(),
+            ("CLIENT" | "SELECT", _) => b"+OK\r\n".to_vec(),
+            ("GET", [key]) => bulk(state.strings.get(key).map(String::as_str)),
+            ("SET", [key, value]) => {
+                state.strings.insert(key.clone(), value.clone());
+                b"+OK\r\n".to_vec()
+            }
+            ("SADD", [key, members @ ..]) => {
+                let set = state.sets.entry(key.clone()).or_default();
+                let added = members.iter().filter(|m| set.insert(m.to_string())).count();
+                format!(":{added}\r\n").into_bytes()
+            }
+            ("SREM", [key, members @ ..]) => {
+                let set = state.sets.entry(key.clone()).or_default();
+                let removed = members.iter().filter(|m| set.remove(*m)).count();
+                format!(":{removed}\r\n").into_bytes()
+            }
+            ("SMEMBERS", [key]) => {
+                let members: Vec<String> =
+                    state.sets.get(key).into_iter().flatten().cloned().collect();
+                let mut out = format!("*{}\r\n", members.len()).into_bytes();
+                for member in &members {
+                    out.extend(bulk(Some(member)));
+                }
+                out
+            }
+            ("ZADD", [key, score, member]) => {
+                let added = state
+                    .zsets
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(member.clone(), score.clone());
+                format!(":{}\r\n", u8::from(added.is_none())).into_bytes()
+            }
+            ("PUBLISH", [_, _]) => b":0\r\n".to_vec(),
+            _ => format!("-ERR fake redis does not support {name}\r\n").into_bytes(),
+        }
+    }
+}
+
+async fn read_line(stream: &mut BufReader<TcpStream>) -> Option<String> {
+    let mut line = String::new();
+    (stream.read_line(&mut line).await.ok()? > 0).then(|| line.trim_end().to_string())
+}
+
+/// One RESP array of bulk strings.
+async fn read_command(stream: &mut BufReader<TcpStream>) -> Option<Vec<String>> {
+    let count: usize = read_line(stream).await?.strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len: usize = read_line(stream).await?.strip_prefix('$')?.parse().ok()?;
+        let mut buf = vec![0; len + 2];
+        stream.read_exact(&mut buf).await.ok()?;
+        buf.truncate(len);
+        args.push(String::from_utf8(buf).ok()?);
+    }
+    (!args.is_empty()).then_some(args)
+}
+
+fn bulk(value: Option<&str>) -> Vec<u8> {
+    match value {
+        Some(value) => format!("${}\r\n{value}\r\n", value.len()).into_bytes(),
+        None => b"$-1\r\n".to_vec(),
+    }
+}
@@ -1,15 +1,45 @@
 use crate::models::*;
 use crate::redis_db::RedisDb;
-use crate::tree::build_tree;
+use crate::request_timeout;
+use crate::tree::{build_key_tree, build_tree_with_stats, infer_schema, json_diff};
 use crate::AppState;
+use actix_web::body::{to_bytes, BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::from_fn;
 use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 
+use std::collections::hash_map::RandomState;
 use std::collections::HashSet;
-use std::sync::Arc;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::sync::{Arc, LazyLock};
 use std::time::Duration;
 
 const THROTTLE_EXPIRY: Duration = Duration::from_secs(60);
 const MAX_THROTTLE_ENTRIES: usize = 50_000;
+/// Default for `TRUSTED_PROXY_HOPS`: Railway's proxy only.
+const DEFAULT_TRUSTED_PROXY_HOPS: usize = 1;
+
+/// Contract used by `/v1/kv/get` and `/v1/kv/query` when `contractId` is omitted.
+static DEFAULT_CONTRACT: LazyLock<Option<String>> = LazyLock::new(|| {
+    std::env::var("DEFAULT_CONTRACT")
+        .ok()
+        .filter(|c| !c.is_empty())
+});
+
+static DROP_THRESHOLD: LazyLock<DropThreshold> = LazyLock::new(DropThreshold::from_env);
+
+/// Fill an omitted (empty) `contractId` from the configured default. With no
+/// default it stays empty and the usual validation rejects it.
+fn apply_default_contract(contract_id: &mut String, default: Option<&str>) {
+    if contract_id.is_empty() {
+        if let Some(default) = default {
+            *contract_id = default.to_string();
+        }
+    }
+}
 
 pub(crate) async fn require_db(state: &AppState) -> Result<Arc<RedisDb>, ApiError> {
     state
@@ -20,6 +50,261 @@ pub(crate) async fn require_db(state: &AppState) -> Result<Arc<RedisDb>, ApiErro
         .ok_or(ApiError::DatabaseUnavailable)
 }
 
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Selects the caller's `WatchTiers` entry on `/v1/kv/watch`.
+pub(crate) const API_KEY_HEADER: &str = "x-api-key";
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+pub(crate) const API_VERSION_HEADER: &str = "x-api-version";
+/// Version of the response shapes, sent as `X-API-Version` on every response.
+/// Bump it on any breaking change to a response body or header.
+pub const API_VERSION: &str = "2";
+
+/// Tag a response with [`API_VERSION`].
+pub(crate) fn apply_api_version<B>(res: &mut ServiceResponse<B>) {
+    res.headers_mut().insert(
+        header::HeaderName::from_static(API_VERSION_HEADER),
+        header::HeaderValue::from_static(API_VERSION),
+    );
+}
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Use the caller's `X-Request-ID` when it is a short printable token,
+/// otherwise generate a UUID.
+pub(crate) fn request_id_for(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Request id of the request currently being handled, if inside [`with_request_id`].
+pub(crate) fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Run the rest of the service chain with `request_id` in scope (so error
+/// bodies can include it) and echo it back in the `X-Request-ID` header.
+pub(crate) async fn with_request_id<B>(
+    request_id: String,
+    fut: impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>,
+) -> Result<ServiceResponse<B>, actix_web::Error> {
+    let mut res = REQUEST_ID.scope(request_id.clone(), fut).await?;
+    if let Ok(value) = header::HeaderValue::from_str(&request_id) {
+        res.headers_mut()
+            .insert(header::HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    Ok(res)
+}
+
+/// Default for `INDEXER_STALE_SECS`.
+const DEFAULT_INDEXER_STALE_SECS: u64 = 60;
+
+/// Window after which an unchanged indexer block height counts as stale
+/// (`INDEXER_STALE_SECS`, default 60).
+pub(crate) fn indexer_stale_window() -> Duration {
+    let secs = std::env::var("INDEXER_STALE_SECS")
+        .ok()
+        .map(|s| s.parse().expect("Invalid INDEXER_STALE_SECS"))
+        .unwrap_or(DEFAULT_INDEXER_STALE_SECS);
+    Duration::from_secs(secs)
+}
+
+/// Max concurrent `/v1/kv/watch` connections (`MAX_WATCHES`, default 100).
+pub(crate) fn max_watches() -> usize {
+    std::env::var("MAX_WATCHES")
+        .ok()
+        .map(|s| s.parse().expect("Invalid MAX_WATCHES"))
+        .unwrap_or(DEFAULT_MAX_WATCHES)
+}
+
+/// Proxies in front of the server that append to `X-Forwarded-For`
+/// (`TRUSTED_PROXY_HOPS`, default 1 for Railway alone; 2 with a CDN in front).
+pub(crate) fn trusted_proxy_hops() -> usize {
+    std::env::var("TRUSTED_PROXY_HOPS")
+        .ok()
+        .map(|s| s.parse().expect("Invalid TRUSTED_PROXY_HOPS"))
+        .unwrap_or(DEFAULT_TRUSTED_PROXY_HOPS)
+}
+
+/// Tracks when the polled indexer block height last advanced, for the
+/// `X-Indexer-Stale` header.
+pub(crate) struct StalenessTracker {
+    window: Duration,
+    last_height: u64,
+    last_advance: std::time::Instant,
+}
+
+impl StalenessTracker {
+    pub(crate) fn new(window: Duration, now: std::time::Instant) -> Self {
+        Self {
+            window,
+            last_height: 0,
+            last_advance: now,
+        }
+    }
+
+    /// Record the latest polled height and return whether the indexer is stale.
+    /// A height of 0 (not known yet) is never stale.
+    pub(crate) fn observe(&mut self, height: u64, now: std::time::Instant) -> bool {
+        if height != self.last_height {
+            self.last_height = height;
+            self.last_advance = now;
+        }
+        height > 0 && now.duration_since(self.last_advance) >= self.window
+    }
+}
+
+/// True when the client asked for pretty-printed JSON via `pretty=1`/`pretty=true`
+/// or an `Accept` media type carrying a `+pretty` marker (e.g. `application/json+pretty`).
+pub(crate) fn wants_pretty(req: &ServiceRequest) -> bool {
+    let by_query = req
+        .query_string()
+        .split('&')
+        .any(|pair| pair == "pretty=1" || pair == "pretty=true");
+    let by_accept = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("+pretty"));
+    by_query || by_accept
+}
+
+/// Re-serialize a compact JSON body with indentation. `None` if it isn't valid JSON.
+fn prettify_json(body: &[u8]) -> Option<Vec<u8>> {
+    let value: serde_json::Value = serde_json::from_slice(body).ok()?;
+    serde_json::to_vec_pretty(&value).ok()
+}
+
+/// Response step of the pretty-print middleware. Only `application/json` bodies
+/// are rewritten, so SSE streams and static files pass through untouched.
+pub(crate) async fn prettify_response<B>(
+    res: ServiceResponse<B>,
+    pretty: bool,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error>
+where
+    B: MessageBody + 'static,
+{
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !pretty || !is_json {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let bytes = to_bytes(body)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.into().to_string()))?;
+    let body = prettify_json(&bytes).unwrap_or_else(|| bytes.to_vec());
+    Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(body))))
+}
+
+/// Content types of streamed responses, which manage their own caching.
+const STREAMING_CONTENT_TYPES: &[&str] = &["text/event-stream", "application/x-ndjson"];
+
+/// Default `Cache-Control` for a successful GET, or `None` to leave it unset.
+/// Streaming responses are left alone so they never pick up a `max-age`.
+fn default_cache_control(path: &str, content_type: Option<&str>) -> Option<&'static str> {
+    let is_stream =
+        content_type.is_some_and(|ct| STREAMING_CONTENT_TYPES.iter().any(|s| ct.starts_with(s)));
+    if is_stream {
+        None
+    } else if path == "/health" || path == "/v1/status" || path == "/v1/kv/watch" {
+        Some("no-cache")
+    } else if path.starts_with("/v1/") {
+        Some("public, max-age=5")
+    } else {
+        None
+    }
+}
+
+/// Caching headers for API responses. Handlers that set their own Cache-Control
+/// take precedence. `/v1/` responses also get `Vary: Accept`, since the body shape
+/// depends on it (`+pretty`); `fields`/`value_format` variants differ by query
+/// string, which is part of the cache key for any conforming cache.
+pub(crate) fn apply_cache_headers<B>(
+    res: &mut ServiceResponse<B>,
+    method: &actix_web::http::Method,
+    path: &str,
+) {
+    if *method != actix_web::http::Method::GET || !res.status().is_success() {
+        return;
+    }
+    if !res.headers().contains_key(header::CACHE_CONTROL) {
+        let content_type = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok());
+        if let Some(cc) = default_cache_control(path, content_type) {
+            res.headers_mut()
+                .insert(header::CACHE_CONTROL, header::HeaderValue::from_static(cc));
+        }
+    }
+    if path.starts_with("/v1/") {
+        res.headers_mut()
+            .append(header::VARY, header::HeaderValue::from_static("Accept"));
+    }
+}
+
+/// Middleware body adding the headers every response carries: indexer block
+/// and staleness, cache policy, [`API_VERSION`] and security headers. JSON is
+/// pretty-printed last when the request asked for it.
+pub(crate) fn apply_response_headers<S, B>(
+    indexer_block: u64,
+    indexer_stale: bool,
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<BoxBody>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody + 'static,
+{
+    let path = req.path().to_string();
+    let method = req.method().clone();
+    let pretty = wants_pretty(&req);
+    let fut = srv.call(req);
+    async move {
+        let mut res = fut.await?;
+        if indexer_block > 0 {
+            res.headers_mut().insert(
+                header::HeaderName::from_static("x-indexer-block"),
+                header::HeaderValue::from(indexer_block),
+            );
+        }
+        if indexer_stale {
+            res.headers_mut().insert(
+                header::HeaderName::from_static("x-indexer-stale"),
+                header::HeaderValue::from_static("true"),
+            );
+        }
+        apply_cache_headers(&mut res, &method, &path);
+        apply_api_version(&mut res);
+        // Security headers on all responses.
+        res.headers_mut().insert(
+            header::HeaderName::from_static("x-content-type-options"),
+            header::HeaderValue::from_static("nosniff"),
+        );
+        res.headers_mut().insert(
+            header::HeaderName::from_static("x-frame-options"),
+            header::HeaderValue::from_static("DENY"),
+        );
+        prettify_response(res, pretty).await
+    }
+}
+
 /// Attempt to JSON-decode the `"value"` field in a serialized entry.
 /// If the value is a JSON string, it is parsed into the decoded JSON type
 /// (e.g., `"\"Alice\""` becomes `"Alice"`, `"42"` becomes `42`).
@@ -37,23 +322,100 @@ fn decode_value_in_json(json: &mut serde_json::Value) {
     }
 }
 
+/// Replace a string `"value"` with the base64 of its UTF-8 bytes and mark it
+/// with `valueEncoding: "base64"`. Values are stored as UTF-8 strings, so this
+/// spares clients JSON string escapes; it can't recover non-UTF-8 input.
+fn base64_value_in_json(json: &mut serde_json::Value) {
+    let Some(map) = json.as_object_mut() else {
+        return;
+    };
+    let Some(encoded) = map
+        .get("value")
+        .and_then(|v| v.as_str())
+        .map(|raw| BASE64.encode(raw))
+    else {
+        return;
+    };
+    map.insert("value".to_string(), serde_json::json!(encoded));
+    map.insert("valueEncoding".to_string(), serde_json::json!("base64"));
+}
+
+/// Render a single entry's `"value"` per `value_format` (`Raw` leaves it untouched).
+fn format_value_in_json(json: &mut serde_json::Value, format: ValueFormat) {
+    match format {
+        ValueFormat::Raw => {}
+        ValueFormat::Json => decode_value_in_json(json),
+        ValueFormat::Base64 => base64_value_in_json(json),
+    }
+}
+
+/// Apply `max_value_bytes`: cut an over-long `value` on a char boundary and
+/// mark it with `valueTruncated` and the original `valueSize` in bytes.
+/// A truncated value is no longer valid JSON, so `value_format=json` leaves it
+/// as a string.
+fn truncate_value_in_json(json: &mut serde_json::Value, max_bytes: usize) {
+    let Some(map) = json.as_object_mut() else {
+        return;
+    };
+    let Some(value) = map.get("value").and_then(|v| v.as_str()) else {
+        return;
+    };
+    if value.len() <= max_bytes {
+        return;
+    }
+    let size = value.len();
+    let truncated = truncate_value(value, max_bytes).to_string();
+    map.insert("value".to_string(), serde_json::json!(truncated));
+    map.insert("valueTruncated".to_string(), serde_json::json!(true));
+    map.insert("valueSize".to_string(), serde_json::json!(size));
+}
+
+fn entries_to_json(
+    entries: Vec<KvEntry>,
+    fields: &Option<HashSet<String>>,
+    format: ValueFormat,
+    max_value_bytes: Option<usize>,
+) -> Vec<serde_json::Value> {
+    entries
+        .into_iter()
+        .map(|e| {
+            let mut json = e.to_json_with_fields(fields);
+            if let Some(max_bytes) = max_value_bytes {
+                truncate_value_in_json(&mut json, max_bytes);
+            }
+            format_value_in_json(&mut json, format);
+            json
+        })
+        .collect()
+}
+
+/// Apply `with_rank`: number entries by position in the page (`pageIndex`)
+/// and estimate the global position as `first_rank + pageIndex`
+/// (`approxRank`), where `first_rank` is the sorted position of the page's
+/// first key when it was scanned. Rows dropped as unreadable shift the
+/// estimate for the entries after them.
+fn assign_ranks(items: &mut [serde_json::Value], first_rank: usize) {
+    for (index, item) in items.iter_mut().enumerate() {
+        let Some(map) = item.as_object_mut() else {
+            continue;
+        };
+        map.insert("pageIndex".to_string(), serde_json::json!(index));
+        map.insert(
+            "approxRank".to_string(),
+            serde_json::json!(first_rank + index),
+        );
+    }
+}
+
 fn respond_paginated(
     entries: Vec<KvEntry>,
     meta: PaginationMeta,
     fields: &Option<HashSet<String>>,
-    decode: bool,
+    format: ValueFormat,
+    max_value_bytes: Option<usize>,
 ) -> HttpResponse {
-    if fields.is_some() || decode {
-        let filtered: Vec<_> = entries
-            .into_iter()
-            .map(|e| {
-                let mut json = e.to_json_with_fields(fields);
-                if decode {
-                    decode_value_in_json(&mut json);
-                }
-                json
-            })
-            .collect();
+    if fields.is_some() || format != ValueFormat::Raw || max_value_bytes.is_some() {
+        let filtered = entries_to_json(entries, fields, format, max_value_bytes);
         HttpResponse::Ok().json(serde_json::json!({ "data": filtered, "meta": meta }))
     } else {
         HttpResponse::Ok().json(PaginatedResponse {
@@ -63,6 +425,40 @@ fn respond_paginated(
     }
 }
 
+/// Collects parameter errors so an endpoint can report all of them at once
+/// instead of stopping at the first.
+#[derive(Default)]
+pub(crate) struct ParamErrors(Vec<FieldError>);
+
+impl ParamErrors {
+    /// Record a validation failure, returning the value on success.
+    pub(crate) fn check<T>(&mut self, result: Result<T, ApiError>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(ApiError::InvalidParameter(msg)) => {
+                self.0.push(FieldError::from_message(&msg));
+                None
+            }
+            Err(ApiError::InvalidParameters(errors)) => {
+                self.0.extend(errors);
+                None
+            }
+            Err(e) => {
+                self.0.push(FieldError::from_message(&e.to_string()));
+                None
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Result<(), ApiError> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::InvalidParameters(self.0))
+        }
+    }
+}
+
 pub(crate) fn validate_account_id(value: &str, name: &str) -> Result<(), ApiError> {
     if value.is_empty() {
         return Err(ApiError::InvalidParameter(format!(
@@ -144,6 +540,44 @@ fn validate_block_range(from_block: Option<i64>, to_block: Option<i64>) -> Resul
     Ok(())
 }
 
+/// `from_account`/`to_account` bound a lexicographic range, so they may be
+/// bare prefixes like `"m"` rather than full account ids.
+fn validate_account_range(from: Option<&str>, to: Option<&str>) -> Result<(), ApiError> {
+    if let Some(from) = from {
+        validate_key(from, "from_account", MAX_ACCOUNT_ID_LENGTH)?;
+    }
+    if let Some(to) = to {
+        validate_key(to, "to_account", MAX_ACCOUNT_ID_LENGTH)?;
+    }
+    if let (Some(from), Some(to)) = (from, to) {
+        if from >= to {
+            return Err(ApiError::InvalidParameter(
+                "from_account: must be < to_account".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn validate_max_value_bytes(max_value_bytes: Option<usize>) -> Result<(), ApiError> {
+    if max_value_bytes == Some(0) {
+        return Err(ApiError::InvalidParameter(
+            "max_value_bytes: must be at least 1".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_max_count(max_count: Option<usize>) -> Result<usize, ApiError> {
+    let max_count = max_count.unwrap_or(DEFAULT_MAX_COUNT);
+    if max_count == 0 || max_count > MAX_COUNT_ONLY {
+        return Err(ApiError::InvalidParameter(format!(
+            "max_count: must be between 1 and {MAX_COUNT_ONLY}"
+        )));
+    }
+    Ok(max_count)
+}
+
 fn validate_prefix(prefix: &Option<String>) -> Result<(), ApiError> {
     if let Some(ref p) = prefix {
         if p.is_empty() {
@@ -160,16 +594,28 @@ fn validate_prefix(prefix: &Option<String>) -> Result<(), ApiError> {
     Ok(())
 }
 
-/// Extract client IP from X-Forwarded-For (rightmost entry = added by Railway's proxy).
-/// Correct for a single trusted proxy hop. If a CDN is added in front, this would
-/// need to skip additional hops from the right.
-fn extract_client_ip(req: &HttpRequest) -> String {
+/// The client address in an `X-Forwarded-For` value behind `hops` trusted
+/// proxies: each appends the address it received from, so the client is the
+/// `hops`-th entry from the right. Entries further left are client-supplied
+/// and ignored. `None` with 0 hops or when the header is too short.
+fn client_ip_from_forwarded_for(header: &str, hops: usize) -> Option<&str> {
+    let entry = header.rsplit(',').nth(hops.checked_sub(1)?)?.trim();
+    (!entry.is_empty() && entry != "unknown").then_some(entry)
+}
+
+/// Extract client IP from X-Forwarded-For, trusting `app_state.trusted_proxy_hops`
+/// proxies. With 0 hops the header is ignored and the peer address is used.
+pub(crate) fn extract_client_ip(req: &HttpRequest, app_state: &AppState) -> String {
+    let hops = app_state.trusted_proxy_hops;
+    if hops == 0 {
+        return req
+            .peer_addr()
+            .map_or_else(|| "unknown".to_string(), |a| a.ip().to_string());
+    }
     req.headers()
         .get("X-Forwarded-For")
         .and_then(|v| v.to_str().ok())
-        .and_then(|s| s.rsplit(',').next())
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty() && *s != "unknown")
+        .and_then(|s| client_ip_from_forwarded_for(s, hops))
         .map(|s| s.to_string())
         .or_else(|| {
             req.connection_info()
@@ -190,7 +636,7 @@ fn check_scan_throttle(app_state: &AppState, ip: &str) -> Result<(), ApiError> {
         .scan_throttle
         .lock()
         .unwrap_or_else(|e| e.into_inner());
-    let now = std::time::Instant::now();
+    let now = app_state.clock.now();
     let cutoff = now - THROTTLE_EXPIRY;
     throttle.retain(|_, ts| *ts > cutoff);
     if let Some(last) = throttle.get(ip) {
@@ -219,7 +665,7 @@ fn check_scan_throttle(app_state: &AppState, ip: &str) -> Result<(), ApiError> {
     ),
     tag = "health"
 )]
-#[get("/health")]
+#[get("/health", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn health_check(app_state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
     let db = app_state.db.read().await.clone();
     match db.as_ref() {
@@ -255,20 +701,31 @@ pub async fn health_check(app_state: web::Data<AppState>) -> Result<HttpResponse
     ),
     tag = "kv"
 )]
-#[get("/v1/kv/get")]
+#[get("/v1/kv/get", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn get_kv_handler(
     query: web::Query<GetParams>,
     app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, ApiError> {
+    let mut query = query.into_inner();
+    apply_default_contract(&mut query.current_account_id, DEFAULT_CONTRACT.as_deref());
     validate_account_id(&query.predecessor_id, "accountId")?;
     validate_account_id(&query.current_account_id, "contractId")?;
     validate_key(&query.key, "key", MAX_KEY_LENGTH)?;
+    // An empty pointer is valid and addresses the whole document (RFC 6901).
+    if let Some(ref pointer) = query.pointer {
+        if pointer.len() > MAX_KEY_LENGTH {
+            return Err(ApiError::InvalidParameter(format!(
+                "pointer: cannot exceed {MAX_KEY_LENGTH} characters"
+            )));
+        }
+    }
 
     tracing::info!(
         target: PROJECT_ID,
         accountId = %query.predecessor_id,
         contractId = %query.current_account_id,
         key = %query.key,
+        pointer = ?query.pointer,
         "GET /v1/kv/get"
     );
 
@@ -277,24 +734,126 @@ pub async fn get_kv_handler(
         .get_kv(&query.predecessor_id, &query.current_account_id, &query.key)
         .await?;
 
+    if let Some(ref pointer) = query.pointer {
+        let entry = entry.ok_or_else(|| ApiError::NotFound("key".to_string()))?;
+        let value = resolve_pointer(&entry.value, pointer)?;
+        return Ok(HttpResponse::Ok().json(DataResponse { data: value }));
+    }
+
     // Apply field selection and optional value decoding
     let fields = parse_field_set(&query.fields)?;
-    let decode = should_decode(&query.value_format)?;
+    let format = parse_value_format(&query.value_format)?;
+    Ok(respond_entry(entry, &fields, format))
+}
+
+fn respond_entry(
+    entry: Option<KvEntry>,
+    fields: &Option<HashSet<String>>,
+    format: ValueFormat,
+) -> HttpResponse {
     match entry {
         Some(entry) => {
-            if fields.is_some() || decode {
-                let mut json = entry.to_json_with_fields(&fields);
-                if decode {
-                    decode_value_in_json(&mut json);
-                }
-                Ok(HttpResponse::Ok().json(serde_json::json!({ "data": json })))
+            if fields.is_some() || format != ValueFormat::Raw {
+                let mut json = entry.to_json_with_fields(fields);
+                format_value_in_json(&mut json, format);
+                HttpResponse::Ok().json(serde_json::json!({ "data": json }))
             } else {
-                Ok(HttpResponse::Ok().json(DataResponse { data: Some(entry) }))
+                HttpResponse::Ok().json(DataResponse { data: Some(entry) })
             }
         }
-        None => Ok(HttpResponse::Ok().json(DataResponse {
+        None => HttpResponse::Ok().json(DataResponse {
             data: Option::<KvEntry>::None,
-        })),
+        }),
+    }
+}
+
+/// Long-poll variant of `/v1/kv/get` for clients that can't use SSE.
+///
+/// Blocks until the key's `block_height` reaches `min_block`, then returns the
+/// entry like `/v1/kv/get`. Returns 204 if `timeout_secs` passes first; the
+/// request is idempotent, so clients can simply retry. Each open request holds
+/// one of the `MAX_WATCHES` slots shared with `/v1/kv/watch`.
+#[utoipa::path(
+    get,
+    path = "/v1/kv/get-await",
+    params(GetAwaitParams),
+    responses(
+        (status = 200, description = "Entry at or past min_block", body = inline(DataResponse<Option<KvEntry>>)),
+        (status = 204, description = "Timed out before the key reached min_block"),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 429, description = "Too many watch connections", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    ),
+    tag = "kv"
+)]
+#[get("/v1/kv/get-await")]
+pub async fn get_await_kv_handler(
+    query: web::Query<GetAwaitParams>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut errors = ParamErrors::default();
+    errors.check(validate_account_id(&query.predecessor_id, "accountId"));
+    errors.check(validate_account_id(&query.current_account_id, "contractId"));
+    errors.check(validate_key(&query.key, "key", MAX_KEY_LENGTH));
+    if query.timeout_secs == 0 || query.timeout_secs > MAX_AWAIT_TIMEOUT_SECS {
+        errors.check::<()>(Err(ApiError::InvalidParameter(format!(
+            "timeout_secs: must be between 1 and {MAX_AWAIT_TIMEOUT_SECS}"
+        ))));
+    }
+    let fields = errors.check(parse_field_set(&query.fields));
+    let format = errors.check(parse_value_format(&query.value_format));
+    errors.finish()?;
+    let (fields, format) = (fields.unwrap_or_default(), format.unwrap_or_default());
+
+    let _guard = claim_watch_slot(&app_state.watch_count, app_state.max_watches)?;
+    let db = require_db(&app_state).await?;
+
+    tracing::info!(
+        target: PROJECT_ID,
+        accountId = %query.predecessor_id,
+        contractId = %query.current_account_id,
+        key = %query.key,
+        min_block = query.min_block,
+        timeout_secs = query.timeout_secs,
+        "GET /v1/kv/get-await"
+    );
+
+    let entry = await_min_block(
+        query.min_block,
+        Duration::from_secs(query.timeout_secs),
+        Duration::from_millis(AWAIT_POLL_MILLIS),
+        || db.get_kv(&query.predecessor_id, &query.current_account_id, &query.key),
+    )
+    .await?;
+
+    match entry {
+        Some(entry) => Ok(respond_entry(Some(entry), &fields, format)),
+        None => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
+/// Poll `fetch` until it returns an entry at or past `min_block`, or `timeout`
+/// elapses (`None`). Returns without sleeping if the first fetch already matches.
+async fn await_min_block<F, Fut>(
+    min_block: u64,
+    timeout: Duration,
+    poll: Duration,
+    mut fetch: F,
+) -> anyhow::Result<Option<KvEntry>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<Option<KvEntry>>>,
+{
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if let Some(entry) = fetch().await?.filter(|e| e.block_height >= min_block) {
+            return Ok(Some(entry));
+        }
+        let now = tokio::time::Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        tokio::time::sleep(poll.min(deadline - now)).await;
     }
 }
 
@@ -310,30 +869,46 @@ pub async fn get_kv_handler(
     ),
     tag = "kv"
 )]
-#[get("/v1/kv/query")]
+#[get("/v1/kv/query", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn query_kv_handler(
     query: web::Query<QueryParams>,
     app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, ApiError> {
-    validate_account_id(&query.predecessor_id, "accountId")?;
-    validate_account_id(&query.current_account_id, "contractId")?;
-    validate_limit(query.limit)?;
-    validate_prefix(&query.key_prefix)?;
-
-    validate_cursor_or_offset(
+    let mut query = query.into_inner();
+    apply_default_contract(&mut query.current_account_id, DEFAULT_CONTRACT.as_deref());
+    query.after_key = query.after_key.as_deref().map(parse_key_cursor);
+
+    let mut errors = ParamErrors::default();
+    errors.check(validate_account_id(&query.predecessor_id, "accountId"));
+    errors.check(validate_account_id(&query.current_account_id, "contractId"));
+    errors.check(validate_limit(query.limit));
+    errors.check(validate_prefix(&query.key_prefix));
+    errors.check(validate_cursor_or_offset(
         query.after_key.as_deref(),
         "after_key",
         query.offset,
         |c, n| validate_key(c, n, MAX_KEY_LENGTH),
-    )?;
-
-    if let Some(ref fmt) = query.format {
-        if fmt != "tree" {
-            return Err(ApiError::InvalidParameter(
-                "format: must be 'tree' or omitted".to_string(),
-            ));
-        }
+    ));
+    if query.format.as_deref().is_some_and(|fmt| fmt != "tree") {
+        errors.check::<()>(Err(ApiError::InvalidParameter(
+            "format: must be 'tree' or omitted".to_string(),
+        )));
+    }
+    if query.keys_only == Some(true) && query.format.as_deref() != Some("tree") {
+        errors.check::<()>(Err(ApiError::InvalidParameter(
+            "keys_only: requires format=tree".to_string(),
+        )));
     }
+    errors.check(validate_max_value_bytes(query.max_value_bytes));
+    let max_count = if query.count_only == Some(true) {
+        errors.check(validate_max_count(query.max_count))
+    } else {
+        None
+    };
+    let fields = errors.check(parse_field_set(&query.fields));
+    let format = errors.check(parse_value_format(&query.value_format));
+    errors.finish()?;
+    let (fields, format) = (fields.unwrap_or_default(), format.unwrap_or_default());
 
     tracing::info!(
         target: PROJECT_ID,
@@ -347,24 +922,82 @@ pub async fn query_kv_handler(
     );
 
     let db = require_db(&app_state).await?;
-    let (entries, has_more, dropped) = db.query_kv_with_pagination(&query).await?;
+
+    if let Some(max_count) = max_count {
+        let counted = db
+            .count_kv_prefix(
+                &query.predecessor_id,
+                &query.current_account_id,
+                query.key_prefix.as_deref(),
+                max_count,
+            )
+            .await?;
+        return Ok(HttpResponse::Ok().json(DataResponse {
+            data: CountResponse::capped_at(counted, max_count),
+        }));
+    }
+
+    if query.keys_only == Some(true) {
+        let (keys, has_more, _) = db.query_kv_keys(&query).await?;
+        let (tree, meta) = build_key_tree(&keys);
+        return Ok(HttpResponse::Ok().json(TreeResponse {
+            tree,
+            has_more,
+            meta,
+        }));
+    }
+
+    let (entries, has_more, dropped, first_rank) = db.query_kv_with_pagination(&query).await?;
+    DROP_THRESHOLD.check(dropped, entries.len() + dropped)?;
 
     if query.format.as_deref() == Some("tree") {
         let items: Vec<(String, String)> = entries.into_iter().map(|e| (e.key, e.value)).collect();
-        let tree = build_tree(&items);
-        return Ok(HttpResponse::Ok().json(TreeResponse { tree, has_more }));
+        let (tree, meta) = build_tree_with_stats(&items);
+        return Ok(HttpResponse::Ok().json(TreeResponse {
+            tree,
+            has_more,
+            meta,
+        }));
     }
 
-    let next_cursor = entries.last().map(|e| e.key.clone());
+    let partition_exists = partition_exists(&entries, || {
+        db.partition_exists(&query.predecessor_id, &query.current_account_id)
+    })
+    .await?;
+
+    let next_cursor = entries.last().map(|e| encode_key_cursor(&e.key));
     let meta = PaginationMeta {
         has_more,
         truncated: false,
         next_cursor,
         dropped_rows: dropped_to_option(dropped),
+        partition_exists: Some(partition_exists),
     };
-    let fields = parse_field_set(&query.fields)?;
-    let decode = should_decode(&query.value_format)?;
-    Ok(respond_paginated(entries, meta, &fields, decode))
+    if query.with_rank == Some(true) {
+        let mut data = entries_to_json(entries, &fields, format, query.max_value_bytes);
+        assign_ranks(&mut data, first_rank);
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "data": data, "meta": meta })));
+    }
+    Ok(respond_paginated(
+        entries,
+        meta,
+        &fields,
+        format,
+        query.max_value_bytes,
+    ))
+}
+
+/// A non-empty page proves the partition exists; only an empty one needs the probe.
+async fn partition_exists<F, Fut>(entries: &[KvEntry], probe: F) -> anyhow::Result<bool>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = anyhow::Result<bool>>,
+{
+    if entries.is_empty() {
+        probe().await
+    } else {
+        Ok(true)
+    }
 }
 
 #[utoipa::path(
@@ -378,27 +1011,31 @@ pub async fn query_kv_handler(
     ),
     tag = "kv"
 )]
-#[get("/v1/kv/history")]
+#[get("/v1/kv/history", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn history_kv_handler(
     query: web::Query<HistoryParams>,
     app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, ApiError> {
-    validate_account_id(&query.predecessor_id, "accountId")?;
-    validate_account_id(&query.current_account_id, "contractId")?;
-    validate_key(&query.key, "key", MAX_KEY_LENGTH)?;
-    validate_limit(query.limit)?;
-    validate_order(&query.order)?;
-    validate_block_range(query.from_block, query.to_block)?;
+    let mut errors = ParamErrors::default();
+    errors.check(validate_account_id(&query.predecessor_id, "accountId"));
+    errors.check(validate_account_id(&query.current_account_id, "contractId"));
+    errors.check(validate_key(&query.key, "key", MAX_KEY_LENGTH));
+    errors.check(validate_limit(query.limit));
+    errors.check(validate_order(&query.order));
+    errors.check(validate_block_range(query.from_block, query.to_block));
     if let Some(ref c) = query.cursor {
         if c.len() > MAX_CURSOR_LENGTH {
-            return Err(ApiError::InvalidParameter(
+            errors.check::<()>(Err(ApiError::InvalidParameter(
                 "cursor: exceeds max length".to_string(),
-            ));
-        }
-        if !c.is_empty() {
-            parse_history_cursor(c)?;
+            )));
+        } else if !c.is_empty() {
+            errors.check(parse_history_cursor(c));
         }
     }
+    let fields = errors.check(parse_field_set(&query.fields));
+    let format = errors.check(parse_value_format(&query.value_format));
+    errors.finish()?;
+    let (fields, format) = (fields.unwrap_or_default(), format.unwrap_or_default());
 
     tracing::info!(
         target: PROJECT_ID,
@@ -421,70 +1058,307 @@ pub async fn history_kv_handler(
         truncated: false,
         next_cursor,
         dropped_rows: None,
+        partition_exists: None,
     };
-    let fields = parse_field_set(&query.fields)?;
-    let decode = should_decode(&query.value_format)?;
-    Ok(respond_paginated(entries, meta, &fields, decode))
+    Ok(respond_paginated(entries, meta, &fields, format, None))
 }
 
-/// Find all writers for a key under a contract, with optional account filter
+/// Per-step structural diffs over a page of a key's history
 #[utoipa::path(
     get,
-    path = "/v1/kv/writers",
-    params(WritersParams),
+    path = "/v1/kv/history/diff",
+    params(HistoryDiffParams),
     responses(
-        (status = 200, description = "List of entries from writers", body = inline(PaginatedResponse<KvEntry>)),
+        (status = 200, description = "Diffs between consecutive versions", body = inline(PaginatedResponse<HistoryDiffEntry>)),
         (status = 400, description = "Invalid parameters", body = ErrorResponse),
         (status = 503, description = "Database unavailable", body = ErrorResponse),
     ),
     tag = "kv"
 )]
-#[get("/v1/kv/writers")]
-pub async fn writers_handler(
-    query: web::Query<WritersParams>,
+#[get("/v1/kv/history/diff", wrap = "from_fn(request_timeout::enforce)")]
+pub async fn history_diff_handler(
+    query: web::Query<HistoryDiffParams>,
     app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, ApiError> {
-    validate_account_id(&query.current_account_id, "contractId")?;
-    validate_key(&query.key, "key", MAX_KEY_LENGTH)?;
-    validate_limit(query.limit)?;
-    if let Some(ref pred) = query.predecessor_id {
-        validate_account_id(pred, "accountId")?;
+    let mut errors = ParamErrors::default();
+    errors.check(validate_account_id(&query.predecessor_id, "accountId"));
+    errors.check(validate_account_id(&query.current_account_id, "contractId"));
+    errors.check(validate_key(&query.key, "key", MAX_KEY_LENGTH));
+    errors.check(validate_limit(query.limit));
+    errors.check(validate_order(&query.order));
+    errors.check(validate_block_range(query.from_block, query.to_block));
+    if let Some(ref c) = query.cursor {
+        if c.len() > MAX_CURSOR_LENGTH {
+            errors.check::<()>(Err(ApiError::InvalidParameter(
+                "cursor: exceeds max length".to_string(),
+            )));
+        } else if !c.is_empty() {
+            errors.check(parse_history_cursor(c));
+        }
     }
-
-    validate_cursor_or_offset(
-        query.after_account.as_deref(),
-        "after_account",
-        query.offset,
-        validate_account_id,
-    )?;
+    errors.finish()?;
 
     tracing::info!(
         target: PROJECT_ID,
+        accountId = %query.predecessor_id,
         contractId = %query.current_account_id,
         key = %query.key,
-        accountId = ?query.predecessor_id,
         limit = query.limit,
-        offset = query.offset,
-        after_account = ?query.after_account,
-        "GET /v1/kv/writers"
+        cursor = ?query.cursor,
+        order = %query.order,
+        "GET /v1/kv/history/diff"
     );
 
     let db = require_db(&app_state).await?;
-    let (entries, has_more, truncated, dropped) = db.query_writers(&query).await?;
+    let (entries, has_more, _truncated, _) = db.get_kv_history(&query.history_params()).await?;
 
-    let next_cursor = entries.last().map(|e| e.predecessor_id.clone());
     let meta = PaginationMeta {
         has_more,
-        truncated,
-        next_cursor,
-        dropped_rows: dropped_to_option(dropped),
+        truncated: false,
+        next_cursor: diff_page_cursor(&entries, has_more),
+        dropped_rows: None,
+        partition_exists: None,
     };
-    let fields = parse_field_set(&query.fields)?;
-    let decode = should_decode(&query.value_format)?;
-    Ok(respond_paginated(entries, meta, &fields, decode))
+    let ascending = query.order.eq_ignore_ascii_case("asc");
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
+        data: history_diffs(&entries, ascending),
+        meta,
+    }))
 }
 
-/// List unique writer accounts for a contract (or across all contracts).
+/// Cursor for the next diff page. The overfetched last row is the newer side
+/// of this page's last diff and the older side of the next page's first, so
+/// the next page resumes after the row before it.
+fn diff_page_cursor(entries: &[KvEntry], has_more: bool) -> Option<String> {
+    if !has_more {
+        return None;
+    }
+    entries
+        .iter()
+        .rev()
+        .nth(1)
+        .and_then(KvEntry::history_cursor)
+}
+
+/// Diff each pair of consecutive versions in a history page, keeping the
+/// page's order. Values are decoded as in `value_format=json` (non-JSON stays
+/// a string, a deletion is `null`). A page of N rows yields N-1 diffs.
+fn history_diffs(entries: &[KvEntry], ascending: bool) -> Vec<HistoryDiffEntry> {
+    let decode = |e: &KvEntry| {
+        serde_json::from_str(&e.value)
+            .unwrap_or_else(|_| serde_json::Value::String(e.value.clone()))
+    };
+    entries
+        .windows(2)
+        .map(|pair| {
+            let (old, new) = if ascending {
+                (&pair[0], &pair[1])
+            } else {
+                (&pair[1], &pair[0])
+            };
+            HistoryDiffEntry {
+                from_block_height: old.block_height,
+                block_height: new.block_height,
+                block_timestamp: new.block_timestamp,
+                changes: json_diff(&decode(old), &decode(new)),
+            }
+        })
+        .collect()
+}
+
+/// Stream a key's full history as NDJSON, oldest first
+///
+/// Reads the whole block range page by page, without client-driven
+/// pagination, stopping after `max_rows` rows.
+#[utoipa::path(
+    get,
+    path = "/v1/kv/history/export",
+    params(HistoryExportParams),
+    responses(
+        (status = 200, description = "One KvEntry JSON object per line", content_type = "application/x-ndjson", body = KvEntry),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    ),
+    tag = "kv"
+)]
+#[get("/v1/kv/history/export", wrap = "from_fn(request_timeout::enforce)")]
+pub async fn history_export_handler(
+    query: web::Query<HistoryExportParams>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut errors = ParamErrors::default();
+    errors.check(validate_account_id(&query.predecessor_id, "accountId"));
+    errors.check(validate_account_id(&query.current_account_id, "contractId"));
+    errors.check(validate_key(&query.key, "key", MAX_KEY_LENGTH));
+    errors.check(validate_block_range(query.from_block, query.to_block));
+    if query.max_rows == 0 || query.max_rows > MAX_EXPORT_ROWS {
+        errors.check::<()>(Err(ApiError::InvalidParameter(format!(
+            "max_rows: must be between 1 and {MAX_EXPORT_ROWS}"
+        ))));
+    }
+    errors.finish()?;
+
+    tracing::info!(
+        target: PROJECT_ID,
+        accountId = %query.predecessor_id,
+        contractId = %query.current_account_id,
+        key = %query.key,
+        max_rows = query.max_rows,
+        from_block = ?query.from_block,
+        to_block = ?query.to_block,
+        "GET /v1/kv/history/export"
+    );
+
+    let db = require_db(&app_state).await?;
+    let total = db.count_kv_history(&query).await?;
+    let rows = db.export_kv_history(&query).await?;
+    Ok(ndjson_response(rows, total > query.max_rows))
+}
+
+/// NDJSON body, one entry per line. Headers are sent before the first row, so
+/// a read error mid-stream can only abort the body; it is logged here.
+fn ndjson_response<S>(rows: S, truncated: bool) -> HttpResponse
+where
+    S: futures::Stream<Item = anyhow::Result<KvEntry>> + 'static,
+{
+    use futures::StreamExt;
+    let body = rows.map(|row| {
+        let entry = row.map_err(|e| {
+            tracing::warn!(target: PROJECT_ID, error = %e, "History export aborted");
+            actix_web::error::ErrorInternalServerError("History export failed")
+        })?;
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        Ok::<_, actix_web::Error>(web::Bytes::from(line))
+    });
+
+    let mut response = HttpResponse::Ok();
+    response.content_type("application/x-ndjson");
+    if truncated {
+        response.insert_header(("X-Results-Truncated", "true"));
+    }
+    response.streaming(body)
+}
+
+/// Cross-contract writer results are (contract, writer) pairs, so a `fields`
+/// selection always keeps `contractId` and `accountId`.
+fn with_writer_pair_fields(fields: Option<HashSet<String>>) -> Option<HashSet<String>> {
+    fields.map(|mut set| {
+        set.insert("contractId".to_string());
+        set.insert("accountId".to_string());
+        set
+    })
+}
+
+/// Find all writers for a key under a contract, with optional account filter
+///
+/// When `contractId` is omitted, lists writers of the key across all contracts
+/// (throttled to 1 req/sec per IP, limit clamped to 1,000, sorted by contract then writer).
+#[utoipa::path(
+    get,
+    path = "/v1/kv/writers",
+    params(WritersParams),
+    responses(
+        (status = 200, description = "List of entries from writers", body = inline(PaginatedResponse<KvEntry>)),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 429, description = "Too many scan requests", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    ),
+    tag = "kv"
+)]
+#[get("/v1/kv/writers", wrap = "from_fn(request_timeout::enforce)")]
+pub async fn writers_handler(
+    req: HttpRequest,
+    query: web::Query<WritersParams>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut query = query.into_inner();
+    query.after_account = query.after_account.as_deref().map(parse_account_cursor);
+    let is_scan = query.current_account_id.is_none();
+
+    if let Some(ref cid) = query.current_account_id {
+        validate_account_id(cid, "contractId")?;
+    } else {
+        if query.offset > 0 {
+            return Err(ApiError::InvalidParameter(
+                "offset: requires contractId".to_string(),
+            ));
+        }
+        if query.after_account.is_some() {
+            return Err(ApiError::InvalidParameter(
+                "after_account: requires contractId".to_string(),
+            ));
+        }
+        query.limit = query.limit.min(MAX_SCAN_LIMIT);
+    }
+    validate_key(&query.key, "key", MAX_KEY_LENGTH)?;
+    validate_limit(query.limit)?;
+    if let Some(ref pred) = query.predecessor_id {
+        validate_account_id(pred, "accountId")?;
+    }
+    if let Some(ref excluded) = query.exclude_account {
+        validate_account_id(excluded, "exclude_account")?;
+    }
+
+    validate_cursor_or_offset(
+        query.after_account.as_deref(),
+        "after_account",
+        query.offset,
+        validate_account_id,
+    )?;
+    validate_max_value_bytes(query.max_value_bytes)?;
+
+    if is_scan {
+        check_scan_throttle(&app_state, &extract_client_ip(&req, &app_state))?;
+    }
+
+    tracing::info!(
+        target: PROJECT_ID,
+        contractId = ?query.current_account_id,
+        scan = is_scan,
+        key = %query.key,
+        accountId = ?query.predecessor_id,
+        exclude_account = ?query.exclude_account,
+        limit = query.limit,
+        offset = query.offset,
+        after_account = ?query.after_account,
+        "GET /v1/kv/writers"
+    );
+
+    let db = require_db(&app_state).await?;
+    let (entries, has_more, truncated, dropped) = db.query_writers(&query).await?;
+    DROP_THRESHOLD.check(dropped, entries.len() + dropped)?;
+
+    // after_account is a per-contract cursor; there is no cross-contract one
+    let next_cursor = if is_scan {
+        None
+    } else {
+        entries
+            .last()
+            .map(|e| encode_account_cursor(&e.predecessor_id))
+    };
+    let meta = PaginationMeta {
+        has_more,
+        truncated,
+        next_cursor,
+        dropped_rows: dropped_to_option(dropped),
+        partition_exists: None,
+    };
+    let mut fields = parse_field_set(&query.fields)?;
+    if is_scan {
+        fields = with_writer_pair_fields(fields);
+    }
+    let format = parse_value_format(&query.value_format)?;
+    Ok(respond_paginated(
+        entries,
+        meta,
+        &fields,
+        format,
+        query.max_value_bytes,
+    ))
+}
+
+/// List unique writer accounts for a contract (or across all contracts).
 ///
 /// Returns deduplicated predecessor accounts that have written to the given contract.
 /// Providing a `key` filter is recommended — omitting it scans the entire contract partition
@@ -505,12 +1379,14 @@ pub async fn writers_handler(
     ),
     tag = "kv"
 )]
-#[get("/v1/kv/accounts")]
+#[get("/v1/kv/accounts", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn accounts_handler(
     req: HttpRequest,
     query: web::Query<AccountsQueryParams>,
     app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, ApiError> {
+    let mut query = query.into_inner();
+    query.after_account = query.after_account.as_deref().map(parse_account_cursor);
     let contract_id = query.contract_id.as_deref();
     let is_scan = contract_id.is_none();
 
@@ -527,7 +1403,13 @@ pub async fn accounts_handler(
         }
     } else if let Some(cid) = contract_id {
         validate_account_id(cid, "contractId")?;
+        if query.from_account.is_some() || query.to_account.is_some() {
+            return Err(ApiError::InvalidParameter(
+                "from_account/to_account: only supported without contractId".to_string(),
+            ));
+        }
     }
+    validate_account_range(query.from_account.as_deref(), query.to_account.as_deref())?;
 
     let limit = if is_scan {
         query.limit.min(MAX_SCAN_LIMIT)
@@ -548,7 +1430,7 @@ pub async fn accounts_handler(
     )?;
 
     if is_scan {
-        check_scan_throttle(&app_state, &extract_client_ip(&req))?;
+        check_scan_throttle(&app_state, &extract_client_ip(&req, &app_state))?;
     }
 
     tracing::info!(
@@ -559,6 +1441,8 @@ pub async fn accounts_handler(
         limit = limit,
         offset = query.offset,
         after_account = ?query.after_account,
+        from_account = ?query.from_account,
+        to_account = ?query.to_account,
         "GET /v1/kv/accounts"
     );
 
@@ -574,18 +1458,22 @@ pub async fn accounts_handler(
         )
         .await?
     } else {
-        let (accounts, has_more, dropped) = db
-            .query_all_accounts(limit, query.after_account.as_deref())
-            .await?;
-        (accounts, has_more, false, dropped)
+        db.query_all_accounts(
+            limit,
+            query.after_account.as_deref(),
+            query.from_account.as_deref(),
+            query.to_account.as_deref(),
+        )
+        .await?
     };
 
-    let next_cursor = accounts.last().cloned();
+    let next_cursor = accounts.last().map(|a| encode_account_cursor(a));
     let meta = PaginationMeta {
         has_more,
         truncated,
         next_cursor,
         dropped_rows: dropped_to_option(dropped),
+        partition_exists: None,
     };
 
     Ok(HttpResponse::Ok().json(PaginatedResponse {
@@ -607,7 +1495,7 @@ pub async fn accounts_handler(
     ),
     tag = "kv"
 )]
-#[get("/v1/kv/contracts")]
+#[get("/v1/kv/contracts", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn contracts_handler(
     req: HttpRequest,
     query: web::Query<ContractsQueryParams>,
@@ -637,7 +1525,7 @@ pub async fn contracts_handler(
         db.query_contracts_by_account(account_id, limit, query.after_contract.as_deref())
             .await?
     } else {
-        check_scan_throttle(&app_state, &extract_client_ip(&req))?;
+        check_scan_throttle(&app_state, &extract_client_ip(&req, &app_state))?;
 
         tracing::info!(
             target: PROJECT_ID,
@@ -656,6 +1544,7 @@ pub async fn contracts_handler(
         truncated: false,
         next_cursor,
         dropped_rows: dropped_to_option(dropped),
+        partition_exists: None,
     };
 
     Ok(HttpResponse::Ok().json(PaginatedResponse {
@@ -664,6 +1553,219 @@ pub async fn contracts_handler(
     }))
 }
 
+/// List every KV entry written by a receipt (debugging aid).
+///
+/// Entries are sorted by contract, account, then key. The result is never paginated:
+/// a single receipt writes a bounded number of keys.
+#[utoipa::path(
+    get,
+    path = "/v1/kv/by-receipt",
+    params(ReceiptParams),
+    responses(
+        (status = 200, description = "Entries written by the receipt", body = inline(PaginatedResponse<KvEntry>)),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    ),
+    tag = "kv"
+)]
+#[get("/v1/kv/by-receipt", wrap = "from_fn(request_timeout::enforce)")]
+pub async fn by_receipt_handler(
+    query: web::Query<ReceiptParams>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    validate_key(&query.receipt_id, "receipt_id", MAX_RECEIPT_ID_LENGTH)?;
+    let fields = parse_field_set(&query.fields)?;
+    let format = parse_value_format(&query.value_format)?;
+
+    tracing::info!(
+        target: PROJECT_ID,
+        receipt_id = %query.receipt_id,
+        "GET /v1/kv/by-receipt"
+    );
+
+    let db = require_db(&app_state).await?;
+    let (entries, dropped) = db.get_kv_by_receipt(&query.receipt_id).await?;
+    DROP_THRESHOLD.check(dropped, entries.len() + dropped)?;
+
+    let meta = PaginationMeta {
+        has_more: false,
+        truncated: false,
+        next_cursor: None,
+        dropped_rows: dropped_to_option(dropped),
+        partition_exists: None,
+    };
+    Ok(respond_paginated(entries, meta, &fields, format, None))
+}
+
+/// List every KV entry written under a transaction, across all of its receipts.
+///
+/// Entries are sorted by contract, account, then key. An unknown `tx_hash` returns an
+/// empty list. Writes whose tx hash wasn't known at index time are not included.
+#[utoipa::path(
+    get,
+    path = "/v1/kv/by-tx",
+    params(TxParams),
+    responses(
+        (status = 200, description = "Entries written under the transaction", body = inline(PaginatedResponse<KvEntry>)),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    ),
+    tag = "kv"
+)]
+#[get("/v1/kv/by-tx", wrap = "from_fn(request_timeout::enforce)")]
+pub async fn by_tx_handler(
+    query: web::Query<TxParams>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    validate_key(&query.tx_hash, "tx_hash", MAX_TX_HASH_LENGTH)?;
+    let fields = parse_field_set(&query.fields)?;
+    let format = parse_value_format(&query.value_format)?;
+
+    tracing::info!(
+        target: PROJECT_ID,
+        tx_hash = %query.tx_hash,
+        "GET /v1/kv/by-tx"
+    );
+
+    let db = require_db(&app_state).await?;
+    let (entries, dropped) = db.get_kv_by_tx(&query.tx_hash).await?;
+    DROP_THRESHOLD.check(dropped, entries.len() + dropped)?;
+
+    let meta = PaginationMeta {
+        has_more: false,
+        truncated: false,
+        next_cursor: None,
+        dropped_rows: dropped_to_option(dropped),
+        partition_exists: None,
+    };
+    Ok(respond_paginated(entries, meta, &fields, format, None))
+}
+
+/// Return a handful of entries from somewhere inside a partition, for previews.
+///
+/// Starts after one of a few probe points chosen at random, so repeated calls
+/// see different slices. Sampling is approximate and not uniform: only the first
+/// `SAMPLE_SCAN_BUDGET` keys are considered. An empty partition returns an empty list.
+#[utoipa::path(
+    get,
+    path = "/v1/kv/sample",
+    params(SampleParams),
+    responses(
+        (status = 200, description = "Sampled entries", body = inline(PaginatedResponse<KvEntry>)),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    ),
+    tag = "kv"
+)]
+#[get("/v1/kv/sample", wrap = "from_fn(request_timeout::enforce)")]
+pub async fn sample_kv_handler(
+    query: web::Query<SampleParams>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut errors = ParamErrors::default();
+    errors.check(validate_account_id(&query.predecessor_id, "accountId"));
+    errors.check(validate_account_id(&query.current_account_id, "contractId"));
+    errors.check(validate_prefix(&query.key_prefix));
+    if query.limit == 0 || query.limit > MAX_SAMPLE_LIMIT {
+        errors.check::<()>(Err(ApiError::InvalidParameter(format!(
+            "limit: must be between 1 and {MAX_SAMPLE_LIMIT}"
+        ))));
+    }
+    let fields = errors.check(parse_field_set(&query.fields));
+    let format = errors.check(parse_value_format(&query.value_format));
+    errors.finish()?;
+    let (fields, format) = (fields.unwrap_or_default(), format.unwrap_or_default());
+
+    tracing::info!(
+        target: PROJECT_ID,
+        accountId = %query.predecessor_id,
+        contractId = %query.current_account_id,
+        key_prefix = ?query.key_prefix,
+        limit = query.limit,
+        "GET /v1/kv/sample"
+    );
+
+    let db = require_db(&app_state).await?;
+    let seed = RandomState::new().hash_one(std::time::SystemTime::now());
+    let (entries, dropped) = db.sample_kv(&query, seed).await?;
+    DROP_THRESHOLD.check(dropped, entries.len() + dropped)?;
+
+    let meta = PaginationMeta {
+        has_more: false,
+        truncated: false,
+        next_cursor: None,
+        dropped_rows: dropped_to_option(dropped),
+        partition_exists: None,
+    };
+    Ok(respond_paginated(entries, meta, &fields, format, None))
+}
+
+/// Infer the shape of values under a prefix from a sample of its keys.
+///
+/// Best-effort: only the first `limit` keys (in key order) are read, so fields
+/// or types that appear only in other entries are missed. Deleted entries are skipped.
+#[utoipa::path(
+    get,
+    path = "/v1/kv/schema",
+    params(SchemaParams),
+    responses(
+        (status = 200, description = "Merged schema of sampled values", body = inline(DataResponse<SchemaResponse>)),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    ),
+    tag = "kv"
+)]
+#[get("/v1/kv/schema", wrap = "from_fn(request_timeout::enforce)")]
+pub async fn schema_kv_handler(
+    query: web::Query<SchemaParams>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut errors = ParamErrors::default();
+    errors.check(validate_account_id(&query.predecessor_id, "accountId"));
+    errors.check(validate_account_id(&query.current_account_id, "contractId"));
+    errors.check(validate_prefix(&query.key_prefix));
+    if query.limit == 0 || query.limit > MAX_SAMPLE_LIMIT {
+        errors.check::<()>(Err(ApiError::InvalidParameter(format!(
+            "limit: must be between 1 and {MAX_SAMPLE_LIMIT}"
+        ))));
+    }
+    errors.finish()?;
+
+    tracing::info!(
+        target: PROJECT_ID,
+        accountId = %query.predecessor_id,
+        contractId = %query.current_account_id,
+        key_prefix = ?query.key_prefix,
+        limit = query.limit,
+        "GET /v1/kv/schema"
+    );
+
+    let db = require_db(&app_state).await?;
+    let sample = SampleParams {
+        predecessor_id: query.predecessor_id.clone(),
+        current_account_id: query.current_account_id.clone(),
+        key_prefix: query.key_prefix.clone(),
+        limit: query.limit,
+        fields: None,
+        value_format: None,
+    };
+    // Seed 0 always starts at the first key, so the schema is stable between calls.
+    let (entries, dropped) = db.sample_kv(&sample, 0).await?;
+    DROP_THRESHOLD.check(dropped, entries.len() + dropped)?;
+
+    let values: Vec<&str> = entries
+        .iter()
+        .filter(|e| !e.is_deleted)
+        .map(|e| e.value.as_str())
+        .collect();
+    Ok(HttpResponse::Ok().json(DataResponse {
+        data: SchemaResponse {
+            sampled: values.len(),
+            schema: infer_schema(values),
+        },
+    }))
+}
+
 /// Compare a key's value at two different block heights
 #[utoipa::path(
     get,
@@ -676,7 +1778,7 @@ pub async fn contracts_handler(
     ),
     tag = "kv"
 )]
-#[get("/v1/kv/diff")]
+#[get("/v1/kv/diff", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn diff_kv_handler(
     query: web::Query<DiffParams>,
     app_state: web::Data<AppState>,
@@ -718,17 +1820,12 @@ pub async fn diff_kv_handler(
     .await?;
 
     let fields = parse_field_set(&query.fields)?;
-    let decode = should_decode(&query.value_format)?;
-    if fields.is_some() || decode {
+    let format = parse_value_format(&query.value_format)?;
+    if fields.is_some() || format != ValueFormat::Raw {
         let mut a_json = a.as_ref().map(|e| e.to_json_with_fields(&fields));
         let mut b_json = b.as_ref().map(|e| e.to_json_with_fields(&fields));
-        if decode {
-            if let Some(ref mut v) = a_json {
-                decode_value_in_json(v);
-            }
-            if let Some(ref mut v) = b_json {
-                decode_value_in_json(v);
-            }
+        for json in [&mut a_json, &mut b_json].into_iter().flatten() {
+            format_value_in_json(json, format);
         }
         Ok(HttpResponse::Ok().json(serde_json::json!({ "data": { "a": a_json, "b": b_json } })))
     } else {
@@ -749,7 +1846,7 @@ pub async fn diff_kv_handler(
     ),
     tag = "kv"
 )]
-#[get("/v1/kv/timeline")]
+#[get("/v1/kv/timeline", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn timeline_kv_handler(
     query: web::Query<TimelineParams>,
     app_state: web::Data<AppState>,
@@ -790,10 +1887,11 @@ pub async fn timeline_kv_handler(
         truncated: false,
         next_cursor,
         dropped_rows: None,
+        partition_exists: None,
     };
     let fields = parse_field_set(&query.fields)?;
-    let decode = should_decode(&query.value_format)?;
-    Ok(respond_paginated(entries, meta, &fields, decode))
+    let format = parse_value_format(&query.value_format)?;
+    Ok(respond_paginated(entries, meta, &fields, format, None))
 }
 
 /// Batch lookup: get values for multiple keys in a single request
@@ -808,7 +1906,7 @@ pub async fn timeline_kv_handler(
     ),
     tag = "kv"
 )]
-#[post("/v1/kv/batch")]
+#[post("/v1/kv/batch", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn batch_kv_handler(
     body: web::Json<BatchQuery>,
     app_state: web::Data<AppState>,
@@ -837,58 +1935,32 @@ pub async fn batch_kv_handler(
             )));
         }
     }
+    let format = parse_value_format(&body.value_format)?;
 
     tracing::info!(
         target: PROJECT_ID,
         accountId = %body.predecessor_id,
         contractId = %body.current_account_id,
         key_count = body.keys.len(),
+        value_format = ?format,
         "POST /v1/kv/batch"
     );
 
-    // Verify DB is available before starting batch
-    let _ = require_db(&app_state).await?;
+    // One handle for the whole batch: an unavailable DB fails the request once
+    // instead of yielding a "Database unavailable" error per key.
+    let db = require_db(&app_state).await?;
 
     use futures::stream::{self, StreamExt};
     let items: Vec<BatchResultItem> = stream::iter(body.keys.iter().map(|key| {
-        let scylladb = app_state.db.clone();
+        let db = db.clone();
         let predecessor_id = body.predecessor_id.clone();
         let current_account_id = body.current_account_id.clone();
         let key = key.clone();
         async move {
-            let db = scylladb.read().await.clone();
-            let Some(ref db) = db else {
-                return BatchResultItem {
-                    key,
-                    found: false,
-                    value: None,
-                    error: Some("Database unavailable".to_string()),
-                };
-            };
-            match db.get_kv_last(&predecessor_id, &current_account_id, &key).await {
-                Ok(Some(entry)) => BatchResultItem {
-                    key,
-                    found: true,
-                    value: Some(entry.value),
-                    error: None,
-                },
-                Ok(None) => BatchResultItem {
-                    key,
-                    found: false,
-                    value: None,
-                    error: None,
-                },
-                Err(e) => {
-                    // Log full error internally, return generic message to client
-                    tracing::warn!(target: PROJECT_ID, error = %e, key = %key, "Batch key lookup failed");
-                    BatchResultItem {
-                        key,
-                        found: false,
-                        value: None,
-                        error: Some("Lookup failed".to_string()),
-                    }
-                }
-            }
+            let lookup = db
+                .get_kv_last(&predecessor_id, &current_account_id, &key)
+                .await;
+            batch_item(key, lookup, format)
         }
     }))
     .buffered(10)
@@ -898,6 +1970,51 @@ pub async fn batch_kv_handler(
     Ok(HttpResponse::Ok().json(DataResponse { data: items }))
 }
 
+/// One `/v1/kv/batch` result. With `ValueFormat::Json`, values that parse as
+/// JSON are returned decoded and anything else stays a raw string, as in
+/// `decode_value_in_json`; with `Base64` the value is base64 of its bytes.
+fn batch_item(
+    key: String,
+    lookup: anyhow::Result<Option<KvEntry>>,
+    format: ValueFormat,
+) -> BatchResultItem {
+    match lookup {
+        Ok(Some(entry)) => {
+            let value = match format {
+                ValueFormat::Raw => serde_json::Value::String(entry.value),
+                ValueFormat::Json => serde_json::from_str(&entry.value)
+                    .unwrap_or(serde_json::Value::String(entry.value)),
+                ValueFormat::Base64 => serde_json::Value::String(BASE64.encode(&entry.value)),
+            };
+            BatchResultItem {
+                key,
+                found: true,
+                value: Some(value),
+                value_encoding: (format == ValueFormat::Base64).then(|| "base64".to_string()),
+                error: None,
+            }
+        }
+        Ok(None) => BatchResultItem {
+            key,
+            found: false,
+            value: None,
+            value_encoding: None,
+            error: None,
+        },
+        Err(e) => {
+            // Log full error internally, return generic message to client
+            tracing::warn!(target: PROJECT_ID, error = %e, key = %key, "Batch key lookup failed");
+            BatchResultItem {
+                key,
+                found: false,
+                value: None,
+                value_encoding: None,
+                error: Some("Lookup failed".to_string()),
+            }
+        }
+    }
+}
+
 /// List edge sources for a given edge type and target
 #[utoipa::path(
     get,
@@ -910,7 +2027,7 @@ pub async fn batch_kv_handler(
     ),
     tag = "kv"
 )]
-#[get("/v1/kv/edges")]
+#[get("/v1/kv/edges", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn edges_handler(
     query: web::Query<EdgesParams>,
     app_state: web::Data<AppState>,
@@ -953,6 +2070,7 @@ pub async fn edges_handler(
         truncated: false,
         next_cursor,
         dropped_rows: None,
+        partition_exists: None,
     };
 
     Ok(HttpResponse::Ok().json(PaginatedResponse {
@@ -961,20 +2079,48 @@ pub async fn edges_handler(
     }))
 }
 
-/// Count edges for a given edge type and target
+/// Count distinct contracts (approximate; scans the keyspace)
 #[utoipa::path(
     get,
-    path = "/v1/kv/edges/count",
-    params(EdgesCountParams),
+    path = "/v1/kv/contracts/count",
     responses(
-        (status = 200, description = "Edge count", body = inline(DataResponse<EdgesCountResponse>)),
-        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 200, description = "Approximate number of distinct contracts", body = inline(DataResponse<ContractsCountResponse>)),
+        (status = 429, description = "Too many requests", body = ErrorResponse),
         (status = 503, description = "Database unavailable", body = ErrorResponse),
     ),
     tag = "kv"
 )]
-#[get("/v1/kv/edges/count")]
-pub async fn edges_count_handler(
+#[get("/v1/kv/contracts/count", wrap = "from_fn(request_timeout::enforce)")]
+pub async fn contracts_count_handler(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    check_scan_throttle(&app_state, &extract_client_ip(&req, &app_state))?;
+
+    tracing::info!(target: PROJECT_ID, "GET /v1/kv/contracts/count");
+
+    let db = require_db(&app_state).await?;
+    let count = db.count_contracts().await?;
+
+    Ok(HttpResponse::Ok().json(DataResponse {
+        data: ContractsCountResponse { count },
+    }))
+}
+
+/// Count edges for a given edge type and target
+#[utoipa::path(
+    get,
+    path = "/v1/kv/edges/count",
+    params(EdgesCountParams),
+    responses(
+        (status = 200, description = "Edge count", body = inline(DataResponse<EdgesCountResponse>)),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    ),
+    tag = "kv"
+)]
+#[get("/v1/kv/edges/count", wrap = "from_fn(request_timeout::enforce)")]
+pub async fn edges_count_handler(
     query: web::Query<EdgesCountParams>,
     app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, ApiError> {
@@ -1004,7 +2150,10 @@ pub async fn edges_count_handler(
 ///
 /// Returns a `text/event-stream` that emits `change` events whenever the
 /// watched key's block height advances.  Supports `Last-Event-ID` for
-/// reconnection.  Server limits concurrent watches to `MAX_CONCURRENT_WATCHES`.
+/// reconnection.  Server limits concurrent watches to `MAX_WATCHES`.
+///
+/// Changes are pushed from the indexer via Redis pub/sub when available;
+/// otherwise the key is polled every `interval` seconds.
 #[utoipa::path(
     get,
     path = "/v1/kv/watch",
@@ -1026,28 +2175,18 @@ pub async fn watch_kv_handler(
     validate_account_id(&query.predecessor_id, "accountId")?;
     validate_account_id(&query.current_account_id, "contractId")?;
     validate_key(&query.key, "key", MAX_KEY_LENGTH)?;
+    let fields = parse_field_set(&query.fields)?;
 
-    let poll_secs = query.interval.clamp(MIN_POLL_INTERVAL, MAX_POLL_INTERVAL);
-
-    // Atomically claim a watch slot; rollback if over limit
-    let prev = app_state
-        .watch_count
-        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    if prev >= MAX_CONCURRENT_WATCHES {
-        app_state
-            .watch_count
-            .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
-        return Err(ApiError::TooManyRequests(
-            "Too many active watch connections".to_string(),
-        ));
-    }
+    let api_key = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let poll_secs = app_state.watch_tiers.poll_interval(query.interval, api_key);
 
-    // RAII guard: created immediately after incrementing watch_count so that
-    // early disconnects (before the stream is polled) still decrement.
-    let guard = WatchGuard(app_state.watch_count.clone());
+    let guard = claim_watch_slot(&app_state.watch_count, app_state.max_watches)?;
 
     // Verify DB is available (guard's Drop handles rollback on error)
-    let _ = require_db(&app_state).await?;
+    let db = require_db(&app_state).await?;
 
     tracing::info!(
         target: PROJECT_ID,
@@ -1070,15 +2209,53 @@ pub async fn watch_kv_handler(
     let current_account_id = query.current_account_id.clone();
     let key = query.key.clone();
 
+    // Prefer indexer push notifications; fall back to polling if pub/sub is
+    // unavailable or the subscription drops.
+    let changes = match db.subscribe_changes(&current_account_id).await {
+        Ok(changes) => Some(Box::pin(changes)),
+        Err(e) => {
+            tracing::warn!(target: PROJECT_ID, error = %e, "Watch pub/sub unavailable, polling");
+            None
+        }
+    };
+
     let stream = async_stream::stream! {
-        let _guard = guard; // move RAII guard into the stream so it lives until disconnect
+        let mut changes = changes;
         let mut last_known_block = last_block.unwrap_or(0);
+        // With pub/sub, poll once for catch-up and then rely on pushed changes.
+        let mut caught_up = false;
         let mut poll_interval = tokio::time::interval(Duration::from_secs(poll_secs));
         let mut heartbeat_interval = tokio::time::interval(Duration::from_secs(SSE_HEARTBEAT_SECS));
 
         loop {
             tokio::select! {
-                _ = poll_interval.tick() => {
+                change = async {
+                    match changes.as_mut() {
+                        Some(changes) => futures::StreamExt::next(changes).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    let Some(change) = change else {
+                        tracing::warn!(target: PROJECT_ID, "Watch pub/sub closed, falling back to polling");
+                        changes = None;
+                        continue;
+                    };
+                    if let Some(event) = WatchEvent::from_change(
+                        change,
+                        &predecessor_id,
+                        &current_account_id,
+                        &key,
+                        last_known_block,
+                    ) {
+                        last_known_block = event.block_height;
+                        if let Ok(data) = serde_json::to_string(&event.to_json_with_fields(&fields)) {
+                            let msg = format!("id: {last_known_block}\nevent: change\ndata: {data}\n\n");
+                            yield Ok::<actix_web::web::Bytes, actix_web::Error>(actix_web::web::Bytes::from(msg));
+                        }
+                    }
+                }
+                _ = poll_interval.tick(), if changes.is_none() || !caught_up => {
+                    caught_up = true;
                     // Clone the Arc and drop the guard before awaiting DB call,
                     // so the RwLock is not held across .await (blocks reconnection).
                     let db = scylladb.read().await.clone();
@@ -1094,7 +2271,7 @@ pub async fn watch_kv_handler(
                                     predecessor_id: entry.predecessor_id.clone(),
                                     current_account_id: entry.current_account_id.clone(),
                                 };
-                                if let Ok(data) = serde_json::to_string(&event) {
+                                if let Ok(data) = serde_json::to_string(&event.to_json_with_fields(&fields)) {
                                     let msg = format!("id: {}\nevent: change\ndata: {}\n\n", last_known_block, data);
                                     yield Ok::<actix_web::web::Bytes, actix_web::Error>(actix_web::web::Bytes::from(msg));
                                 }
@@ -1123,11 +2300,87 @@ pub async fn watch_kv_handler(
         .insert_header(("Cache-Control", "no-cache"))
         .insert_header(("Connection", "keep-alive"))
         .insert_header(("X-Accel-Buffering", "no"))
-        .streaming(stream))
+        .streaming(close_on_shutdown(stream, app_state.shutdown.clone(), guard)))
+}
+
+/// Forward `inner` until shutdown is signalled, then send `event: close` and end.
+/// Owns the watch guard so the slot is released as soon as the stream ends or
+/// the client disconnects.
+fn close_on_shutdown<S>(
+    inner: S,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+    guard: WatchGuard,
+) -> impl futures::Stream<Item = Result<actix_web::web::Bytes, actix_web::Error>>
+where
+    S: futures::Stream<Item = Result<actix_web::web::Bytes, actix_web::Error>>,
+{
+    async_stream::stream! {
+        let _guard = guard;
+        futures::pin_mut!(inner);
+        loop {
+            tokio::select! {
+                _ = shutdown_requested(&mut shutdown) => {
+                    yield Ok(actix_web::web::Bytes::from_static(WATCH_CLOSE_EVENT.as_bytes()));
+                    break;
+                }
+                item = futures::StreamExt::next(&mut inner) => match item {
+                    Some(item) => yield item,
+                    None => break,
+                },
+            }
+        }
+    }
+}
+
+/// Final SSE event sent to open watches when the server shuts down.
+const WATCH_CLOSE_EVENT: &str = "event: close\ndata: {\"reason\":\"shutdown\"}\n\n";
+
+/// Resolves once shutdown is signalled. Never resolves if the sender is gone
+/// without signalling, so a dropped sender doesn't close every stream.
+async fn shutdown_requested(shutdown: &mut tokio::sync::watch::Receiver<bool>) {
+    if shutdown.wait_for(|stopping| *stopping).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Tell open watch streams to close, then wait (up to `timeout`) for their
+/// guards to drop. Returns `true` if the watch count reached zero.
+pub(crate) async fn drain_watches(
+    shutdown: &tokio::sync::watch::Sender<bool>,
+    watch_count: &std::sync::atomic::AtomicUsize,
+    timeout: Duration,
+) -> bool {
+    shutdown.send_replace(true);
+    let deadline = tokio::time::Instant::now() + timeout;
+    while watch_count.load(std::sync::atomic::Ordering::Relaxed) > 0 {
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    true
 }
 
 /// RAII guard that decrements the watch counter when the SSE stream drops.
 struct WatchGuard(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+/// Atomically claim a watch slot; rollback if over limit.
+///
+/// The guard is created immediately after incrementing `count` so that early
+/// disconnects (before the stream is polled) still decrement.
+fn claim_watch_slot(
+    count: &Arc<std::sync::atomic::AtomicUsize>,
+    max_watches: usize,
+) -> Result<WatchGuard, ApiError> {
+    let prev = count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    if prev >= max_watches {
+        count.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+        return Err(ApiError::TooManyRequests(
+            "Too many active watch connections".to_string(),
+        ));
+    }
+    Ok(WatchGuard(count.clone()))
+}
 impl Drop for WatchGuard {
     fn drop(&mut self) {
         self.0.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
@@ -1143,7 +2396,7 @@ impl Drop for WatchGuard {
     ),
     tag = "kv"
 )]
-#[get("/v1/status")]
+#[get("/v1/status", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn status_handler(app_state: web::Data<AppState>) -> HttpResponse {
     let db = app_state.db.read().await.clone();
     let indexer_block = match db.as_ref() {
@@ -1154,5 +2407,1474 @@ pub async fn status_handler(app_state: web::Data<AppState>) -> HttpResponse {
     HttpResponse::Ok().json(StatusResponse {
         indexer_block,
         timestamp: chrono::Utc::now().to_rfc3339(),
+        active_watches: app_state
+            .watch_count
+            .load(std::sync::atomic::Ordering::Relaxed),
+        max_watches: app_state.max_watches,
     })
 }
+
+/// Require `Authorization: Bearer <ADMIN_TOKEN>`. Always fails when no token is configured.
+fn require_admin(req: &HttpRequest, app_state: &AppState) -> Result<(), ApiError> {
+    let expected = app_state
+        .admin_token
+        .as_deref()
+        .ok_or(ApiError::Unauthorized)?;
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(ApiError::Unauthorized)?;
+    // Compare without short-circuiting so timing doesn't leak the token prefix.
+    let matches = given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0;
+    if matches {
+        Ok(())
+    } else {
+        Err(ApiError::Unauthorized)
+    }
+}
+
+/// Clear the per-IP scan throttle and the response cache.
+///
+/// Meant for tests and debugging; requires `Authorization: Bearer <ADMIN_TOKEN>`.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/flush-caches",
+    responses(
+        (status = 200, description = "Number of entries cleared per cache", body = FlushCachesResponse),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorResponse),
+    ),
+    tag = "admin"
+)]
+#[post("/v1/admin/flush-caches", wrap = "from_fn(request_timeout::enforce)")]
+pub async fn flush_caches_handler(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    require_admin(&req, &app_state)?;
+
+    let scan_throttle = {
+        let mut throttle = app_state
+            .scan_throttle
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let cleared = throttle.len();
+        throttle.clear();
+        cleared
+    };
+    let response_cache = app_state
+        .response_cache
+        .as_ref()
+        .map_or(0, |cache| cache.clear());
+
+    tracing::info!(
+        target: PROJECT_ID,
+        scan_throttle,
+        response_cache,
+        "POST /v1/admin/flush-caches"
+    );
+
+    Ok(HttpResponse::Ok().json(FlushCachesResponse {
+        scan_throttle,
+        response_cache,
+    }))
+}
+
+/// Compare a contract's writers in the accounts index with a bounded scan of
+/// its latest values, to spot lag in the index's asynchronous population.
+///
+/// Requires `Authorization: Bearer <ADMIN_TOKEN>`.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/accounts-staleness",
+    params(AccountsStalenessParams),
+    responses(
+        (status = 200, description = "Indexed vs scanned writer counts and their delta", body = AccountsStaleness),
+        (status = 400, description = "Invalid contractId", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    ),
+    tag = "admin"
+)]
+#[get(
+    "/v1/admin/accounts-staleness",
+    wrap = "from_fn(request_timeout::enforce)"
+)]
+pub async fn accounts_staleness_handler(
+    req: HttpRequest,
+    query: web::Query<AccountsStalenessParams>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    require_admin(&req, &app_state)?;
+    validate_account_id(&query.current_account_id, "contractId")?;
+
+    tracing::info!(
+        target: PROJECT_ID,
+        contractId = %query.current_account_id,
+        "GET /v1/admin/accounts-staleness"
+    );
+
+    let db = require_db(&app_state).await?;
+    let staleness = db
+        .query_accounts_staleness(&query.current_account_id)
+        .await?;
+    Ok(HttpResponse::Ok().json(staleness))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn entry(key: &str, value: &str) -> KvEntry {
+        KvEntry {
+            predecessor_id: "alice.near".to_string(),
+            current_account_id: "social.near".to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+            block_height: 1,
+            block_timestamp: 2,
+            receipt_id: "r".to_string(),
+            tx_hash: "t".to_string(),
+            order_id: None,
+            content_type: None,
+            is_deleted: false,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_pretty_param_matches_compact() {
+        use actix_web::dev::Service;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(|req, srv| {
+                    let pretty = wants_pretty(&req);
+                    let fut = srv.call(req);
+                    async move { prettify_response(fut.await?, pretty).await }
+                })
+                .route(
+                    "/v1/kv/get",
+                    web::get().to(|| async {
+                        HttpResponse::Ok()
+                            .json(serde_json::json!({ "data": { "key": "k", "value": "v" } }))
+                    }),
+                ),
+        )
+        .await;
+
+        let compact = test::call_and_read_body(
+            &app,
+            test::TestRequest::get().uri("/v1/kv/get").to_request(),
+        )
+        .await;
+        let pretty = test::call_and_read_body(
+            &app,
+            test::TestRequest::get()
+                .uri("/v1/kv/get?pretty=1")
+                .to_request(),
+        )
+        .await;
+        let accept = test::call_and_read_body(
+            &app,
+            test::TestRequest::get()
+                .uri("/v1/kv/get")
+                .insert_header((header::ACCEPT, "application/json+pretty"))
+                .to_request(),
+        )
+        .await;
+
+        assert!(!compact.contains(&b'\n'));
+        assert!(pretty.contains(&b'\n'));
+        assert_eq!(pretty, accept);
+        let compact: serde_json::Value = serde_json::from_slice(&compact).unwrap();
+        let pretty: serde_json::Value = serde_json::from_slice(&pretty).unwrap();
+        assert_eq!(compact, pretty);
+    }
+
+    #[actix_web::test]
+    async fn test_request_id_echoed_in_header_and_error_body() {
+        use actix_web::dev::Service;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(|req, srv| {
+                    let request_id = request_id_for(&req);
+                    let fut = srv.call(req);
+                    with_request_id(request_id, fut)
+                })
+                .route(
+                    "/ok",
+                    web::get().to(|| async { HttpResponse::Ok().finish() }),
+                )
+                .route(
+                    "/fail",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(ApiError::InvalidParameter(
+                            "key: cannot be empty".to_string(),
+                        ))
+                    }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/ok")
+            .insert_header((REQUEST_ID_HEADER, "trace-123"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "trace-123");
+
+        let req = test::TestRequest::get()
+            .uri("/fail")
+            .insert_header((REQUEST_ID_HEADER, "trace-456"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "trace-456");
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["request_id"], "trace-456");
+
+        // Without a header an id is generated
+        let res = test::call_service(&app, test::TestRequest::get().uri("/ok").to_request()).await;
+        let generated = res
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(uuid::Uuid::parse_str(generated).is_ok());
+    }
+
+    #[test]
+    fn test_count_only_caps_at_max_count() {
+        assert_eq!(
+            CountResponse::capped_at(0, 99),
+            CountResponse {
+                count: 0,
+                capped: false
+            }
+        );
+        assert_eq!(
+            CountResponse::capped_at(99, 99),
+            CountResponse {
+                count: 99,
+                capped: false
+            }
+        );
+        // A SCAN batch can overshoot; anything past the cap reports "99+"
+        assert_eq!(
+            CountResponse::capped_at(100, 99),
+            CountResponse {
+                count: 99,
+                capped: true
+            }
+        );
+        assert_eq!(
+            CountResponse::capped_at(1_500, 99),
+            CountResponse {
+                count: 99,
+                capped: true
+            }
+        );
+
+        assert_eq!(
+            serde_json::to_value(DataResponse {
+                data: CountResponse::capped_at(5, 3)
+            })
+            .unwrap(),
+            serde_json::json!({ "data": { "count": 3, "capped": true } })
+        );
+    }
+
+    #[test]
+    fn test_validate_max_count() {
+        assert_eq!(validate_max_count(None).unwrap(), DEFAULT_MAX_COUNT);
+        assert_eq!(validate_max_count(Some(99)).unwrap(), 99);
+        assert!(validate_max_count(Some(0)).is_err());
+        assert!(validate_max_count(Some(MAX_COUNT_ONLY + 1)).is_err());
+    }
+
+    #[test]
+    fn test_default_contract_fills_omitted_contract_id() {
+        let params: QueryParams =
+            serde_json::from_value(serde_json::json!({ "accountId": "alice.near" })).unwrap();
+        let mut contract_id = params.current_account_id;
+        apply_default_contract(&mut contract_id, Some("app.near"));
+        assert_eq!(contract_id, "app.near");
+        assert!(validate_account_id(&contract_id, "contractId").is_ok());
+
+        // An explicit contractId wins over the default
+        let mut explicit = "other.near".to_string();
+        apply_default_contract(&mut explicit, Some("app.near"));
+        assert_eq!(explicit, "other.near");
+    }
+
+    #[test]
+    fn test_omitted_contract_id_rejected_without_default() {
+        let params: GetParams =
+            serde_json::from_value(serde_json::json!({ "accountId": "alice.near", "key": "k" }))
+                .unwrap();
+        let mut contract_id = params.current_account_id;
+        apply_default_contract(&mut contract_id, None);
+        let err = validate_account_id(&contract_id, "contractId").unwrap_err();
+        assert!(matches!(err, ApiError::InvalidParameter(_)));
+    }
+
+    #[test]
+    fn test_entries_to_json_decodes_values() {
+        let values = [r#"{"theme":"dark"}"#, "42", "not json"];
+        let entries: Vec<KvEntry> = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| entry(&format!("k{i}"), v))
+            .collect();
+
+        let decoded = entries_to_json(entries, &None, ValueFormat::Json, None);
+
+        assert_eq!(decoded[0]["value"]["theme"], "dark");
+        assert_eq!(decoded[1]["value"], 42);
+        assert_eq!(decoded[2]["value"], "not json");
+    }
+
+    /// App state with no database connected.
+    pub(crate) fn offline_state() -> AppState {
+        AppState {
+            db: Arc::new(tokio::sync::RwLock::new(None)),
+            chain_id: fastnear_primitives::types::ChainId::Mainnet,
+            scan_throttle: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            watch_count: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+            max_watches: DEFAULT_MAX_WATCHES,
+            watch_tiers: Default::default(),
+            response_cache: None,
+            admin_token: None,
+            shutdown: tokio::sync::watch::channel(false).1,
+            request_timeout: None,
+            clock: Arc::new(crate::clock::SystemClock),
+            trusted_proxy_hops: DEFAULT_TRUSTED_PROXY_HOPS,
+            inflight: Default::default(),
+            max_inflight_per_ip: None,
+        }
+    }
+
+    #[test]
+    fn test_client_ip_from_forwarded_for() {
+        // spoofed, client, CDN edge as seen by Railway
+        let xff = "6.6.6.6, 1.2.3.4, 10.0.0.1";
+        assert_eq!(client_ip_from_forwarded_for(xff, 0), None);
+        assert_eq!(client_ip_from_forwarded_for(xff, 1), Some("10.0.0.1"));
+        assert_eq!(client_ip_from_forwarded_for(xff, 2), Some("1.2.3.4"));
+        // The spoofed leftmost entry is only reached when every hop is trusted
+        assert_eq!(client_ip_from_forwarded_for(xff, 3), Some("6.6.6.6"));
+        assert_eq!(client_ip_from_forwarded_for(xff, 4), None);
+
+        assert_eq!(client_ip_from_forwarded_for("1.2.3.4", 1), Some("1.2.3.4"));
+        assert_eq!(client_ip_from_forwarded_for("1.2.3.4", 2), None);
+        assert_eq!(client_ip_from_forwarded_for("unknown, 10.0.0.1", 2), None);
+    }
+
+    #[test]
+    fn test_extract_client_ip_respects_hops() {
+        use actix_web::test::TestRequest;
+
+        let req = TestRequest::default()
+            .insert_header(("X-Forwarded-For", "6.6.6.6, 1.2.3.4, 10.0.0.1"))
+            .peer_addr("192.168.0.9:4000".parse().unwrap())
+            .to_http_request();
+        let state = |hops| AppState {
+            trusted_proxy_hops: hops,
+            ..offline_state()
+        };
+
+        assert_eq!(extract_client_ip(&req, &state(0)), "192.168.0.9");
+        assert_eq!(extract_client_ip(&req, &state(1)), "10.0.0.1");
+        assert_eq!(extract_client_ip(&req, &state(2)), "1.2.3.4");
+    }
+
+    #[actix_web::test]
+    async fn test_contracts_count_is_throttled() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(offline_state()))
+                .service(contracts_count_handler),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        let first = test::call_service(&app, get("/v1/kv/contracts/count")).await;
+        assert_eq!(first.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let second = test::call_service(&app, get("/v1/kv/contracts/count")).await;
+        assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[actix_web::test]
+    async fn test_writers_without_contract_is_throttled() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(offline_state()))
+                .service(writers_handler),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        // With a contract there is no throttle; both calls reach the (missing) db
+        for _ in 0..2 {
+            let res = test::call_service(
+                &app,
+                get("/v1/kv/writers?contractId=social.near&key=profile"),
+            )
+            .await;
+            assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        let first = test::call_service(&app, get("/v1/kv/writers?key=profile")).await;
+        assert_eq!(first.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let second = test::call_service(&app, get("/v1/kv/writers?key=profile")).await;
+        assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let res = test::call_service(&app, get("/v1/kv/writers?key=profile&offset=10")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = test::call_service(
+            &app,
+            get("/v1/kv/writers?contractId=social.near&key=profile&exclude_account="),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_scan_throttle_expires_with_clock() {
+        let clock = Arc::new(crate::clock::MockClock::new());
+        let state = AppState {
+            clock: clock.clone(),
+            ..offline_state()
+        };
+
+        assert!(check_scan_throttle(&state, "1.2.3.4").is_ok());
+        clock.advance(std::time::Duration::from_millis(999));
+        assert!(check_scan_throttle(&state, "1.2.3.4").is_err());
+        // Another client is not affected
+        assert!(check_scan_throttle(&state, "5.6.7.8").is_ok());
+
+        clock.advance(std::time::Duration::from_millis(1));
+        assert!(check_scan_throttle(&state, "1.2.3.4").is_ok());
+
+        // Entries older than THROTTLE_EXPIRY are pruned
+        clock.advance(THROTTLE_EXPIRY + std::time::Duration::from_secs(1));
+        assert!(check_scan_throttle(&state, "9.9.9.9").is_ok());
+        assert_eq!(state.scan_throttle.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_cross_contract_writers_keep_contract_id() {
+        let mut e = entry("profile", "{}");
+        e.current_account_id = "other.near".to_string();
+
+        let fields = parse_field_set(&Some("value".to_string())).unwrap();
+        let json = e.to_json_with_fields(&with_writer_pair_fields(fields));
+        assert_eq!(json["contractId"], "other.near");
+        assert_eq!(json["accountId"], "alice.near");
+        assert_eq!(json["value"], "{}");
+        assert!(json.get("blockHeight").is_none());
+
+        assert!(with_writer_pair_fields(None).is_none());
+    }
+
+    #[test]
+    fn test_staleness_frozen_vs_advancing_height() {
+        let window = Duration::from_secs(60);
+        let start = std::time::Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        let mut frozen = StalenessTracker::new(window, start);
+        assert!(!frozen.observe(100, at(0)));
+        assert!(!frozen.observe(100, at(59)));
+        assert!(frozen.observe(100, at(60)));
+        // Recovers as soon as the height moves again
+        assert!(!frozen.observe(101, at(61)));
+
+        let mut advancing = StalenessTracker::new(window, start);
+        for i in 0..10 {
+            assert!(!advancing.observe(100 + i, at(i * 30)));
+        }
+
+        // Unknown height never reports stale
+        let mut unknown = StalenessTracker::new(window, start);
+        assert!(!unknown.observe(0, at(600)));
+    }
+
+    #[actix_web::test]
+    async fn test_sse_watch_not_given_max_age() {
+        use actix_web::dev::Service;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(|req, srv| {
+                    let method = req.method().clone();
+                    let path = req.path().to_string();
+                    let fut = srv.call(req);
+                    async move {
+                        let mut res = fut.await?;
+                        apply_cache_headers(&mut res, &method, &path);
+                        Ok(res)
+                    }
+                })
+                .route(
+                    "/v1/kv/watch",
+                    web::get().to(|| async {
+                        HttpResponse::Ok()
+                            .content_type("text/event-stream")
+                            .insert_header(("Cache-Control", "no-cache"))
+                            .body(": ping\n\n")
+                    }),
+                )
+                .route(
+                    "/v1/kv/get",
+                    web::get().to(|| async { HttpResponse::Ok().json(serde_json::json!({})) }),
+                ),
+        )
+        .await;
+
+        let res = test::call_service(
+            &app,
+            test::TestRequest::get().uri("/v1/kv/watch").to_request(),
+        )
+        .await;
+        let cc = res
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(!cc.contains("max-age"), "watch got {cc}");
+
+        let res = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/v1/kv/get?fields=key")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(
+            res.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=5"
+        );
+        assert_eq!(res.headers().get(header::VARY).unwrap(), "Accept");
+    }
+
+    #[actix_web::test]
+    async fn test_responses_carry_api_version() {
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(offline_state()))
+                .wrap_fn(|req, srv| apply_response_headers(0, false, req, srv))
+                .service(status_handler)
+                .service(get_kv_handler)
+                .route(
+                    "/v1/kv/watch",
+                    web::get().to(|| async {
+                        HttpResponse::Ok()
+                            .content_type("text/event-stream")
+                            .body(": ping\n\n")
+                    }),
+                ),
+        )
+        .await;
+
+        for uri in [
+            "/v1/status",
+            "/v1/kv/watch",
+            "/v1/kv/get?accountId=&contractId=x&key=k",
+        ] {
+            let res =
+                test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(res.headers().get(API_VERSION_HEADER).unwrap(), "2", "{uri}");
+            assert_eq!(
+                res.headers().get("x-content-type-options").unwrap(),
+                "nosniff",
+                "{uri}"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_event_stream_keeps_own_cache_control() {
+        use actix_web::http::Method;
+        use actix_web::test;
+
+        let req = test::TestRequest::get()
+            .uri("/v1/kv/watch")
+            .to_http_request();
+        let mut res = ServiceResponse::new(
+            req.clone(),
+            HttpResponse::Ok()
+                .content_type("text/event-stream")
+                .insert_header((header::CACHE_CONTROL, "no-cache"))
+                .finish(),
+        );
+        apply_cache_headers(&mut res, &Method::GET, "/v1/kv/watch");
+        let values: Vec<_> = res.headers().get_all(header::CACHE_CONTROL).collect();
+        assert_eq!(values, ["no-cache"]);
+
+        let mut res = ServiceResponse::new(
+            req,
+            HttpResponse::Ok()
+                .content_type("application/x-ndjson")
+                .finish(),
+        );
+        apply_cache_headers(&mut res, &Method::GET, "/v1/kv/export");
+        assert!(res.headers().get(header::CACHE_CONTROL).is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_query_reports_all_invalid_params() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(offline_state()))
+                .service(query_kv_handler),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/v1/kv/query?accountId=&contractId=social.near&limit=0&fields=bogus&value_format=xml")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = test::read_body_json(res).await;
+        let fields: Vec<&str> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(fields, ["accountId", "limit", "fields", "value_format"]);
+        assert_eq!(body["code"], "INVALID_PARAMETER");
+        let summary = body["error"].as_str().unwrap();
+        assert!(
+            summary.starts_with("Invalid parameters: accountId: cannot be empty; "),
+            "{summary}"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_watch_limit_enforced_and_reported() {
+        use actix_web::{test, App};
+
+        let state = offline_state();
+        assert_eq!(state.max_watches, 100);
+        let guards: Vec<WatchGuard> = (0..100)
+            .map(|_| claim_watch_slot(&state.watch_count, state.max_watches).unwrap())
+            .collect();
+        assert!(matches!(
+            claim_watch_slot(&state.watch_count, state.max_watches),
+            Err(ApiError::TooManyRequests(_))
+        ));
+
+        let count = state.watch_count.clone();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(status_handler),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/v1/status").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["active_watches"], 100);
+        assert_eq!(body["max_watches"], 100);
+
+        drop(guards);
+        assert_eq!(count.load(std::sync::atomic::Ordering::Relaxed), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_await_min_block() {
+        let start = std::time::Instant::now();
+        let found = await_min_block(
+            1,
+            Duration::from_secs(30),
+            Duration::from_secs(1),
+            || async { Ok(Some(entry("k", "1"))) },
+        )
+        .await
+        .unwrap();
+        assert_eq!(found.map(|e| e.block_height), Some(1));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let mut polls = 0;
+        let timed_out = await_min_block(
+            5,
+            Duration::from_millis(50),
+            Duration::from_millis(10),
+            || {
+                polls += 1;
+                async { Ok(Some(entry("k", "1"))) }
+            },
+        )
+        .await
+        .unwrap();
+        assert!(timed_out.is_none());
+        assert!(polls > 1);
+    }
+
+    /// `offline_state` backed by `redis`.
+    pub(crate) async fn fake_redis_state(redis: &crate::fake_redis::FakeRedis) -> AppState {
+        let state = offline_state();
+        *state.db.write().await = Some(Arc::new(redis.db().await));
+        state
+    }
+
+    #[actix_web::test]
+    async fn test_get_await_handler_returns_entry_or_204() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let redis = crate::fake_redis::FakeRedis::start().await;
+        redis.set(
+            &fastkv_common::keys::kv("alice.near", "social.near", "profile/name"),
+            r#"{"predecessor_id":"alice.near","current_account_id":"social.near","key":"profile/name","value":"\"Alice\"","block_height":42,"block_timestamp":7,"receipt_id":"r","tx_hash":""}"#,
+        );
+        let state = web::Data::new(fake_redis_state(&redis).await);
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(get_await_kv_handler),
+        )
+        .await;
+        let base = "/v1/kv/get-await?accountId=alice.near&contractId=social.near&key=profile/name&timeout_secs=1";
+
+        // Already at min_block: answered from the first read.
+        let res = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("{base}&min_block=42"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["data"]["blockHeight"], 42);
+        assert_eq!(redis.commands().iter().filter(|c| *c == "GET").count(), 1);
+
+        let res = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("{base}&min_block=43"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(redis.commands().iter().filter(|c| *c == "GET").count() > 2);
+        assert_eq!(
+            state.watch_count.load(std::sync::atomic::Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_get_pointer_resolves_into_value() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let redis = crate::fake_redis::FakeRedis::start().await;
+        redis.set(
+            &fastkv_common::keys::kv("alice.near", "social.near", "profile"),
+            r#"{"predecessor_id":"alice.near","current_account_id":"social.near","key":"profile","value":"{\"image\":{\"url\":\"ipfs://x\"}}","block_height":42,"block_timestamp":7,"receipt_id":"r","tx_hash":""}"#,
+        );
+        let state = web::Data::new(fake_redis_state(&redis).await);
+        let app = test::init_service(App::new().app_data(state).service(get_kv_handler)).await;
+        let base = "/v1/kv/get?accountId=alice.near&contractId=social.near&key=profile";
+
+        for (pointer, expected) in [
+            ("/image/url", serde_json::json!("ipfs://x")),
+            ("", serde_json::json!({"image": {"url": "ipfs://x"}})),
+        ] {
+            let uri = format!("{base}&pointer={pointer}");
+            let res =
+                test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK, "{pointer:?}");
+            let body: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(body["data"], expected, "{pointer:?}");
+        }
+
+        let res = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("{base}&pointer=/missing"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_by_tx_returns_every_write_of_the_tx() {
+        use actix_web::{test, App};
+
+        let redis = crate::fake_redis::FakeRedis::start().await;
+        let indexer = redis_db::RedisDb::connect(redis.url(), "mainnet")
+            .await
+            .unwrap();
+        let write = |receipt: &str, key: &str, tx_hash: Option<&str>| redis_db::FastDataKv {
+            receipt_id: receipt.to_string(),
+            action_index: 0,
+            tx_hash: tx_hash.map(str::to_string),
+            signer_id: "alice.near".to_string(),
+            predecessor_id: "alice.near".to_string(),
+            current_account_id: "social.near".to_string(),
+            block_height: 42,
+            block_timestamp: 7,
+            shard_id: 0,
+            receipt_index: 0,
+            order_id: 0,
+            key: key.to_string(),
+            value: "1".to_string(),
+            encrypted_key_id: None,
+            is_deleted: false,
+            content_type: Default::default(),
+        };
+        // Two receipts of one transaction, plus a write with no tx hash.
+        for kv in [
+            write("r2", "b", Some("tx1")),
+            write("r1", "a", Some("tx1")),
+            write("r3", "c", None),
+        ] {
+            indexer.add_kv(&kv).await.unwrap();
+        }
+
+        let state = web::Data::new(fake_redis_state(&redis).await);
+        let app = test::init_service(App::new().app_data(state).service(by_tx_handler)).await;
+
+        let res = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/v1/kv/by-tx?tx_hash=tx1")
+                .to_request(),
+        )
+        .await;
+        assert!(res.status().is_success());
+        let body: serde_json::Value = test::read_body_json(res).await;
+        let receipts: Vec<_> = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["receiptId"].clone())
+            .collect();
+        assert_eq!(receipts, ["r1", "r2"]);
+        assert!(body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|e| e["txHash"] == "tx1"));
+
+        let res = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/v1/kv/by-tx?tx_hash=unknown")
+                .to_request(),
+        )
+        .await;
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["data"], serde_json::json!([]));
+    }
+
+    #[actix_web::test]
+    async fn test_get_await_bounds_timeout() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(offline_state()))
+                .service(get_await_kv_handler),
+        )
+        .await;
+        let base = "/v1/kv/get-await?accountId=alice.near&contractId=social.near&key=k&min_block=1";
+        for (query, expected) in [
+            ("", StatusCode::SERVICE_UNAVAILABLE),
+            ("&timeout_secs=30", StatusCode::SERVICE_UNAVAILABLE),
+            ("&timeout_secs=0", StatusCode::BAD_REQUEST),
+            ("&timeout_secs=31", StatusCode::BAD_REQUEST),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&format!("{base}{query}"))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), expected, "{query}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_flush_caches_requires_token_and_empties_throttle() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let state = AppState {
+            admin_token: Some("secret".to_string()),
+            ..offline_state()
+        };
+        state
+            .scan_throttle
+            .lock()
+            .unwrap()
+            .insert("1.2.3.4".to_string(), std::time::Instant::now());
+        let throttle = state.scan_throttle.clone();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(flush_caches_handler),
+        )
+        .await;
+
+        for auth in [None, Some("Bearer wrong"), Some("secret")] {
+            let mut req = test::TestRequest::post().uri("/v1/admin/flush-caches");
+            if let Some(auth) = auth {
+                req = req.insert_header((header::AUTHORIZATION, auth));
+            }
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{auth:?}");
+        }
+        assert_eq!(throttle.lock().unwrap().len(), 1);
+
+        let req = test::TestRequest::post()
+            .uri("/v1/admin/flush-caches")
+            .insert_header((header::AUTHORIZATION, "Bearer secret"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["scan_throttle"], 1);
+        assert_eq!(body["response_cache"], 0);
+        assert!(throttle.lock().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_shutdown_closes_watches_and_releases_slots() {
+        use actix_web::web::Bytes;
+        use futures::StreamExt;
+
+        let count = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let streams: Vec<_> = (0..3)
+            .map(|_| {
+                let guard = claim_watch_slot(&count, DEFAULT_MAX_WATCHES).unwrap();
+                let inner = futures::stream::pending::<Result<Bytes, actix_web::Error>>();
+                actix_web::rt::spawn(
+                    close_on_shutdown(inner, shutdown_rx.clone(), guard)
+                        .map(|chunk| chunk.unwrap())
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        assert_eq!(count.load(std::sync::atomic::Ordering::Relaxed), 3);
+
+        assert!(drain_watches(&shutdown_tx, &count, Duration::from_secs(5)).await);
+        assert_eq!(count.load(std::sync::atomic::Ordering::Relaxed), 0);
+        for stream in streams {
+            let chunks = stream.await.unwrap();
+            assert_eq!(
+                chunks,
+                vec![Bytes::from_static(WATCH_CLOSE_EVENT.as_bytes())]
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_sample_validates_limit() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(offline_state()))
+                .service(sample_kv_handler),
+        )
+        .await;
+        let base = "/v1/kv/sample?accountId=alice.near&contractId=social.near";
+        for (query, expected) in [
+            ("", StatusCode::SERVICE_UNAVAILABLE),
+            ("&limit=100", StatusCode::SERVICE_UNAVAILABLE),
+            ("&limit=0", StatusCode::BAD_REQUEST),
+            ("&limit=101", StatusCode::BAD_REQUEST),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&format!("{base}{query}"))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), expected, "{query}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_batch_keeps_its_db_handle_when_db_drops_mid_batch() {
+        use actix_web::{test, App};
+
+        let redis = crate::fake_redis::FakeRedis::start().await;
+        let keys: Vec<String> = (0..12).map(|i| format!("k{i}")).collect();
+        for key in &keys {
+            redis.set(
+                &fastkv_common::keys::kv("alice.near", "social.near", key),
+                &format!(r#"{{"predecessor_id":"alice.near","current_account_id":"social.near","key":"{key}","value":"1","block_height":1,"block_timestamp":0,"receipt_id":"r","tx_hash":""}}"#),
+            );
+        }
+        let state = web::Data::new(fake_redis_state(&redis).await);
+        // The DB goes away as soon as the first lookup reaches it; keys past
+        // the first `buffered(10)` window start after that.
+        let db = state.db.clone();
+        redis.on_command(move |args| {
+            if args[0] == "GET" {
+                if let Ok(mut db) = db.try_write() {
+                    *db = None;
+                }
+            }
+        });
+        let app = test::init_service(App::new().app_data(state).service(batch_kv_handler)).await;
+        let req = test::TestRequest::post()
+            .uri("/v1/kv/batch")
+            .set_json(serde_json::json!({ "accountId": "alice.near", "contractId": "social.near", "keys": keys }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let items = body["data"].as_array().unwrap();
+        assert_eq!(items.len(), keys.len());
+        assert!(
+            items
+                .iter()
+                .all(|item| item["found"] == true && item.get("error").is_none()),
+            "{body}"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_batch_without_db_fails_once() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(offline_state()))
+                .service(batch_kv_handler),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/v1/kv/batch")
+            .set_json(serde_json::json!({
+                "accountId": "alice.near",
+                "contractId": "social.near",
+                "keys": ["a", "b", "c"],
+            }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "DATABASE_UNAVAILABLE");
+    }
+
+    #[test]
+    fn test_history_diffs_follow_page_order() {
+        let at = |bh: u64, value: &str| KvEntry {
+            block_height: bh,
+            ..entry("profile", value)
+        };
+        let desc = [
+            at(30, r#"{"name":"Bob"}"#),
+            at(20, r#"{"name":"Alice"}"#),
+            at(10, "not json"),
+        ];
+
+        let diffs = history_diffs(&desc, false);
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(
+            (diffs[0].from_block_height, diffs[0].block_height),
+            (20, 30)
+        );
+        assert_eq!(diffs[0].changes[0].path, "/name");
+        assert_eq!(diffs[0].changes[0].new, Some(serde_json::json!("Bob")));
+        assert_eq!(
+            (diffs[1].from_block_height, diffs[1].block_height),
+            (10, 20)
+        );
+        assert_eq!(diffs[1].changes[0].old, Some(serde_json::json!("not json")));
+
+        let mut asc = desc.clone();
+        asc.reverse();
+        let diffs = history_diffs(&asc, true);
+        assert_eq!(
+            (diffs[0].from_block_height, diffs[0].block_height),
+            (10, 20)
+        );
+        assert!(history_diffs(&asc[..1], true).is_empty());
+    }
+
+    #[test]
+    fn test_diff_page_cursor_keeps_boundary_row() {
+        let at = |bh: u64| KvEntry {
+            block_height: bh,
+            order_id: Some(bh as i64 * 10),
+            ..entry("profile", "1")
+        };
+        // limit=2 diffs: three rows fetched, more remain.
+        let page = [at(30), at(20), at(10)];
+        let cursor = diff_page_cursor(&page, true).unwrap();
+        // Resumes after block 20, so block 10 opens the next page and 20 -> 10 isn't lost.
+        assert_eq!(parse_history_cursor(&cursor).unwrap(), (20, 200));
+        assert_eq!(cursor, page[1].history_cursor().unwrap());
+        assert_eq!(diff_page_cursor(&page, false), None);
+    }
+
+    #[test]
+    fn test_batch_item_value_format() {
+        let item = batch_item(
+            "profile".to_string(),
+            Ok(Some(entry("profile", r#"{"name":"Alice"}"#))),
+            ValueFormat::Json,
+        );
+        assert_eq!(item.value, Some(serde_json::json!({"name": "Alice"})));
+        let item = batch_item(
+            "name".to_string(),
+            Ok(Some(entry("name", r#""Alice""#))),
+            ValueFormat::Json,
+        );
+        assert_eq!(item.value, Some(serde_json::json!("Alice")));
+
+        // Invalid JSON is returned raw
+        let item = batch_item(
+            "bio".to_string(),
+            Ok(Some(entry("bio", "not json"))),
+            ValueFormat::Json,
+        );
+        assert_eq!(item.value, Some(serde_json::json!("not json")));
+
+        // raw (the default) leaves JSON untouched
+        let item = batch_item(
+            "name".to_string(),
+            Ok(Some(entry("name", r#""Alice""#))),
+            ValueFormat::Raw,
+        );
+        assert_eq!(item.value, Some(serde_json::json!(r#""Alice""#)));
+
+        let item = batch_item("gone".to_string(), Ok(None), ValueFormat::Json);
+        assert!(!item.found && item.value.is_none());
+
+        let item = batch_item(
+            "name".to_string(),
+            Ok(Some(entry("name", r#""Alice""#))),
+            ValueFormat::Base64,
+        );
+        assert_eq!(
+            item.value,
+            Some(serde_json::json!(BASE64.encode(r#""Alice""#)))
+        );
+        assert_eq!(item.value_encoding.as_deref(), Some("base64"));
+    }
+
+    #[test]
+    fn test_base64_value_format_round_trips() {
+        let values = [
+            "\u{0}\u{1b}[0m\u{7f}",
+            "caf\u{e9} \u{1f642}",
+            r#"{"a":"\"q\""}"#,
+            "null",
+        ];
+        let entries: Vec<KvEntry> = values.iter().map(|v| entry("k", v)).collect();
+
+        let encoded = entries_to_json(entries.clone(), &None, ValueFormat::Base64, None);
+        for (json, original) in encoded.iter().zip(values) {
+            assert_eq!(json["valueEncoding"], "base64");
+            let bytes = BASE64.decode(json["value"].as_str().unwrap()).unwrap();
+            assert_eq!(bytes, original.as_bytes());
+        }
+
+        // raw and json are unchanged and carry no marker
+        let raw = entries_to_json(entries.clone(), &None, ValueFormat::Raw, None);
+        assert_eq!(raw[2]["value"], values[2]);
+        let decoded = entries_to_json(entries, &None, ValueFormat::Json, None);
+        assert_eq!(decoded[2]["value"]["a"], "\"q\"");
+        assert!(raw
+            .iter()
+            .chain(&decoded)
+            .all(|json| json.get("valueEncoding").is_none()));
+    }
+
+    #[actix_web::test]
+    async fn test_batch_rejects_unknown_value_format() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(offline_state()))
+                .service(batch_kv_handler),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/v1/kv/batch")
+            .set_json(serde_json::json!({
+                "accountId": "alice.near",
+                "contractId": "social.near",
+                "keys": ["a"],
+                "value_format": "xml",
+            }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_history_export_writes_ndjson_rows() {
+        let rows = (5..=10).map(|block_height| {
+            Ok(KvEntry {
+                block_height,
+                ..entry("profile/name", "\"Alice\"")
+            })
+        });
+
+        let res = ndjson_response(futures::stream::iter(rows), true);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/x-ndjson"
+        );
+        assert_eq!(res.headers().get("X-Results-Truncated").unwrap(), "true");
+        let body = to_bytes(res.into_body()).await.unwrap();
+        let heights: Vec<u64> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|l| {
+                serde_json::from_str::<serde_json::Value>(l).unwrap()["blockHeight"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(heights, vec![5, 6, 7, 8, 9, 10]);
+
+        let res = ndjson_response(
+            futures::stream::iter(Vec::<anyhow::Result<KvEntry>>::new()),
+            false,
+        );
+        assert!(res.headers().get("X-Results-Truncated").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_history_export_validates_params() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(offline_state()))
+                .service(history_export_handler),
+        )
+        .await;
+        for query in [
+            "accountId=a.near&contractId=c.near&key=k&max_rows=0",
+            "accountId=a.near&contractId=c.near&key=k&max_rows=100001",
+            "accountId=a.near&contractId=c.near&key=k&from_block=9&to_block=3",
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/v1/kv/history/export?{query}"))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{query}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_schema_validates_params() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(offline_state()))
+                .service(schema_kv_handler),
+        )
+        .await;
+        for (query, expected) in [
+            (
+                "accountId=alice.near&contractId=social.near",
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                "accountId=alice.near&contractId=social.near&key_prefix=profile/&limit=100",
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                "accountId=alice.near&contractId=social.near&limit=0",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "accountId=alice.near&contractId=social.near&limit=101",
+                StatusCode::BAD_REQUEST,
+            ),
+            ("accountId=&contractId=social.near", StatusCode::BAD_REQUEST),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/v1/kv/schema?{query}"))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), expected, "{query}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_by_receipt_requires_receipt_id() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(offline_state()))
+                .service(by_receipt_handler),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        let res = test::call_service(&app, get("/v1/kv/by-receipt?receipt_id=")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = test::call_service(&app, get("/v1/kv/by-receipt")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        // Valid request reaches the database layer
+        let res = test::call_service(&app, get("/v1/kv/by-receipt?receipt_id=rcpt")).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn test_accounts_range_validation() {
+        use actix_web::http::StatusCode;
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(offline_state()))
+                .service(accounts_handler),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        for uri in [
+            "/v1/kv/accounts?from_account=m&to_account=m",
+            "/v1/kv/accounts?from_account=n&to_account=m",
+            "/v1/kv/accounts?from_account=",
+            "/v1/kv/accounts?contractId=social.near&from_account=a",
+        ] {
+            let res = test::call_service(&app, get(uri)).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+        // Valid range reaches the database layer
+        let res =
+            test::call_service(&app, get("/v1/kv/accounts?from_account=a&to_account=m")).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn test_max_value_bytes_marks_truncated_entries() {
+        let meta = PaginationMeta {
+            has_more: false,
+            truncated: false,
+            next_cursor: None,
+            dropped_rows: None,
+            partition_exists: None,
+        };
+        let entries = vec![entry("short", "\"ok\""), entry("long", "\"héllo wörld\"")];
+        let res = respond_paginated(entries, meta, &None, ValueFormat::Json, Some(4));
+        let body = to_bytes(res.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let short = &json["data"][0];
+        assert_eq!(short["value"], "ok");
+        assert!(short.get("valueTruncated").is_none());
+        assert!(short.get("valueSize").is_none());
+
+        // "\"hé" is 4 bytes; the cut value stays a string under value_format=json.
+        let long = &json["data"][1];
+        assert_eq!(long["value"], "\"hé");
+        assert_eq!(long["valueTruncated"], true);
+        assert_eq!(long["valueSize"], "\"héllo wörld\"".len());
+    }
+
+    #[actix_web::test]
+    async fn test_with_rank_numbers_page_entries() {
+        let entries = || vec![entry("a", "1"), entry("b", "2"), entry("c", "3")];
+
+        let mut data = entries_to_json(entries(), &None, ValueFormat::Raw, None);
+        assign_ranks(&mut data, 20);
+        let ranks: Vec<_> = data
+            .iter()
+            .map(|e| {
+                (
+                    e["key"].clone(),
+                    e["pageIndex"].clone(),
+                    e["approxRank"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            ranks,
+            vec![
+                (
+                    serde_json::json!("a"),
+                    serde_json::json!(0),
+                    serde_json::json!(20)
+                ),
+                (
+                    serde_json::json!("b"),
+                    serde_json::json!(1),
+                    serde_json::json!(21)
+                ),
+                (
+                    serde_json::json!("c"),
+                    serde_json::json!(2),
+                    serde_json::json!(22)
+                ),
+            ]
+        );
+
+        // Not requested: neither field appears
+        let meta = PaginationMeta {
+            has_more: false,
+            truncated: false,
+            next_cursor: None,
+            dropped_rows: None,
+            partition_exists: None,
+        };
+        let res = respond_paginated(entries(), meta, &None, ValueFormat::Raw, None);
+        let json: serde_json::Value =
+            serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert!(json["data"][0].get("pageIndex").is_none());
+        assert!(json["data"][0].get("approxRank").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_partition_exists_distinguishes_empty_match_from_unknown() {
+        // Account wrote to the contract, but the prefix matched nothing
+        let no_match = partition_exists(&[], || async { Ok(true) }).await.unwrap();
+        // Account never wrote to the contract
+        let unknown = partition_exists(&[], || async { Ok(false) }).await.unwrap();
+        assert!(no_match);
+        assert!(!unknown);
+
+        // Non-empty page: no probe needed
+        let found = partition_exists(&[entry("k", "v")], || async {
+            panic!("probe should not run for a non-empty page")
+        })
+        .await
+        .unwrap();
+        assert!(found);
+    }
+}
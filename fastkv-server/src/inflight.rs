@@ -0,0 +1,158 @@
+//! Per-IP cap on concurrent requests.
+//!
+//! The scan throttle only covers the expensive scan modes; a single client can
+//! still keep many cheap requests in flight at once and tie up every DB
+//! connection. [`limit`] counts requests per client IP (as resolved by
+//! `extract_client_ip`) and answers 429 once an IP has `MAX_INFLIGHT_PER_IP`
+//! requests running. The slot is held until the handler returns its response,
+//! so streamed bodies (SSE watch) release it as soon as headers are sent; those
+//! are bounded by `MAX_WATCHES` instead.
+
+use crate::handlers::extract_client_ip;
+use crate::models::ApiError;
+use crate::AppState;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, ResponseError};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub const DEFAULT_MAX_INFLIGHT_PER_IP: usize = 32;
+
+pub type InflightCounts = Arc<Mutex<HashMap<String, usize>>>;
+
+/// `MAX_INFLIGHT_PER_IP` (default 32); `None` when set to 0.
+pub fn from_env() -> Option<usize> {
+    let max: usize = std::env::var("MAX_INFLIGHT_PER_IP")
+        .ok()
+        .map(|s| s.parse().expect("Invalid MAX_INFLIGHT_PER_IP"))
+        .unwrap_or(DEFAULT_MAX_INFLIGHT_PER_IP);
+    (max > 0).then_some(max)
+}
+
+/// RAII guard that releases the IP's slot when the request completes.
+pub struct InflightGuard {
+    counts: InflightCounts,
+    ip: String,
+}
+
+/// Claim a slot for `ip`, or fail if it already has `max` requests running.
+pub fn claim(counts: &InflightCounts, ip: String, max: usize) -> Result<InflightGuard, ApiError> {
+    let mut map = counts.lock().unwrap_or_else(|e| e.into_inner());
+    let n = map.entry(ip.clone()).or_insert(0);
+    if *n >= max {
+        return Err(ApiError::TooManyRequests(format!(
+            "Too many concurrent requests (max {max} per client)"
+        )));
+    }
+    *n += 1;
+    Ok(InflightGuard {
+        counts: counts.clone(),
+        ip,
+    })
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        let mut map = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(n) = map.get_mut(&self.ip) {
+            *n -= 1;
+            if *n == 0 {
+                map.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Run the request under the client's in-flight limit, answering 429 when full.
+pub async fn limit<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let Some(state) = req.app_data::<web::Data<AppState>>().cloned() else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let Some(max) = state.max_inflight_per_ip else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+
+    let ip = extract_client_ip(req.request(), &state);
+    let _guard = match claim(&state.inflight, ip.clone(), max) {
+        Ok(guard) => guard,
+        Err(err) => {
+            tracing::warn!(target: "fastkv-server", %ip, max, "Per-IP in-flight limit reached");
+            let res = err.error_response();
+            return Ok(req.into_response(res).map_into_right_body());
+        }
+    };
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claim_releases_on_drop() {
+        let counts = InflightCounts::default();
+        let a = claim(&counts, "1.1.1.1".to_string(), 1).unwrap();
+        assert!(claim(&counts, "1.1.1.1".to_string(), 1).is_err());
+        drop(a);
+        assert!(counts.lock().unwrap().is_empty());
+        assert!(claim(&counts, "1.1.1.1".to_string(), 1).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_extra_concurrent_request_is_rejected_per_ip() {
+        use actix_web::http::StatusCode;
+        use actix_web::middleware::from_fn;
+        use actix_web::{test, App, HttpResponse};
+        use std::time::Duration;
+
+        let state = AppState {
+            max_inflight_per_ip: Some(2),
+            ..crate::handlers::tests::offline_state()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .wrap(from_fn(limit))
+                .route(
+                    "/slow",
+                    web::get().to(|| async {
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        HttpResponse::Ok().finish()
+                    }),
+                ),
+        )
+        .await;
+        let from = |ip: &str| {
+            test::TestRequest::get()
+                .uri("/slow")
+                .insert_header(("X-Forwarded-For", ip))
+                .to_request()
+        };
+
+        let (a, b, c, other) = tokio::join!(
+            test::call_service(&app, from("1.1.1.1")),
+            test::call_service(&app, from("1.1.1.1")),
+            async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                test::call_service(&app, from("1.1.1.1")).await
+            },
+            async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                test::call_service(&app, from("2.2.2.2")).await
+            },
+        );
+        assert_eq!(a.status(), StatusCode::OK);
+        assert_eq!(b.status(), StatusCode::OK);
+        assert_eq!(c.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(other.status(), StatusCode::OK);
+
+        // Slots are released once the requests finish
+        let res = test::call_service(&app, from("1.1.1.1")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
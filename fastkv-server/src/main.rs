@@ -1,21 +1,35 @@
+mod clock;
+mod compression;
+mod degraded;
+#[cfg(test)]
+mod fake_redis;
 mod handlers;
+mod inflight;
 mod models;
 mod redis_db;
+mod request_timeout;
+mod response_cache;
 mod social_handlers;
 mod tree;
 
 #[cfg(feature = "scylla-backend")]
 mod scylladb;
 
+use crate::compression::CompressionConfig;
 use crate::handlers::{
-    accounts_handler, batch_kv_handler, contracts_handler, diff_kv_handler, edges_count_handler,
-    edges_handler, get_kv_handler, health_check, history_kv_handler, query_kv_handler,
-    status_handler, timeline_kv_handler, watch_kv_handler, writers_handler,
+    accounts_handler, accounts_staleness_handler, batch_kv_handler, by_receipt_handler,
+    by_tx_handler, contracts_count_handler, contracts_handler, diff_kv_handler,
+    edges_count_handler, edges_handler, flush_caches_handler, get_await_kv_handler, get_kv_handler,
+    health_check, history_diff_handler, history_export_handler, history_kv_handler,
+    query_kv_handler, sample_kv_handler, schema_kv_handler, status_handler, timeline_kv_handler,
+    watch_kv_handler, writers_handler,
 };
 use crate::redis_db::RedisDb;
+use crate::response_cache::ResponseCache;
 use crate::social_handlers::{
-    social_account_feed_handler, social_followers_handler, social_following_handler,
-    social_get_handler, social_index_handler, social_keys_handler, social_profile_handler,
+    social_account_feed_handler, social_followers_count_handler, social_followers_handler,
+    social_following_handler, social_get_handler, social_index_handler, social_keys_handler,
+    social_profile_handler, social_widget_handler,
 };
 use actix_cors::Cors;
 use actix_files::Files;
@@ -24,28 +38,39 @@ use actix_web::{dev::Service, middleware, web, App, HttpServer};
 use dotenvy::dotenv;
 use fastnear_primitives::types::ChainId;
 use std::env;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use tracing::Instrument;
 use utoipa::OpenApi;
 use utoipa_scalar::{Scalar, Servable};
 
-use crate::models::PROJECT_ID;
+use crate::models::{WatchTiers, PROJECT_ID};
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         handlers::health_check,
         handlers::status_handler,
+        handlers::flush_caches_handler,
+        handlers::accounts_staleness_handler,
         handlers::get_kv_handler,
+        handlers::get_await_kv_handler,
         handlers::query_kv_handler,
         handlers::history_kv_handler,
+        handlers::history_export_handler,
+        handlers::history_diff_handler,
         handlers::writers_handler,
         handlers::diff_kv_handler,
+        handlers::by_receipt_handler,
+        handlers::by_tx_handler,
+        handlers::sample_kv_handler,
+        handlers::schema_kv_handler,
         handlers::timeline_kv_handler,
         handlers::batch_kv_handler,
         handlers::accounts_handler,
         handlers::contracts_handler,
+        handlers::contracts_count_handler,
         handlers::edges_handler,
         handlers::edges_count_handler,
         handlers::watch_kv_handler,
@@ -54,24 +79,42 @@ use crate::models::PROJECT_ID;
         social_handlers::social_index_handler,
         social_handlers::social_profile_handler,
         social_handlers::social_followers_handler,
+        social_handlers::social_followers_count_handler,
         social_handlers::social_following_handler,
         social_handlers::social_account_feed_handler,
+        social_handlers::social_widget_handler,
     ),
     components(schemas(
         models::KvEntry,
+        models::CountResponse,
         models::HealthResponse,
         models::StatusResponse,
+        models::FlushCachesResponse,
+        models::AccountsStaleness,
         models::GetParams,
+        models::GetAwaitParams,
         models::QueryParams,
         models::HistoryParams,
+        models::HistoryDiffParams,
+        models::HistoryDiffEntry,
+        models::JsonChange,
+        models::JsonChangeOp,
+        models::HistoryExportParams,
         models::WritersParams,
         models::ApiError,
         models::ErrorCode,
         models::ErrorResponse,
+        models::FieldError,
         models::BatchQuery,
         models::BatchResultItem,
         models::TreeResponse,
+        models::TreeStats,
         models::DiffParams,
+        models::ReceiptParams,
+        models::TxParams,
+        models::SampleParams,
+        models::SchemaParams,
+        models::SchemaResponse,
         models::DiffResponse,
         models::TimelineParams,
         models::AccountsQueryParams,
@@ -80,13 +123,16 @@ use crate::models::PROJECT_ID;
         models::EdgesCountParams,
         models::EdgeSourceEntry,
         models::EdgesCountResponse,
+        models::ContractsCountResponse,
         models::SocialGetBody,
         models::SocialGetOptions,
         models::SocialKeysBody,
         models::SocialKeysOptions,
         models::SocialIndexParams,
         models::SocialProfileParams,
+        models::SocialWidgetParams,
         models::SocialFollowParams,
+        models::SocialFollowCountParams,
         models::SocialAccountFeedParams,
         models::IndexEntry,
         models::SocialFollowResponse,
@@ -102,6 +148,7 @@ use crate::models::PROJECT_ID;
     tags(
         (name = "health", description = "Health check endpoints"),
         (name = "kv", description = "Key-Value storage operations"),
+        (name = "admin", description = "Operator endpoints gated by ADMIN_TOKEN"),
         (name = "social", description = "SocialDB-compatible convenience API")
     )
 )]
@@ -115,6 +162,26 @@ pub struct AppState {
     pub scan_throttle: Arc<std::sync::Mutex<std::collections::HashMap<String, std::time::Instant>>>,
     /// Active SSE watch connection count.
     pub watch_count: Arc<std::sync::atomic::AtomicUsize>,
+    /// Limit on `watch_count` (`MAX_WATCHES`).
+    pub max_watches: usize,
+    /// Per-API-key minimum watch poll interval (`WATCH_MIN_INTERVAL`, `WATCH_TIER_KEYS`).
+    pub watch_tiers: Arc<WatchTiers>,
+    /// Short-TTL cache of GET response bodies; `None` when `RESPONSE_CACHE_SIZE` is 0.
+    pub response_cache: Option<Arc<ResponseCache>>,
+    /// Bearer token for `/v1/admin/*` (`ADMIN_TOKEN`); `None` disables those endpoints.
+    pub admin_token: Option<String>,
+    /// Flips to `true` on shutdown so open watch streams send `event: close`.
+    pub shutdown: tokio::sync::watch::Receiver<bool>,
+    /// Per-request budget (`REQUEST_TIMEOUT_MS`); `None` disables it.
+    pub request_timeout: Option<std::time::Duration>,
+    /// Time source for throttle windows and cache TTLs; tests use a `MockClock`.
+    pub clock: Arc<dyn clock::Clock>,
+    /// Proxies trusted to append to `X-Forwarded-For` (`TRUSTED_PROXY_HOPS`).
+    pub trusted_proxy_hops: usize,
+    /// Requests currently running per client IP.
+    pub inflight: inflight::InflightCounts,
+    /// Limit on `inflight` per IP (`MAX_INFLIGHT_PER_IP`); `None` disables it.
+    pub max_inflight_per_ip: Option<usize>,
 }
 
 #[actix_web::main]
@@ -130,12 +197,16 @@ async fn main() -> std::io::Result<()> {
 
     tracing::info!(target: PROJECT_ID, "FastKV server starting");
 
-    let chain_id: ChainId = env::var("CHAIN_ID")
-        .expect("CHAIN_ID required")
-        .try_into()
-        .expect("Invalid chain id");
+    let chain_id: ChainId = fastkv_common::chain_id_from_env()
+        .and_then(|id| id.to_string().try_into())
+        .unwrap_or_else(|e| panic!("{e}"));
 
-    tracing::info!(target: PROJECT_ID, %chain_id, "Configuration loaded");
+    tracing::info!(
+        target: PROJECT_ID,
+        %chain_id,
+        redis_keys = %fastkv_common::keys::chain_prefixes(&chain_id.to_string()).join(", "),
+        "Configuration loaded"
+    );
 
     // Redis connection
     let chain_id_str = chain_id.to_string();
@@ -181,10 +252,17 @@ async fn main() -> std::io::Result<()> {
     }
 
     // Background task to cache indexer block height for response headers
+    // (and whether it has stopped advancing, for X-Indexer-Stale)
+    let clock: Arc<dyn clock::Clock> = Arc::new(clock::SystemClock);
     let indexer_block_cache = Arc::new(AtomicU64::new(0));
+    let indexer_stale = Arc::new(AtomicBool::new(false));
     {
         let cache = Arc::clone(&indexer_block_cache);
+        let stale = Arc::clone(&indexer_stale);
         let db = Arc::clone(&db);
+        let clock = Arc::clone(&clock);
+        let mut tracker =
+            handlers::StalenessTracker::new(handlers::indexer_stale_window(), clock.now());
         tokio::spawn(async move {
             loop {
                 let db_guard = db.read().await.clone();
@@ -193,6 +271,8 @@ async fn main() -> std::io::Result<()> {
                         cache.store(h, Ordering::Release);
                     }
                 }
+                let is_stale = tracker.observe(cache.load(Ordering::Acquire), clock.now());
+                stale.store(is_stale, Ordering::Release);
                 tokio::time::sleep(std::time::Duration::from_secs(5)).await;
             }
         });
@@ -203,20 +283,46 @@ async fn main() -> std::io::Result<()> {
         std::time::Instant,
     >::new()));
 
+    let response_cache =
+        ResponseCache::from_env().map(|cache| Arc::new(cache.with_clock(Arc::clone(&clock))));
+    let compression = Arc::new(CompressionConfig::from_env());
+    let request_timeout = request_timeout::from_env();
+    let max_watches = handlers::max_watches();
+    let watch_tiers = Arc::new(WatchTiers::from_env());
+    let trusted_proxy_hops = handlers::trusted_proxy_hops();
+    let inflight = inflight::InflightCounts::default();
+    let max_inflight_per_ip = inflight::from_env();
+    let admin_token = env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
+    let watch_count = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let drain_count = Arc::clone(&watch_count);
+
     let port = env::var("PORT").unwrap_or_else(|_| "3001".to_string());
     tracing::info!(target: PROJECT_ID, %port, "Binding HTTP server");
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         let block_cache = Arc::clone(&indexer_block_cache);
+        let cache_block = Arc::clone(&indexer_block_cache);
+        let block_stale = Arc::clone(&indexer_stale);
+        let compression = Arc::clone(&compression);
+        let min_compress_size = compression.min_size;
 
         // Configure CORS middleware
         let cors = Cors::default()
             .allow_any_origin()
             .allowed_methods(vec!["GET", "POST"])
-            .allowed_headers(vec![header::CONTENT_TYPE, header::ACCEPT])
+            .allowed_headers(vec![
+                header::CONTENT_TYPE,
+                header::ACCEPT,
+                header::HeaderName::from_static(handlers::REQUEST_ID_HEADER),
+                header::HeaderName::from_static(handlers::API_KEY_HEADER),
+            ])
             .expose_headers(vec![
                 "X-Results-Truncated",
                 "X-Indexer-Block",
+                "X-Indexer-Stale",
+                "X-Request-ID",
+                "X-API-Version",
             ])
             .max_age(3600);
 
@@ -226,73 +332,74 @@ async fn main() -> std::io::Result<()> {
                 db: Arc::clone(&db),
                 chain_id,
                 scan_throttle: scan_throttle.clone(),
-                watch_count: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+                watch_count: watch_count.clone(),
+                max_watches,
+                watch_tiers: Arc::clone(&watch_tiers),
+                response_cache: response_cache.clone(),
+                admin_token: admin_token.clone(),
+                shutdown: shutdown_rx.clone(),
+                request_timeout,
+                clock: Arc::clone(&clock),
+                trusted_proxy_hops,
+                inflight: inflight.clone(),
+                max_inflight_per_ip,
             }))
+            // Inside the response cache so cache hits do not take a slot.
+            .wrap(middleware::from_fn(inflight::limit))
+            .wrap_fn(move |req, srv| {
+                let cache = req
+                    .app_data::<web::Data<AppState>>()
+                    .and_then(|state| state.response_cache.clone());
+                response_cache::call(cache, cache_block.load(Ordering::Acquire), req, srv)
+            })
             .wrap(cors)
             .wrap_fn({
                 let cache = block_cache;
+                let stale = block_stale;
                 move |req, srv| {
-                    let h = cache.load(Ordering::Acquire);
-                    let path = req.path().to_string();
-                    let method = req.method().clone();
-                    let fut = srv.call(req);
-                    async move {
-                        let mut res = fut.await?;
-                        if h > 0 {
-                            res.headers_mut().insert(
-                                header::HeaderName::from_static("x-indexer-block"),
-                                header::HeaderValue::from(h),
-                            );
-                        }
-                        // Default Cache-Control for successful GET API responses.
-                        // Handlers that set their own Cache-Control header take precedence.
-                        if method == actix_web::http::Method::GET
-                            && res.status().is_success()
-                            && !res.headers().contains_key(header::CACHE_CONTROL)
-                        {
-                            let cc = if path == "/health" || path == "/v1/status" {
-                                "no-cache"
-                            } else if path.starts_with("/v1/") {
-                                "public, max-age=5"
-                            } else {
-                                ""
-                            };
-                            if !cc.is_empty() {
-                                res.headers_mut().insert(
-                                    header::CACHE_CONTROL,
-                                    header::HeaderValue::from_static(cc),
-                                );
-                            }
-                        }
-                        // Security headers on all responses.
-                        res.headers_mut().insert(
-                            header::HeaderName::from_static("x-content-type-options"),
-                            header::HeaderValue::from_static("nosniff"),
-                        );
-                        res.headers_mut().insert(
-                            header::HeaderName::from_static("x-frame-options"),
-                            header::HeaderValue::from_static("DENY"),
-                        );
-                        Ok(res)
-                    }
+                    handlers::apply_response_headers(
+                        cache.load(Ordering::Acquire),
+                        stale.load(Ordering::Acquire),
+                        req,
+                        srv,
+                    )
                 }
             })
+            .wrap_fn(move |req, srv| compression::skip_small(min_compress_size, req, srv))
             .wrap(middleware::Compress::default())
+            .wrap_fn(move |req, srv| compression::negotiate(compression.clone(), req, srv))
             .wrap(middleware::Logger::new(
                 "%{r}a \"%r\"	%s %b \"%{Referer}i\" \"%{User-Agent}i\" %T",
             ))
             .wrap(tracing_actix_web::TracingLogger::default())
+            // Outermost: request id is in scope for every layer and handler above.
+            .wrap_fn(|req, srv| {
+                let request_id = handlers::request_id_for(&req);
+                let span = tracing::info_span!("request", request_id = %request_id);
+                let fut = srv.call(req);
+                handlers::with_request_id(request_id, fut).instrument(span)
+            })
             .service(Scalar::with_url("/docs", ApiDoc::openapi()))
             .service(health_check)
             .service(status_handler)
+            .service(flush_caches_handler)
+            .service(accounts_staleness_handler)
             .service(get_kv_handler)
+            .service(get_await_kv_handler)
             .service(query_kv_handler)
             .service(history_kv_handler)
+            .service(history_export_handler)
+            .service(history_diff_handler)
             .service(writers_handler)
             .service(batch_kv_handler)
             .service(diff_kv_handler)
+            .service(by_receipt_handler)
+            .service(by_tx_handler)
+            .service(sample_kv_handler)
+            .service(schema_kv_handler)
             .service(timeline_kv_handler)
             .service(accounts_handler)
+            .service(contracts_count_handler)
             .service(contracts_handler)
             .service(edges_handler)
             .service(edges_count_handler)
@@ -301,14 +408,56 @@ async fn main() -> std::io::Result<()> {
             .service(social_keys_handler)
             .service(social_index_handler)
             .service(social_profile_handler)
+            .service(social_followers_count_handler)
             .service(social_followers_handler)
             .service(social_following_handler)
             .service(social_account_feed_handler)
+            .service(social_widget_handler)
             .service(Files::new("/", "./static").index_file("index.html"))
     })
+    // Signals are handled below so watch streams can be drained before stopping.
+    .disable_signals()
     .bind(format!("0.0.0.0:{}", port))?
-    .run()
-    .await?;
+    .run();
+
+    let handle = server.handle();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!(target: PROJECT_ID, "Shutdown requested, closing watch streams");
+        let drained = handlers::drain_watches(
+            &shutdown_tx,
+            &drain_count,
+            std::time::Duration::from_secs(models::WATCH_DRAIN_TIMEOUT_SECS),
+        )
+        .await;
+        if !drained {
+            tracing::warn!(
+                target: PROJECT_ID,
+                remaining = drain_count.load(Ordering::Relaxed),
+                "Watch streams still open after drain timeout"
+            );
+        }
+        handle.stop(true).await;
+    });
+
+    server.await?;
 
     Ok(())
 }
+
+/// Resolves on SIGINT (Ctrl-C) or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
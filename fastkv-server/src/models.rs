@@ -1,4 +1,6 @@
 use actix_web::{error::ResponseError, http::StatusCode, HttpResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use fastkv_common::ContentType;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -12,10 +14,23 @@ pub const MAX_BATCH_KEY_LENGTH: usize = 1024;
 pub const MAX_SOCIAL_RESULTS: usize = 1000;
 pub const MAX_SOCIAL_KEYS: usize = 100;
 pub const MAX_STREAM_ERRORS: usize = 10;
+#[cfg_attr(not(feature = "scylla-backend"), allow(dead_code))]
 pub const MAX_DEDUP_SCAN: usize = 100_000;
 pub const MAX_EDGE_TYPE_LENGTH: usize = 256;
 pub const MAX_SCAN_LIMIT: usize = 1000;
 pub const MAX_CURSOR_LENGTH: usize = 1024;
+pub const MAX_RECEIPT_ID_LENGTH: usize = 128;
+pub const MAX_TX_HASH_LENGTH: usize = 128;
+pub const MAX_SAMPLE_LIMIT: usize = 100;
+/// Keys read from a partition to choose a `/v1/kv/sample` starting point.
+pub const SAMPLE_SCAN_BUDGET: usize = 1000;
+pub const SAMPLE_PROBE_POINTS: usize = 8;
+pub const DEFAULT_MAX_COUNT: usize = 100;
+pub const MAX_COUNT_ONLY: usize = 10_000;
+pub const MAX_EXPORT_ROWS: usize = 100_000;
+/// Rows read from the latest-value table by `/v1/admin/accounts-staleness`.
+#[cfg_attr(not(feature = "scylla-backend"), allow(dead_code))]
+pub const STALENESS_SCAN_ROWS: usize = 10_000;
 pub const PROJECT_ID: &str = "near-garden";
 
 // Internal types for Redis storage (JSON-serialized)
@@ -50,12 +65,35 @@ pub struct KvEntry {
     pub block_timestamp: u64,
     pub receipt_id: String,
     pub tx_hash: String,
+    /// Position within the block, set on history entries read from ScyllaDB.
+    /// With `blockHeight` it forms the history cursor (see
+    /// [`KvEntry::history_cursor`]). Redis history members don't store it:
+    /// their JSON is their identity, so adding a field would duplicate them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_id: Option<i64>,
+    /// Content type detected at ingestion (`json`, `text` or `binary`). Absent
+    /// on entries indexed before detection and on history entries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub content_type: Option<ContentType>,
     /// True when the entry represents a deletion (value is the literal string "null").
     #[serde(skip_serializing_if = "std::ops::Not::not")]
     pub is_deleted: bool,
 }
 
 impl KvEntry {
+    /// Total order used when merging entries from several partitions:
+    /// `key`, then `predecessor_id`, then `block_height`. Within a single
+    /// `s_kv_last` partition `key` is unique, so this agrees with clustering
+    /// order and keeps `after_key` cursors stable across merged pages.
+    pub fn merge_order(&self, other: &Self) -> std::cmp::Ordering {
+        (&self.key, &self.predecessor_id, self.block_height).cmp(&(
+            &other.key,
+            &other.predecessor_id,
+            other.block_height,
+        ))
+    }
+
     /// Convert to JSON with only requested fields. Pass a pre-built HashSet to avoid
     /// rebuilding it per entry when called in a loop.
     pub fn to_json_with_fields(
@@ -104,9 +142,18 @@ impl KvEntry {
             if field_set.contains("txHash") {
                 map.insert("txHash".to_string(), serde_json::json!(&self.tx_hash));
             }
+            if let Some(order_id) = self.order_id.filter(|_| field_set.contains("orderId")) {
+                map.insert("orderId".to_string(), serde_json::json!(order_id));
+            }
+            if let Some(content_type) = self.content_type.filter(|_| field_set.contains("contentType")) {
+                map.insert("contentType".to_string(), serde_json::json!(content_type));
+            }
             if field_set.contains("isDeleted") && self.is_deleted {
                 map.insert("isDeleted".to_string(), serde_json::json!(true));
             }
+            if field_set.contains("valueType") {
+                map.insert("valueType".to_string(), serde_json::json!(value_type(&self.value)));
+            }
 
             serde_json::Value::Object(map)
         } else {
@@ -117,6 +164,46 @@ impl KvEntry {
             })
         }
     }
+
+    /// History cursor that resumes right after this entry, when it carries an
+    /// `orderId` (ScyllaDB backend only).
+    pub fn history_cursor(&self) -> Option<String> {
+        self.order_id
+            .map(|order_id| encode_history_cursor(self.block_height as i64, order_id))
+    }
+}
+
+/// Longest prefix of `value` of at most `max_bytes` bytes that ends on a char
+/// boundary, so a multi-byte UTF-8 sequence is dropped whole rather than split.
+pub fn truncate_value(value: &str, max_bytes: usize) -> &str {
+    if value.len() <= max_bytes {
+        return value;
+    }
+    let mut end = max_bytes;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// Classify a raw stored value as a JSON type label: `"object"`, `"array"`,
+/// `"number"`, `"string"`, `"boolean"`, `"null"`, or `"invalid"`.
+/// The first non-whitespace character picks the label; a non-allocating parse
+/// confirms the value is well-formed JSON.
+pub fn value_type(raw: &str) -> &'static str {
+    let label = match raw.trim_start().as_bytes().first() {
+        Some(b'{') => "object",
+        Some(b'[') => "array",
+        Some(b'"') => "string",
+        Some(b't' | b'f') => "boolean",
+        Some(b'n') => "null",
+        Some(b'-' | b'0'..=b'9') => "number",
+        _ => return "invalid",
+    };
+    match serde_json::from_str::<serde::de::IgnoredAny>(raw) {
+        Ok(_) => label,
+        Err(_) => "invalid",
+    }
 }
 
 /// Convert a bigint (i64) to u64, clamping negatives to 0.
@@ -124,6 +211,252 @@ pub fn bigint_to_u64(val: i64) -> u64 {
     val.max(0) as u64
 }
 
+/// Outcome of a paginated stream collection.
+#[cfg_attr(not(feature = "scylla-backend"), allow(dead_code))]
+#[derive(Debug)]
+pub struct PageResult<T> {
+    pub items: Vec<T>,
+    pub has_more: bool,
+    pub truncated: bool,
+    pub dropped_rows: usize,
+    /// Raw rows read from the stream, including ones that failed to deserialize.
+    pub scanned: usize,
+}
+
+/// Collects rows from a typed stream with standard pagination semantics.
+///
+/// **Overfetch mode** (`scan_cap = None`):
+///   Skips `offset` valid items (those where `transform` returns `Some`),
+///   collects up to `limit + 1`, sets `has_more` from overfetch.
+///
+/// **Scan-cap mode** (`scan_cap = Some(cap)`):
+///   Collects ALL valid items up to `cap` raw rows scanned.
+///   Sets `truncated = true` if cap hit. Does NOT apply offset/limit
+///   (caller post-sorts then slices). `has_more` is left as `false`.
+///
+/// Stream errors (rows that failed to deserialize) are counted in
+/// `dropped_rows` and skipped.
+#[cfg_attr(not(feature = "scylla-backend"), allow(dead_code))]
+pub async fn collect_page<T, R, E, S, F>(
+    stream: &mut S,
+    limit: usize,
+    offset: usize,
+    scan_cap: Option<usize>,
+    mut transform: F,
+) -> PageResult<T>
+where
+    S: futures::Stream<Item = Result<R, E>> + Unpin,
+    E: fmt::Display,
+    F: FnMut(R) -> Option<T>,
+{
+    let mut items = match scan_cap {
+        None => Vec::with_capacity(limit + 1),
+        Some(_) => Vec::new(),
+    };
+    let mut dropped_rows = 0usize;
+    let mut skipped = 0usize;
+    let mut scanned = 0usize;
+    let mut truncated = false;
+
+    while let Some(row_result) = futures::StreamExt::next(stream).await {
+        // Scan-cap check (before deser — matches current behavior)
+        if scan_cap.is_some_and(|cap| scanned >= cap) {
+            truncated = true;
+            break;
+        }
+        scanned += 1;
+
+        let row = match row_result {
+            Ok(r) => r,
+            Err(e) => {
+                dropped_rows += 1;
+                tracing::warn!(
+                    target: "fastkv-server",
+                    error = %e,
+                    "Failed to deserialize row"
+                );
+                continue;
+            }
+        };
+
+        // Transform + filter (None = filtered out, not counted for offset/limit)
+        let item = match transform(row) {
+            Some(item) => item,
+            None => continue,
+        };
+
+        // Offset skip (overfetch mode only)
+        if scan_cap.is_none() && skipped < offset {
+            skipped += 1;
+            continue;
+        }
+
+        items.push(item);
+
+        // Early break at limit+1 (overfetch mode only)
+        if scan_cap.is_none() && items.len() > limit {
+            break;
+        }
+    }
+
+    let has_more = if scan_cap.is_none() {
+        let over = items.len() > limit;
+        items.truncate(limit);
+        over
+    } else {
+        false // caller computes after post-sort + slice
+    };
+
+    PageResult {
+        items,
+        has_more,
+        truncated,
+        dropped_rows,
+        scanned,
+    }
+}
+
+// ScyllaDB rows. Fields match the selected columns by name, so each struct
+// mirrors one column list in `ScyllaDb::new`.
+
+/// `s_kv_last` / `kv_reverse` / view rows (`columns`).
+#[cfg(feature = "scylla-backend")]
+#[derive(scylla::DeserializeRow, Debug, Clone)]
+pub struct KvRow {
+    pub predecessor_id: String,
+    pub current_account_id: String,
+    pub key: String,
+    pub value: String,
+    pub block_height: i64,
+    pub block_timestamp: i64,
+    pub receipt_id: String,
+    pub tx_hash: String,
+}
+
+#[cfg(feature = "scylla-backend")]
+impl From<KvRow> for KvEntry {
+    fn from(row: KvRow) -> Self {
+        let is_deleted = row.value == "null";
+        Self {
+            predecessor_id: row.predecessor_id,
+            current_account_id: row.current_account_id,
+            key: row.key,
+            value: row.value,
+            block_height: bigint_to_u64(row.block_height),
+            block_timestamp: bigint_to_u64(row.block_timestamp),
+            receipt_id: row.receipt_id,
+            tx_hash: row.tx_hash,
+            order_id: None,
+            content_type: None,
+            is_deleted,
+        }
+    }
+}
+
+/// `s_kv` rows (`history_columns`).
+#[cfg(feature = "scylla-backend")]
+#[derive(scylla::DeserializeRow, Debug, Clone)]
+pub struct KvHistoryRow {
+    pub predecessor_id: String,
+    pub current_account_id: String,
+    pub key: String,
+    pub block_height: i64,
+    pub order_id: i64,
+    pub value: String,
+    pub block_timestamp: i64,
+    pub receipt_id: String,
+    pub tx_hash: String,
+    pub signer_id: String,
+    pub shard_id: i32,
+    pub receipt_index: i32,
+    pub action_index: i32,
+}
+
+#[cfg(feature = "scylla-backend")]
+impl From<KvHistoryRow> for KvEntry {
+    fn from(row: KvHistoryRow) -> Self {
+        let is_deleted = row.value == "null";
+        Self {
+            predecessor_id: row.predecessor_id,
+            current_account_id: row.current_account_id,
+            key: row.key,
+            value: row.value,
+            block_height: bigint_to_u64(row.block_height),
+            block_timestamp: bigint_to_u64(row.block_timestamp),
+            receipt_id: row.receipt_id,
+            tx_hash: row.tx_hash,
+            order_id: Some(row.order_id),
+            content_type: None,
+            is_deleted,
+        }
+    }
+}
+
+/// `s_kv_by_block` rows (`timeline_columns`).
+#[cfg(feature = "scylla-backend")]
+#[derive(scylla::DeserializeRow, Debug, Clone)]
+pub struct KvTimelineRow {
+    pub predecessor_id: String,
+    pub current_account_id: String,
+    pub block_height: i64,
+    pub key: String,
+    pub order_id: i64,
+    pub value: String,
+    pub block_timestamp: i64,
+    pub receipt_id: String,
+    pub tx_hash: String,
+}
+
+#[cfg(feature = "scylla-backend")]
+impl From<KvTimelineRow> for KvEntry {
+    fn from(row: KvTimelineRow) -> Self {
+        let is_deleted = row.value == "null";
+        Self {
+            predecessor_id: row.predecessor_id,
+            current_account_id: row.current_account_id,
+            key: row.key,
+            value: row.value,
+            block_height: bigint_to_u64(row.block_height),
+            block_timestamp: bigint_to_u64(row.block_timestamp),
+            receipt_id: row.receipt_id,
+            tx_hash: row.tx_hash,
+            order_id: Some(row.order_id),
+            content_type: None,
+            is_deleted,
+        }
+    }
+}
+
+/// `SELECT predecessor_id` rows.
+#[cfg(feature = "scylla-backend")]
+#[derive(scylla::DeserializeRow, Debug, Clone)]
+pub struct ContractAccountRow {
+    pub predecessor_id: String,
+}
+
+/// `SELECT current_account_id` rows.
+#[cfg(feature = "scylla-backend")]
+#[derive(scylla::DeserializeRow, Debug, Clone)]
+pub struct ContractRow {
+    pub current_account_id: String,
+}
+
+/// `SELECT current_account_id, key` rows.
+#[cfg(feature = "scylla-backend")]
+#[derive(scylla::DeserializeRow, Debug, Clone)]
+pub struct ContractKeyRow {
+    pub current_account_id: String,
+    pub key: String,
+}
+
+/// `kv_edges` rows.
+#[cfg(feature = "scylla-backend")]
+#[derive(scylla::DeserializeRow, Debug, Clone)]
+pub struct EdgeRow {
+    pub source: String,
+    pub block_height: i64,
+}
+
 // Pagination metadata returned in all paginated responses
 #[derive(Serialize, utoipa::ToSchema)]
 pub struct PaginationMeta {
@@ -136,6 +469,10 @@ pub struct PaginationMeta {
     /// Number of rows skipped due to deserialization errors. Omitted when zero.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub dropped_rows: Option<u32>,
+    /// `/v1/kv/query` only: whether the account has ever written to the contract,
+    /// so an empty page can be told apart from an unknown account/contract pair.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition_exists: Option<bool>,
 }
 
 // Standardized paginated response for all list endpoints
@@ -157,6 +494,19 @@ pub struct TreeResponse {
     /// True when results were capped by the limit parameter.
     #[serde(skip_serializing_if = "std::ops::Not::not")]
     pub has_more: bool,
+    pub meta: TreeStats,
+}
+
+/// Size of a `format=tree` result, counted over key path segments. A stored
+/// value that is itself a JSON object is one leaf, not a subtree.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct TreeStats {
+    /// Keys present in the tree (excludes keys dropped by path conflicts).
+    pub leaves: usize,
+    /// Segments in the longest key, e.g. 3 for `profile/image/url`.
+    pub max_depth: usize,
+    /// Distinct key paths, interior and leaf: `profile`, `profile/image`, ...
+    pub nodes: usize,
 }
 
 #[derive(Serialize, utoipa::ToSchema)]
@@ -171,14 +521,35 @@ pub struct HealthResponse {
 pub struct GetParams {
     #[serde(rename = "accountId")]
     pub predecessor_id: String,
+    /// Falls back to the `DEFAULT_CONTRACT` env when omitted.
     #[serde(rename = "contractId")]
+    #[serde(default)]
     pub current_account_id: String,
     pub key: String,
     #[serde(default)]
     pub fields: Option<String>, // Comma-separated field names
-    /// Value format: "raw" (default) or "json" (decoded).
+    /// Value format: "raw" (default), "json" (decoded) or "base64" (bytes, with `valueEncoding`).
     #[serde(default)]
     pub value_format: Option<String>,
+    /// RFC 6901 JSON pointer into the decoded value, e.g. `/image/url`.
+    /// Returns only the pointed-to sub-value; an empty pointer returns the whole document.
+    #[serde(default)]
+    pub pointer: Option<String>,
+}
+
+/// Resolve an RFC 6901 `pointer` against a stored value parsed as JSON.
+/// `""` is the whole value; `~1` and `~0` escape `/` and `~`.
+pub fn resolve_pointer(value: &str, pointer: &str) -> Result<serde_json::Value, ApiError> {
+    if !pointer.is_empty() && !pointer.starts_with('/') {
+        return Err(ApiError::InvalidParameter(
+            "pointer: must be empty or start with '/'".to_string(),
+        ));
+    }
+    let json: serde_json::Value = serde_json::from_str(value)
+        .map_err(|_| ApiError::NotFound(format!("pointer '{pointer}': value is not JSON")))?;
+    json.pointer(pointer)
+        .cloned()
+        .ok_or_else(|| ApiError::NotFound(format!("pointer '{pointer}' does not resolve")))
 }
 
 const VALID_FIELDS: &[&str] = &[
@@ -190,7 +561,10 @@ const VALID_FIELDS: &[&str] = &[
     "blockTimestamp",
     "receiptId",
     "txHash",
+    "orderId",
+    "contentType",
     "isDeleted",
+    "valueType",
 ];
 
 /// Parse a comma-separated fields string into a set of field names.
@@ -232,18 +606,263 @@ pub(crate) fn dropped_to_option(n: usize) -> Option<u32> {
     }
 }
 
-/// Resolve whether to decode values based on `value_format`.
-pub fn should_decode(value_format: &Option<String>) -> Result<bool, ApiError> {
+/// How many rows may fail to deserialize before a page is reported as a
+/// `DatabaseError` (500) instead of a partial 200 with `dropped_rows`.
+/// Configured with `MAX_DROPPED_ROWS` (absolute) and `MAX_DROPPED_FRACTION`
+/// (of scanned rows, 0.0–1.0); either limit is off when unset.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DropThreshold {
+    pub max_rows: Option<usize>,
+    pub max_fraction: Option<f64>,
+}
+
+impl DropThreshold {
+    pub fn from_env() -> Self {
+        let max_rows = std::env::var("MAX_DROPPED_ROWS")
+            .ok()
+            .map(|s| s.parse().expect("Invalid MAX_DROPPED_ROWS"));
+        let max_fraction = std::env::var("MAX_DROPPED_FRACTION").ok().map(|s| {
+            let f: f64 = s.parse().expect("Invalid MAX_DROPPED_FRACTION");
+            assert!((0.0..=1.0).contains(&f), "MAX_DROPPED_FRACTION must be in 0.0..=1.0");
+            f
+        });
+        Self {
+            max_rows,
+            max_fraction,
+        }
+    }
+
+    /// True when `dropped` of `scanned` rows is above either limit.
+    pub fn exceeded(&self, dropped: usize, scanned: usize) -> bool {
+        let over_rows = self.max_rows.is_some_and(|max| dropped > max);
+        let over_fraction = self
+            .max_fraction
+            .is_some_and(|max| scanned > 0 && dropped as f64 / scanned as f64 > max);
+        over_rows || over_fraction
+    }
+
+    pub fn check(&self, dropped: usize, scanned: usize) -> Result<(), ApiError> {
+        if self.exceeded(dropped, scanned) {
+            tracing::error!(
+                target: PROJECT_ID,
+                dropped,
+                scanned,
+                "Dropped-row threshold exceeded"
+            );
+            return Err(ApiError::DatabaseError(format!(
+                "{dropped} of {scanned} rows could not be read"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Where per-contract `/v1/kv/writers` lookups read from, set by
+/// `REVERSE_SOURCE`: the `kv_reverse` table (default, one row per writer) or
+/// the `mv_kv_cur_key` view (one row per write, deduplicated while scanning).
+/// Only the selected source's statements are prepared, so a keyspace can lack
+/// the other one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReverseSource {
+    #[default]
+    Table,
+    View,
+}
+
+/// Prepared statement serving a per-contract writers query.
+#[cfg_attr(not(feature = "scylla-backend"), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritersStatement {
+    /// `reverse_list`: whole `kv_reverse` partition.
+    TableList,
+    /// `reverse_list_cursor`: `kv_reverse` rows after `after_account`.
+    TableCursor,
+    /// `reverse_kv`: `mv_kv_cur_key` rows, newest first.
+    View,
+}
+
+impl std::str::FromStr for ReverseSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Self::Table),
+            "view" => Ok(Self::View),
+            other => Err(format!("must be 'table' or 'view' (got '{other}')")),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "scylla-backend"), allow(dead_code))]
+impl ReverseSource {
+    pub fn from_env() -> Self {
+        std::env::var("REVERSE_SOURCE")
+            .ok()
+            .map(|s| s.parse().expect("Invalid REVERSE_SOURCE"))
+            .unwrap_or_default()
+    }
+
+    /// Statement for a writers query, with or without an `after_account` cursor.
+    /// The view has no per-writer clustering, so it applies the cursor while scanning.
+    pub fn writers_statement(self, has_cursor: bool) -> WritersStatement {
+        match (self, has_cursor) {
+            (Self::Table, false) => WritersStatement::TableList,
+            (Self::Table, true) => WritersStatement::TableCursor,
+            (Self::View, _) => WritersStatement::View,
+        }
+    }
+}
+
+/// How entry values are rendered, from `value_format`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValueFormat {
+    /// The stored string as is.
+    #[default]
+    Raw,
+    /// Parsed as JSON; values that don't parse stay strings.
+    Json,
+    /// Base64 of the stored string's UTF-8 bytes, marked with `valueEncoding: "base64"`.
+    Base64,
+}
+
+/// Resolve how to render values based on `value_format`.
+pub fn parse_value_format(value_format: &Option<String>) -> Result<ValueFormat, ApiError> {
     match value_format.as_deref() {
-        Some("json") => Ok(true),
-        Some("raw") | None => Ok(false),
+        Some("json") => Ok(ValueFormat::Json),
+        Some("raw") | None => Ok(ValueFormat::Raw),
+        Some("base64") => Ok(ValueFormat::Base64),
         Some(other) => Err(ApiError::InvalidParameter(format!(
-            "value_format: must be 'json' or 'raw' (got '{other}')"
+            "value_format: must be 'json', 'raw' or 'base64' (got '{other}')"
         ))),
     }
 }
 
+/// Layout version of opaque cursors; bump when the packed payload changes.
+const CURSOR_VERSION: u8 = 1;
+const HISTORY_CURSOR: u8 = b'h';
+const TIMELINE_CURSOR: u8 = b't';
+const KEY_CURSOR: u8 = b'k';
+const ACCOUNT_CURSOR: u8 = b'a';
+
+/// Opaque cursor: `version | kind | payload | crc32(version..payload)`,
+/// base64url without padding. The alphabet has no `:`, which is how the
+/// history/timeline parsers tell it apart from the legacy plaintext forms.
+fn encode_cursor(kind: u8, payload: &[u8]) -> String {
+    let mut bytes = Vec::with_capacity(payload.len() + 6);
+    bytes.push(CURSOR_VERSION);
+    bytes.push(kind);
+    bytes.extend_from_slice(payload);
+    bytes.extend_from_slice(&crc32(&bytes).to_be_bytes());
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Payload of an opaque cursor of `kind`, after checking version and checksum.
+fn decode_cursor(cursor: &str, kind: u8) -> Result<Vec<u8>, ApiError> {
+    let malformed = || ApiError::InvalidParameter("cursor: malformed or modified cursor".to_string());
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| malformed())?;
+    if bytes.len() < 6 {
+        return Err(malformed());
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+    if crc32(body).to_be_bytes() != checksum {
+        return Err(malformed());
+    }
+    if body[0] != CURSOR_VERSION {
+        return Err(ApiError::InvalidParameter(format!(
+            "cursor: unsupported cursor version {}",
+            body[0]
+        )));
+    }
+    if body[1] != kind {
+        return Err(ApiError::InvalidParameter(
+            "cursor: cursor belongs to a different endpoint".to_string(),
+        ));
+    }
+    Ok(body[2..].to_vec())
+}
+
+/// CRC-32 (IEEE). Catches truncated or hand-edited cursors; it is not a MAC.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Split a block height off the front of an opaque cursor payload.
+fn cursor_block_height(payload: &[u8]) -> Result<(i64, &[u8]), ApiError> {
+    let (bh, rest) = payload.split_first_chunk::<8>().ok_or_else(|| {
+        ApiError::InvalidParameter("cursor: malformed or modified cursor".to_string())
+    })?;
+    let block_height = i64::from_be_bytes(*bh);
+    if block_height < 0 {
+        return Err(ApiError::InvalidParameter(
+            "cursor: block_height must be non-negative".to_string(),
+        ));
+    }
+    Ok((block_height, rest))
+}
+
+/// Opaque `next_cursor` for `/v1/kv/history`.
+pub fn encode_history_cursor(block_height: i64, order_id: i64) -> String {
+    let mut payload = [0u8; 16];
+    payload[..8].copy_from_slice(&block_height.to_be_bytes());
+    payload[8..].copy_from_slice(&order_id.to_be_bytes());
+    encode_cursor(HISTORY_CURSOR, &payload)
+}
+
+/// Opaque `next_cursor` for `/v1/kv/timeline`. Only the Scylla backend pages
+/// the timeline by cursor.
+#[cfg_attr(not(feature = "scylla-backend"), allow(dead_code))]
+pub fn encode_timeline_cursor(block_height: i64, key: &str) -> String {
+    let mut payload = block_height.to_be_bytes().to_vec();
+    payload.extend_from_slice(key.as_bytes());
+    encode_cursor(TIMELINE_CURSOR, &payload)
+}
+
+/// Opaque `next_cursor` for `/v1/kv/query`, passed back as `after_key`.
+pub fn encode_key_cursor(key: &str) -> String {
+    encode_cursor(KEY_CURSOR, key.as_bytes())
+}
+
+/// Opaque `next_cursor` for `/v1/kv/accounts` and `/v1/kv/writers`, passed
+/// back as `after_account`.
+pub fn encode_account_cursor(account_id: &str) -> String {
+    encode_cursor(ACCOUNT_CURSOR, account_id.as_bytes())
+}
+
+/// The key behind an `after_key` cursor. Keys can be any string, so a value
+/// that is not a valid opaque key cursor is taken as a plain key, as before.
+pub fn parse_key_cursor(cursor: &str) -> String {
+    decode_text_cursor(cursor, KEY_CURSOR)
+}
+
+/// The account behind an `after_account` cursor, opaque or plain.
+pub fn parse_account_cursor(cursor: &str) -> String {
+    decode_text_cursor(cursor, ACCOUNT_CURSOR)
+}
+
+fn decode_text_cursor(cursor: &str, kind: u8) -> String {
+    decode_cursor(cursor, kind)
+        .ok()
+        .and_then(|payload| String::from_utf8(payload).ok())
+        .unwrap_or_else(|| cursor.to_string())
+}
+
+/// Accepts an opaque cursor or the legacy `block_height:order_id`.
 pub fn parse_history_cursor(cursor: &str) -> Result<(i64, i64), ApiError> {
+    if !cursor.contains(':') {
+        let payload = decode_cursor(cursor, HISTORY_CURSOR)?;
+        let (block_height, rest) = cursor_block_height(&payload)?;
+        let order_id = <[u8; 8]>::try_from(rest).map_err(|_| {
+            ApiError::InvalidParameter("cursor: malformed or modified cursor".to_string())
+        })?;
+        return Ok((block_height, i64::from_be_bytes(order_id)));
+    }
     let (bh_str, oid_str) = cursor.split_once(':').ok_or_else(|| {
         ApiError::InvalidParameter("cursor: expected format block_height:order_id".to_string())
     })?;
@@ -261,7 +880,16 @@ pub fn parse_history_cursor(cursor: &str) -> Result<(i64, i64), ApiError> {
     Ok((block_height, order_id))
 }
 
+/// Accepts an opaque cursor or the legacy `block_height:key`.
 pub fn parse_timeline_cursor(cursor: &str) -> Result<(i64, String), ApiError> {
+    if !cursor.contains(':') {
+        let payload = decode_cursor(cursor, TIMELINE_CURSOR)?;
+        let (block_height, key) = cursor_block_height(&payload)?;
+        let key = std::str::from_utf8(key).map_err(|_| {
+            ApiError::InvalidParameter("cursor: malformed or modified cursor".to_string())
+        })?;
+        return Ok((block_height, key.to_string()));
+    }
     let (bh_str, key) = cursor.split_once(':').ok_or_else(|| {
         ApiError::InvalidParameter("cursor: expected format block_height:key".to_string())
     })?;
@@ -285,11 +913,21 @@ pub fn validate_limit(limit: usize) -> Result<(), ApiError> {
     Ok(())
 }
 
+/// Extract the key id from an encrypted value of the form
+/// `enc:AES256:<key_id>:<ciphertext>` (optionally JSON-quoted).
+/// Same format the kv-sub-indexer uses to set `encrypted_key_id`.
+pub fn detect_encrypted(value: &str) -> Option<&str> {
+    let rest = value.trim_matches('"').strip_prefix("enc:AES256:")?;
+    rest.split_once(':').map(|(key_id, _)| key_id)
+}
+
 #[derive(Deserialize, Clone, utoipa::ToSchema, utoipa::IntoParams)]
 pub struct QueryParams {
     #[serde(rename = "accountId")]
     pub predecessor_id: String,
+    /// Falls back to the `DEFAULT_CONTRACT` env when omitted.
     #[serde(rename = "contractId")]
+    #[serde(default)]
     pub current_account_id: String,
     #[serde(default)]
     pub key_prefix: Option<String>,
@@ -304,25 +942,66 @@ pub struct QueryParams {
     /// Response format. Use `"tree"` for nested JSON; omit for paginated list.
     #[serde(default)]
     pub format: Option<String>,
-    /// Value format: "raw" (default) or "json" (decoded).
+    /// Value format: "raw" (default), "json" (decoded) or "base64" (bytes, with `valueEncoding`).
     #[serde(default)]
     pub value_format: Option<String>,
     /// Cursor: return entries with key alphabetically after this value (exclusive).
     /// Cannot be combined with offset > 0.
     #[serde(default)]
     pub after_key: Option<String>,
+    /// Return only `{count, capped}` for the prefix instead of entries.
+    #[serde(default)]
+    pub count_only: Option<bool>,
+    /// With `count_only`: stop counting past this many matches (default 100, max 10,000).
+    #[serde(default)]
+    pub max_count: Option<usize>,
+    /// With `format=tree`: build the tree from keys alone, every leaf `true`,
+    /// without reading values.
+    #[serde(default)]
+    pub keys_only: Option<bool>,
+    /// Cut each `value` to at most this many bytes (see `truncate_value`).
+    #[serde(default)]
+    pub max_value_bytes: Option<usize>,
+    /// Add each entry's `pageIndex` and its approximate position in the
+    /// sorted prefix, `approxRank`. Ignored with `format=tree` and `count_only`.
+    #[serde(default)]
+    pub with_rank: Option<bool>,
+}
+
+/// Capped match count returned by `/v1/kv/query?count_only=true`.
+#[derive(Debug, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct CountResponse {
+    /// Number of matches, at most `max_count`.
+    pub count: usize,
+    /// True when more than `max_count` entries match.
+    pub capped: bool,
+}
+
+impl CountResponse {
+    /// Build from a raw count that may have overshot the cap.
+    pub fn capped_at(counted: usize, max_count: usize) -> Self {
+        Self {
+            count: counted.min(max_count),
+            capped: counted > max_count,
+        }
+    }
 }
 
 // GET /v1/kv/writers — replaces /v1/kv/reverse and /v1/kv/by-key
 #[derive(Deserialize, Clone, utoipa::ToSchema, utoipa::IntoParams)]
 pub struct WritersParams {
-    #[serde(rename = "contractId")]
-    pub current_account_id: String,
+    /// Contract account. When omitted, lists writers of `key` across all contracts
+    /// (scan, throttled; `offset` and `after_account` are not supported).
+    #[serde(rename = "contractId", default)]
+    pub current_account_id: Option<String>,
     pub key: String,
     /// Optional: filter to a specific writer account
     #[serde(rename = "accountId")]
     #[serde(default)]
     pub predecessor_id: Option<String>,
+    /// Optional: drop writes by this account (e.g. the contract's own)
+    #[serde(default)]
+    pub exclude_account: Option<String>,
     #[serde(default)]
     pub exclude_deleted: Option<bool>,
     #[serde(default = "default_limit")]
@@ -331,13 +1010,44 @@ pub struct WritersParams {
     pub offset: usize,
     #[serde(default)]
     pub fields: Option<String>,
-    /// Value format: "raw" (default) or "json" (decoded).
+    /// Value format: "raw" (default), "json" (decoded) or "base64" (bytes, with `valueEncoding`).
     #[serde(default)]
     pub value_format: Option<String>,
     /// Cursor: return writers with account ID alphabetically after this value (exclusive).
     /// Cannot be combined with offset > 0.
     #[serde(default)]
     pub after_account: Option<String>,
+    /// Cut each `value` to at most this many bytes (see `truncate_value`).
+    #[serde(default)]
+    pub max_value_bytes: Option<usize>,
+}
+
+impl WritersParams {
+    /// Whether writes by `predecessor_id` are filtered out by `exclude_account`.
+    pub fn excludes_writer(&self, predecessor_id: &str) -> bool {
+        self.exclude_account.as_deref() == Some(predecessor_id)
+    }
+
+    /// Page of writers from `latest` (one current entry per writer, any order):
+    /// applies the writer and deletion filters, sorts by accountId, then
+    /// `after_account` or `offset`, and `limit`. Returns `(entries, has_more)`.
+    #[cfg_attr(not(feature = "scylla-backend"), allow(dead_code))]
+    pub fn page_latest_writers(&self, latest: Vec<KvEntry>) -> (Vec<KvEntry>, bool) {
+        let exclude_deleted = self.exclude_deleted.unwrap_or(false);
+        let mut entries: Vec<KvEntry> = latest
+            .into_iter()
+            .filter(|e| !(exclude_deleted && e.value == "null"))
+            .filter(|e| !self.excludes_writer(&e.predecessor_id))
+            .filter(|e| self.predecessor_id.as_ref().is_none_or(|pred| e.predecessor_id == *pred))
+            .filter(|e| self.after_account.as_ref().is_none_or(|cursor| e.predecessor_id > *cursor))
+            .collect();
+        entries.sort_by(|a, b| a.predecessor_id.cmp(&b.predecessor_id));
+        let offset = if self.after_account.is_some() { 0 } else { self.offset };
+        let mut entries: Vec<KvEntry> = entries.into_iter().skip(offset).collect();
+        let has_more = entries.len() > self.limit;
+        entries.truncate(self.limit);
+        (entries, has_more)
+    }
 }
 
 fn default_limit() -> usize {
@@ -365,6 +1075,29 @@ pub struct HistoryParams {
     pub value_format: Option<String>,
     #[serde(default)]
     pub cursor: Option<String>,
+    /// Drop rows whose value equals the previously returned value for the
+    /// same key. Applied per page: the first row of a page is always kept.
+    #[serde(default)]
+    pub collapse_unchanged: Option<bool>,
+}
+
+/// Page-local filter for `collapse_unchanged`: keeps a history row only when
+/// its value differs from the last kept value for the same key.
+#[derive(Default)]
+pub struct CollapseUnchanged {
+    last_value: std::collections::HashMap<String, String>,
+}
+
+impl CollapseUnchanged {
+    pub fn keep(&mut self, key: &str, value: &str) -> bool {
+        match self.last_value.get(key) {
+            Some(prev) if prev == value => false,
+            _ => {
+                self.last_value.insert(key.to_string(), value.to_string());
+                true
+            }
+        }
+    }
 }
 
 fn default_history_limit() -> usize {
@@ -375,6 +1108,106 @@ fn default_order_desc() -> String {
     "desc".to_string()
 }
 
+// GET /v1/kv/history/diff
+#[derive(Deserialize, Clone, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct HistoryDiffParams {
+    #[serde(rename = "accountId")]
+    pub predecessor_id: String,
+    #[serde(rename = "contractId")]
+    pub current_account_id: String,
+    pub key: String,
+    #[serde(default = "default_history_limit")]
+    pub limit: usize,
+    #[serde(default = "default_order_desc")]
+    pub order: String,
+    #[serde(default)]
+    pub from_block: Option<i64>,
+    #[serde(default)]
+    pub to_block: Option<i64>,
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
+impl HistoryDiffParams {
+    /// The `/v1/kv/history` page the diffs are computed over: one row more
+    /// than `limit`, so a page of `limit` diffs needs no row from the next.
+    pub fn history_params(&self) -> HistoryParams {
+        HistoryParams {
+            predecessor_id: self.predecessor_id.clone(),
+            current_account_id: self.current_account_id.clone(),
+            key: self.key.clone(),
+            limit: self.limit + 1,
+            order: self.order.clone(),
+            from_block: self.from_block,
+            to_block: self.to_block,
+            fields: None,
+            value_format: None,
+            cursor: self.cursor.clone(),
+            collapse_unchanged: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonChangeOp {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// One difference between two decoded values, addressed by JSON pointer
+/// (`""` is the whole value).
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct JsonChange {
+    pub op: JsonChangeOp,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<serde_json::Value>,
+}
+
+/// Transition from the version at `from_block_height` to the one at
+/// `block_height`.
+#[derive(Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryDiffEntry {
+    pub from_block_height: u64,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub changes: Vec<JsonChange>,
+}
+
+// GET /v1/kv/history/export
+#[derive(Deserialize, Clone, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct HistoryExportParams {
+    #[serde(rename = "accountId")]
+    pub predecessor_id: String,
+    #[serde(rename = "contractId")]
+    pub current_account_id: String,
+    pub key: String,
+    #[serde(default)]
+    pub from_block: Option<i64>,
+    #[serde(default)]
+    pub to_block: Option<i64>,
+    /// Stop after this many rows (1–100,000). `X-Results-Truncated: true`
+    /// is set when the range holds more.
+    #[serde(default = "default_export_max_rows")]
+    pub max_rows: usize,
+}
+
+impl HistoryExportParams {
+    /// Inclusive block-height bounds of the export.
+    pub fn block_range(&self) -> (i64, i64) {
+        (self.from_block.unwrap_or(0), self.to_block.unwrap_or(i64::MAX))
+    }
+}
+
+fn default_export_max_rows() -> usize {
+    10_000
+}
+
 // Internal accounts query parameters (used by social handlers, not exposed in API)
 #[derive(Deserialize, Clone)]
 pub struct AccountsParams {
@@ -404,28 +1237,121 @@ pub struct AccountsQueryParams {
     #[serde(default = "default_limit")]
     pub limit: usize,
     #[serde(default)]
-    pub offset: usize,
-    /// Cursor: return accounts after this value (exclusive).
-    /// Token-ordered (Murmur3) in scan mode; lexicographic with contractId.
-    /// Cannot be combined with offset > 0.
-    /// Note: responses always emit next_cursor (even when has_more is false);
-    /// use it for resumption, especially when truncated=true.
+    pub offset: usize,
+    /// Cursor: return accounts after this value (exclusive).
+    /// Token-ordered (Murmur3) in scan mode; lexicographic with contractId.
+    /// Cannot be combined with offset > 0.
+    /// Note: responses always emit next_cursor (even when has_more is false);
+    /// use it for resumption, especially when truncated=true.
+    #[serde(default)]
+    pub after_account: Option<String>,
+    /// Scan mode only: lowest account to return (inclusive). With `to_account`
+    /// this selects a lexicographic `[from_account, to_account)` range so
+    /// several workers can split the account space.
+    #[serde(default)]
+    pub from_account: Option<String>,
+    /// Scan mode only: end of the range (exclusive).
+    #[serde(default)]
+    pub to_account: Option<String>,
+}
+
+// Contracts listing query parameters
+#[derive(Deserialize, Clone, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct ContractsQueryParams {
+    /// Optional: list contracts this account has written to (single-partition, cheap).
+    /// When omitted, lists all contracts globally (full scan, throttled).
+    #[serde(rename = "accountId", default)]
+    pub predecessor_id: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+    /// Cursor: return contracts after this value (TOKEN-ordered when global, lexicographic when per-account).
+    #[serde(default)]
+    pub after_contract: Option<String>,
+}
+
+// GET /v1/kv/by-receipt
+#[derive(Deserialize, Clone, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct ReceiptParams {
+    pub receipt_id: String,
+    #[serde(default)]
+    pub fields: Option<String>,
+    /// Value format: "raw" (default), "json" (decoded) or "base64" (bytes, with `valueEncoding`).
+    #[serde(default)]
+    pub value_format: Option<String>,
+}
+
+// GET /v1/kv/by-tx
+#[derive(Deserialize, Clone, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct TxParams {
+    pub tx_hash: String,
+    #[serde(default)]
+    pub fields: Option<String>,
+    /// Value format: "raw" (default), "json" (decoded) or "base64" (bytes, with `valueEncoding`).
+    #[serde(default)]
+    pub value_format: Option<String>,
+}
+
+// GET /v1/kv/sample
+#[derive(Deserialize, Clone, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct SampleParams {
+    #[serde(rename = "accountId")]
+    pub predecessor_id: String,
+    #[serde(rename = "contractId")]
+    pub current_account_id: String,
+    #[serde(default)]
+    pub key_prefix: Option<String>,
+    /// Max entries to return (1–100).
+    #[serde(default = "default_sample_limit")]
+    pub limit: usize,
+    #[serde(default)]
+    pub fields: Option<String>,
+    /// Value format: "raw" (default), "json" (decoded) or "base64" (bytes, with `valueEncoding`).
     #[serde(default)]
-    pub after_account: Option<String>,
+    pub value_format: Option<String>,
 }
 
-// Contracts listing query parameters
+fn default_sample_limit() -> usize {
+    10
+}
+
+// GET /v1/kv/schema
 #[derive(Deserialize, Clone, utoipa::ToSchema, utoipa::IntoParams)]
-pub struct ContractsQueryParams {
-    /// Optional: list contracts this account has written to (single-partition, cheap).
-    /// When omitted, lists all contracts globally (full scan, throttled).
-    #[serde(rename = "accountId", default)]
-    pub predecessor_id: Option<String>,
-    #[serde(default = "default_limit")]
-    pub limit: usize,
-    /// Cursor: return contracts after this value (TOKEN-ordered when global, lexicographic when per-account).
+pub struct SchemaParams {
+    #[serde(rename = "accountId")]
+    pub predecessor_id: String,
+    #[serde(rename = "contractId")]
+    pub current_account_id: String,
     #[serde(default)]
-    pub after_contract: Option<String>,
+    pub key_prefix: Option<String>,
+    /// Number of keys to sample (1–100).
+    #[serde(default = "default_schema_limit")]
+    pub limit: usize,
+}
+
+fn default_schema_limit() -> usize {
+    50
+}
+
+/// Inferred value schema returned by `/v1/kv/schema`.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct SchemaResponse {
+    /// Number of (non-deleted) values the schema was inferred from.
+    pub sampled: usize,
+    /// JSON-Schema-style `{ type, properties, items }`; `type` is an array when
+    /// several types were observed.
+    #[schema(value_type = Object)]
+    pub schema: serde_json::Value,
+}
+
+/// Pick the exclusive starting key for `/v1/kv/sample` from sorted `keys`.
+///
+/// The candidates are `SAMPLE_PROBE_POINTS` evenly spaced positions, so the
+/// result is approximate and not uniform over the partition. `None` means
+/// "start from the first key".
+pub fn sample_seed(keys: &[String], seed: u64) -> Option<&str> {
+    let probe = (seed % SAMPLE_PROBE_POINTS as u64) as usize;
+    let start = probe * keys.len() / SAMPLE_PROBE_POINTS;
+    start.checked_sub(1).map(|i| keys[i].as_str())
 }
 
 // Diff query parameters
@@ -440,7 +1366,7 @@ pub struct DiffParams {
     pub block_height_b: i64,
     #[serde(default)]
     pub fields: Option<String>,
-    /// Value format: "raw" (default) or "json" (decoded).
+    /// Value format: "raw" (default), "json" (decoded) or "base64" (bytes, with `valueEncoding`).
     #[serde(default)]
     pub value_format: Option<String>,
 }
@@ -481,12 +1407,19 @@ pub struct BatchQuery {
     #[serde(rename = "contractId")]
     pub current_account_id: String,
     pub keys: Vec<String>,
+    /// Value format: "raw" (default), "json" (decoded) or "base64" (bytes, with `valueEncoding`).
+    #[serde(default)]
+    pub value_format: Option<String>,
 }
 
 #[derive(Serialize, utoipa::ToSchema)]
 pub struct BatchResultItem {
     pub key: String,
-    pub value: Option<String>,
+    /// Raw value string, or the decoded JSON with `value_format: "json"`.
+    pub value: Option<serde_json::Value>,
+    /// `"base64"` when `value` holds the base64 of the stored bytes.
+    #[serde(rename = "valueEncoding", skip_serializing_if = "Option::is_none")]
+    pub value_encoding: Option<String>,
     pub found: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
@@ -511,6 +1444,9 @@ pub struct SocialGetOptions {
     pub with_block_height: Option<bool>,
     #[serde(default)]
     pub return_deleted: Option<bool>,
+    /// Only return these paths of the assembled tree (e.g. `["*/profile/name"]`).
+    #[serde(default)]
+    pub fields: Option<Vec<String>>,
 }
 
 // POST /v1/social/keys request body
@@ -561,6 +1497,21 @@ pub struct SocialProfileParams {
     #[serde(default)]
     #[serde(alias = "contractId")]
     pub contract_id: Option<String>,
+    /// Comma-separated paths to keep, e.g. `profile/name,profile/image`.
+    #[serde(default)]
+    pub fields: Option<String>,
+}
+
+// GET /v1/social/widget query params
+#[derive(Deserialize, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct SocialWidgetParams {
+    #[serde(alias = "accountId")]
+    pub account_id: String,
+    /// Widget name; the source is read from `widget/{name}`.
+    pub name: String,
+    #[serde(default)]
+    #[serde(alias = "contractId")]
+    pub contract_id: Option<String>,
 }
 
 // GET /v1/social/followers and /v1/social/following query params
@@ -581,6 +1532,13 @@ pub struct SocialFollowParams {
     pub after_account: Option<String>,
 }
 
+// GET /v1/social/followers/count query params
+#[derive(Deserialize, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct SocialFollowCountParams {
+    #[serde(alias = "accountId")]
+    pub account_id: String,
+}
+
 // GET /v1/social/feed/account query params
 #[derive(Deserialize, utoipa::ToSchema, utoipa::IntoParams)]
 pub struct SocialAccountFeedParams {
@@ -613,7 +1571,9 @@ pub struct IndexEntry {
 #[derive(Serialize, utoipa::ToSchema)]
 pub struct SocialFollowResponse {
     pub data: Vec<String>,
-    pub count: usize,
+    /// Total number of accounts, or `null` when it is not known without a
+    /// full scan. Use `/v1/social/followers/count` for the total.
+    pub count: Option<usize>,
     pub meta: PaginationMeta,
 }
 
@@ -627,6 +1587,10 @@ pub enum ErrorCode {
     DatabaseError,
     DatabaseUnavailable,
     TooManyRequests,
+    NotFound,
+    Unauthorized,
+    Timeout,
+    FeatureUnavailable,
 }
 
 /// Structured error response returned by all endpoints on failure.
@@ -634,23 +1598,65 @@ pub enum ErrorCode {
 pub struct ErrorResponse {
     pub error: String,
     pub code: ErrorCode,
+    /// Correlation id of the failed request (echoed in the `X-Request-ID` header).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Every invalid parameter, when the endpoint validates them all at once.
+    /// `error` is then a summary of this list.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+/// One invalid request parameter.
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    /// Split a validator message of the form `"field: message"`.
+    pub fn from_message(msg: &str) -> Self {
+        match msg.split_once(": ") {
+            Some((field, message)) => Self {
+                field: field.to_string(),
+                message: message.to_string(),
+            },
+            None => Self {
+                field: String::new(),
+                message: msg.to_string(),
+            },
+        }
+    }
 }
 
 #[derive(Debug, Serialize, utoipa::ToSchema)]
 pub enum ApiError {
     InvalidParameter(String),
+    InvalidParameters(Vec<FieldError>),
     DatabaseError(String),
     DatabaseUnavailable,
     TooManyRequests(String),
+    NotFound(String),
+    Unauthorized,
+    Timeout,
+    /// The backend started without this feature's tables.
+    FeatureUnavailable(String),
 }
 
 impl ApiError {
     pub fn code(&self) -> ErrorCode {
         match self {
-            ApiError::InvalidParameter(_) => ErrorCode::InvalidParameter,
+            ApiError::InvalidParameter(_) | ApiError::InvalidParameters(_) => {
+                ErrorCode::InvalidParameter
+            }
             ApiError::DatabaseError(_) => ErrorCode::DatabaseError,
             ApiError::DatabaseUnavailable => ErrorCode::DatabaseUnavailable,
             ApiError::TooManyRequests(_) => ErrorCode::TooManyRequests,
+            ApiError::NotFound(_) => ErrorCode::NotFound,
+            ApiError::Unauthorized => ErrorCode::Unauthorized,
+            ApiError::Timeout => ErrorCode::Timeout,
+            ApiError::FeatureUnavailable(_) => ErrorCode::FeatureUnavailable,
         }
     }
 }
@@ -659,9 +1665,20 @@ impl fmt::Display for ApiError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ApiError::InvalidParameter(msg) => write!(f, "Invalid parameter: {}", msg),
+            ApiError::InvalidParameters(errors) => {
+                let summary: Vec<String> = errors
+                    .iter()
+                    .map(|e| format!("{}: {}", e.field, e.message))
+                    .collect();
+                write!(f, "Invalid parameters: {}", summary.join("; "))
+            }
             ApiError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
             ApiError::DatabaseUnavailable => write!(f, "Database unavailable"),
             ApiError::TooManyRequests(msg) => write!(f, "{}", msg),
+            ApiError::NotFound(msg) => write!(f, "Not found: {msg}"),
+            ApiError::Unauthorized => write!(f, "Missing or invalid admin token"),
+            ApiError::Timeout => write!(f, "Request exceeded the server time limit"),
+            ApiError::FeatureUnavailable(msg) => write!(f, "Feature unavailable: {msg}"),
         }
     }
 }
@@ -669,10 +1686,16 @@ impl fmt::Display for ApiError {
 impl ResponseError for ApiError {
     fn error_response(&self) -> HttpResponse {
         let status = match self {
-            ApiError::InvalidParameter(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidParameter(_) | ApiError::InvalidParameters(_) => {
+                StatusCode::BAD_REQUEST
+            }
             ApiError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
             ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::FeatureUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
         };
 
         let mut response = HttpResponse::build(status);
@@ -682,12 +1705,21 @@ impl ResponseError for ApiError {
         response.json(ErrorResponse {
             error: self.to_string(),
             code: self.code(),
+            request_id: crate::handlers::current_request_id(),
+            errors: match self {
+                ApiError::InvalidParameters(errors) => errors.clone(),
+                _ => Vec::new(),
+            },
         })
     }
 }
 
 impl From<anyhow::Error> for ApiError {
     fn from(err: anyhow::Error) -> Self {
+        // A feature disabled at startup is expected, not a database fault
+        if let Some(unavailable) = err.downcast_ref::<crate::degraded::FeatureUnavailable>() {
+            return ApiError::FeatureUnavailable(unavailable.to_string());
+        }
         // Log full error internally for debugging, but return generic message to client
         // to prevent information disclosure (paths, IPs, schema details)
         tracing::error!(
@@ -735,6 +1767,10 @@ pub struct EdgeSourceEntry {
 pub struct StatusResponse {
     pub indexer_block: Option<u64>,
     pub timestamp: String,
+    /// Open `/v1/kv/watch` connections.
+    pub active_watches: usize,
+    /// Configured `MAX_WATCHES`; new watches get 429 once `active_watches` reaches it.
+    pub max_watches: usize,
 }
 
 #[derive(Serialize, utoipa::ToSchema)]
@@ -745,12 +1781,117 @@ pub struct EdgesCountResponse {
     pub count: usize,
 }
 
+/// `GET /v1/kv/contracts/count`. Computed by a keyspace scan, so it may be
+/// slightly off while the indexer is writing.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ContractsCountResponse {
+    pub count: usize,
+}
+
+/// Entries removed by `POST /v1/admin/flush-caches`.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct FlushCachesResponse {
+    pub scan_throttle: usize,
+    pub response_cache: usize,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+pub struct AccountsStalenessParams {
+    #[serde(rename = "contractId")]
+    pub current_account_id: String,
+}
+
+/// `GET /v1/admin/accounts-staleness`: how far the asynchronously populated
+/// `kv_accounts` index trails the writers actually present in `s_kv_last`.
+#[derive(Debug, PartialEq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountsStaleness {
+    pub contract_id: String,
+    /// Distinct writers listed in `kv_accounts`.
+    pub indexed_accounts: usize,
+    /// Distinct writers found by the bounded `s_kv_last` scan.
+    pub scanned_accounts: usize,
+    /// `scanned_accounts - indexed_accounts`. Negative while the scan is truncated.
+    pub delta: i64,
+    /// The scan stopped at its budget (`STALENESS_SCAN_ROWS` rows on Scylla,
+    /// 10,000 `SCAN` calls on Redis); `scanned_accounts` is a lower bound.
+    pub scan_truncated: bool,
+    /// More writers in `s_kv_last` than in `kv_accounts`. A truncated scan can
+    /// only undercount, so this holds regardless of `scan_truncated`.
+    pub lagging: bool,
+}
+
+impl AccountsStaleness {
+    pub fn new(
+        contract_id: &str,
+        indexed_accounts: usize,
+        scanned_accounts: usize,
+        scan_truncated: bool,
+    ) -> Self {
+        let delta = scanned_accounts as i64 - indexed_accounts as i64;
+        Self {
+            contract_id: contract_id.to_string(),
+            indexed_accounts,
+            scanned_accounts,
+            delta,
+            scan_truncated,
+            lagging: delta > 0,
+        }
+    }
+}
+
 // ===== SSE Watch API types =====
 
-pub const MAX_CONCURRENT_WATCHES: usize = 100;
+/// Default for `MAX_WATCHES`.
+pub const DEFAULT_MAX_WATCHES: usize = 100;
 pub const MIN_POLL_INTERVAL: u64 = 2;
 pub const MAX_POLL_INTERVAL: u64 = 30;
 pub const SSE_HEARTBEAT_SECS: u64 = 15;
+/// How long shutdown waits for watch streams to close before stopping anyway.
+pub const WATCH_DRAIN_TIMEOUT_SECS: u64 = 10;
+
+/// Per-tier floor on the `/v1/kv/watch` poll interval, resolved from the
+/// `X-Api-Key` header. Configured with `WATCH_MIN_INTERVAL` (callers without a
+/// listed key) and `WATCH_TIER_KEYS`, comma-separated `<api_key>=<secs>` pairs.
+/// The global `MIN_POLL_INTERVAL`–`MAX_POLL_INTERVAL` clamp still applies.
+#[derive(Debug, Clone, Default)]
+pub struct WatchTiers {
+    pub default_min: u64,
+    pub keys: std::collections::HashMap<String, u64>,
+}
+
+impl WatchTiers {
+    pub fn from_env() -> Self {
+        let default_min = std::env::var("WATCH_MIN_INTERVAL")
+            .ok()
+            .map_or(MIN_POLL_INTERVAL, |s| s.parse().expect("Invalid WATCH_MIN_INTERVAL"));
+        let keys = std::env::var("WATCH_TIER_KEYS")
+            .ok()
+            .map(|s| {
+                s.split(',')
+                    .filter(|pair| !pair.trim().is_empty())
+                    .map(|pair| {
+                        let (key, secs) = pair.trim().split_once('=').expect("Invalid WATCH_TIER_KEYS");
+                        (key.to_string(), secs.parse().expect("Invalid WATCH_TIER_KEYS"))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { default_min, keys }
+    }
+
+    /// Poll interval for a watch asking for `requested` seconds: raised to the
+    /// caller's tier minimum, then held within the global bounds.
+    pub fn poll_interval(&self, requested: u64, api_key: Option<&str>) -> u64 {
+        let tier_min = api_key
+            .and_then(|key| self.keys.get(key))
+            .copied()
+            .unwrap_or(self.default_min);
+        requested
+            .max(tier_min)
+            .clamp(MIN_POLL_INTERVAL, MAX_POLL_INTERVAL)
+    }
+}
 
 /// Parameters for the SSE key watch endpoint.
 #[derive(Deserialize, Clone, utoipa::ToSchema, utoipa::IntoParams)]
@@ -763,15 +1904,46 @@ pub struct WatchParams {
     pub current_account_id: String,
     /// Key to watch for changes.
     pub key: String,
-    /// Poll interval in seconds (default 5, clamped to 2–30).
+    /// Poll interval in seconds (default 5, clamped to 2–30 and raised to the
+    /// caller's `X-Api-Key` tier minimum).
     #[serde(default = "default_watch_interval")]
     pub interval: u64,
+    /// Comma-separated event fields to keep, e.g. `value,blockHeight`.
+    #[serde(default)]
+    pub fields: Option<String>,
 }
 
 fn default_watch_interval() -> u64 {
     5
 }
 
+pub const MAX_AWAIT_TIMEOUT_SECS: u64 = 30;
+pub const AWAIT_POLL_MILLIS: u64 = 500;
+
+/// Parameters for the `/v1/kv/get-await` long poll.
+#[derive(Deserialize, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct GetAwaitParams {
+    #[serde(rename = "accountId")]
+    pub predecessor_id: String,
+    #[serde(rename = "contractId")]
+    pub current_account_id: String,
+    pub key: String,
+    /// Return once the entry's block height is at least this.
+    pub min_block: u64,
+    /// Seconds to wait before giving up with 204 (default 10, max 30).
+    #[serde(default = "default_await_timeout")]
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub fields: Option<String>,
+    /// Value format: "raw" (default), "json" (decoded) or "base64" (bytes, with `valueEncoding`).
+    #[serde(default)]
+    pub value_format: Option<String>,
+}
+
+fn default_await_timeout() -> u64 {
+    10
+}
+
 /// SSE event payload emitted when a watched key changes.
 #[derive(Serialize, utoipa::ToSchema)]
 #[serde(rename_all = "camelCase")]
@@ -786,10 +1958,184 @@ pub struct WatchEvent {
     pub current_account_id: String,
 }
 
+/// Change notification published by the indexer on `changes:{chain}:{contract}`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct KvChange {
+    pub predecessor_id: String,
+    pub key: String,
+    pub value: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+}
+
+impl WatchEvent {
+    /// Build a watch event from a pub/sub change if it concerns the watched
+    /// key and is newer than `last_known_block`.
+    pub fn from_change(
+        change: KvChange,
+        predecessor_id: &str,
+        current_account_id: &str,
+        key: &str,
+        last_known_block: u64,
+    ) -> Option<Self> {
+        if change.predecessor_id != predecessor_id
+            || change.key != key
+            || change.block_height <= last_known_block
+        {
+            return None;
+        }
+        Some(Self {
+            key: change.key,
+            value: change.value,
+            block_height: change.block_height,
+            block_timestamp: change.block_timestamp,
+            predecessor_id: change.predecessor_id,
+            current_account_id: current_account_id.to_string(),
+        })
+    }
+
+    /// Event JSON restricted to `fields` (from [`parse_field_set`]); all
+    /// fields when `None`. Fields a watch event does not carry are ignored.
+    pub fn to_json_with_fields(
+        &self,
+        fields: &Option<std::collections::HashSet<String>>,
+    ) -> serde_json::Value {
+        let mut json = serde_json::to_value(self).unwrap_or_else(|e| {
+            tracing::error!(target: "fastkv-server", error = %e, "Failed to serialize WatchEvent");
+            serde_json::Value::Null
+        });
+        if let (Some(field_set), Some(map)) = (fields, json.as_object_mut()) {
+            map.retain(|name, _| field_set.contains(name));
+        }
+        json
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn kv(key: &str, predecessor_id: &str, block_height: u64) -> KvEntry {
+        KvEntry {
+            predecessor_id: predecessor_id.to_string(),
+            current_account_id: "social.near".to_string(),
+            key: key.to_string(),
+            value: "1".to_string(),
+            block_height,
+            block_timestamp: 0,
+            receipt_id: String::new(),
+            tx_hash: String::new(),
+            order_id: None,
+            content_type: None,
+            is_deleted: false,
+        }
+    }
+
+    #[test]
+    fn test_truncate_value_on_char_boundary() {
+        assert_eq!(truncate_value("hello", 10), "hello");
+        assert_eq!(truncate_value("hello", 5), "hello");
+        assert_eq!(truncate_value("hello", 3), "hel");
+        // "é" is 2 bytes and "€" 3: a cut inside either drops the whole char.
+        assert_eq!(truncate_value("héllo", 2), "h");
+        assert_eq!(truncate_value("héllo", 3), "hé");
+        assert_eq!(truncate_value("a€b", 2), "a");
+        assert_eq!(truncate_value("a€b", 3), "a");
+        assert_eq!(truncate_value("a€b", 4), "a€");
+        assert_eq!(truncate_value("€", 1), "");
+    }
+
+    #[test]
+    fn test_merge_order_is_deterministic() {
+        let expected = [
+            ("a", "bob.near", 9),
+            ("b", "alice.near", 5),
+            ("b", "alice.near", 7),
+            ("b", "bob.near", 1),
+            ("c", "alice.near", 1),
+        ];
+        let mut entries: Vec<KvEntry> = expected.iter().rev().map(|&(k, p, h)| kv(k, p, h)).collect();
+        entries.swap(0, 3);
+        entries.sort_by(KvEntry::merge_order);
+        let got: Vec<(&str, &str, u64)> = entries
+            .iter()
+            .map(|e| (e.key.as_str(), e.predecessor_id.as_str(), e.block_height))
+            .collect();
+        assert_eq!(got, expected);
+
+        assert_eq!(kv("a", "x", 1).merge_order(&kv("a", "x", 1)), std::cmp::Ordering::Equal);
+    }
+
+    #[test]
+    fn test_resolve_pointer() {
+        let value = r#"{"image":{"url":"ipfs://x"},"tags":["a","b"],"a/b":{"~k":1}}"#;
+        assert_eq!(resolve_pointer(value, "/image/url").unwrap(), "ipfs://x");
+        assert_eq!(resolve_pointer(value, "/tags/1").unwrap(), "b");
+        assert_eq!(resolve_pointer(value, "/a~1b/~0k").unwrap(), 1);
+        assert_eq!(resolve_pointer(value, "").unwrap()["tags"][0], "a");
+
+        for missing in ["/image/alt", "/tags/2", "/tags/-", "/tags/01", "/image/url/x"] {
+            assert!(matches!(resolve_pointer(value, missing), Err(ApiError::NotFound(_))), "{missing}");
+        }
+        assert!(matches!(resolve_pointer("not json", "/x"), Err(ApiError::NotFound(_))));
+        assert!(matches!(resolve_pointer(value, "image"), Err(ApiError::InvalidParameter(_))));
+    }
+
+    #[test]
+    fn test_sample_seed_probe_points() {
+        assert_eq!(sample_seed(&[], 5), None);
+
+        let keys: Vec<String> = (0..80).map(|i| format!("k{i:02}")).collect();
+        assert_eq!(sample_seed(&keys, 0), None);
+        assert_eq!(sample_seed(&keys, 1), Some("k09"));
+        assert_eq!(sample_seed(&keys, 7), Some("k69"));
+        assert_eq!(sample_seed(&keys, 9), sample_seed(&keys, 1));
+    }
+
+    #[test]
+    fn test_value_type_labels() {
+        let cases = [
+            (r#"{"name":"Alice"}"#, "object"),
+            ("[1,2]", "array"),
+            ("42", "number"),
+            ("-1.5e3", "number"),
+            (r#""hello""#, "string"),
+            ("true", "boolean"),
+            ("false", "boolean"),
+            ("null", "null"),
+            ("  {} ", "object"),
+            (r#"{"name":"#, "invalid"),
+            ("nul", "invalid"),
+            ("plain text", "invalid"),
+            ("", "invalid"),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(value_type(raw), expected, "raw value {raw:?}");
+        }
+    }
+
+    #[test]
+    fn test_value_type_field() {
+        let entry = KvEntry {
+            predecessor_id: "alice.near".to_string(),
+            current_account_id: "social.near".to_string(),
+            key: "profile".to_string(),
+            value: "null".to_string(),
+            block_height: 1,
+            block_timestamp: 2,
+            receipt_id: "r".to_string(),
+            tx_hash: "t".to_string(),
+            order_id: None,
+            content_type: None,
+            is_deleted: true,
+        };
+        let fields = parse_field_set(&Some("key,valueType".to_string())).unwrap();
+        assert_eq!(
+            entry.to_json_with_fields(&fields),
+            serde_json::json!({ "key": "profile", "valueType": "null" })
+        );
+    }
+
     #[test]
     fn test_kv_entry_serialization() {
         let entry = KvEntry {
@@ -801,6 +2147,8 @@ mod tests {
             block_timestamp: 1234567890123456789,
             receipt_id: "abc123".to_string(),
             tx_hash: "def456".to_string(),
+            order_id: None,
+            content_type: None,
             is_deleted: false,
         };
 
@@ -825,6 +2173,8 @@ mod tests {
             block_timestamp: 200,
             receipt_id: "r".to_string(),
             tx_hash: "t".to_string(),
+            order_id: None,
+            content_type: None,
             is_deleted: true,
         };
 
@@ -846,6 +2196,8 @@ mod tests {
             block_timestamp: 200,
             receipt_id: "r".to_string(),
             tx_hash: "t".to_string(),
+            order_id: None,
+            content_type: None,
             is_deleted: false,
         };
 
@@ -857,12 +2209,13 @@ mod tests {
     }
 
     #[test]
-    fn test_should_decode() {
-        assert!(should_decode(&Some("json".to_string())).unwrap());
-        assert!(!should_decode(&Some("raw".to_string())).unwrap());
-        assert!(!should_decode(&None).unwrap());
+    fn test_parse_value_format() {
+        assert_eq!(parse_value_format(&Some("json".to_string())).unwrap(), ValueFormat::Json);
+        assert_eq!(parse_value_format(&Some("raw".to_string())).unwrap(), ValueFormat::Raw);
+        assert_eq!(parse_value_format(&None).unwrap(), ValueFormat::Raw);
+        assert_eq!(parse_value_format(&Some("base64".to_string())).unwrap(), ValueFormat::Base64);
         // Invalid value_format
-        assert!(should_decode(&Some("invalid".to_string())).is_err());
+        assert!(parse_value_format(&Some("invalid".to_string())).is_err());
     }
 
     #[test]
@@ -885,6 +2238,7 @@ mod tests {
             truncated: false,
             next_cursor: Some("abc".to_string()),
             dropped_rows: None,
+            partition_exists: None,
         };
         let json = serde_json::to_value(&meta).unwrap();
         assert_eq!(json["has_more"], true);
@@ -897,6 +2251,7 @@ mod tests {
             truncated: true,
             next_cursor: None,
             dropped_rows: None,
+            partition_exists: None,
         };
         let json = serde_json::to_value(&meta_no_cursor).unwrap();
         assert_eq!(json["truncated"], true);
@@ -910,6 +2265,7 @@ mod tests {
             truncated: false,
             next_cursor: Some("last_key".to_string()),
             dropped_rows: None,
+            partition_exists: None,
         };
         let json = serde_json::to_value(&meta).unwrap();
         assert_eq!(json["has_more"], false);
@@ -924,6 +2280,7 @@ mod tests {
             truncated: false,
             next_cursor: None,
             dropped_rows: Some(3),
+            partition_exists: None,
         };
         let json = serde_json::to_value(&meta).unwrap();
         assert_eq!(json["dropped_rows"], 3);
@@ -945,6 +2302,33 @@ mod tests {
         assert!(parse_field_set(&input).is_err());
     }
 
+    #[test]
+    fn test_history_entry_order_id_matches_cursor() {
+        let entry = KvEntry {
+            order_id: Some(300_012_005),
+            content_type: None,
+            ..kv("profile", "alice.near", 139000500)
+        };
+        let json = entry.to_json_with_fields(&None);
+        assert_eq!(json["orderId"], 300_012_005);
+        assert_eq!(json["blockHeight"], 139000500);
+        let fields = parse_field_set(&Some("blockHeight,orderId".to_string())).unwrap();
+        assert_eq!(
+            entry.to_json_with_fields(&fields),
+            serde_json::json!({ "blockHeight": 139000500, "orderId": 300_012_005 })
+        );
+
+        // The cursor built from an entry resumes at that entry's (blockHeight, orderId)
+        let cursor = entry.history_cursor().unwrap();
+        assert_eq!(parse_history_cursor(&cursor).unwrap(), (139000500, 300_012_005));
+
+        // Non-history entries carry no orderId
+        let current = kv("profile", "alice.near", 1);
+        assert!(current.to_json_with_fields(&None).get("orderId").is_none());
+        assert!(current.to_json_with_fields(&fields).get("orderId").is_none());
+        assert!(current.history_cursor().is_none());
+    }
+
     #[test]
     fn test_parse_field_set_none() {
         assert!(parse_field_set(&None).unwrap().is_none());
@@ -956,15 +2340,128 @@ mod tests {
         assert!(parse_field_set(&input).unwrap().is_none());
     }
 
+    #[test]
+    fn test_reverse_source_selects_writers_statement() {
+        assert_eq!(ReverseSource::default(), ReverseSource::Table);
+        assert_eq!("view".parse::<ReverseSource>(), Ok(ReverseSource::View));
+        assert!("mv".parse::<ReverseSource>().is_err());
+
+        assert_eq!(ReverseSource::Table.writers_statement(false), WritersStatement::TableList);
+        assert_eq!(ReverseSource::Table.writers_statement(true), WritersStatement::TableCursor);
+        assert_eq!(ReverseSource::View.writers_statement(false), WritersStatement::View);
+        assert_eq!(ReverseSource::View.writers_statement(true), WritersStatement::View);
+    }
+
+    #[test]
+    fn test_page_latest_writers() {
+        let latest = || {
+            let deleted = KvEntry { value: "null".to_string(), ..kv("profile/name", "dave.near", 4) };
+            vec![kv("profile/name", "carol.near", 3), kv("profile/name", "alice.near", 1), deleted, kv("profile/name", "bob.near", 2)]
+        };
+        let writers = |params: serde_json::Value| -> Vec<String> {
+            let params: WritersParams = serde_json::from_value(params).unwrap();
+            let (page, _) = params.page_latest_writers(latest());
+            page.into_iter().map(|e| e.predecessor_id).collect()
+        };
+        let base = serde_json::json!({ "contractId": "social.near", "key": "profile/name" });
+        let with = |extra: serde_json::Value| {
+            let mut params = base.clone();
+            params.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            params
+        };
+
+        // Sorted by accountId, then limit with has_more
+        assert_eq!(writers(base.clone()), ["alice.near", "bob.near", "carol.near", "dave.near"]);
+        let params: WritersParams = serde_json::from_value(with(serde_json::json!({ "limit": 2 }))).unwrap();
+        let (page, has_more) = params.page_latest_writers(latest());
+        assert_eq!(page.len(), 2);
+        assert!(has_more);
+
+        // after_account resumes after the cursor and ignores offset; offset alone skips
+        assert_eq!(writers(with(serde_json::json!({ "after_account": "bob.near", "offset": 1 }))), ["carol.near", "dave.near"]);
+        assert_eq!(writers(with(serde_json::json!({ "offset": 3 }))), ["dave.near"]);
+
+        // Writer and deletion filters
+        assert_eq!(writers(with(serde_json::json!({ "exclude_deleted": true }))), ["alice.near", "bob.near", "carol.near"]);
+        assert_eq!(writers(with(serde_json::json!({ "exclude_account": "alice.near" }))), ["bob.near", "carol.near", "dave.near"]);
+        assert_eq!(writers(with(serde_json::json!({ "accountId": "carol.near" }))), ["carol.near"]);
+    }
+
+    #[test]
+    fn test_watch_tier_poll_interval() {
+        let tiers = WatchTiers {
+            default_min: 10,
+            keys: [("premium-key".to_string(), 2)].into_iter().collect(),
+        };
+        // Lower tier (no key, or an unknown one) is raised to its minimum
+        assert_eq!(tiers.poll_interval(2, None), 10);
+        assert_eq!(tiers.poll_interval(3, Some("guess")), 10);
+        assert_eq!(tiers.poll_interval(20, None), 20);
+        // Premium gets the faster interval
+        assert_eq!(tiers.poll_interval(2, Some("premium-key")), 2);
+        // Global bounds stay the hard floor and ceiling
+        assert_eq!(tiers.poll_interval(0, Some("premium-key")), MIN_POLL_INTERVAL);
+        assert_eq!(tiers.poll_interval(600, None), MAX_POLL_INTERVAL);
+        let slow = WatchTiers { default_min: 60, ..Default::default() };
+        assert_eq!(slow.poll_interval(5, None), MAX_POLL_INTERVAL);
+    }
+
+    #[test]
+    fn test_drop_threshold() {
+        let off = DropThreshold::default();
+        assert!(off.check(1000, 1000).is_ok());
+
+        let rows = DropThreshold {
+            max_rows: Some(5),
+            max_fraction: None,
+        };
+        assert!(rows.check(5, 10).is_ok());
+        assert!(matches!(rows.check(6, 10), Err(ApiError::DatabaseError(_))));
+
+        let fraction = DropThreshold {
+            max_rows: None,
+            max_fraction: Some(0.5),
+        };
+        // Partial page under the limit is still returned
+        assert!(fraction.check(5, 10).is_ok());
+        assert!(fraction.check(6, 10).is_err());
+        assert!(fraction.check(0, 0).is_ok());
+    }
+
     #[test]
     fn test_error_response_serialization() {
         let resp = ErrorResponse {
             error: "test".to_string(),
             code: ErrorCode::InvalidParameter,
+            request_id: None,
+            errors: Vec::new(),
         };
         let json = serde_json::to_value(&resp).unwrap();
         assert_eq!(json["error"], "test");
         assert_eq!(json["code"], "INVALID_PARAMETER");
+        assert!(json.get("errors").is_none());
+    }
+
+    #[test]
+    fn test_collapse_unchanged_drops_repeats() {
+        let rows = [
+            ("profile/name", "\"Alice\""),
+            ("profile/name", "\"Alice\""),
+            ("profile/name", "\"Alicia\""),
+            ("profile/name", "\"Alicia\""),
+            ("profile/name", "\"Alicia\""),
+            ("profile/name", "\"Alice\""),
+            ("profile/bio", "\"Alice\""),
+        ];
+        let mut collapse = CollapseUnchanged::default();
+        let kept: Vec<usize> = rows
+            .iter()
+            .enumerate()
+            .filter(|(_, (key, value))| collapse.keep(key, value))
+            .map(|(i, _)| i)
+            .collect();
+        // Reverting to an earlier value is a change; other keys are tracked separately
+        assert_eq!(kept, vec![0, 2, 5, 6]);
     }
 
     #[test]
@@ -1002,4 +2499,307 @@ mod tests {
         assert!(parse_timeline_cursor("123").is_err());
         assert!(parse_timeline_cursor("-1:key").is_err());
     }
+
+    #[test]
+    fn test_opaque_cursor_roundtrip() {
+        let c = encode_history_cursor(139000500, -3);
+        assert!(!c.contains(':') && !c.contains('+') && !c.contains('/') && !c.contains('='));
+        assert_eq!(parse_history_cursor(&c).unwrap(), (139000500, -3));
+
+        let c = encode_timeline_cursor(100, "key:with:colons/é");
+        assert!(!c.contains(':'));
+        assert_eq!(parse_timeline_cursor(&c).unwrap(), (100, "key:with:colons/é".to_string()));
+        let c = encode_timeline_cursor(0, "");
+        assert_eq!(parse_timeline_cursor(&c).unwrap(), (0, String::new()));
+    }
+
+    #[test]
+    fn test_opaque_cursor_rejects_tampering() {
+        let c = encode_history_cursor(139000500, 3);
+
+        // Flip one payload bit and re-encode: the checksum no longer matches
+        let mut bytes = URL_SAFE_NO_PAD.decode(&c).unwrap();
+        bytes[9] ^= 1;
+        assert!(parse_history_cursor(&URL_SAFE_NO_PAD.encode(&bytes)).is_err());
+
+        // Truncated, not base64, or empty
+        assert!(parse_history_cursor(&c[..c.len() - 2]).is_err());
+        assert!(parse_history_cursor("not*base64").is_err());
+        assert!(parse_history_cursor("AAAA").is_err());
+
+        // A valid cursor for another endpoint
+        let timeline = encode_timeline_cursor(139000500, "k");
+        assert!(parse_history_cursor(&timeline).is_err());
+        assert!(parse_timeline_cursor(&c).is_err());
+
+        // Unknown version with a correct checksum
+        let mut body = URL_SAFE_NO_PAD.decode(&c).unwrap();
+        body.truncate(body.len() - 4);
+        body[0] = 2;
+        body.extend_from_slice(&crc32(&body).to_be_bytes());
+        assert!(parse_history_cursor(&URL_SAFE_NO_PAD.encode(&body)).is_err());
+    }
+
+    #[test]
+    fn test_key_and_account_cursors() {
+        let c = encode_key_cursor("profile/name:é");
+        assert!(!c.contains('/') && !c.contains(':'));
+        assert_eq!(parse_key_cursor(&c), "profile/name:é");
+        let c = encode_account_cursor("alice.near");
+        assert_eq!(parse_account_cursor(&c), "alice.near");
+
+        // Plain values from older clients still work
+        assert_eq!(parse_key_cursor("profile/name"), "profile/name");
+        assert_eq!(parse_account_cursor("alice.near"), "alice.near");
+        // A cursor of the other kind is not decoded
+        assert_eq!(parse_account_cursor(&encode_key_cursor("k")), encode_key_cursor("k"));
+    }
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    fn kv_change(predecessor_id: &str, key: &str, block_height: u64) -> KvChange {
+        serde_json::from_value(serde_json::json!({
+            "predecessor_id": predecessor_id,
+            "key": key,
+            "value": "\"hello\"",
+            "block_height": block_height,
+            "block_timestamp": 1700000000000000000u64,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_watch_event_from_change() {
+        let change = kv_change("alice.near", "profile/name", 150);
+        let event = WatchEvent::from_change(change, "alice.near", "social.near", "profile/name", 100)
+            .expect("matching change should produce an event");
+        assert_eq!(event.key, "profile/name");
+        assert_eq!(event.block_height, 150);
+        assert_eq!(event.current_account_id, "social.near");
+        assert_eq!(event.value, "\"hello\"");
+    }
+
+    #[test]
+    fn test_watch_event_from_change_ignores_other_keys_and_stale() {
+        let other_key = kv_change("alice.near", "profile/bio", 150);
+        assert!(WatchEvent::from_change(other_key, "alice.near", "social.near", "profile/name", 100).is_none());
+
+        let other_account = kv_change("bob.near", "profile/name", 150);
+        assert!(WatchEvent::from_change(other_account, "alice.near", "social.near", "profile/name", 100).is_none());
+
+        let stale = kv_change("alice.near", "profile/name", 100);
+        assert!(WatchEvent::from_change(stale, "alice.near", "social.near", "profile/name", 100).is_none());
+    }
+
+    #[test]
+    fn test_watch_event_projection() {
+        let change = kv_change("alice.near", "profile/name", 150);
+        let event = WatchEvent::from_change(change, "alice.near", "social.near", "profile/name", 100).unwrap();
+
+        let fields = parse_field_set(&Some("value,blockHeight,txHash".to_string())).unwrap();
+        assert_eq!(
+            event.to_json_with_fields(&fields),
+            serde_json::json!({"value": "\"hello\"", "blockHeight": 150})
+        );
+
+        let full = event.to_json_with_fields(&None);
+        assert_eq!(full.as_object().unwrap().len(), 6);
+        assert_eq!(full["accountId"], "alice.near");
+    }
+
+    fn make_err() -> String {
+        "test deser error".to_string()
+    }
+
+    #[tokio::test]
+    async fn test_collect_page_overfetch() {
+        let items: Vec<Result<i32, String>> = (1..=6).map(Ok).collect();
+        let mut s = futures::stream::iter(items);
+        let page = collect_page(&mut s, 5, 0, None, Some).await;
+        assert_eq!(page.items.len(), 5);
+        assert!(page.has_more);
+        assert!(!page.truncated);
+        assert_eq!(page.dropped_rows, 0);
+    }
+
+    #[tokio::test]
+    async fn test_collect_page_under_limit() {
+        let items: Vec<Result<i32, String>> = (1..=3).map(Ok).collect();
+        let mut s = futures::stream::iter(items);
+        let page = collect_page(&mut s, 5, 0, None, Some).await;
+        assert_eq!(page.items.len(), 3);
+        assert!(!page.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_collect_page_with_offset() {
+        let items: Vec<Result<i32, String>> = (1..=10).map(Ok).collect();
+        let mut s = futures::stream::iter(items);
+        let page = collect_page(&mut s, 5, 3, None, Some).await;
+        assert_eq!(page.items, vec![4, 5, 6, 7, 8]);
+        assert!(page.has_more); // item 9 caused overfetch
+    }
+
+    #[tokio::test]
+    async fn test_collect_page_dropped_rows() {
+        let items: Vec<Result<i32, String>> =
+            vec![Ok(1), Err(make_err()), Ok(2), Err(make_err()), Ok(3)];
+        let mut s = futures::stream::iter(items);
+        let page = collect_page(&mut s, 10, 0, None, Some).await;
+        assert_eq!(page.items, vec![1, 2, 3]);
+        assert_eq!(page.dropped_rows, 2);
+        assert_eq!(page.scanned, 5);
+        assert!(!page.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_collect_page_scan_cap() {
+        let items: Vec<Result<i32, String>> = (1..=20).map(Ok).collect();
+        let mut s = futures::stream::iter(items);
+        let page = collect_page(&mut s, 100, 0, Some(10), Some).await;
+        assert!(page.truncated);
+        assert_eq!(page.items.len(), 10);
+        assert_eq!(page.scanned, 10);
+        assert!(!page.has_more); // caller computes in scan-cap mode
+    }
+
+    #[tokio::test]
+    async fn test_collect_page_filter_via_transform() {
+        // Only keep odd numbers; offset should count filtered items
+        let items: Vec<Result<i32, String>> = (1..=10).map(Ok).collect();
+        let mut s = futures::stream::iter(items);
+        let page = collect_page(
+            &mut s,
+            3,
+            1,
+            None,
+            |n| {
+                if n % 2 == 1 {
+                    Some(n)
+                } else {
+                    None
+                }
+            },
+        )
+        .await;
+        // Odd items: 1, 3, 5, 7, 9. Skip 1 (offset), take 3+1: [3,5,7,9]
+        assert_eq!(page.items, vec![3, 5, 7]);
+        assert!(page.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_collect_page_empty_stream() {
+        let items: Vec<Result<i32, String>> = vec![];
+        let mut s = futures::stream::iter(items);
+        let page = collect_page(&mut s, 10, 0, None, Some).await;
+        assert!(page.items.is_empty());
+        assert!(!page.has_more);
+        assert!(!page.truncated);
+        assert_eq!(page.dropped_rows, 0);
+    }
+
+    #[tokio::test]
+    async fn test_collect_page_all_filtered() {
+        // Every item filtered out by transform — result is empty, no has_more
+        let items: Vec<Result<i32, String>> = (1..=10).map(Ok).collect();
+        let mut s = futures::stream::iter(items);
+        let page: PageResult<i32> = collect_page(&mut s, 5, 0, None, |_| None).await;
+        assert!(page.items.is_empty());
+        assert!(!page.has_more);
+        assert!(!page.truncated);
+        assert_eq!(page.dropped_rows, 0);
+    }
+
+    /// Xorshift generator for `test_collect_page_invariants`: reproducible
+    /// from the seed in a failure message, without a proptest dependency.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    /// Random streams (with failed rows), limits, offsets, filters and scan
+    /// caps, checked against a plain slice model of each mode.
+    #[tokio::test]
+    async fn test_collect_page_invariants() {
+        for seed in 1..=1000u64 {
+            let mut rng = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            // `None` is a row that fails to deserialize.
+            let rows: Vec<Option<i32>> = (0..rng.below(40))
+                .map(|i| (rng.below(5) != 0).then_some(i as i32))
+                .collect();
+            let limit = rng.below(12);
+            let offset = rng.below(10);
+            let modulus = 1 + rng.below(3) as i32;
+            let scan_cap = (rng.below(3) == 0).then(|| rng.below(30));
+            let keep = |n: &i32| n % modulus == 0;
+            let ctx = format!(
+                "seed={seed} rows={rows:?} limit={limit} offset={offset} modulus={modulus} scan_cap={scan_cap:?}"
+            );
+
+            let results: Vec<Result<i32, String>> =
+                rows.iter().map(|r| r.ok_or_else(make_err)).collect();
+            let mut s = futures::stream::iter(results);
+            let page = collect_page(&mut s, limit, offset, scan_cap, |n| keep(&n).then_some(n)).await;
+
+            let errors_in = |rows: &[Option<i32>]| rows.iter().filter(|r| r.is_none()).count();
+            match scan_cap {
+                Some(cap) => {
+                    // Every kept row within the cap; offset/limit are the caller's job.
+                    let window = &rows[..rows.len().min(cap)];
+                    let expected: Vec<i32> = window.iter().flatten().copied().filter(keep).collect();
+                    assert_eq!(page.items, expected, "{ctx}");
+                    assert_eq!(page.scanned, window.len(), "{ctx}");
+                    assert_eq!(page.dropped_rows, errors_in(window), "{ctx}");
+                    assert_eq!(page.truncated, rows.len() > cap, "{ctx}");
+                    assert!(!page.has_more, "{ctx}");
+                }
+                None => {
+                    // Filtered-out rows don't count toward offset.
+                    let kept: Vec<i32> = rows.iter().flatten().copied().filter(keep).collect();
+                    let expected: Vec<i32> = kept.iter().skip(offset).take(limit).copied().collect();
+                    assert!(page.items.len() <= limit, "{ctx}");
+                    assert_eq!(page.items, expected, "{ctx}");
+                    assert_eq!(page.has_more, kept.len() > offset + limit, "{ctx}");
+                    assert!(!page.truncated, "{ctx}");
+                    // Rows after the overfetch item are never read.
+                    assert_eq!(page.dropped_rows, errors_in(&rows[..page.scanned]), "{ctx}");
+                    if !page.has_more {
+                        assert_eq!(page.scanned, rows.len(), "{ctx}");
+                        assert_eq!(page.dropped_rows, errors_in(&rows), "{ctx}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_accounts_staleness_delta() {
+        let caught_up = AccountsStaleness::new("social.near", 5, 5, false);
+        assert_eq!((caught_up.delta, caught_up.lagging), (0, false));
+
+        let lagging = AccountsStaleness::new("social.near", 3, 7, false);
+        assert_eq!((lagging.delta, lagging.lagging), (4, true));
+
+        // A truncated scan undercounts: a negative delta is not a verdict.
+        let truncated = AccountsStaleness::new("social.near", 20, 12, true);
+        assert_eq!((truncated.delta, truncated.lagging), (-8, false));
+        assert!(truncated.scan_truncated);
+    }
+
+    #[test]
+    fn test_detect_encrypted() {
+        assert_eq!(detect_encrypted("enc:AES256:abc123:Zm9v"), Some("abc123"));
+        assert_eq!(detect_encrypted("\"enc:AES256:abc123:Zm9v\""), Some("abc123"));
+        assert_eq!(detect_encrypted("enc:AES256:abc123"), None);
+        assert_eq!(detect_encrypted("\"Alice\""), None);
+    }
 }
@@ -1,25 +1,210 @@
 use anyhow::Result;
+use fastkv_common::{keys, StoredKvEntry};
+use futures::{Stream, StreamExt};
 use redis::{AsyncCommands, Client as RedisClient};
-use serde::{Deserialize, Serialize};
-
-use crate::models::{KvEntry, HistoryParams, WritersParams, TimelineParams};
-
-/// Internal stored entry for Redis JSON serialization
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct StoredKvEntry {
-    predecessor_id: String,
-    current_account_id: String,
-    key: String,
-    value: String,
-    block_height: u64,
-    block_timestamp: u64,
-    receipt_id: String,
-    tx_hash: String,
+use std::collections::BTreeSet;
+use std::future::Future;
+
+use crate::models::{
+    sample_seed, AccountsStaleness, CollapseUnchanged, HistoryExportParams, KvChange, KvEntry, HistoryParams,
+    SampleParams, TimelineParams, WritersParams, SAMPLE_SCAN_BUDGET,
+};
+
+/// Rows read per round-trip by `export_kv_history`.
+const HISTORY_EXPORT_PAGE_SIZE: usize = 1000;
+
+/// `SCAN` calls (`COUNT 1000` each) `query_accounts_staleness` makes before
+/// reporting a truncated scan. `STALENESS_SCAN_ROWS` caps matched rows on
+/// Scylla; a Redis `SCAN` walks the whole keyspace, so it gets its own budget.
+const STALENESS_SCAN_CALLS: usize = 10_000;
+
+/// Writer of `redis_key` when it is a `kv:{writer}:{contract_id}:{key}` key.
+fn staleness_writer<'a>(redis_key: &'a str, contract_id: &str) -> Option<&'a str> {
+    // Account IDs never contain ':', so the writer is the first segment.
+    let (writer, rest) = redis_key.strip_prefix("kv:")?.split_once(':')?;
+    rest.strip_prefix(contract_id)?.starts_with(':').then_some(writer)
+}
+
+/// Parse a stored entry, logging (and returning `None` for) unreadable JSON.
+fn parse_stored(redis_key: &str, json: &str) -> Option<KvEntry> {
+    match serde_json::from_str::<StoredKvEntry>(json) {
+        Ok(stored) => Some(stored.into()),
+        Err(e) => {
+            tracing::warn!(target: "fastkv-server", key = %redis_key, error = %e, "Failed to deserialize stored entry");
+            None
+        }
+    }
+}
+
+/// Read up to `max_rows` sorted-set rows scored from `start` upwards, `page_size`
+/// at a time, stopping at the first short page. `fetch(min, skip, count)` returns
+/// `(member, score)` pairs scored `>= min`, skipping the first `skip` of them.
+/// Each page starts at the last score seen, so `skip` only covers rows sharing
+/// that score rather than growing with every page. An error ends the stream.
+pub(crate) fn paged_rows<F, Fut>(
+    start: i64,
+    max_rows: usize,
+    page_size: usize,
+    mut fetch: F,
+) -> impl Stream<Item = Result<String>>
+where
+    F: FnMut(i64, usize, usize) -> Fut,
+    Fut: Future<Output = Result<Vec<(String, i64)>>>,
+{
+    async_stream::try_stream! {
+        let (mut min, mut skip, mut read) = (start, 0, 0);
+        while read < max_rows {
+            let count = page_size.min(max_rows - read);
+            let page = fetch(min, skip, count).await?;
+            let len = page.len();
+            for (row, score) in page {
+                if score == min {
+                    skip += 1;
+                } else {
+                    (min, skip) = (score, 1);
+                }
+                yield row;
+            }
+            if len < count {
+                break;
+            }
+            read += len;
+        }
+    }
+}
+
+/// A key's history in `[start, end]` oldest first, up to `max_rows` entries,
+/// read through `fetch` as in [`paged_rows`].
+fn history_rows<F, Fut>(
+    history_key: String,
+    (start, end): (i64, i64),
+    max_rows: usize,
+    mut fetch: F,
+) -> impl Stream<Item = Result<KvEntry>>
+where
+    F: FnMut(i64, i64, usize, usize) -> Fut,
+    Fut: Future<Output = Result<Vec<(String, i64)>>>,
+{
+    paged_rows(start, max_rows, HISTORY_EXPORT_PAGE_SIZE, move |min, skip, count| fetch(min, end, skip, count))
+        .filter_map(move |row| {
+            let entry = match row {
+                Ok(json) => parse_stored(&history_key, &json).map(Ok),
+                Err(e) => Some(Err(e)),
+            };
+            std::future::ready(entry)
+        })
+}
+
+/// Drop `kv:{predecessor}:…` keys of the excluded writer before paging, so an
+/// excluded account doesn't leave a short page.
+fn writer_keys_page(keys: Vec<String>, params: &WritersParams) -> (Vec<String>, bool) {
+    let mut keys: Vec<String> = keys
+        .into_iter()
+        .filter(|k| !k.split(':').nth(1).is_some_and(|pred| params.excludes_writer(pred)))
+        .collect();
+    let has_more = keys.len() > params.limit;
+    keys.truncate(params.limit);
+    (keys, has_more)
+}
+
+/// Whether `account` is in `[from, to)` and strictly after the cursor.
+fn in_account_range(account: &str, from: Option<&str>, to: Option<&str>, after: Option<&str>) -> bool {
+    from.is_none_or(|f| account >= f)
+        && to.is_none_or(|t| account < t)
+        && after.is_none_or(|c| account > c)
+}
+
+/// The smallest `offset + limit + 1` distinct names seen so far, so a full
+/// `SCAN` can be paged in sorted order with bounded memory. A rehash
+/// returning a key twice is harmless.
+struct SortedPage {
+    names: BTreeSet<String>,
+    offset: usize,
+    limit: usize,
+}
+
+impl SortedPage {
+    fn new(offset: usize, limit: usize) -> Self {
+        Self { names: BTreeSet::new(), offset, limit }
+    }
+
+    fn insert(&mut self, name: String) {
+        self.names.insert(name);
+        if self.names.len() > self.offset + self.limit + 1 {
+            self.names.pop_last();
+        }
+    }
+
+    /// The `limit` names after the first `offset`, and whether more remain.
+    fn finish(self) -> (Vec<String>, bool) {
+        let has_more = self.names.len() > self.offset + self.limit;
+        let page = self.names.into_iter().skip(self.offset).take(self.limit).collect();
+        (page, has_more)
+    }
+}
+
+/// A `/v1/kv/query` page built from scanned key names: keys after the
+/// `after_key` cursor go to the page, the rest are only counted so the page's
+/// first key gets its position in the whole prefix.
+struct KeyPage<'a> {
+    page: SortedPage,
+    after_key: Option<&'a str>,
+    before_cursor: usize,
+}
+
+impl<'a> KeyPage<'a> {
+    fn new(after_key: Option<&'a str>, offset: usize, limit: usize) -> Self {
+        // A cursor replaces the offset, as on Scylla.
+        let offset = if after_key.is_some() { 0 } else { offset };
+        Self { page: SortedPage::new(offset, limit), after_key, before_cursor: 0 }
+    }
+
+    fn insert(&mut self, key: &str) {
+        if self.after_key.is_some_and(|after| key <= after) {
+            self.before_cursor += 1;
+        } else {
+            self.page.insert(key.to_string());
+        }
+    }
+
+    /// Returns `(keys, has_more, first_rank)`.
+    fn finish(self) -> (Vec<String>, bool, usize) {
+        let first_rank = self.before_cursor + self.page.offset;
+        let (keys, has_more) = self.page.finish();
+        (keys, has_more, first_rank)
+    }
+}
+
+/// Parse the members of a `receipt:*`/`tx:*` set, sorted by (contract, account, key).
+/// Returns (entries, dropped_rows).
+fn entries_from_index_members(members: Vec<String>) -> (Vec<KvEntry>, usize) {
+    let total = members.len();
+    let mut entries: Vec<KvEntry> = members
+        .iter()
+        .filter_map(|json| serde_json::from_str::<StoredKvEntry>(json).ok())
+        .map(KvEntry::from)
+        .collect();
+    let dropped = total - entries.len();
+    entries.sort_by(|a, b| {
+        (&a.current_account_id, &a.predecessor_id, &a.key)
+            .cmp(&(&b.current_account_id, &b.predecessor_id, &b.key))
+    });
+    (entries, dropped)
+}
+
+/// Sort scanned Redis keys and take up to `limit` after the sampled seed.
+fn sample_window(mut keys: Vec<String>, limit: usize, seed: u64) -> Vec<String> {
+    keys.sort_unstable();
+    let start = match sample_seed(&keys, seed) {
+        Some(after) => keys.partition_point(|k| k.as_str() <= after),
+        None => 0,
+    };
+    keys.into_iter().skip(start).take(limit).collect()
 }
 
 impl From<StoredKvEntry> for KvEntry {
     fn from(s: StoredKvEntry) -> Self {
-        let is_deleted = s.value == "null";
+        let is_deleted = s.is_deleted();
         Self {
             predecessor_id: s.predecessor_id,
             current_account_id: s.current_account_id,
@@ -29,6 +214,9 @@ impl From<StoredKvEntry> for KvEntry {
             block_timestamp: s.block_timestamp,
             receipt_id: s.receipt_id,
             tx_hash: s.tx_hash,
+            // Not stored: adding it to history members would change their identity.
+            order_id: None,
+            content_type: s.content_type,
             is_deleted,
         }
     }
@@ -43,10 +231,14 @@ impl RedisDb {
     pub async fn new(chain_id: String) -> Result<Self> {
         let redis_url = std::env::var("REDIS_URL")
             .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
-        
+        Self::connect(&redis_url, chain_id).await
+    }
+
+    /// Connect to `redis_url` and check it answers `PING`.
+    pub async fn connect(redis_url: &str, chain_id: String) -> Result<Self> {
         tracing::info!("Connecting to Redis: {}", redis_url);
         
-        let client = redis::Client::open(redis_url.as_str())?;
+        let client = redis::Client::open(redis_url)?;
         
         // Test connection
         let mut conn = client.get_multiplexed_async_connection().await?;
@@ -62,31 +254,6 @@ impl RedisDb {
         Ok(())
     }
     
-    // Key format helpers
-    fn kv_key(&self, predecessor_id: &str, current_account_id: &str, key: &str) -> String {
-        format!("kv:{}:{}:{}", predecessor_id, current_account_id, key)
-    }
-    
-    fn kv_prefix(&self, predecessor_id: &str, current_account_id: &str) -> String {
-        format!("kv:{}:{}:", predecessor_id, current_account_id)
-    }
-    
-    fn history_key(&self, predecessor_id: &str, current_account_id: &str, key: &str) -> String {
-        format!("history:{}:{}:{}", predecessor_id, current_account_id, key)
-    }
-    
-    fn accounts_key(&self, current_account_id: &str) -> String {
-        format!("accounts:{}", current_account_id)
-    }
-    
-    fn contracts_key(&self, predecessor_id: &str) -> String {
-        format!("contracts:{}", predecessor_id)
-    }
-    
-    fn meta_key(&self, suffix: &str) -> String {
-        format!("meta:{}", suffix)
-    }
-    
     // Core read operations
     
     pub async fn get_kv(
@@ -96,7 +263,7 @@ impl RedisDb {
         key: &str,
     ) -> Result<Option<KvEntry>> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let key_str = self.kv_key(predecessor_id, current_account_id, key);
+        let key_str = keys::kv(predecessor_id, current_account_id, key);
         
         let data: Option<String> = conn.get(&key_str).await?;
         
@@ -121,52 +288,120 @@ impl RedisDb {
     pub async fn query_kv_with_pagination(
         &self,
         params: &crate::models::QueryParams,
-    ) -> Result<(Vec<KvEntry>, bool, usize)> {
+    ) -> Result<(Vec<KvEntry>, bool, usize, usize)> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let prefix = self.kv_prefix(&params.predecessor_id, &params.current_account_id);
-        
-        let pattern = if let Some(ref prefix_filter) = params.key_prefix {
-            format!("{}{}*", prefix, prefix_filter)
-        } else {
-            format!("{}*", prefix)
-        };
-        
-        let (_new_cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
-            .arg(0u64)
-            .arg("MATCH")
-            .arg(&pattern)
-            .arg("COUNT")
-            .arg((params.limit + 1) as i64)
-            .query_async(&mut conn)
-            .await?;
-        
-        let has_more = keys.len() > params.limit;
-        let keys: Vec<String> = keys.into_iter().take(params.limit).collect();
-        let dropped = 0usize;
+        let prefix = keys::kv_prefix(&params.predecessor_id, &params.current_account_id);
+        let (keys, has_more, first_rank) = self.query_kv_keys(params).await?;
+        let mut dropped = 0usize;
         
         let mut entries = Vec::new();
         for key in keys {
-            let data: Option<String> = conn.get(&key).await?;
+            let redis_key = format!("{prefix}{key}");
+            let data: Option<String> = conn.get(&redis_key).await?;
             if let Some(json) = data {
-                if let Ok(stored) = serde_json::from_str::<StoredKvEntry>(&json) {
-                    entries.push(stored.into());
+                match parse_stored(&redis_key, &json) {
+                    Some(entry) => entries.push(entry),
+                    None => dropped += 1,
                 }
             }
         }
         
-        Ok((entries, has_more, dropped))
+        Ok((entries, has_more, dropped, first_rank))
     }
     
+    /// Keys of a `query_kv_with_pagination` page, sorted, without reading values.
+    /// SCAN returns keys in hash order, so the whole prefix is walked, keeping
+    /// the first `offset + limit + 1` keys after `after_key`.
+    /// Returns `(keys, has_more, first_rank)`, where `first_rank` is the
+    /// position of the page's first key among all keys under the prefix.
+    pub async fn query_kv_keys(
+        &self,
+        params: &crate::models::QueryParams,
+    ) -> Result<(Vec<String>, bool, usize)> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let prefix = keys::kv_prefix(&params.predecessor_id, &params.current_account_id);
+        let pattern = format!("{}{}*", prefix, params.key_prefix.as_deref().unwrap_or(""));
+        let mut page = KeyPage::new(params.after_key.as_deref(), params.offset, params.limit);
+        let mut cursor = 0u64;
+        loop {
+            let (next, found): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(&mut conn)
+                .await?;
+            found
+                .iter()
+                .filter_map(|k| k.strip_prefix(&prefix))
+                .for_each(|k| page.insert(k));
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        Ok(page.finish())
+    }
+
+    /// Count keys under the query prefix without reading values. Scanning stops
+    /// once more than `max_count` keys are seen, so the result may exceed
+    /// `max_count` by up to one SCAN batch; callers cap it via `CountResponse`.
+    pub async fn count_kv_prefix(
+        &self,
+        predecessor_id: &str,
+        current_account_id: &str,
+        key_prefix: Option<&str>,
+        max_count: usize,
+    ) -> Result<usize> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let pattern = format!(
+            "{}{}*",
+            keys::kv_prefix(predecessor_id, current_account_id),
+            key_prefix.unwrap_or("")
+        );
+
+        let mut cursor = 0u64;
+        let mut counted = 0usize;
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(&mut conn)
+                .await?;
+            counted += keys.len();
+            if counted > max_count || next == 0 {
+                return Ok(counted);
+            }
+            cursor = next;
+        }
+    }
+
+    /// Whether `predecessor_id` has ever written to `current_account_id`
+    /// (one `SISMEMBER` on the account's contracts set).
+    pub async fn partition_exists(&self, predecessor_id: &str, current_account_id: &str) -> Result<bool> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        Ok(conn
+            .sismember(keys::contracts(predecessor_id), current_account_id)
+            .await?)
+    }
+
     pub async fn query_writers(
         &self,
         params: &WritersParams,
     ) -> Result<(Vec<KvEntry>, bool, bool, usize)> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
         
+        // No contractId: match the key under every contract
+        let contract_id = params.current_account_id.as_deref().unwrap_or("*");
         let pattern = if let Some(ref account_id) = params.predecessor_id {
-            format!("kv:{}:{}:{}*", account_id, params.current_account_id, params.key)
+            format!("kv:{}:{}:{}*", account_id, contract_id, params.key)
         } else {
-            format!("kv:*:{}:{}*", params.current_account_id, params.key)
+            format!("kv:*:{}:{}*", contract_id, params.key)
         };
         
         let (_cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
@@ -178,20 +413,21 @@ impl RedisDb {
             .query_async(&mut conn)
             .await?;
         
-        let has_more = keys.len() > params.limit;
-        let keys: Vec<String> = keys.into_iter().take(params.limit).collect();
+        let (keys, has_more) = writer_keys_page(keys, params);
+        let mut dropped = 0usize;
         
         let mut entries = Vec::new();
         for key in keys {
             let data: Option<String> = conn.get(&key).await?;
             if let Some(json) = data {
-                if let Ok(stored) = serde_json::from_str::<StoredKvEntry>(&json) {
-                    entries.push(stored.into());
+                match parse_stored(&key, &json) {
+                    Some(entry) => entries.push(entry),
+                    None => dropped += 1,
                 }
             }
         }
         
-        Ok((entries, has_more, false, 0))
+        Ok((entries, has_more, false, dropped))
     }
     
     pub async fn query_accounts_by_contract(
@@ -203,7 +439,7 @@ impl RedisDb {
         _after_account: Option<&str>,
     ) -> Result<(Vec<String>, bool, bool, usize)> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let key = self.accounts_key(contract_id);
+        let key = keys::accounts(contract_id);
         
         let members: Vec<String> = conn.smembers(&key).await?;
         
@@ -212,30 +448,82 @@ impl RedisDb {
         
         Ok((accounts, has_more, false, 0))
     }
+
+    /// Compare the `accounts:{contract}` set with the distinct writers of the
+    /// contract's `kv:*` keys. The `SCAN` runs to completion unless it takes
+    /// more than `STALENESS_SCAN_CALLS` calls.
+    pub async fn query_accounts_staleness(&self, contract_id: &str) -> Result<AccountsStaleness> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let indexed: usize = conn.scard(keys::accounts(contract_id)).await?;
+
+        let mut writers = std::collections::HashSet::new();
+        let mut calls = 0usize;
+        let mut truncated = false;
+        let mut cursor = 0u64;
+        loop {
+            let (next, found): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(format!("kv:*:{contract_id}:*"))
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(&mut conn)
+                .await?;
+            for key in &found {
+                if let Some(writer) = staleness_writer(key, contract_id) {
+                    writers.insert(writer.to_string());
+                }
+            }
+            calls += 1;
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+            if calls >= STALENESS_SCAN_CALLS {
+                truncated = true;
+                break;
+            }
+        }
+
+        Ok(AccountsStaleness::new(contract_id, indexed, writers.len(), truncated))
+    }
     
+    /// Every writer account (one `contracts:{account}` set each) in
+    /// `[from, to)` and after the `after_account` cursor, sorted. Walks the
+    /// whole keyspace with `SCAN`, keeping only the smallest `limit + 1`
+    /// matches, so pages are exact and `truncated` is always false.
+    /// Returns (accounts, has_more, truncated, dropped_rows).
     pub async fn query_all_accounts(
         &self,
         limit: usize,
-        _after_account: Option<&str>,
-    ) -> Result<(Vec<String>, bool, usize)> {
+        after_account: Option<&str>,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<(Vec<String>, bool, bool, usize)> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        
-        let (_cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
-            .arg(0u64)
-            .arg("MATCH")
-            .arg("accounts:*")
-            .arg("COUNT")
-            .arg(limit as i64)
-            .query_async(&mut conn)
-            .await?;
-        
-        let accounts: Vec<String> = keys
-            .into_iter()
-            .filter_map(|k| k.strip_prefix("accounts:").map(|s| s.to_string()))
-            .take(limit)
-            .collect();
-        
-        Ok((accounts, false, 0))
+        let mut page = SortedPage::new(0, limit);
+        let mut cursor = 0u64;
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg("contracts:*")
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(&mut conn)
+                .await?;
+            keys.into_iter()
+                .filter_map(|k| k.strip_prefix("contracts:").map(str::to_string))
+                .filter(|a| in_account_range(a, from, to, after_account))
+                .for_each(|a| page.insert(a));
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        let (accounts, has_more) = page.finish();
+        Ok((accounts, has_more, false, 0))
     }
     
     pub async fn query_contracts_by_account(
@@ -245,7 +533,7 @@ impl RedisDb {
         _after_contract: Option<&str>,
     ) -> Result<(Vec<String>, bool, usize)> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let key = self.contracts_key(account_id);
+        let key = keys::contracts(account_id);
         
         let members: Vec<String> = conn.smembers(&key).await?;
         let has_more = members.len() > limit;
@@ -253,6 +541,32 @@ impl RedisDb {
         Ok((members.into_iter().take(limit).collect(), has_more, 0))
     }
     
+    /// Number of contracts with at least one write, i.e. `accounts:{contract}`
+    /// sets. Walks the whole keyspace with `SCAN`, so the result is
+    /// approximate: keys created or deleted mid-scan may or may not be
+    /// counted, and a rehash can return a key twice.
+    pub async fn count_contracts(&self) -> Result<usize> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let mut count = 0;
+        let mut cursor = 0u64;
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg("accounts:*")
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(&mut conn)
+                .await?;
+            count += keys.len();
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+        Ok(count)
+    }
+
     pub async fn query_all_contracts(
         &self,
         limit: usize,
@@ -278,6 +592,66 @@ impl RedisDb {
         Ok((contracts, false, 0))
     }
     
+    /// Every entry a receipt wrote, from the `receipt:{receipt_id}` index.
+    /// Returns (entries, dropped_rows).
+    pub async fn get_kv_by_receipt(&self, receipt_id: &str) -> Result<(Vec<KvEntry>, usize)> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let members: Vec<String> = conn.smembers(keys::receipt(receipt_id)).await?;
+        Ok(entries_from_index_members(members))
+    }
+    
+    /// Every entry written under a transaction, from the `tx:{tx_hash}` index.
+    /// Returns (entries, dropped_rows); an unknown tx yields no entries.
+    pub async fn get_kv_by_tx(&self, tx_hash: &str) -> Result<(Vec<KvEntry>, usize)> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let members: Vec<String> = conn.smembers(keys::tx(tx_hash)).await?;
+        Ok(entries_from_index_members(members))
+    }
+    
+    /// Approximate sample of a partition: SCAN at most `SAMPLE_SCAN_BUDGET` keys,
+    /// start after one of a few probe points and read up to `limit` entries.
+    /// Returns (entries, dropped_rows).
+    pub async fn sample_kv(&self, params: &SampleParams, seed: u64) -> Result<(Vec<KvEntry>, usize)> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let pattern = format!(
+            "{}{}*",
+            keys::kv_prefix(&params.predecessor_id, &params.current_account_id),
+            params.key_prefix.as_deref().unwrap_or("")
+        );
+
+        let mut cursor = 0u64;
+        let mut keys = Vec::new();
+        loop {
+            let (next, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(SAMPLE_SCAN_BUDGET)
+                .query_async(&mut conn)
+                .await?;
+            keys.extend(batch);
+            if keys.len() >= SAMPLE_SCAN_BUDGET || next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        keys.truncate(SAMPLE_SCAN_BUDGET);
+
+        let mut entries = Vec::new();
+        let mut dropped = 0usize;
+        for key in sample_window(keys, params.limit, seed) {
+            let data: Option<String> = conn.get(&key).await?;
+            if let Some(json) = data {
+                match parse_stored(&key, &json) {
+                    Some(entry) => entries.push(entry),
+                    None => dropped += 1,
+                }
+            }
+        }
+        Ok((entries, dropped))
+    }
+
     pub async fn get_kv_at_block(
         &self,
         predecessor_id: &str,
@@ -286,7 +660,7 @@ impl RedisDb {
         block_height: u64,
     ) -> Result<Option<KvEntry>> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let history_key = self.history_key(predecessor_id, current_account_id, key);
+        let history_key = keys::history(predecessor_id, current_account_id, key);
         
         let entries: Vec<(i64, String)> = conn
             .zrangebyscore_withscores(&history_key, 0, block_height as i64)
@@ -305,7 +679,7 @@ impl RedisDb {
         params: &HistoryParams,
     ) -> Result<(Vec<KvEntry>, bool, bool, Option<String>)> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let history_key = self.history_key(&params.predecessor_id, &params.current_account_id, &params.key);
+        let history_key = keys::history(&params.predecessor_id, &params.current_account_id, &params.key);
         
         let start = params.from_block.unwrap_or(0);
         let end = params.to_block.unwrap_or(i64::MAX);
@@ -314,27 +688,64 @@ impl RedisDb {
             .zrangebyscore_withscores(&history_key, start, end)
             .await?;
         
-        let has_more = entries.len() > params.limit;
-        
-        let history_entries: Vec<KvEntry> = entries
+        let mut collapse = params.collapse_unchanged.unwrap_or(false).then(CollapseUnchanged::default);
+        let mut history_entries: Vec<KvEntry> = entries
             .into_iter()
-            .take(params.limit)
             .filter_map(|(_, json)| {
                 let stored: StoredKvEntry = serde_json::from_str(&json).ok()?;
+                if let Some(ref mut collapse) = collapse {
+                    if !collapse.keep(&stored.key, &stored.value) {
+                        return None;
+                    }
+                }
                 Some(stored.into())
             })
             .collect();
+
+        let has_more = history_entries.len() > params.limit;
+        history_entries.truncate(params.limit);
         
         let next_cursor = None;
         Ok((history_entries, has_more, false, next_cursor))
     }
     
+    /// Number of history rows in the export's block range.
+    pub async fn count_kv_history(&self, params: &HistoryExportParams) -> Result<usize> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let history_key = keys::history(&params.predecessor_id, &params.current_account_id, &params.key);
+        let (start, end) = params.block_range();
+        Ok(conn.zcount(&history_key, start, end).await?)
+    }
+
+    /// Stream a key's history oldest first, up to `max_rows` rows, reading one
+    /// `ZRANGEBYSCORE ... WITHSCORES LIMIT` page per round-trip instead of the
+    /// whole range.
+    pub async fn export_kv_history(
+        &self,
+        params: &HistoryExportParams,
+    ) -> Result<impl Stream<Item = Result<KvEntry>> + 'static> {
+        let conn = self.client.get_multiplexed_async_connection().await?;
+        let history_key = keys::history(&params.predecessor_id, &params.current_account_id, &params.key);
+
+        let page_key = history_key.clone();
+        Ok(history_rows(history_key, params.block_range(), params.max_rows, move |min, max, skip, count| {
+            let mut conn = conn.clone();
+            let history_key = page_key.clone();
+            async move {
+                let page: Vec<(String, i64)> = conn
+                    .zrangebyscore_limit_withscores(&history_key, min, max, skip as isize, count as isize)
+                    .await?;
+                Ok(page)
+            }
+        }))
+    }
+
     pub async fn get_kv_timeline(
         &self,
         params: &TimelineParams,
     ) -> Result<(Vec<KvEntry>, bool, bool, usize, Option<String>)> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let history_key = self.history_key(&params.predecessor_id, &params.current_account_id, "");
+        let history_key = keys::history(&params.predecessor_id, &params.current_account_id, "");
         
         let start = params.from_block.unwrap_or(0);
         let end = params.to_block.unwrap_or(i64::MAX);
@@ -369,14 +780,34 @@ impl RedisDb {
         Ok((Vec::new(), false, 0))
     }
     
-    pub async fn count_edges(&self, _edge_type: &str, _target: &str) -> Result<usize> {
-        // TODO: Implement edges count in Redis
-        Ok(0)
+    /// Live edges of a type to `target`: the `edges:{edge_type}:{target}` set
+    /// maintained by the kv-sub-indexer.
+    pub async fn count_edges(&self, edge_type: &str, target: &str) -> Result<usize> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        Ok(conn.scard(keys::edges(edge_type, target)).await?)
+    }
+    
+    /// Subscribe to change notifications published by the indexer for a
+    /// contract. Malformed messages are skipped. The stream ends if the
+    /// pub/sub connection drops.
+    pub async fn subscribe_changes(
+        &self,
+        current_account_id: &str,
+    ) -> Result<impl futures::Stream<Item = KvChange> + Send + 'static> {
+        use futures::StreamExt;
+
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub.subscribe(keys::changes(&self.chain_id, current_account_id)).await?;
+
+        Ok(pubsub.into_on_message().filter_map(|msg| async move {
+            let payload: String = msg.get_payload().ok()?;
+            serde_json::from_str(&payload).ok()
+        }))
     }
     
     pub async fn get_indexer_block_height(&self) -> Result<Option<u64>> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let key = self.meta_key(&self.chain_id);
+        let key = keys::meta(&self.chain_id);
         
         let height: Option<String> = conn.get(&key).await?;
         Ok(height.and_then(|h| h.parse().ok()))
@@ -387,7 +818,7 @@ impl RedisDb {
     pub async fn set_kv(&self, entry: &KvEntry) -> Result<()> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
         
-        let key = self.kv_key(&entry.predecessor_id, &entry.current_account_id, &entry.key);
+        let key = keys::kv(&entry.predecessor_id, &entry.current_account_id, &entry.key);
         let stored = StoredKvEntry {
             predecessor_id: entry.predecessor_id.clone(),
             current_account_id: entry.current_account_id.clone(),
@@ -397,17 +828,19 @@ impl RedisDb {
             block_timestamp: entry.block_timestamp,
             receipt_id: entry.receipt_id.clone(),
             tx_hash: entry.tx_hash.clone(),
+            deleted: StoredKvEntry::deletion_marker(entry.is_deleted, &entry.value),
+            content_type: entry.content_type,
         };
         
         let json = serde_json::to_string(&stored)?;
         conn.set(&key, &json).await?;
         
         // Also add to accounts set
-        let accounts_key = self.accounts_key(&entry.current_account_id);
+        let accounts_key = keys::accounts(&entry.current_account_id);
         conn.sadd(&accounts_key, &entry.predecessor_id).await?;
         
         // Also add to contracts set
-        let contracts_key = self.contracts_key(&entry.predecessor_id);
+        let contracts_key = keys::contracts(&entry.predecessor_id);
         conn.sadd(&contracts_key, &entry.current_account_id).await?;
         
         Ok(())
@@ -422,7 +855,7 @@ impl RedisDb {
     ) -> Result<()> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
         
-        let history_key = self.history_key(predecessor_id, current_account_id, key);
+        let history_key = keys::history(predecessor_id, current_account_id, key);
         let stored = StoredKvEntry {
             predecessor_id: entry.predecessor_id.clone(),
             current_account_id: entry.current_account_id.clone(),
@@ -432,6 +865,8 @@ impl RedisDb {
             block_timestamp: entry.block_timestamp,
             receipt_id: entry.receipt_id.clone(),
             tx_hash: entry.tx_hash.clone(),
+            deleted: StoredKvEntry::deletion_marker(entry.is_deleted, &entry.value),
+            content_type: None,
         };
         
         let json = serde_json::to_string(&stored)?;
@@ -442,9 +877,335 @@ impl RedisDb {
     
     pub async fn set_indexer_block_height(&self, height: u64) -> Result<()> {
         let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let key = self.meta_key(&self.chain_id);
+        let key = keys::meta(&self.chain_id);
         
         conn.set(&key, height.to_string()).await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastkv_common::ContentType;
+
+    /// In-memory stand-in for
+    /// `ZRANGEBYSCORE key min max WITHSCORES LIMIT skip count`.
+    fn zrange_page(rows: &[(i64, String)], (min, max): (i64, i64), skip: usize, count: usize) -> Vec<(String, i64)> {
+        rows.iter()
+            .filter(|(score, _)| (min..=max).contains(score))
+            .skip(skip)
+            .take(count)
+            .map(|(score, row)| (row.clone(), *score))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_paged_rows_pages_by_last_score() {
+        // Two rows per block height, so pages split ties.
+        let rows: Vec<(i64, String)> = (1..=10).flat_map(|h| [(h, format!("{h}a")), (h, format!("{h}b"))]).collect();
+
+        let mut skips = vec![];
+        let all: Vec<String> = paged_rows(3, 100, 3, |min, skip, count| {
+            skips.push((min, skip));
+            std::future::ready(Ok(zrange_page(&rows, (min, 10), skip, count)))
+        })
+        .map(Result::unwrap)
+        .collect()
+        .await;
+        let expected: Vec<String> = rows.iter().filter(|(h, _)| *h >= 3).map(|(_, row)| row.clone()).collect();
+        assert_eq!(all, expected);
+        // Each page resumes at the last score, skipping only the rows it already read there.
+        assert_eq!(skips, vec![(3, 0), (4, 1), (5, 2), (7, 1), (8, 2), (10, 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_history_rows_respects_range_and_cap() {
+        let rows: Vec<(i64, String)> = (1..=25)
+            .map(|h| (h, STORED_KV_FIXTURE.replace(r#""block_height":42"#, &format!(r#""block_height":{h}"#))))
+            .collect();
+        let params: HistoryExportParams = serde_json::from_value(serde_json::json!({
+            "accountId": "alice.near",
+            "contractId": "contract.near",
+            "key": "profile/name",
+            "from_block": 5,
+            "to_block": 20,
+            "max_rows": 6,
+        }))
+        .unwrap();
+
+        let fetch = |min, max, skip, count| std::future::ready(Ok(zrange_page(&rows, (min, max), skip, count)));
+        let heights: Vec<u64> = history_rows("history".to_string(), params.block_range(), params.max_rows, fetch)
+            .map(|entry| entry.unwrap().block_height)
+            .collect()
+            .await;
+        assert_eq!(heights, vec![5, 6, 7, 8, 9, 10]);
+
+        let fetch = |min, max, skip, count| std::future::ready(Ok(zrange_page(&rows, (min, max), skip, count)));
+        let uncapped: Vec<u64> = history_rows("history".to_string(), params.block_range(), 100, fetch)
+            .map(|entry| entry.unwrap().block_height)
+            .collect()
+            .await;
+        assert_eq!(uncapped, (5..=20).collect::<Vec<u64>>());
+    }
+
+    #[tokio::test]
+    async fn test_paged_rows_stops_on_error() {
+        let rows: Vec<Result<String>> = paged_rows(0, 10, 2, |min, _, _| {
+            std::future::ready(if min == 0 {
+                Ok(vec![("a".to_string(), 1), ("b".to_string(), 2)])
+            } else {
+                Err(anyhow::anyhow!("connection reset"))
+            })
+        })
+        .collect()
+        .await;
+        assert_eq!(rows.len(), 3);
+        assert!(rows[2].is_err());
+    }
+
+    #[test]
+    fn test_account_range_boundaries() {
+        let range = (Some("b"), Some("d"));
+        assert!(!in_account_range("a.near", range.0, range.1, None));
+        assert!(in_account_range("b", range.0, range.1, None));
+        assert!(in_account_range("b.near", range.0, range.1, None));
+        assert!(in_account_range("czz.near", range.0, range.1, None));
+        assert!(!in_account_range("d", range.0, range.1, None));
+        assert!(!in_account_range("d.near", range.0, range.1, None));
+        // Open-ended on either side.
+        assert!(in_account_range("zzz.near", Some("m"), None, None));
+        assert!(in_account_range("a.near", None, Some("m"), None));
+        assert!(!in_account_range("m.near", None, Some("m"), None));
+    }
+
+    #[test]
+    fn test_account_range_with_cursor() {
+        let accounts = ["a.near", "b.near", "bob.near", "c.near", "carol.near", "d.near"];
+        let page = |after: Option<&str>, limit: usize| {
+            let mut page = SortedPage::new(0, limit);
+            // Reversed and repeated, as SCAN may return keys in any order and more than once.
+            accounts
+                .iter()
+                .rev()
+                .chain(&accounts)
+                .filter(|a| in_account_range(a, Some("b"), Some("d"), after))
+                .for_each(|a| page.insert(a.to_string()));
+            page.finish()
+        };
+
+        let (first, has_more) = page(None, 2);
+        assert_eq!(first, vec!["b.near", "bob.near"]);
+        assert!(has_more);
+        let (second, has_more) = page(Some("bob.near"), 2);
+        assert_eq!(second, vec!["c.near", "carol.near"]);
+        assert!(!has_more);
+        // A cursor before `from` doesn't widen the range; one at or past `to` ends it.
+        assert_eq!(page(Some("a.near"), 10).0.len(), 4);
+        assert!(page(Some("carol.near"), 10).0.is_empty());
+        assert!(page(Some("zzz.near"), 10).0.is_empty());
+    }
+
+    #[test]
+    fn test_sorted_page_offset() {
+        let page = |offset: usize, limit: usize| {
+            let mut page = SortedPage::new(offset, limit);
+            (0..50).rev().for_each(|i| page.insert(format!("k{i:02}")));
+            page.finish()
+        };
+        assert_eq!(page(10, 3), (vec!["k10".to_string(), "k11".to_string(), "k12".to_string()], true));
+        assert_eq!(page(47, 3).0, vec!["k47", "k48", "k49"]);
+        assert!(!page(47, 3).1);
+        assert_eq!(page(60, 3), (vec![], false));
+    }
+
+    #[test]
+    fn test_key_page_ranks() {
+        let page = |after_key: Option<&str>, offset: usize| {
+            let mut page = KeyPage::new(after_key, offset, 2);
+            ["d", "a", "e", "c", "b"].into_iter().for_each(|k| page.insert(k));
+            page.finish()
+        };
+        assert_eq!(page(None, 0), (vec!["a".to_string(), "b".to_string()], true, 0));
+        assert_eq!(page(None, 2), (vec!["c".to_string(), "d".to_string()], true, 2));
+        // A cursor page starts right after the cursor, ranked by the keys up to it.
+        assert_eq!(page(Some("b"), 0), (vec!["c".to_string(), "d".to_string()], true, 2));
+        assert_eq!(page(Some("bb"), 0).2, 2);
+        // The offset is ignored alongside a cursor.
+        assert_eq!(page(Some("c"), 3), (vec!["d".to_string(), "e".to_string()], false, 3));
+    }
+
+    fn writers_params(exclude_account: Option<&str>, limit: usize) -> WritersParams {
+        serde_json::from_value(serde_json::json!({
+            "contractId": "social.near",
+            "key": "profile",
+            "exclude_account": exclude_account,
+            "limit": limit,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_writer_keys_page_excludes_account() {
+        let keys: Vec<String> = ["alice.near", "social.near", "bob.near", "social.near", "carol.near"]
+            .iter()
+            .map(|a| format!("kv:{a}:social.near:profile"))
+            .collect();
+        let preds = |keys: &[String]| -> Vec<String> {
+            keys.iter().map(|k| k.split(':').nth(1).unwrap().to_string()).collect()
+        };
+
+        let (page, has_more) = writer_keys_page(keys.clone(), &writers_params(Some("social.near"), 10));
+        assert_eq!(preds(&page), ["alice.near", "bob.near", "carol.near"]);
+        assert!(!has_more);
+
+        // Excluded keys don't count towards the page, so has_more reflects
+        // only the remaining writers.
+        let (page, has_more) = writer_keys_page(keys.clone(), &writers_params(Some("social.near"), 2));
+        assert_eq!(preds(&page), ["alice.near", "bob.near"]);
+        assert!(has_more);
+        let (_, has_more) = writer_keys_page(keys.clone(), &writers_params(Some("social.near"), 3));
+        assert!(!has_more);
+
+        // A key that merely contains the excluded account elsewhere is kept
+        let (page, _) = writer_keys_page(keys.clone(), &writers_params(Some("profile"), 10));
+        assert_eq!(page, keys);
+        let (page, _) = writer_keys_page(keys.clone(), &writers_params(None, 10));
+        assert_eq!(page, keys);
+    }
+
+    #[test]
+    fn test_staleness_writer() {
+        assert_eq!(staleness_writer("kv:alice.near:social.near:profile/name", "social.near"), Some("alice.near"));
+        assert_eq!(staleness_writer("kv:alice.near:social.near.x:profile/name", "social.near"), None);
+        // The MATCH pattern also hits keys naming the contract further in.
+        assert_eq!(staleness_writer("kv:alice.near:other.near:social.near:x", "social.near"), None);
+        assert_eq!(staleness_writer("history:alice.near:social.near:x", "social.near"), None);
+    }
+
+    #[test]
+    fn test_sample_window() {
+        assert!(sample_window(Vec::new(), 10, 3).is_empty());
+
+        let keys: Vec<String> = (0..40).rev().map(|i| format!("kv:a:c:k{i:02}")).collect();
+        let first = sample_window(keys.clone(), 10, 0);
+        assert_eq!(first.len(), 10);
+        assert_eq!(first[0], "kv:a:c:k00");
+
+        let tail = sample_window(keys, 10, 7);
+        assert_eq!(tail, vec!["kv:a:c:k35", "kv:a:c:k36", "kv:a:c:k37", "kv:a:c:k38", "kv:a:c:k39"]);
+    }
+
+    /// Pinned copy of `STORED_KV_FIXTURE` in fastkv-common.
+    const STORED_KV_FIXTURE: &str = r#"{"predecessor_id":"alice.near","current_account_id":"contract.near","key":"profile/name","value":"\"Alice\"","block_height":42,"block_timestamp":7,"receipt_id":"r","tx_hash":""}"#;
+
+    #[test]
+    fn test_reads_indexer_stored_entry() {
+        let stored: StoredKvEntry = serde_json::from_str(STORED_KV_FIXTURE).unwrap();
+        let entry = KvEntry::from(stored);
+        assert_eq!(entry.predecessor_id, "alice.near");
+        assert_eq!(entry.current_account_id, "contract.near");
+        assert_eq!(entry.key, "profile/name");
+        assert_eq!(entry.value, "\"Alice\"");
+        assert_eq!(entry.block_height, 42);
+        assert_eq!(entry.block_timestamp, 7);
+        assert_eq!(entry.receipt_id, "r");
+        assert_eq!(entry.tx_hash, "");
+        assert_eq!(entry.content_type, None);
+        assert!(!entry.is_deleted);
+    }
+
+    #[test]
+    fn test_serves_current_value_content_type() {
+        let json = STORED_KV_FIXTURE.replace('}', r#","content_type":"binary"}"#);
+        let entry = KvEntry::from(serde_json::from_str::<StoredKvEntry>(&json).unwrap());
+        assert_eq!(entry.content_type, Some(ContentType::Binary));
+        assert_eq!(serde_json::to_value(&entry).unwrap()["contentType"], "binary");
+    }
+
+    #[test]
+    fn test_round_trips_own_writes() {
+        let stored: StoredKvEntry = serde_json::from_str(STORED_KV_FIXTURE).unwrap();
+        assert_eq!(serde_json::to_string(&stored).unwrap(), STORED_KV_FIXTURE);
+    }
+
+    #[test]
+    fn test_missing_tx_hash_defaults_to_empty() {
+        let json = r#"{"predecessor_id":"a.near","current_account_id":"c.near","key":"k","value":"1","block_height":1,"block_timestamp":0,"receipt_id":"r"}"#;
+        let stored: StoredKvEntry = serde_json::from_str(json).unwrap();
+        assert_eq!(stored.tx_hash, "");
+    }
+
+    #[test]
+    fn test_stored_null_value_is_not_a_tombstone() {
+        let json = r#"{"predecessor_id":"a.near","current_account_id":"c.near","key":"k","value":"null","block_height":1,"block_timestamp":0,"receipt_id":"r","deleted":false}"#;
+        let entry = KvEntry::from(serde_json::from_str::<StoredKvEntry>(json).unwrap());
+        assert_eq!(entry.value, "null");
+        assert!(!entry.is_deleted);
+    }
+
+    #[test]
+    fn test_explicit_tombstone_marker() {
+        let json = r#"{"predecessor_id":"a.near","current_account_id":"c.near","key":"k","value":"","block_height":1,"block_timestamp":0,"receipt_id":"r","deleted":true}"#;
+        let entry = KvEntry::from(serde_json::from_str::<StoredKvEntry>(json).unwrap());
+        assert!(entry.is_deleted);
+
+        // No marker: fall back to the "null" heuristic
+        let json = r#"{"predecessor_id":"a.near","current_account_id":"c.near","key":"k","value":"null","block_height":1,"block_timestamp":0,"receipt_id":"r"}"#;
+        let entry = KvEntry::from(serde_json::from_str::<StoredKvEntry>(json).unwrap());
+        assert!(entry.is_deleted);
+    }
+
+    #[test]
+    fn test_deletion_marker_only_when_heuristic_disagrees() {
+        assert_eq!(StoredKvEntry::deletion_marker(true, "null"), None);
+        assert_eq!(StoredKvEntry::deletion_marker(false, "\"Alice\""), None);
+        assert_eq!(StoredKvEntry::deletion_marker(false, "null"), Some(false));
+        assert_eq!(StoredKvEntry::deletion_marker(true, ""), Some(true));
+    }
+
+    #[test]
+    fn test_entries_from_index_members() {
+        let entry = |contract: &str, key: &str| {
+            format!(r#"{{"predecessor_id":"alice.near","current_account_id":"{contract}","key":"{key}","value":"1","block_height":42,"block_timestamp":7,"receipt_id":"rcpt","tx_hash":""}}"#)
+        };
+        let members = vec![
+            entry("social.near", "profile/name"),
+            "not json".to_string(),
+            entry("app.near", "b"),
+            entry("social.near", "graph/follow"),
+        ];
+
+        let (entries, dropped) = entries_from_index_members(members);
+        assert_eq!(dropped, 1);
+        let got: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|e| (e.current_account_id.as_str(), e.key.as_str()))
+            .collect();
+        assert_eq!(
+            got,
+            [("app.near", "b"), ("social.near", "graph/follow"), ("social.near", "profile/name")]
+        );
+        assert!(entries.iter().all(|e| e.receipt_id == "rcpt"));
+    }
+
+    #[test]
+    fn test_tx_index_members() {
+        let write = |receipt: &str, key: &str| {
+            format!(r#"{{"predecessor_id":"alice.near","current_account_id":"social.near","key":"{key}","value":"1","block_height":42,"block_timestamp":7,"receipt_id":"{receipt}","tx_hash":"tx1"}}"#)
+        };
+        // One tx fanning out into several receipts
+        let members = vec![write("r2", "post/main"), write("r1", "profile/name"), write("r1", "index/post")];
+        let (entries, dropped) = entries_from_index_members(members);
+        assert_eq!(dropped, 0);
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(|e| e.tx_hash == "tx1"));
+        let keys: Vec<&str> = entries.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, ["index/post", "post/main", "profile/name"]);
+
+        // Unknown tx: SMEMBERS on a missing key is an empty set
+        let (entries, dropped) = entries_from_index_members(Vec::new());
+        assert!(entries.is_empty());
+        assert_eq!(dropped, 0);
+    }
+}
@@ -0,0 +1,102 @@
+//! Wall-clock budget for each request.
+//!
+//! A query against a huge partition can run several DB statements, each up to
+//! the 10s statement timeout. Handlers that exceed `REQUEST_TIMEOUT_MS` are
+//! dropped and the client gets a 504 instead.
+//!
+//! [`enforce`] is attached per route (`#[get(..., wrap = "...")]`) rather than
+//! app-wide: building the 504 needs the `HttpRequest`, and an app-level layer
+//! would have to hold a clone of it while routing, which actix does not allow.
+//! Long-lived routes that bound their own duration (SSE watch, long poll) are
+//! simply left unwrapped.
+
+use crate::models::ApiError;
+use crate::AppState;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, ResponseError};
+use std::time::Duration;
+
+pub const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 15_000;
+
+/// `REQUEST_TIMEOUT_MS` (default 15,000); `None` when set to 0.
+pub fn from_env() -> Option<Duration> {
+    let ms: u64 = std::env::var("REQUEST_TIMEOUT_MS")
+        .ok()
+        .map(|s| s.parse().expect("Invalid REQUEST_TIMEOUT_MS"))
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT_MS);
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+/// Run the route under `AppState::request_timeout`, answering 504 if it expires.
+pub async fn enforce<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let timeout = req
+        .app_data::<web::Data<AppState>>()
+        .and_then(|state| state.request_timeout);
+    let Some(timeout) = timeout else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+
+    let http_req = req.request().clone();
+    match tokio::time::timeout(timeout, next.call(req)).await {
+        Ok(res) => Ok(res?.map_into_left_body()),
+        Err(_) => {
+            tracing::warn!(
+                target: "fastkv-server",
+                path = %http_req.path(),
+                timeout_ms = timeout.as_millis() as u64,
+                "Request timed out"
+            );
+            let res = ApiError::Timeout.error_response();
+            Ok(ServiceResponse::new(http_req, res).map_into_right_body())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_slow_handler_is_cut_off() {
+        use actix_web::http::StatusCode;
+        use actix_web::middleware::from_fn;
+        use actix_web::{test, App, HttpResponse};
+
+        let state = AppState {
+            request_timeout: Some(Duration::from_millis(20)),
+            ..crate::handlers::tests::offline_state()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .service(
+                    web::resource("/slow")
+                        .wrap(from_fn(enforce))
+                        .route(web::get().to(|| async {
+                            tokio::time::sleep(Duration::from_secs(5)).await;
+                            HttpResponse::Ok().finish()
+                        })),
+                )
+                .service(
+                    web::resource("/fast")
+                        .wrap(from_fn(enforce))
+                        .route(web::get().to(HttpResponse::Ok)),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/slow").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "TIMEOUT");
+
+        let req = test::TestRequest::get().uri("/fast").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,441 @@
+//! Short-lived cache of serialized GET responses.
+//!
+//! Polling dashboards tend to repeat identical `/v1/kv/*` requests many times
+//! per second. Responses are cached for [`RESPONSE_CACHE_TTL`] keyed by path and
+//! normalized query string, and the whole cache is dropped as soon as the
+//! indexer block height advances. Hits replay the handler's headers along with
+//! the body. Enabled with `RESPONSE_CACHE_SIZE` (0 = off).
+
+use crate::clock::{Clock, SystemClock};
+use actix_web::body::{to_bytes, BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::Bytes;
+use actix_web::HttpResponse;
+use futures::future::{ready, Either};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const RESPONSE_CACHE_TTL: Duration = Duration::from_secs(2);
+
+/// GET endpoints never cached: streams, long polls, and randomized results.
+const UNCACHEABLE_PATHS: &[&str] = &["/v1/kv/watch", "/v1/kv/get-await", "/v1/kv/sample"];
+
+/// A cached response: body plus the headers the handler set.
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+struct CachedBody {
+    response: CachedResponse,
+    inserted: Instant,
+    last_used: u64,
+}
+
+struct Inner {
+    block_height: u64,
+    entries: HashMap<String, CachedBody>,
+    tick: u64,
+}
+
+pub struct ResponseCache {
+    capacity: usize,
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    inner: Mutex<Inner>,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            clock: Arc::new(SystemClock),
+            inner: Mutex::new(Inner {
+                block_height: 0,
+                entries: HashMap::new(),
+                tick: 0,
+            }),
+        }
+    }
+
+    /// Read TTL timestamps from `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns `None` when `RESPONSE_CACHE_SIZE` is unset or 0.
+    pub fn from_env() -> Option<Self> {
+        let capacity: usize = std::env::var("RESPONSE_CACHE_SIZE")
+            .ok()
+            .map(|s| s.parse().expect("Invalid RESPONSE_CACHE_SIZE"))
+            .unwrap_or(0);
+        (capacity > 0).then(|| Self::new(capacity, RESPONSE_CACHE_TTL))
+    }
+
+    /// Drop everything cached at an older block height.
+    fn sync_block(inner: &mut Inner, block_height: u64) {
+        if block_height != inner.block_height {
+            inner.entries.clear();
+            inner.block_height = block_height;
+        }
+    }
+
+    pub fn get(&self, key: &str, block_height: u64, now: Instant) -> Option<CachedResponse> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        Self::sync_block(&mut inner, block_height);
+        inner.tick += 1;
+        let tick = inner.tick;
+        match inner.entries.get_mut(key) {
+            Some(entry) if now.duration_since(entry.inserted) < self.ttl => {
+                entry.last_used = tick;
+                Some(entry.response.clone())
+            }
+            Some(_) => {
+                inner.entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: String, block_height: u64, response: CachedResponse, now: Instant) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        Self::sync_block(&mut inner, block_height);
+        if !inner.entries.contains_key(&key) && inner.entries.len() >= self.capacity {
+            // Evict the least recently used entry
+            if let Some(lru) = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(k, _)| k.clone())
+            {
+                inner.entries.remove(&lru);
+            }
+        }
+        inner.tick += 1;
+        let last_used = inner.tick;
+        inner.entries.insert(
+            key,
+            CachedBody {
+                response,
+                inserted: now,
+                last_used,
+            },
+        );
+    }
+
+    /// Drop every entry; returns how many were cached.
+    pub fn clear(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let cleared = inner.entries.len();
+        inner.entries.clear();
+        cleared
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+}
+
+/// Cache key for an idempotent GET, or `None` if the request is not cacheable.
+/// Query pairs are sorted so `a=1&b=2` and `b=2&a=1` share an entry.
+pub fn cache_key(req: &ServiceRequest) -> Option<String> {
+    let path = req.path();
+    let cacheable = req.method() == Method::GET
+        && (path.starts_with("/v1/kv/") || path.starts_with("/v1/social/"))
+        && !UNCACHEABLE_PATHS.contains(&path);
+    if !cacheable {
+        return None;
+    }
+    let mut pairs: Vec<&str> = req
+        .query_string()
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .collect();
+    pairs.sort_unstable();
+    Some(format!("{}?{}", path, pairs.join("&")))
+}
+
+/// Middleware body: serve from `cache` when possible, otherwise call `srv` and
+/// store successful JSON responses.
+pub fn call<S, B>(
+    cache: Option<Arc<ResponseCache>>,
+    block_height: u64,
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<BoxBody>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody + 'static,
+{
+    let key = cache.as_ref().and_then(|_| cache_key(&req));
+    let (cache, key) = match (cache, key) {
+        (Some(cache), Some(key)) => (cache, key),
+        _ => {
+            let fut = srv.call(req);
+            return Either::Left(Either::Left(
+                async move { Ok(fut.await?.map_into_boxed_body()) },
+            ));
+        }
+    };
+
+    if let Some(cached) = cache.get(&key, block_height, cache.clock.now()) {
+        let mut res = HttpResponse::Ok().body(cached.body);
+        *res.headers_mut() = cached.headers;
+        return Either::Right(ready(Ok(req.into_response(res))));
+    }
+
+    let fut = srv.call(req);
+    Either::Left(Either::Right(async move {
+        let res = fut.await?;
+        let is_json = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/json"));
+        if res.status() != StatusCode::OK || !is_json {
+            return Ok(res.map_into_boxed_body());
+        }
+
+        let (req, res) = res.into_parts();
+        let (res, body) = res.into_parts();
+        let bytes = to_bytes(body)
+            .await
+            .map_err(|e| actix_web::error::ErrorInternalServerError(e.into().to_string()))?;
+        let now = cache.clock.now();
+        let cached = CachedResponse {
+            headers: res.headers().clone(),
+            body: bytes.clone(),
+        };
+        cache.insert(key, block_height, cached, now);
+        Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(bytes))))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+    fn response(body: &'static [u8]) -> CachedResponse {
+        CachedResponse {
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(body),
+        }
+    }
+
+    #[test]
+    fn test_hit_within_ttl() {
+        let cache = ResponseCache::new(8, RESPONSE_CACHE_TTL);
+        let start = Instant::now();
+        cache.insert("/v1/kv/query?a=1".to_string(), 100, response(b"{}"), start);
+
+        let hit = cache.get("/v1/kv/query?a=1", 100, start + Duration::from_millis(1500));
+        assert_eq!(hit.map(|r| r.body), Some(Bytes::from_static(b"{}")));
+
+        assert!(cache
+            .get("/v1/kv/query?a=1", 100, start + RESPONSE_CACHE_TTL)
+            .is_none());
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_block_height_advance_evicts() {
+        let cache = ResponseCache::new(8, RESPONSE_CACHE_TTL);
+        let now = Instant::now();
+        cache.insert("a".to_string(), 100, response(b"1"), now);
+        cache.insert("b".to_string(), 100, response(b"2"), now);
+
+        assert!(cache.get("a", 101, now).is_none());
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_capacity_evicts_least_recently_used() {
+        let cache = ResponseCache::new(2, RESPONSE_CACHE_TTL);
+        let now = Instant::now();
+        cache.insert("a".to_string(), 1, response(b"a"), now);
+        cache.insert("b".to_string(), 1, response(b"b"), now);
+        assert!(cache.get("a", 1, now).is_some());
+
+        cache.insert("c".to_string(), 1, response(b"c"), now);
+        assert!(cache.get("a", 1, now).is_some());
+        assert!(cache.get("b", 1, now).is_none());
+        assert!(cache.get("c", 1, now).is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_entry_expires_with_clock() {
+        use crate::clock::MockClock;
+        use actix_web::{test, web, App};
+
+        let clock = Arc::new(MockClock::new());
+        let cache = Arc::new(ResponseCache::new(8, RESPONSE_CACHE_TTL).with_clock(clock.clone()));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| call(Some(cache.clone()), 100, req, srv))
+                .route(
+                    "/v1/kv/query",
+                    web::get().to({
+                        let calls = calls.clone();
+                        move || {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            async { HttpResponse::Ok().json(serde_json::json!({})) }
+                        }
+                    }),
+                ),
+        )
+        .await;
+        let query = || {
+            test::TestRequest::get()
+                .uri("/v1/kv/query?a=1")
+                .to_request()
+        };
+
+        test::call_service(&app, query()).await;
+        clock.advance(RESPONSE_CACHE_TTL - Duration::from_millis(1));
+        test::call_service(&app, query()).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        clock.advance(Duration::from_millis(1));
+        test::call_service(&app, query()).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_serves_repeat_query_from_cache() {
+        use actix_web::{test, web, App};
+
+        let cache = Arc::new(ResponseCache::new(8, RESPONSE_CACHE_TTL));
+        let block = Arc::new(AtomicU64::new(100));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let app = test::init_service(
+            App::new()
+                .wrap_fn({
+                    let block = block.clone();
+                    move |req, srv| {
+                        call(Some(cache.clone()), block.load(Ordering::Acquire), req, srv)
+                    }
+                })
+                .route(
+                    "/v1/kv/query",
+                    web::get().to({
+                        let calls = calls.clone();
+                        move || {
+                            let n = calls.fetch_add(1, Ordering::SeqCst);
+                            async move { HttpResponse::Ok().json(serde_json::json!({ "n": n })) }
+                        }
+                    }),
+                ),
+        )
+        .await;
+
+        let first = test::call_and_read_body(
+            &app,
+            test::TestRequest::get()
+                .uri("/v1/kv/query?b=2&a=1")
+                .to_request(),
+        )
+        .await;
+        let second = test::call_and_read_body(
+            &app,
+            test::TestRequest::get()
+                .uri("/v1/kv/query?a=1&b=2")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(first, second);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        block.store(101, Ordering::Release);
+        let third = test::call_and_read_body(
+            &app,
+            test::TestRequest::get()
+                .uri("/v1/kv/query?a=1&b=2")
+                .to_request(),
+        )
+        .await;
+        assert_ne!(first, third);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_hit_keeps_handler_headers() {
+        use actix_web::{test, web, App};
+
+        let cache = Arc::new(ResponseCache::new(8, RESPONSE_CACHE_TTL));
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| call(Some(cache.clone()), 100, req, srv))
+                .route(
+                    "/v1/kv/query",
+                    web::get().to(|| async {
+                        HttpResponse::Ok()
+                            .insert_header(("X-Results-Truncated", "true"))
+                            .json(serde_json::json!({}))
+                    }),
+                ),
+        )
+        .await;
+
+        for _ in 0..2 {
+            let res = test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri("/v1/kv/query?a=1")
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(res.headers().get("x-results-truncated").unwrap(), "true");
+            assert_eq!(
+                res.headers().get(header::CONTENT_TYPE).unwrap(),
+                "application/json"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_skips_randomized_endpoints() {
+        use actix_web::{test, web, App};
+
+        let cache = Arc::new(ResponseCache::new(8, RESPONSE_CACHE_TTL));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| call(Some(cache.clone()), 100, req, srv))
+                .route(
+                    "/v1/kv/sample",
+                    web::get().to({
+                        let calls = calls.clone();
+                        move || {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            async { HttpResponse::Ok().json(serde_json::json!({})) }
+                        }
+                    }),
+                ),
+        )
+        .await;
+
+        for _ in 0..2 {
+            test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri("/v1/kv/sample?a=1")
+                    .to_request(),
+            )
+            .await;
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,120 +1,21 @@
 use scylla::client::session::Session;
 use scylla::client::session_builder::SessionBuilder;
-use scylla::errors::NextRowError;
 use scylla::statement::prepared::PreparedStatement;
 
+use crate::degraded::{require, DbFeature, Degraded};
 use crate::models::{
-    bigint_to_u64, AccountsParams, ContractAccountRow, ContractKeyRow, ContractRow, EdgeRow, EdgeSourceEntry,
-    HistoryParams, KvEntry, KvHistoryRow, KvRow, KvTimelineRow, QueryParams, TimelineParams,
-    WritersParams, MAX_DEDUP_SCAN,
+    bigint_to_u64, collect_page, AccountsParams, AccountsStaleness, CollapseUnchanged, DropThreshold, ContractAccountRow, ContractKeyRow, ContractRow, EdgeRow, EdgeSourceEntry,
+    HistoryParams, KvEntry, KvHistoryRow, KvRow, KvTimelineRow, QueryParams, ReverseSource, SampleParams, TimelineParams,
+    PageResult, WritersParams, WritersStatement, MAX_DEDUP_SCAN, SAMPLE_SCAN_BUDGET, STALENESS_SCAN_ROWS, sample_seed,
 };
 use fastnear_primitives::types::ChainId;
 use futures::stream::StreamExt;
-use futures::Stream;
 use rustls::pki_types::pem::PemObject;
 use rustls::{ClientConfig, RootCertStore};
 use std::collections::HashSet;
 use std::env;
 use std::sync::Arc;
 
-/// Outcome of a paginated stream collection.
-#[derive(Debug)]
-pub struct PageResult<T> {
-    pub items: Vec<T>,
-    pub has_more: bool,
-    pub truncated: bool,
-    pub dropped_rows: usize,
-}
-
-/// Collects rows from a typed stream with standard pagination semantics.
-///
-/// **Overfetch mode** (`scan_cap = None`):
-///   Skips `offset` valid items (those where `transform` returns `Some`),
-///   collects up to `limit + 1`, sets `has_more` from overfetch.
-///
-/// **Scan-cap mode** (`scan_cap = Some(cap)`):
-///   Collects ALL valid items up to `cap` raw rows scanned.
-///   Sets `truncated = true` if cap hit. Does NOT apply offset/limit
-///   (caller post-sorts then slices). `has_more` is left as `false`.
-pub async fn collect_page<T, R, S, F>(
-    stream: &mut S,
-    limit: usize,
-    offset: usize,
-    scan_cap: Option<usize>,
-    mut transform: F,
-) -> PageResult<T>
-where
-    S: Stream<Item = Result<R, NextRowError>> + Unpin,
-    F: FnMut(R) -> Option<T>,
-{
-    let mut items = match scan_cap {
-        None => Vec::with_capacity(limit + 1),
-        Some(_) => Vec::new(),
-    };
-    let mut dropped_rows = 0usize;
-    let mut skipped = 0usize;
-    let mut scanned = 0usize;
-    let mut truncated = false;
-
-    while let Some(row_result) = stream.next().await {
-        // Scan-cap check (before deser — matches current behavior)
-        if let Some(cap) = scan_cap {
-            scanned += 1;
-            if scanned > cap {
-                truncated = true;
-                break;
-            }
-        }
-
-        let row = match row_result {
-            Ok(r) => r,
-            Err(e) => {
-                dropped_rows += 1;
-                tracing::warn!(
-                    target: "fastkv-server",
-                    error = %e,
-                    "Failed to deserialize row"
-                );
-                continue;
-            }
-        };
-
-        // Transform + filter (None = filtered out, not counted for offset/limit)
-        let item = match transform(row) {
-            Some(item) => item,
-            None => continue,
-        };
-
-        // Offset skip (overfetch mode only)
-        if scan_cap.is_none() && skipped < offset {
-            skipped += 1;
-            continue;
-        }
-
-        items.push(item);
-
-        // Early break at limit+1 (overfetch mode only)
-        if scan_cap.is_none() && items.len() > limit {
-            break;
-        }
-    }
-
-    let has_more = if scan_cap.is_none() {
-        let over = items.len() > limit;
-        items.truncate(limit);
-        over
-    } else {
-        false // caller computes after post-sort + slice
-    };
-
-    PageResult {
-        items,
-        has_more,
-        truncated,
-        dropped_rows,
-    }
-}
-
 /// Validate that a CQL identifier (keyspace/table name) contains only safe characters.
 pub(crate) fn validate_identifier(name: &str, label: &str) -> anyhow::Result<()> {
     if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
@@ -132,27 +33,37 @@ pub struct ScyllaDb {
     get_kv_last: PreparedStatement,
     query_kv_no_prefix: PreparedStatement,
     query_kv_cursor: PreparedStatement,
-    pub(crate) reverse_kv: PreparedStatement,
-    reverse_list: PreparedStatement,
-    reverse_list_cursor: PreparedStatement,
+    pub(crate) reverse_kv: Option<PreparedStatement>,
+    reverse_list: Option<PreparedStatement>,
+    reverse_list_cursor: Option<PreparedStatement>,
+    writers_by_key: Option<PreparedStatement>,
     history_asc: PreparedStatement,
     history_desc: PreparedStatement,
     get_kv_at_block: PreparedStatement,
-    timeline_asc: PreparedStatement,
-    timeline_desc: PreparedStatement,
-    accounts_by_contract: PreparedStatement,
-    accounts_by_contract_key: PreparedStatement,
-    accounts_all: PreparedStatement,
-    accounts_all_cursor: PreparedStatement,
-    contracts_all: PreparedStatement,
-    contracts_all_cursor: PreparedStatement,
+    timeline_asc: Option<PreparedStatement>,
+    timeline_desc: Option<PreparedStatement>,
+    accounts_by_contract: Option<PreparedStatement>,
+    accounts_by_contract_key: Option<PreparedStatement>,
+    accounts_staleness_scan: Option<PreparedStatement>,
+    accounts_all: Option<PreparedStatement>,
+    accounts_all_cursor: Option<PreparedStatement>,
+    contracts_all: Option<PreparedStatement>,
+    contracts_all_cursor: Option<PreparedStatement>,
     contracts_by_account: PreparedStatement,
-    edges_list: PreparedStatement,
-    edges_list_cursor: PreparedStatement,
-    edges_count: PreparedStatement,
+    edges_list: Option<PreparedStatement>,
+    edges_list_cursor: Option<PreparedStatement>,
+    edges_count: Option<PreparedStatement>,
     prefix_query: PreparedStatement,
     prefix_cursor_query: PreparedStatement,
+    partition_probe: PreparedStatement,
+    sample_probe: PreparedStatement,
+    sample_probe_prefix: PreparedStatement,
     meta_query: PreparedStatement,
+    drop_threshold: DropThreshold,
+    /// Which of `reverse_list*` / `reverse_kv` serves per-contract writers.
+    reverse_source: ReverseSource,
+    /// Optional features whose statements failed to prepare (see `degraded`).
+    pub disabled_features: HashSet<DbFeature>,
 
     pub scylla_session: Session,
     pub table_name: String,
@@ -162,6 +73,7 @@ pub struct ScyllaDb {
     pub all_accounts_table_name: String,
     pub kv_edges_table_name: String,
     pub kv_reverse_table_name: String,
+    pub key_view_name: String,
 }
 
 pub fn create_rustls_client_config() -> Arc<ClientConfig> {
@@ -271,6 +183,8 @@ impl ScyllaDb {
             env::var("KV_EDGES_TABLE_NAME").unwrap_or_else(|_| "kv_edges".to_string());
         let kv_reverse_table_name =
             env::var("KV_REVERSE_TABLE_NAME").unwrap_or_else(|_| "kv_reverse".to_string());
+        let key_view_name =
+            env::var("KEY_VIEW_NAME").unwrap_or_else(|_| "mv_kv_key".to_string());
 
         validate_identifier(&table_name, "TABLE_NAME")?;
         validate_identifier(&history_table_name, "HISTORY_TABLE_NAME")?;
@@ -279,11 +193,17 @@ impl ScyllaDb {
         validate_identifier(&all_accounts_table_name, "ALL_ACCOUNTS_TABLE_NAME")?;
         validate_identifier(&kv_edges_table_name, "KV_EDGES_TABLE_NAME")?;
         validate_identifier(&kv_reverse_table_name, "KV_REVERSE_TABLE_NAME")?;
+        validate_identifier(&key_view_name, "KEY_VIEW_NAME")?;
 
         let columns = "predecessor_id, current_account_id, key, value, block_height, block_timestamp, receipt_id, tx_hash";
         let history_columns = "predecessor_id, current_account_id, key, block_height, order_id, value, block_timestamp, receipt_id, tx_hash, signer_id, shard_id, receipt_index, action_index";
         let timeline_columns = "predecessor_id, current_account_id, block_height, key, order_id, value, block_timestamp, receipt_id, tx_hash";
 
+        // Only the selected reverse source is prepared; the other may be absent
+        let reverse_source = ReverseSource::from_env();
+
+        // Core statements still fail startup; optional ones only disable their feature
+        let mut degraded = Degraded::default();
         Ok(Self {
             get_kv: Self::prepare_query(
                 &scylla_session,
@@ -305,21 +225,51 @@ impl ScyllaDb {
                 &format!("SELECT {} FROM {} WHERE predecessor_id = ? AND current_account_id = ? AND key > ?", columns, table_name),
                 scylla::frame::types::Consistency::LocalOne,
             ).await?,
-            reverse_kv: Self::prepare_query(
-                &scylla_session,
-                &format!("SELECT {} FROM {} WHERE current_account_id = ? AND key = ? ORDER BY block_height DESC, order_id DESC, predecessor_id DESC", columns, reverse_view_name),
-                scylla::frame::types::Consistency::LocalOne,
-            ).await?,
-            reverse_list: Self::prepare_query(
-                &scylla_session,
-                &format!("SELECT {} FROM {} WHERE current_account_id = ? AND key = ?", columns, kv_reverse_table_name),
-                scylla::frame::types::Consistency::LocalOne,
-            ).await?,
-            reverse_list_cursor: Self::prepare_query(
-                &scylla_session,
-                &format!("SELECT {} FROM {} WHERE current_account_id = ? AND key = ? AND predecessor_id > ?", columns, kv_reverse_table_name),
-                scylla::frame::types::Consistency::LocalOne,
-            ).await?,
+            reverse_kv: match reverse_source {
+                ReverseSource::View => degraded.optional(
+                    DbFeature::Writers,
+                    "reverse_kv",
+                    Self::prepare_query(
+                        &scylla_session,
+                        &format!("SELECT {} FROM {} WHERE current_account_id = ? AND key = ? ORDER BY block_height DESC, order_id DESC, predecessor_id DESC", columns, reverse_view_name),
+                        scylla::frame::types::Consistency::LocalOne,
+                    ).await,
+                ),
+                ReverseSource::Table => None,
+            },
+            reverse_list: match reverse_source {
+                ReverseSource::Table => degraded.optional(
+                    DbFeature::Writers,
+                    "reverse_list",
+                    Self::prepare_query(
+                        &scylla_session,
+                        &format!("SELECT {} FROM {} WHERE current_account_id = ? AND key = ?", columns, kv_reverse_table_name),
+                        scylla::frame::types::Consistency::LocalOne,
+                    ).await,
+                ),
+                ReverseSource::View => None,
+            },
+            reverse_list_cursor: match reverse_source {
+                ReverseSource::Table => degraded.optional(
+                    DbFeature::Writers,
+                    "reverse_list_cursor",
+                    Self::prepare_query(
+                        &scylla_session,
+                        &format!("SELECT {} FROM {} WHERE current_account_id = ? AND key = ? AND predecessor_id > ?", columns, kv_reverse_table_name),
+                        scylla::frame::types::Consistency::LocalOne,
+                    ).await,
+                ),
+                ReverseSource::View => None,
+            },
+            writers_by_key: degraded.optional(
+                DbFeature::Writers,
+                "writers_by_key",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT {} FROM {} WHERE key = ? ORDER BY block_height DESC, order_id DESC", columns, key_view_name),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
             history_asc: Self::prepare_query(
                 &scylla_session,
                 &format!("SELECT {} FROM {} WHERE predecessor_id = ? AND current_account_id = ? AND key = ? AND block_height >= ? AND block_height <= ? ORDER BY block_height ASC, order_id ASC", history_columns, history_table_name),
@@ -335,75 +285,145 @@ impl ScyllaDb {
                 &format!("SELECT {} FROM {} WHERE predecessor_id = ? AND current_account_id = ? AND key = ? AND block_height = ?", history_columns, history_table_name),
                 scylla::frame::types::Consistency::LocalOne,
             ).await?,
-            timeline_desc: Self::prepare_query(
-                &scylla_session,
-                &format!("SELECT {} FROM s_kv_by_block WHERE predecessor_id = ? AND current_account_id = ? AND block_height >= ? AND block_height <= ? ORDER BY block_height DESC, key ASC", timeline_columns),
-                scylla::frame::types::Consistency::LocalOne,
-            ).await?,
-            timeline_asc: Self::prepare_query(
-                &scylla_session,
-                &format!("SELECT {} FROM s_kv_by_block WHERE predecessor_id = ? AND current_account_id = ? AND block_height >= ? AND block_height <= ? ORDER BY block_height ASC, key DESC", timeline_columns),
-                scylla::frame::types::Consistency::LocalOne,
-            ).await?,
+            timeline_desc: degraded.optional(
+                DbFeature::Timeline,
+                "timeline_desc",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT {} FROM s_kv_by_block WHERE predecessor_id = ? AND current_account_id = ? AND block_height >= ? AND block_height <= ? ORDER BY block_height DESC, key ASC", timeline_columns),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
+            timeline_asc: degraded.optional(
+                DbFeature::Timeline,
+                "timeline_asc",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT {} FROM s_kv_by_block WHERE predecessor_id = ? AND current_account_id = ? AND block_height >= ? AND block_height <= ? ORDER BY block_height ASC, key DESC", timeline_columns),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
             // LocalOne for single-node deployment (LocalQuorum requires 2+ nodes)
-            accounts_by_contract: Self::prepare_query(
-                &scylla_session,
-                &format!("SELECT predecessor_id FROM {} WHERE current_account_id = ?", kv_accounts_table_name),
-                scylla::frame::types::Consistency::LocalOne,
-            ).await?,
-            accounts_by_contract_key: Self::prepare_query(
-                &scylla_session,
-                &format!("SELECT predecessor_id FROM {} WHERE current_account_id = ? AND key = ?", kv_accounts_table_name),
-                scylla::frame::types::Consistency::LocalOne,
-            ).await?,
-            accounts_all: Self::prepare_query(
-                &scylla_session,
-                &format!("SELECT predecessor_id FROM {}", all_accounts_table_name),
-                scylla::frame::types::Consistency::LocalOne,
-            ).await?,
-            accounts_all_cursor: Self::prepare_query(
-                &scylla_session,
-                &format!("SELECT predecessor_id FROM {} WHERE TOKEN(predecessor_id) > TOKEN(?)", all_accounts_table_name),
-                scylla::frame::types::Consistency::LocalOne,
-            ).await?,
-            contracts_all: Self::prepare_query(
-                &scylla_session,
-                &format!("SELECT current_account_id FROM {}", kv_accounts_table_name),
-                scylla::frame::types::Consistency::LocalOne,
-            ).await?,
-            contracts_all_cursor: Self::prepare_query(
-                &scylla_session,
-                &format!("SELECT current_account_id FROM {} WHERE TOKEN(current_account_id) > TOKEN(?)", kv_accounts_table_name),
-                scylla::frame::types::Consistency::LocalOne,
-            ).await?,
+            accounts_by_contract: degraded.optional(
+                DbFeature::Accounts,
+                "accounts_by_contract",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT predecessor_id FROM {} WHERE current_account_id = ?", kv_accounts_table_name),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
+            accounts_by_contract_key: degraded.optional(
+                DbFeature::Accounts,
+                "accounts_by_contract_key",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT predecessor_id FROM {} WHERE current_account_id = ? AND key = ?", kv_accounts_table_name),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
+            // `s_kv_last` is partitioned by predecessor_id, so this filters
+            // across partitions; the LIMIT keeps it a bounded probe.
+            accounts_staleness_scan: degraded.optional(
+                DbFeature::Accounts,
+                "accounts_staleness_scan",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT predecessor_id FROM {} WHERE current_account_id = ? LIMIT {} ALLOW FILTERING", table_name, STALENESS_SCAN_ROWS),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
+            accounts_all: degraded.optional(
+                DbFeature::Accounts,
+                "accounts_all",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT predecessor_id FROM {}", all_accounts_table_name),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
+            accounts_all_cursor: degraded.optional(
+                DbFeature::Accounts,
+                "accounts_all_cursor",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT predecessor_id FROM {} WHERE TOKEN(predecessor_id) > TOKEN(?)", all_accounts_table_name),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
+            contracts_all: degraded.optional(
+                DbFeature::Accounts,
+                "contracts_all",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT current_account_id FROM {}", kv_accounts_table_name),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
+            contracts_all_cursor: degraded.optional(
+                DbFeature::Accounts,
+                "contracts_all_cursor",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT current_account_id FROM {} WHERE TOKEN(current_account_id) > TOKEN(?)", kv_accounts_table_name),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
             contracts_by_account: Self::prepare_query(
                 &scylla_session,
                 &format!("SELECT current_account_id, key FROM {} WHERE predecessor_id = ?", table_name),
                 scylla::frame::types::Consistency::LocalOne,
             ).await?,
-            edges_list: Self::prepare_query(
+            edges_list: degraded.optional(
+                DbFeature::Edges,
+                "edges_list",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT source, block_height FROM {} WHERE edge_type = ? AND target = ?", kv_edges_table_name),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
+            edges_list_cursor: degraded.optional(
+                DbFeature::Edges,
+                "edges_list_cursor",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT source, block_height FROM {} WHERE edge_type = ? AND target = ? AND source > ?", kv_edges_table_name),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
+            edges_count: degraded.optional(
+                DbFeature::Edges,
+                "edges_count",
+                Self::prepare_query(
+                    &scylla_session,
+                    &format!("SELECT COUNT(*) FROM {} WHERE edge_type = ? AND target = ?", kv_edges_table_name),
+                    scylla::frame::types::Consistency::LocalOne,
+                ).await,
+            ),
+            prefix_query: Self::prepare_query(
                 &scylla_session,
-                &format!("SELECT source, block_height FROM {} WHERE edge_type = ? AND target = ?", kv_edges_table_name),
+                &format!("SELECT {} FROM {} WHERE predecessor_id = ? AND current_account_id = ? AND key >= ? AND key < ?", columns, table_name),
                 scylla::frame::types::Consistency::LocalOne,
             ).await?,
-            edges_list_cursor: Self::prepare_query(
+            prefix_cursor_query: Self::prepare_query(
                 &scylla_session,
-                &format!("SELECT source, block_height FROM {} WHERE edge_type = ? AND target = ? AND source > ?", kv_edges_table_name),
+                &format!("SELECT {} FROM {} WHERE predecessor_id = ? AND current_account_id = ? AND key > ? AND key < ?", columns, table_name),
                 scylla::frame::types::Consistency::LocalOne,
             ).await?,
-            edges_count: Self::prepare_query(
+            partition_probe: Self::prepare_query(
                 &scylla_session,
-                &format!("SELECT COUNT(*) FROM {} WHERE edge_type = ? AND target = ?", kv_edges_table_name),
+                &format!("SELECT key FROM {} WHERE predecessor_id = ? AND current_account_id = ? LIMIT 1", table_name),
                 scylla::frame::types::Consistency::LocalOne,
             ).await?,
-            prefix_query: Self::prepare_query(
+            sample_probe: Self::prepare_query(
                 &scylla_session,
-                &format!("SELECT {} FROM {} WHERE predecessor_id = ? AND current_account_id = ? AND key >= ? AND key < ?", columns, table_name),
+                &format!("SELECT key FROM {} WHERE predecessor_id = ? AND current_account_id = ? LIMIT {}", table_name, SAMPLE_SCAN_BUDGET),
                 scylla::frame::types::Consistency::LocalOne,
             ).await?,
-            prefix_cursor_query: Self::prepare_query(
+            sample_probe_prefix: Self::prepare_query(
                 &scylla_session,
-                &format!("SELECT {} FROM {} WHERE predecessor_id = ? AND current_account_id = ? AND key > ? AND key < ?", columns, table_name),
+                &format!("SELECT key FROM {} WHERE predecessor_id = ? AND current_account_id = ? AND key >= ? AND key < ? LIMIT {}", table_name, SAMPLE_SCAN_BUDGET),
                 scylla::frame::types::Consistency::LocalOne,
             ).await?,
             meta_query: Self::prepare_query(
@@ -411,6 +431,9 @@ impl ScyllaDb {
                 "SELECT last_processed_block_height FROM meta WHERE suffix = ?",
                 scylla::frame::types::Consistency::LocalOne,
             ).await?,
+            drop_threshold: DropThreshold::from_env(),
+            reverse_source,
+            disabled_features: degraded.finish(),
             scylla_session,
             table_name,
             history_table_name,
@@ -419,9 +442,23 @@ impl ScyllaDb {
             all_accounts_table_name,
             kv_edges_table_name,
             kv_reverse_table_name,
+            key_view_name,
         })
     }
 
+    /// Turn a page with too many undeserializable rows into an error instead of
+    /// a misleading partial result (see [`DropThreshold`]).
+    fn check_dropped<T>(&self, page: &PageResult<T>) -> anyhow::Result<()> {
+        if self.drop_threshold.exceeded(page.dropped_rows, page.scanned) {
+            anyhow::bail!(
+                "{} of {} scanned rows failed to deserialize",
+                page.dropped_rows,
+                page.scanned
+            );
+        }
+        Ok(())
+    }
+
     pub async fn prepare_query(
         scylla_db_session: &Session,
         query_text: &str,
@@ -433,6 +470,20 @@ impl ScyllaDb {
         Ok(scylla_db_session.prepare(query).await?)
     }
 
+    /// `LIMIT 1` probe: whether the (account, contract) partition has any row.
+    pub async fn partition_exists(
+        &self,
+        predecessor_id: &str,
+        current_account_id: &str,
+    ) -> anyhow::Result<bool> {
+        let result = self
+            .scylla_session
+            .execute_unpaged(&self.partition_probe, (predecessor_id, current_account_id))
+            .await?
+            .into_rows_result()?;
+        Ok(result.rows_num() > 0)
+    }
+
     pub async fn get_kv(
         &self,
         predecessor_id: &str,
@@ -475,7 +526,8 @@ impl ScyllaDb {
         Ok(value)
     }
 
-    /// Query writers for a key under a contract using the kv_reverse table.
+    /// Query writers for a key under a contract using the kv_reverse table
+    /// (or the reverse view, see `REVERSE_SOURCE`).
     /// predecessor_id is the clustering key so rows are naturally deduplicated.
     /// Supports cursor pagination via `after_account`.
     /// Optionally filters to a specific writer (predecessor_id).
@@ -484,20 +536,28 @@ impl ScyllaDb {
         &self,
         params: &WritersParams,
     ) -> anyhow::Result<(Vec<KvEntry>, bool, bool, usize)> {
-        let mut rows_stream = match &params.after_account {
-            Some(cursor) => self
+        let Some(contract_id) = params.current_account_id.as_deref() else {
+            return self.query_writers_all_contracts(params).await;
+        };
+
+        let statement = self.reverse_source.writers_statement(params.after_account.is_some());
+        let mut rows_stream = match (statement, &params.after_account) {
+            (WritersStatement::View, _) => {
+                return self.query_writers_from_view(params, contract_id).await;
+            }
+            (WritersStatement::TableCursor, Some(cursor)) => self
                 .scylla_session
                 .execute_iter(
-                    self.reverse_list_cursor.clone(),
-                    (&params.current_account_id, &params.key, cursor),
+                    require(&self.reverse_list_cursor, DbFeature::Writers)?.clone(),
+                    (contract_id, &params.key, cursor),
                 )
                 .await?
                 .rows_stream::<KvRow>()?,
-            None => self
+            _ => self
                 .scylla_session
                 .execute_iter(
-                    self.reverse_list.clone(),
-                    (&params.current_account_id, &params.key),
+                    require(&self.reverse_list, DbFeature::Writers)?.clone(),
+                    (contract_id, &params.key),
                 )
                 .await?
                 .rows_stream::<KvRow>()?,
@@ -518,6 +578,9 @@ impl ScyllaDb {
                         return None;
                     }
                 }
+                if params.excludes_writer(&entry.predecessor_id) {
+                    return None;
+                }
                 if exclude_deleted && entry.value == "null" {
                     return None;
                 }
@@ -525,10 +588,102 @@ impl ScyllaDb {
             },
         )
         .await;
+        self.check_dropped(&page)?;
 
         Ok((page.items, page.has_more, page.truncated, page.dropped_rows))
     }
 
+    /// Query writers for a key under a contract using the `mv_kv_cur_key` view
+    /// (`REVERSE_SOURCE=view`). The view is history, newest first, so the first
+    /// row seen per writer is its current value. Scans at most MAX_DEDUP_SCAN
+    /// rows; results are sorted by accountId, and `after_account` is applied
+    /// after dedup.
+    /// Returns (entries, has_more, truncated, dropped_rows).
+    async fn query_writers_from_view(
+        &self,
+        params: &WritersParams,
+        contract_id: &str,
+    ) -> anyhow::Result<(Vec<KvEntry>, bool, bool, usize)> {
+        let mut rows_stream = self
+            .scylla_session
+            .execute_iter(require(&self.reverse_kv, DbFeature::Writers)?.clone(), (contract_id, &params.key))
+            .await?
+            .rows_stream::<KvRow>()?;
+
+        let mut seen = HashSet::new();
+        let page = collect_page(
+            &mut rows_stream,
+            params.limit,
+            0,
+            Some(MAX_DEDUP_SCAN),
+            |row: KvRow| {
+                if !seen.insert(row.predecessor_id.clone()) {
+                    return None;
+                }
+                Some(KvEntry::from(row))
+            },
+        )
+        .await;
+        self.check_dropped(&page)?;
+
+        let (entries, has_more) = params.page_latest_writers(page.items);
+        Ok((entries, has_more, page.truncated, page.dropped_rows))
+    }
+
+    /// Query writers of a key across every contract using the `mv_kv_key` view.
+    /// The view is history (one row per write), newest first, so the first row seen
+    /// for each (contract, writer) pair is its current value. Scans at most
+    /// MAX_DEDUP_SCAN rows; results are sorted by (contractId, accountId).
+    /// Returns (entries, has_more, truncated, dropped_rows).
+    async fn query_writers_all_contracts(
+        &self,
+        params: &WritersParams,
+    ) -> anyhow::Result<(Vec<KvEntry>, bool, bool, usize)> {
+        let mut rows_stream = self
+            .scylla_session
+            .execute_iter(require(&self.writers_by_key, DbFeature::Writers)?.clone(), (&params.key,))
+            .await?
+            .rows_stream::<KvRow>()?;
+
+        let mut seen = HashSet::new();
+        let page = collect_page(
+            &mut rows_stream,
+            params.limit,
+            0,
+            Some(MAX_DEDUP_SCAN),
+            |row: KvRow| {
+                if !seen.insert((row.current_account_id.clone(), row.predecessor_id.clone())) {
+                    return None;
+                }
+                Some(KvEntry::from(row))
+            },
+        )
+        .await;
+        self.check_dropped(&page)?;
+
+        // Filter after dedup so an older write never stands in for a deleted latest value
+        let exclude_deleted = params.exclude_deleted.unwrap_or(false);
+        let mut entries: Vec<KvEntry> = page
+            .items
+            .into_iter()
+            .filter(|e| !(exclude_deleted && e.value == "null"))
+            .filter(|e| !params.excludes_writer(&e.predecessor_id))
+            .filter(|e| {
+                params
+                    .predecessor_id
+                    .as_ref()
+                    .is_none_or(|pred| e.predecessor_id == *pred)
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            (&a.current_account_id, &a.predecessor_id).cmp(&(&b.current_account_id, &b.predecessor_id))
+        });
+        let has_more = entries.len() > params.limit;
+        entries.truncate(params.limit);
+
+        Ok((entries, has_more, page.truncated, page.dropped_rows))
+    }
+
     pub async fn query_accounts(
         &self,
         params: &AccountsParams,
@@ -538,7 +693,7 @@ impl ScyllaDb {
             Some(cursor) => self
                 .scylla_session
                 .execute_iter(
-                    self.reverse_list_cursor.clone(),
+                    require(&self.reverse_list_cursor, DbFeature::Writers)?.clone(),
                     (&params.current_account_id, &params.key, cursor),
                 )
                 .await?
@@ -546,7 +701,7 @@ impl ScyllaDb {
             None => self
                 .scylla_session
                 .execute_iter(
-                    self.reverse_list.clone(),
+                    require(&self.reverse_list, DbFeature::Writers)?.clone(),
                     (&params.current_account_id, &params.key),
                 )
                 .await?
@@ -568,6 +723,7 @@ impl ScyllaDb {
             },
         )
         .await;
+        self.check_dropped(&page)?;
 
         Ok((page.items, page.has_more, page.dropped_rows))
     }
@@ -587,12 +743,12 @@ impl ScyllaDb {
         let mut rows_stream = match key {
             Some(k) => self
                 .scylla_session
-                .execute_iter(self.accounts_by_contract_key.clone(), (contract_id, k))
+                .execute_iter(require(&self.accounts_by_contract_key, DbFeature::Accounts)?.clone(), (contract_id, k))
                 .await?
                 .rows_stream::<ContractAccountRow>()?,
             None => self
                 .scylla_session
-                .execute_iter(self.accounts_by_contract.clone(), (contract_id,))
+                .execute_iter(require(&self.accounts_by_contract, DbFeature::Accounts)?.clone(), (contract_id,))
                 .await?
                 .rows_stream::<ContractAccountRow>()?,
         };
@@ -656,6 +812,55 @@ impl ScyllaDb {
         Ok((result, has_more, truncated, dropped_rows))
     }
 
+    /// Compare a contract's writers in `kv_accounts` with those found by a
+    /// bounded scan of `s_kv_last`.
+    ///
+    /// `kv_accounts` is populated asynchronously by the indexer after the
+    /// `s_kv_last` write, and both reads here use `LocalOne`, so a small
+    /// positive delta is expected right after a burst of new writers. A delta
+    /// that persists means the population is lagging.
+    pub async fn query_accounts_staleness(&self, contract_id: &str) -> anyhow::Result<AccountsStaleness> {
+        let distinct = |statement: PreparedStatement, cap: usize| async move {
+            let mut rows_stream = self
+                .scylla_session
+                .execute_iter(statement, (contract_id,))
+                .await?
+                .rows_stream::<ContractAccountRow>()?;
+            let mut seen = HashSet::new();
+            let mut rows = 0usize;
+            while let Some(row_result) = rows_stream.next().await {
+                rows += 1;
+                match row_result {
+                    Ok(row) => {
+                        seen.insert(row.predecessor_id);
+                    }
+                    Err(e) => tracing::warn!(
+                        target: "fastkv-server",
+                        error = %e,
+                        "Failed to deserialize row in query_accounts_staleness"
+                    ),
+                }
+                if seen.len() >= cap {
+                    break;
+                }
+            }
+            anyhow::Ok((seen.len(), rows))
+        };
+
+        let (indexed, _) = distinct(
+            require(&self.accounts_by_contract, DbFeature::Accounts)?.clone(),
+            MAX_DEDUP_SCAN,
+        )
+        .await?;
+        let (scanned, rows) = distinct(
+            require(&self.accounts_staleness_scan, DbFeature::Accounts)?.clone(),
+            STALENESS_SCAN_ROWS,
+        )
+        .await?;
+
+        Ok(AccountsStaleness::new(contract_id, indexed, scanned, rows >= STALENESS_SCAN_ROWS))
+    }
+
     /// Query the dedicated `all_accounts` table (one row per unique account).
     /// Uses TOKEN-based cursor for stable pagination across partitions.
     ///
@@ -673,12 +878,12 @@ impl ScyllaDb {
         let mut rows_stream = match after_account {
             Some(cursor) => self
                 .scylla_session
-                .execute_iter(self.accounts_all_cursor.clone(), (cursor,))
+                .execute_iter(require(&self.accounts_all_cursor, DbFeature::Accounts)?.clone(), (cursor,))
                 .await?
                 .rows_stream::<ContractAccountRow>()?,
             None => self
                 .scylla_session
-                .execute_iter(self.accounts_all.clone(), &[])
+                .execute_iter(require(&self.accounts_all, DbFeature::Accounts)?.clone(), &[])
                 .await?
                 .rows_stream::<ContractAccountRow>()?,
         };
@@ -710,6 +915,7 @@ impl ScyllaDb {
             },
         )
         .await;
+        self.check_dropped(&page)?;
 
         Ok((page.items, page.has_more, page.dropped_rows))
     }
@@ -729,12 +935,12 @@ impl ScyllaDb {
         let mut rows_stream = match after_contract {
             Some(cursor) => self
                 .scylla_session
-                .execute_iter(self.contracts_all_cursor.clone(), (cursor,))
+                .execute_iter(require(&self.contracts_all_cursor, DbFeature::Accounts)?.clone(), (cursor,))
                 .await?
                 .rows_stream::<ContractRow>()?,
             None => self
                 .scylla_session
-                .execute_iter(self.contracts_all.clone(), &[])
+                .execute_iter(require(&self.contracts_all, DbFeature::Accounts)?.clone(), &[])
                 .await?
                 .rows_stream::<ContractRow>()?,
         };
@@ -768,10 +974,33 @@ impl ScyllaDb {
             },
         )
         .await;
+        self.check_dropped(&page)?;
 
         Ok((page.items, page.has_more, page.dropped_rows))
     }
 
+    /// Count distinct contracts by streaming the contracts table. Rows are
+    /// clustered per contract, so consecutive duplicates are skipped; the
+    /// count is a point-in-time scan, not a maintained counter.
+    pub async fn count_contracts(&self) -> anyhow::Result<usize> {
+        let mut rows_stream = self
+            .scylla_session
+            .execute_iter(require(&self.contracts_all, DbFeature::Accounts)?.clone(), &[])
+            .await?
+            .rows_stream::<ContractRow>()?;
+
+        let mut count = 0;
+        let mut last_contract: Option<String> = None;
+        while let Some(row) = rows_stream.next().await {
+            let row = row?;
+            if last_contract.as_deref() != Some(row.current_account_id.as_str()) {
+                count += 1;
+                last_contract = Some(row.current_account_id);
+            }
+        }
+        Ok(count)
+    }
+
     /// Query distinct contracts that a specific account has written to.
     /// Uses `s_kv_last` where `predecessor_id` is the partition key, so this
     /// is a cheap single-partition query.
@@ -818,6 +1047,7 @@ impl ScyllaDb {
             },
         )
         .await;
+        self.check_dropped(&page)?;
 
         Ok((page.items, page.has_more, page.dropped_rows))
     }
@@ -896,10 +1126,70 @@ impl ScyllaDb {
             },
         )
         .await;
+        self.check_dropped(&page)?;
 
         Ok((page.items, page.has_more, page.dropped_rows))
     }
 
+    /// Approximate sample of a partition: read up to `SAMPLE_SCAN_BUDGET` keys,
+    /// pick a probe point and page `limit` entries after it.
+    /// Returns (entries, dropped_rows).
+    pub async fn sample_kv(
+        &self,
+        params: &SampleParams,
+        seed: u64,
+    ) -> anyhow::Result<(Vec<KvEntry>, usize)> {
+        let result = match &params.key_prefix {
+            Some(prefix) => {
+                let prefix_end = compute_prefix_end(prefix);
+                self.scylla_session
+                    .execute_unpaged(
+                        &self.sample_probe_prefix,
+                        (
+                            &params.predecessor_id,
+                            &params.current_account_id,
+                            prefix.as_str(),
+                            &prefix_end,
+                        ),
+                    )
+                    .await?
+            }
+            None => {
+                self.scylla_session
+                    .execute_unpaged(
+                        &self.sample_probe,
+                        (&params.predecessor_id, &params.current_account_id),
+                    )
+                    .await?
+            }
+        }
+        .into_rows_result()?;
+        let keys = result
+            .rows::<(String,)>()?
+            .map(|row| row.map(|(key,)| key))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let query = QueryParams {
+            predecessor_id: params.predecessor_id.clone(),
+            current_account_id: params.current_account_id.clone(),
+            key_prefix: params.key_prefix.clone(),
+            exclude_deleted: None,
+            limit: params.limit,
+            offset: 0,
+            fields: None,
+            format: None,
+            value_format: None,
+            after_key: sample_seed(&keys, seed).map(str::to_string),
+            count_only: None,
+            max_count: None,
+            keys_only: None,
+            max_value_bytes: None,
+            with_rank: None,
+        };
+        let (entries, _has_more, dropped) = self.query_kv_with_pagination(&query).await?;
+        Ok((entries, dropped))
+    }
+
     pub async fn get_kv_at_block(
         &self,
         predecessor_id: &str,
@@ -962,9 +1252,9 @@ impl ScyllaDb {
         }
 
         let stmt = if is_asc {
-            self.timeline_asc.clone()
+            require(&self.timeline_asc, DbFeature::Timeline)?.clone()
         } else {
-            self.timeline_desc.clone()
+            require(&self.timeline_desc, DbFeature::Timeline)?.clone()
         };
 
         let mut rows_stream = self
@@ -999,10 +1289,12 @@ impl ScyllaDb {
         )
         .await;
 
+        self.check_dropped(&page)?;
+
         let next_cursor = page
             .items
             .last()
-            .map(|(e, key)| format!("{}:{key}", e.block_height));
+            .map(|(e, key)| crate::models::encode_timeline_cursor(e.block_height as i64, key));
         let entries: Vec<KvEntry> = page.items.into_iter().map(|(e, _)| e).collect();
 
         Ok((entries, page.has_more, page.dropped_rows, next_cursor))
@@ -1020,12 +1312,12 @@ impl ScyllaDb {
         let mut rows_stream = match after_source {
             Some(cursor) => self
                 .scylla_session
-                .execute_iter(self.edges_list_cursor.clone(), (edge_type, target, cursor))
+                .execute_iter(require(&self.edges_list_cursor, DbFeature::Edges)?.clone(), (edge_type, target, cursor))
                 .await?
                 .rows_stream::<EdgeRow>()?,
             None => self
                 .scylla_session
-                .execute_iter(self.edges_list.clone(), (edge_type, target))
+                .execute_iter(require(&self.edges_list, DbFeature::Edges)?.clone(), (edge_type, target))
                 .await?
                 .rows_stream::<EdgeRow>()?,
         };
@@ -1044,6 +1336,7 @@ impl ScyllaDb {
             },
         )
         .await;
+        self.check_dropped(&page)?;
 
         Ok((page.items, page.has_more, page.dropped_rows))
     }
@@ -1051,7 +1344,7 @@ impl ScyllaDb {
     pub async fn count_edges(&self, edge_type: &str, target: &str) -> anyhow::Result<usize> {
         let result = self
             .scylla_session
-            .execute_unpaged(&self.edges_count, (edge_type, target))
+            .execute_unpaged(require(&self.edges_count, DbFeature::Edges)?, (edge_type, target))
             .await?
             .into_rows_result()?;
 
@@ -1111,6 +1404,7 @@ impl ScyllaDb {
             .await?
             .rows_stream::<KvHistoryRow>()?;
 
+        let mut collapse = params.collapse_unchanged.unwrap_or(false).then(CollapseUnchanged::default);
         let page = collect_page(
             &mut rows_stream,
             params.limit,
@@ -1128,19 +1422,25 @@ impl ScyllaDb {
                         }
                     }
                 }
-                let oid = row.order_id;
-                Some((KvEntry::from(row), oid))
+                if let Some(ref mut collapse) = collapse {
+                    if !collapse.keep(&row.key, &row.value) {
+                        return None;
+                    }
+                }
+                let order_id = row.order_id;
+                Some(KvEntry {
+                    order_id: Some(order_id),
+                    content_type: None,
+                    ..KvEntry::from(row)
+                })
             },
         )
         .await;
+        self.check_dropped(&page)?;
 
-        let next_cursor = page
-            .items
-            .last()
-            .map(|(e, oid)| format!("{}:{oid}", e.block_height));
-        let entries: Vec<KvEntry> = page.items.into_iter().map(|(e, _)| e).collect();
+        let next_cursor = page.items.last().and_then(KvEntry::history_cursor);
 
-        Ok((entries, page.has_more, page.dropped_rows, next_cursor))
+        Ok((page.items, page.has_more, page.dropped_rows, next_cursor))
     }
 
     pub async fn get_indexer_block_height(&self) -> anyhow::Result<Option<u64>> {
@@ -1190,113 +1490,232 @@ mod tests {
         assert!(validate_identifier("name-with-dashes", "TEST").is_err());
     }
 
-    fn make_err() -> NextRowError {
-        NextRowError::from(scylla::deserialize::DeserializationError::new(
-            std::io::Error::other("test deser error"),
-        ))
-    }
-
-    #[tokio::test]
-    async fn test_collect_page_overfetch() {
-        let items: Vec<Result<i32, NextRowError>> = (1..=6).map(Ok).collect();
-        let mut s = futures::stream::iter(items);
-        let page = collect_page(&mut s, 5, 0, None, Some).await;
-        assert_eq!(page.items.len(), 5);
-        assert!(page.has_more);
-        assert!(!page.truncated);
-        assert_eq!(page.dropped_rows, 0);
-    }
-
-    #[tokio::test]
-    async fn test_collect_page_under_limit() {
-        let items: Vec<Result<i32, NextRowError>> = (1..=3).map(Ok).collect();
-        let mut s = futures::stream::iter(items);
-        let page = collect_page(&mut s, 5, 0, None, Some).await;
-        assert_eq!(page.items.len(), 3);
-        assert!(!page.has_more);
+    #[test]
+    fn test_compute_prefix_end() {
+        assert_eq!(compute_prefix_end("graph/follow/"), "graph/follow/\u{10ffff}");
+        assert_eq!(compute_prefix_end("test"), "test\u{10ffff}");
+        assert_eq!(compute_prefix_end(""), "\u{10ffff}");
     }
+}
 
-    #[tokio::test]
-    async fn test_collect_page_with_offset() {
-        let items: Vec<Result<i32, NextRowError>> = (1..=10).map(Ok).collect();
-        let mut s = futures::stream::iter(items);
-        let page = collect_page(&mut s, 5, 3, None, Some).await;
-        assert_eq!(page.items, vec![4, 5, 6, 7, 8]);
-        assert!(page.has_more); // item 9 caused overfetch
+/// End-to-end checks of the prepared CQL against a live cluster.
+///
+/// Opt-in: runs only when `SCYLLA_TEST_URL` (comma-separated nodes, no auth or
+/// TLS) is set, and passes as a no-op otherwise. Each run drops and recreates
+/// the `fastkv_server_test` keyspace from [`SCHEMA`], writes fixtures the way
+/// kv-sub-indexer does, and reads them back through [`ScyllaDb`].
+///
+/// ```sh
+/// SCYLLA_TEST_URL=127.0.0.1:9042 cargo test --features scylla-backend integration_tests
+/// ```
+#[cfg(test)]
+mod integration_tests {
+    use super::*;
+    use crate::degraded::FeatureUnavailable;
+    use serde_json::json;
+
+    const TEST_KEYSPACE: &str = "fastkv_server_test";
+
+    /// Tables and views the server reads, as in the kv-sub-indexer schema
+    /// (fastdata-indexer/README.md). `all_accounts` and `s_kv_by_block` are
+    /// left out, so the Accounts and Timeline features start disabled.
+    const SCHEMA: &[&str] = &[
+        "CREATE TABLE s_kv (
+            receipt_id text, action_index int, tx_hash text, signer_id text,
+            predecessor_id text, current_account_id text, block_height bigint, block_timestamp bigint,
+            shard_id int, receipt_index int, order_id bigint, key text, value text,
+            is_deleted boolean, content_type text,
+            PRIMARY KEY ((predecessor_id), current_account_id, key, block_height, order_id)
+        )",
+        "CREATE TABLE s_kv_last (
+            receipt_id text, action_index int, tx_hash text, signer_id text,
+            predecessor_id text, current_account_id text, block_height bigint, block_timestamp bigint,
+            shard_id int, receipt_index int, order_id bigint, key text, value text,
+            is_deleted boolean, content_type text,
+            PRIMARY KEY ((predecessor_id), current_account_id, key)
+        )",
+        "CREATE MATERIALIZED VIEW mv_kv_key AS
+            SELECT * FROM s_kv
+            WHERE key IS NOT NULL AND block_height IS NOT NULL AND order_id IS NOT NULL
+            AND predecessor_id IS NOT NULL AND current_account_id IS NOT NULL
+            PRIMARY KEY ((key), block_height, order_id, predecessor_id, current_account_id)",
+        "CREATE MATERIALIZED VIEW mv_kv_cur_key AS
+            SELECT * FROM s_kv
+            WHERE current_account_id IS NOT NULL AND key IS NOT NULL AND block_height IS NOT NULL
+            AND order_id IS NOT NULL AND predecessor_id IS NOT NULL
+            PRIMARY KEY ((current_account_id), key, block_height, order_id, predecessor_id)",
+        "CREATE TABLE kv_accounts (
+            current_account_id text, key text, predecessor_id text,
+            PRIMARY KEY ((current_account_id), key, predecessor_id)
+        )",
+        "CREATE TABLE kv_reverse (
+            current_account_id text, key text, predecessor_id text,
+            receipt_id text, action_index int, tx_hash text, signer_id text,
+            block_height bigint, block_timestamp bigint, shard_id int, receipt_index int,
+            order_id bigint, value text,
+            PRIMARY KEY ((current_account_id, key), predecessor_id)
+        )",
+        "CREATE TABLE kv_edges (
+            edge_type text, target text, source text, current_account_id text,
+            block_height bigint, block_timestamp bigint, order_id bigint, value text,
+            PRIMARY KEY ((edge_type, target), source)
+        )",
+        "CREATE TABLE meta (suffix text PRIMARY KEY, last_processed_block_height bigint)",
+    ];
+
+    /// Session to `SCYLLA_TEST_URL`, or `None` when it is unset.
+    async fn test_session() -> Option<Session> {
+        let Ok(url) = env::var("SCYLLA_TEST_URL") else {
+            eprintln!("SCYLLA_TEST_URL not set, skipping ScyllaDB integration tests");
+            return None;
+        };
+        let mut builder = SessionBuilder::new();
+        for node in url.split(',').map(str::trim).filter(|node| !node.is_empty()) {
+            builder = builder.known_node(node);
+        }
+        Some(builder.build().await.expect("connect to SCYLLA_TEST_URL"))
     }
 
-    #[tokio::test]
-    async fn test_collect_page_dropped_rows() {
-        let items: Vec<Result<i32, NextRowError>> =
-            vec![Ok(1), Err(make_err()), Ok(2), Err(make_err()), Ok(3)];
-        let mut s = futures::stream::iter(items);
-        let page = collect_page(&mut s, 10, 0, None, Some).await;
-        assert_eq!(page.items, vec![1, 2, 3]);
-        assert_eq!(page.dropped_rows, 2);
-        assert!(!page.has_more);
+    async fn create_keyspace(session: &Session) {
+        session
+            .query_unpaged(format!("DROP KEYSPACE IF EXISTS {TEST_KEYSPACE}"), &[])
+            .await
+            .unwrap();
+        session
+            .query_unpaged(
+                format!("CREATE KEYSPACE {TEST_KEYSPACE} WITH REPLICATION = {{'class': 'SimpleStrategy', 'replication_factor': 1}}"),
+                &[],
+            )
+            .await
+            .unwrap();
+        session.use_keyspace(TEST_KEYSPACE, false).await.unwrap();
+        for ddl in SCHEMA {
+            session.query_unpaged(*ddl, &[]).await.unwrap();
+        }
     }
 
-    #[tokio::test]
-    async fn test_collect_page_scan_cap() {
-        let items: Vec<Result<i32, NextRowError>> = (1..=20).map(Ok).collect();
-        let mut s = futures::stream::iter(items);
-        let page = collect_page(&mut s, 100, 0, Some(10), Some).await;
-        assert!(page.truncated);
-        assert_eq!(page.items.len(), 10);
-        assert!(!page.has_more); // caller computes in scan-cap mode
+    /// Record one write in history, latest-value and reverse tables.
+    async fn write(session: &Session, account: &str, contract: &str, key: &str, value: &str, block_height: i64) {
+        let receipt_id = format!("receipt-{block_height}");
+        let tx_hash = format!("tx-{block_height}");
+        let block_timestamp = block_height * 1_000_000_000;
+        for table in ["s_kv", "s_kv_last"] {
+            session
+                .query_unpaged(
+                    format!(
+                        "INSERT INTO {table} (receipt_id, action_index, tx_hash, signer_id, predecessor_id, current_account_id, \
+                         block_height, block_timestamp, shard_id, receipt_index, order_id, key, value, is_deleted) \
+                         VALUES (?, 0, ?, ?, ?, ?, ?, ?, 0, 0, 0, ?, ?, false)"
+                    ),
+                    (&receipt_id, &tx_hash, account, account, contract, block_height, block_timestamp, key, value),
+                )
+                .await
+                .unwrap();
+        }
+        session
+            .query_unpaged(
+                "INSERT INTO kv_reverse (current_account_id, key, predecessor_id, receipt_id, action_index, tx_hash, signer_id, \
+                 block_height, block_timestamp, shard_id, receipt_index, order_id, value) \
+                 VALUES (?, ?, ?, ?, 0, ?, ?, ?, ?, 0, 0, 0, ?)",
+                (contract, key, account, &receipt_id, &tx_hash, account, block_height, block_timestamp, value),
+            )
+            .await
+            .unwrap();
     }
 
-    #[tokio::test]
-    async fn test_collect_page_filter_via_transform() {
-        // Only keep odd numbers; offset should count filtered items
-        let items: Vec<Result<i32, NextRowError>> = (1..=10).map(Ok).collect();
-        let mut s = futures::stream::iter(items);
-        let page = collect_page(
-            &mut s,
-            3,
-            1,
-            None,
-            |n| {
-                if n % 2 == 1 {
-                    Some(n)
-                } else {
-                    None
-                }
-            },
-        )
-        .await;
-        // Odd items: 1, 3, 5, 7, 9. Skip 1 (offset), take 3+1: [3,5,7,9]
-        assert_eq!(page.items, vec![3, 5, 7]);
-        assert!(page.has_more);
+    fn params<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> T {
+        serde_json::from_value(value).unwrap()
     }
 
-    #[tokio::test]
-    async fn test_collect_page_empty_stream() {
-        let items: Vec<Result<i32, NextRowError>> = vec![];
-        let mut s = futures::stream::iter(items);
-        let page = collect_page(&mut s, 10, 0, None, Some).await;
-        assert!(page.items.is_empty());
-        assert!(!page.has_more);
-        assert!(!page.truncated);
-        assert_eq!(page.dropped_rows, 0);
+    fn keys(entries: &[KvEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.key.as_str()).collect()
     }
 
+    // One test so the keyspace and the KEYSPACE variable are set up once
     #[tokio::test]
-    async fn test_collect_page_all_filtered() {
-        // Every item filtered out by transform — result is empty, no has_more
-        let items: Vec<Result<i32, NextRowError>> = (1..=10).map(Ok).collect();
-        let mut s = futures::stream::iter(items);
-        let page: PageResult<i32> = collect_page(&mut s, 5, 0, None, |_| None).await;
-        assert!(page.items.is_empty());
-        assert!(!page.has_more);
-        assert!(!page.truncated);
-        assert_eq!(page.dropped_rows, 0);
-    }
-
-    #[test]
-    fn test_compute_prefix_end() {
-        assert_eq!(compute_prefix_end("graph/follow/"), "graph/follow/\u{10ffff}");
-        assert_eq!(compute_prefix_end("test"), "test\u{10ffff}");
-        assert_eq!(compute_prefix_end(""), "\u{10ffff}");
+    async fn test_statements_against_test_keyspace() {
+        let Some(session) = test_session().await else {
+            return;
+        };
+        create_keyspace(&session).await;
+        write(&session, "alice.near", "app.near", "profile/name", "\"Alice\"", 100).await;
+        write(&session, "alice.near", "app.near", "profile/bio", "\"hi\"", 100).await;
+        write(&session, "alice.near", "app.near", "settings", "{}", 105).await;
+        write(&session, "alice.near", "app.near", "profile/name", "\"Alice B\"", 110).await;
+        write(&session, "bob.near", "app.near", "profile/name", "\"Bob\"", 120).await;
+
+        env::set_var("KEYSPACE", TEST_KEYSPACE);
+        let db = ScyllaDb::new(ChainId::Testnet, session).await.unwrap();
+        // Degraded wiring: SCHEMA omits s_kv_by_block and all_accounts, so
+        // ScyllaDb::new starts without Timeline and Accounts instead of failing,
+        // and their queries report FeatureUnavailable.
+        assert_eq!(db.disabled_features, HashSet::from([DbFeature::Timeline, DbFeature::Accounts]));
+        let timeline = json!({ "accountId": "alice.near", "contractId": "app.near" });
+        let err = db.get_kv_timeline(&params(timeline)).await.unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&FeatureUnavailable(DbFeature::Timeline)));
+        let err = db.query_all_accounts(10, None).await.unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&FeatureUnavailable(DbFeature::Accounts)));
+
+        // get_kv: latest value wins, missing keys are None
+        let entry = db.get_kv("alice.near", "app.near", "profile/name").await.unwrap().unwrap();
+        assert_eq!(entry.value, "\"Alice B\"");
+        assert_eq!(entry.block_height, 110);
+        assert_eq!(entry.tx_hash, "tx-110");
+        assert!(db.get_kv("alice.near", "app.near", "missing").await.unwrap().is_none());
+
+        // query_kv_with_pagination: limit + has_more, cursor, prefix
+        let query = json!({ "accountId": "alice.near", "contractId": "app.near", "limit": 2 });
+        let (entries, has_more, dropped) = db.query_kv_with_pagination(&params(query)).await.unwrap();
+        assert_eq!(keys(&entries), vec!["profile/bio", "profile/name"]);
+        assert!(has_more);
+        assert_eq!(dropped, 0);
+        let query = json!({ "accountId": "alice.near", "contractId": "app.near", "after_key": "profile/name" });
+        let (entries, has_more, _) = db.query_kv_with_pagination(&params(query)).await.unwrap();
+        assert_eq!(keys(&entries), vec!["settings"]);
+        assert!(!has_more);
+        let query = json!({ "accountId": "alice.near", "contractId": "app.near", "key_prefix": "profile/" });
+        let (entries, _, _) = db.query_kv_with_pagination(&params(query)).await.unwrap();
+        assert_eq!(keys(&entries), vec!["profile/bio", "profile/name"]);
+
+        // query_writers: one row per writer with its latest value
+        let writers = json!({ "contractId": "app.near", "key": "profile/name" });
+        let (entries, has_more, truncated, _) = db.query_writers(&params(writers)).await.unwrap();
+        let found: Vec<_> = entries.iter().map(|e| (e.predecessor_id.as_str(), e.value.as_str())).collect();
+        assert_eq!(found, vec![("alice.near", "\"Alice B\""), ("bob.near", "\"Bob\"")]);
+        assert!(!has_more && !truncated);
+
+        // REVERSE_SOURCE=view: the same writers from mv_kv_cur_key, where alice's
+        // two writes collapse to the newest, and after_account pages past her
+        env::set_var("REVERSE_SOURCE", "view");
+        let view_db = ScyllaDb::new(ChainId::Testnet, test_session().await.unwrap()).await;
+        env::remove_var("REVERSE_SOURCE");
+        let view_db = view_db.unwrap();
+        let writers = json!({ "contractId": "app.near", "key": "profile/name" });
+        let (entries, has_more, truncated, _) = view_db.query_writers(&params(writers)).await.unwrap();
+        let found: Vec<_> = entries.iter().map(|e| (e.predecessor_id.as_str(), e.value.as_str())).collect();
+        assert_eq!(found, vec![("alice.near", "\"Alice B\""), ("bob.near", "\"Bob\"")]);
+        assert!(!has_more && !truncated);
+        let writers = json!({ "contractId": "app.near", "key": "profile/name", "after_account": "alice.near" });
+        let (entries, _, _, _) = view_db.query_writers(&params(writers)).await.unwrap();
+        assert_eq!(entries.iter().map(|e| e.predecessor_id.as_str()).collect::<Vec<_>>(), vec!["bob.near"]);
+
+        // get_kv_history: both orders, and the cursor resumes after the last row
+        let history = json!({ "accountId": "alice.near", "contractId": "app.near", "key": "profile/name" });
+        let (entries, _, _, next_cursor) = db.get_kv_history(&params(history)).await.unwrap();
+        let blocks: Vec<u64> = entries.iter().map(|e| e.block_height).collect();
+        assert_eq!(blocks, vec![110, 100]);
+        assert!(entries.iter().all(|e| e.order_id == Some(0)));
+        assert_eq!(next_cursor, entries.last().unwrap().history_cursor());
+        let history = json!({ "accountId": "alice.near", "contractId": "app.near", "key": "profile/name", "order": "asc", "limit": 1 });
+        let (entries, has_more, _, cursor) = db.get_kv_history(&params(history)).await.unwrap();
+        assert_eq!(entries[0].block_height, 100);
+        assert!(has_more);
+        let history = json!({
+            "accountId": "alice.near", "contractId": "app.near", "key": "profile/name",
+            "order": "asc", "limit": 1, "cursor": cursor,
+        });
+        let (entries, has_more, _, _) = db.get_kv_history(&params(history)).await.unwrap();
+        assert_eq!(entries[0].block_height, 110);
+        assert!(!has_more);
     }
 }
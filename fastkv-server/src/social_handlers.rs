@@ -1,11 +1,17 @@
+use actix_web::middleware::from_fn;
 use actix_web::{get, post, web, HttpResponse};
 
-use crate::handlers::validate_account_id;
+use crate::handlers::{require_db, validate_account_id, validate_key};
 use crate::models::*;
+use crate::request_timeout;
+use crate::tree::prune_tree;
 use crate::AppState;
 
 use std::sync::LazyLock;
 
+/// Edge type for follows: `graph/follow/{target}` written by the follower.
+const FOLLOW_EDGE_TYPE: &str = "graph/follow";
+
 static SOCIAL_CONTRACT: LazyLock<String> = LazyLock::new(|| {
     std::env::var("SOCIAL_CONTRACT").unwrap_or_else(|_| "social.near".to_string())
 });
@@ -20,6 +26,40 @@ fn resolve_contract(contract_id: &Option<String>) -> Result<&str, ApiError> {
     }
 }
 
+fn validate_fields(fields: &[String]) -> Result<(), ApiError> {
+    if fields.len() > MAX_SOCIAL_KEYS {
+        return Err(ApiError::InvalidParameter(format!(
+            "fields: cannot exceed {MAX_SOCIAL_KEYS} paths"
+        )));
+    }
+    if fields.iter().any(|f| f.is_empty()) {
+        return Err(ApiError::InvalidParameter(
+            "fields: paths cannot be empty".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Prune the assembled tree to the requested `fields`, if any.
+fn apply_fields(tree: serde_json::Value, fields: Option<&[String]>) -> serde_json::Value {
+    match fields {
+        Some(fields) => prune_tree(&tree, fields),
+        None => tree,
+    }
+}
+
+/// Widget source from a `widget/{name}` entry. SocialDB stores the source as
+/// a JSON string, so it is unquoted; any other value is returned verbatim.
+fn widget_source(entry: Option<KvEntry>, name: &str) -> Result<String, ApiError> {
+    let entry = entry
+        .filter(|e| !e.is_deleted)
+        .ok_or_else(|| ApiError::NotFound(format!("widget '{name}'")))?;
+    match serde_json::from_str::<serde_json::Value>(&entry.value) {
+        Ok(serde_json::Value::String(source)) => Ok(source),
+        _ => Ok(entry.value),
+    }
+}
+
 // POST /v1/social/get - get values for multiple keys
 #[utoipa::path(
     post,
@@ -32,13 +72,19 @@ fn resolve_contract(contract_id: &Option<String>) -> Result<&str, ApiError> {
     ),
     tag = "social"
 )]
-#[post("/v1/social/get")]
+#[post("/v1/social/get", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn social_get_handler(
-    _body: web::Json<SocialGetBody>,
+    body: web::Json<SocialGetBody>,
     _app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, ApiError> {
+    let fields = body.options.as_ref().and_then(|o| o.fields.as_deref());
+    if let Some(fields) = fields {
+        validate_fields(fields)?;
+    }
+
     // TODO: Implement with Redis
-    Ok(HttpResponse::Ok().json(serde_json::json!({})))
+    let tree = serde_json::json!({});
+    Ok(HttpResponse::Ok().json(apply_fields(tree, fields)))
 }
 
 // POST /v1/social/keys - list keys under a prefix
@@ -53,7 +99,7 @@ pub async fn social_get_handler(
     ),
     tag = "social"
 )]
-#[post("/v1/social/keys")]
+#[post("/v1/social/keys", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn social_keys_handler(
     _body: web::Json<SocialKeysBody>,
     _app_state: web::Data<AppState>,
@@ -74,7 +120,7 @@ pub async fn social_keys_handler(
     ),
     tag = "social"
 )]
-#[get("/v1/social/index")]
+#[get("/v1/social/index", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn social_index_handler(
     _query: web::Query<SocialIndexParams>,
     _app_state: web::Data<AppState>,
@@ -87,6 +133,7 @@ pub async fn social_index_handler(
             truncated: false,
             next_cursor: None,
             dropped_rows: None,
+            partition_exists: None,
         },
     }))
 }
@@ -103,16 +150,24 @@ pub async fn social_index_handler(
     ),
     tag = "social"
 )]
-#[get("/v1/social/profile")]
+#[get("/v1/social/profile", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn social_profile_handler(
     query: web::Query<SocialProfileParams>,
     _app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, ApiError> {
     validate_account_id(&query.account_id, "accountId")?;
     let _contract = resolve_contract(&query.contract_id)?;
-    
+    let fields: Option<Vec<String>> = query
+        .fields
+        .as_ref()
+        .map(|f| f.split(',').map(|p| p.trim().to_string()).collect());
+    if let Some(ref fields) = fields {
+        validate_fields(fields)?;
+    }
+
     // TODO: Implement with Redis
-    Ok(HttpResponse::Ok().json(serde_json::json!({})))
+    let tree = serde_json::json!({});
+    Ok(HttpResponse::Ok().json(apply_fields(tree, fields.as_deref())))
 }
 
 // GET /v1/social/followers - get followers list
@@ -127,7 +182,7 @@ pub async fn social_profile_handler(
     ),
     tag = "social"
 )]
-#[get("/v1/social/followers")]
+#[get("/v1/social/followers", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn social_followers_handler(
     query: web::Query<SocialFollowParams>,
     _app_state: web::Data<AppState>,
@@ -138,16 +193,52 @@ pub async fn social_followers_handler(
     // TODO: Implement with Redis
     Ok(HttpResponse::Ok().json(SocialFollowResponse {
         data: Vec::new(),
-        count: 0,
+        count: None,
         meta: PaginationMeta {
             has_more: false,
             truncated: false,
             next_cursor: None,
             dropped_rows: None,
+            partition_exists: None,
         },
     }))
 }
 
+// GET /v1/social/followers/count - count followers
+#[utoipa::path(
+    get,
+    path = "/v1/social/followers/count",
+    params(SocialFollowCountParams),
+    responses(
+        (status = 200, description = "Follower count", body = inline(DataResponse<EdgesCountResponse>)),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    ),
+    tag = "social"
+)]
+#[get("/v1/social/followers/count", wrap = "from_fn(request_timeout::enforce)")]
+pub async fn social_followers_count_handler(
+    query: web::Query<SocialFollowCountParams>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    validate_account_id(&query.account_id, "accountId")?;
+
+    let db = require_db(&app_state).await?;
+    let count = db.count_edges(FOLLOW_EDGE_TYPE, &query.account_id).await?;
+
+    Ok(HttpResponse::Ok().json(follow_count(&query.account_id, count)))
+}
+
+fn follow_count(account_id: &str, count: usize) -> DataResponse<EdgesCountResponse> {
+    DataResponse {
+        data: EdgesCountResponse {
+            edge_type: FOLLOW_EDGE_TYPE.to_string(),
+            target: account_id.to_string(),
+            count,
+        },
+    }
+}
+
 // GET /v1/social/following - get following list
 #[utoipa::path(
     get,
@@ -160,7 +251,7 @@ pub async fn social_followers_handler(
     ),
     tag = "social"
 )]
-#[get("/v1/social/following")]
+#[get("/v1/social/following", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn social_following_handler(
     query: web::Query<SocialFollowParams>,
     _app_state: web::Data<AppState>,
@@ -171,12 +262,13 @@ pub async fn social_following_handler(
     // TODO: Implement with Redis
     Ok(HttpResponse::Ok().json(SocialFollowResponse {
         data: Vec::new(),
-        count: 0,
+        count: None,
         meta: PaginationMeta {
             has_more: false,
             truncated: false,
             next_cursor: None,
             dropped_rows: None,
+            partition_exists: None,
         },
     }))
 }
@@ -193,7 +285,7 @@ pub async fn social_following_handler(
     ),
     tag = "social"
 )]
-#[get("/v1/social/feed/account")]
+#[get("/v1/social/feed/account", wrap = "from_fn(request_timeout::enforce)")]
 pub async fn social_account_feed_handler(
     _query: web::Query<SocialAccountFeedParams>,
     _app_state: web::Data<AppState>,
@@ -206,6 +298,136 @@ pub async fn social_account_feed_handler(
             truncated: false,
             next_cursor: None,
             dropped_rows: None,
+            partition_exists: None,
         },
     }))
 }
+
+// GET /v1/social/widget - get a widget's source
+#[utoipa::path(
+    get,
+    path = "/v1/social/widget",
+    params(SocialWidgetParams),
+    responses(
+        (status = 200, description = "Widget source", body = String, content_type = "text/plain"),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "Widget not found", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    ),
+    tag = "social"
+)]
+#[get("/v1/social/widget", wrap = "from_fn(request_timeout::enforce)")]
+pub async fn social_widget_handler(
+    query: web::Query<SocialWidgetParams>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    validate_account_id(&query.account_id, "accountId")?;
+    let contract = resolve_contract(&query.contract_id)?;
+    validate_key(&query.name, "name", MAX_KEY_LENGTH)?;
+    let key = format!("widget/{}", query.name);
+
+    let db = require_db(&app_state).await?;
+    let entry = db.get_kv(&query.account_id, contract, &key).await?;
+    let source = widget_source(entry, &query.name)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+
+    fn widget_entry(value: &str) -> KvEntry {
+        KvEntry {
+            predecessor_id: "alice.near".to_string(),
+            current_account_id: "social.near".to_string(),
+            key: "widget/Hello".to_string(),
+            value: value.to_string(),
+            block_height: 1,
+            block_timestamp: 2,
+            receipt_id: "r".to_string(),
+            tx_hash: "t".to_string(),
+            order_id: None,
+            content_type: None,
+            is_deleted: false,
+        }
+    }
+
+    #[test]
+    fn test_widget_source_present() {
+        let entry = widget_entry(r#""return <div>Hello</div>;""#);
+        assert_eq!(
+            widget_source(Some(entry), "Hello").unwrap(),
+            "return <div>Hello</div>;"
+        );
+        // Not a JSON string: returned as stored
+        let entry = widget_entry("return 1;");
+        assert_eq!(widget_source(Some(entry), "Hello").unwrap(), "return 1;");
+    }
+
+    #[test]
+    fn test_widget_source_absent_is_404() {
+        let err = widget_source(None, "Missing").unwrap_err();
+        assert_eq!(err.error_response().status(), StatusCode::NOT_FOUND);
+
+        let mut deleted = widget_entry("null");
+        deleted.is_deleted = true;
+        let err = widget_source(Some(deleted), "Hello").unwrap_err();
+        assert_eq!(err.error_response().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_follow_count_is_the_follow_edge_count() {
+        let json = serde_json::to_value(follow_count("bob.near", 3)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"data": {"edgeType": "graph/follow", "target": "bob.near", "count": 3}})
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_followers_list_paginates_without_count() {
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(crate::handlers::tests::offline_state()))
+                .service(social_followers_count_handler)
+                .service(social_followers_handler),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        // The count endpoint needs the db; the list does not compute a total
+        let res = test::call_service(&app, get("/v1/social/followers/count?accountId=bob.near")).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let res = test::call_service(&app, get("/v1/social/followers?accountId=bob.near&limit=5")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert!(body["count"].is_null());
+        assert_eq!(body["meta"]["has_more"], false);
+    }
+
+    #[actix_web::test]
+    async fn test_widget_handler_wiring() {
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(crate::handlers::tests::offline_state()))
+                .service(social_widget_handler),
+        )
+        .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        let res = test::call_service(&app, get("/v1/social/widget?accountId=alice.near&name=Hello")).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let res = test::call_service(&app, get("/v1/social/widget?accountId=alice.near&name=")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,47 +1,329 @@
-pub fn build_tree(items: &[(String, String)]) -> serde_json::Value {
-    let mut root = serde_json::Map::new();
-    for (key, value) in items {
+use crate::models::{detect_encrypted, JsonChange, JsonChangeOp, TreeStats};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Nest `key/path` items into a JSON object, returning it with its [`TreeStats`].
+pub fn build_tree_with_stats(items: &[(String, String)]) -> (serde_json::Value, TreeStats) {
+    nest(items.iter().map(|(key, value)| {
         // Parse value as JSON, fallback to string if invalid
         let parsed_value = serde_json::from_str(value)
             .unwrap_or_else(|_| serde_json::Value::String(value.clone()));
+        (key.as_str(), mark_encrypted(parsed_value))
+    }))
+}
 
+/// Key structure only: [`build_tree_with_stats`] with every leaf set to `true`,
+/// like SocialDB's `return_type: "True"`.
+pub fn build_key_tree(keys: &[String]) -> (serde_json::Value, TreeStats) {
+    nest(
+        keys.iter()
+            .map(|key| (key.as_str(), serde_json::Value::Bool(true))),
+    )
+}
+
+fn nest<'a>(
+    items: impl IntoIterator<Item = (&'a str, serde_json::Value)>,
+) -> (serde_json::Value, TreeStats) {
+    let mut root = serde_json::Map::new();
+    let mut leaves: BTreeSet<&str> = BTreeSet::new();
+    for (key, value) in items {
         // Split key by '/' and nest
         let parts: Vec<&str> = key.split('/').collect();
-        insert_nested(&mut root, &parts, parsed_value);
+        if insert_nested(&mut root, &parts, value) {
+            // A leaf written over a subtree replaces every leaf under it.
+            let nested = format!("{key}/");
+            let replaced: Vec<&str> = leaves
+                .range(nested.as_str()..)
+                .take_while(|leaf| leaf.starts_with(&nested))
+                .copied()
+                .collect();
+            for leaf in replaced {
+                leaves.remove(leaf);
+            }
+            leaves.insert(key);
+        }
+    }
+    (serde_json::Value::Object(root), tree_stats(&leaves))
+}
+
+fn tree_stats(leaves: &BTreeSet<&str>) -> TreeStats {
+    let mut paths = BTreeSet::new();
+    let mut max_depth = 0;
+    for leaf in leaves {
+        paths.extend(leaf.match_indices('/').map(|(i, _)| &leaf[..i]));
+        paths.insert(*leaf);
+        max_depth = max_depth.max(leaf.split('/').count());
+    }
+    TreeStats {
+        leaves: leaves.len(),
+        max_depth,
+        nodes: paths.len(),
     }
-    serde_json::Value::Object(root)
 }
 
+/// Replace an `enc:AES256:<id>:<b64>` leaf with an opaque marker object so
+/// clients can render it without attempting to decrypt.
+fn mark_encrypted(value: serde_json::Value) -> serde_json::Value {
+    if let serde_json::Value::String(ref s) = value {
+        if let Some(key_id) = detect_encrypted(s) {
+            return serde_json::json!({ "$encrypted": true, "keyId": key_id });
+        }
+    }
+    value
+}
+
+/// Returns false when the value was dropped because of a path conflict.
 fn insert_nested(
     obj: &mut serde_json::Map<String, serde_json::Value>,
     parts: &[&str],
     value: serde_json::Value,
-) {
+) -> bool {
     if parts.is_empty() {
-        return;
+        return false;
     }
     if parts.len() == 1 {
         obj.insert(parts[0].to_string(), value);
+        true
     } else {
         let entry = obj
             .entry(parts[0].to_string())
             .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
         if let serde_json::Value::Object(ref mut nested) = entry {
-            insert_nested(nested, &parts[1..], value);
+            insert_nested(nested, &parts[1..], value)
         } else {
             tracing::warn!(
                 target: "fastkv-server",
                 key = parts[0],
                 "tree path conflict: cannot nest under a scalar value"
             );
+            false
+        }
+    }
+}
+
+/// Keep only the branches of `tree` selected by `fields`.
+///
+/// Each field is a `/`-separated path using SocialDB key-pattern syntax:
+/// `*` matches exactly one segment and a trailing `**` matches the whole
+/// subtree. A path naming an object keeps that object in full.
+pub fn prune_tree(tree: &serde_json::Value, fields: &[String]) -> serde_json::Value {
+    let patterns: Vec<Vec<&str>> = fields.iter().map(|f| f.split('/').collect()).collect();
+    let patterns: Vec<&[&str]> = patterns.iter().map(|p| p.as_slice()).collect();
+    prune(tree, &patterns).unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()))
+}
+
+fn prune(value: &serde_json::Value, patterns: &[&[&str]]) -> Option<serde_json::Value> {
+    if patterns.iter().any(|p| p.is_empty() || *p == ["**"]) {
+        return Some(value.clone());
+    }
+    let obj = value.as_object()?;
+    let mut out = serde_json::Map::new();
+    for (key, child) in obj {
+        let tails: Vec<&[&str]> = patterns
+            .iter()
+            .filter(|p| p[0] == key || p[0] == "*")
+            .map(|p| &p[1..])
+            .collect();
+        if tails.is_empty() {
+            continue;
+        }
+        if let Some(pruned) = prune(child, &tails) {
+            out.insert(key.clone(), pruned);
+        }
+    }
+    (!out.is_empty()).then_some(serde_json::Value::Object(out))
+}
+
+/// Observed shape of a set of JSON values: every type seen, plus merged
+/// member shapes for objects and a merged element shape for arrays.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Shape {
+    types: BTreeSet<&'static str>,
+    properties: BTreeMap<String, Shape>,
+    items: Option<Box<Shape>>,
+}
+
+impl Shape {
+    pub fn of(value: &serde_json::Value) -> Self {
+        let mut shape = Shape::default();
+        shape.add(value);
+        shape
+    }
+
+    /// Fold another observed value into this shape.
+    pub fn add(&mut self, value: &serde_json::Value) {
+        use serde_json::Value;
+        self.types.insert(match value {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        });
+        match value {
+            Value::Object(map) => {
+                for (name, member) in map {
+                    self.properties.entry(name.clone()).or_default().add(member);
+                }
+            }
+            Value::Array(elements) => {
+                let items = self.items.get_or_insert_with(Box::default);
+                for element in elements {
+                    items.add(element);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn merge(&mut self, other: Shape) {
+        self.types.extend(other.types);
+        for (name, shape) in other.properties {
+            self.properties.entry(name).or_default().merge(shape);
+        }
+        if let Some(items) = other.items {
+            self.items.get_or_insert_with(Box::default).merge(*items);
         }
     }
+
+    /// JSON-Schema-style rendering: `type` is a string, or a sorted array when
+    /// more than one type was seen.
+    pub fn to_schema(&self) -> serde_json::Value {
+        let mut schema = serde_json::Map::new();
+        let types: Vec<&str> = self.types.iter().copied().collect();
+        if let [only] = types.as_slice() {
+            schema.insert("type".to_string(), serde_json::json!(only));
+        } else if !types.is_empty() {
+            schema.insert("type".to_string(), serde_json::json!(types));
+        }
+        if !self.properties.is_empty() {
+            let properties: serde_json::Map<String, serde_json::Value> = self
+                .properties
+                .iter()
+                .map(|(name, shape)| (name.clone(), shape.to_schema()))
+                .collect();
+            schema.insert("properties".to_string(), properties.into());
+        }
+        if let Some(items) = &self.items {
+            schema.insert("items".to_string(), items.to_schema());
+        }
+        serde_json::Value::Object(schema)
+    }
+}
+
+/// Merged schema of raw stored values. Values are parsed the same way as in
+/// [`build_tree_with_stats`]: non-JSON falls back to a string and encrypted values become
+/// the `$encrypted` marker object.
+pub fn infer_schema<'a>(values: impl IntoIterator<Item = &'a str>) -> serde_json::Value {
+    let mut shape = Shape::default();
+    for raw in values {
+        let parsed = serde_json::from_str(raw)
+            .unwrap_or_else(|_| serde_json::Value::String(raw.to_string()));
+        shape.merge(Shape::of(&mark_encrypted(parsed)));
+    }
+    shape.to_schema()
+}
+
+/// Structural diff of two JSON values. Objects are compared key by key;
+/// anything else (scalars, arrays, a type change) is a single `changed` at
+/// its path. Paths are JSON pointers, sorted by key.
+pub fn json_diff(old: &serde_json::Value, new: &serde_json::Value) -> Vec<JsonChange> {
+    let mut changes = Vec::new();
+    diff_at(old, new, &mut String::new(), &mut changes);
+    changes
+}
+
+fn diff_at(
+    old: &serde_json::Value,
+    new: &serde_json::Value,
+    path: &mut String,
+    changes: &mut Vec<JsonChange>,
+) {
+    use serde_json::Value;
+
+    let (Value::Object(old_map), Value::Object(new_map)) = (old, new) else {
+        if old != new {
+            changes.push(JsonChange {
+                op: JsonChangeOp::Changed,
+                path: path.clone(),
+                old: Some(old.clone()),
+                new: Some(new.clone()),
+            });
+        }
+        return;
+    };
+
+    let keys: BTreeSet<&String> = old_map.keys().chain(new_map.keys()).collect();
+    for key in keys {
+        let len = path.len();
+        path.push('/');
+        path.push_str(&key.replace('~', "~0").replace('/', "~1"));
+        match (old_map.get(key), new_map.get(key)) {
+            (Some(o), Some(n)) => diff_at(o, n, path, changes),
+            (Some(o), None) => changes.push(JsonChange {
+                op: JsonChangeOp::Removed,
+                path: path.clone(),
+                old: Some(o.clone()),
+                new: None,
+            }),
+            (None, Some(n)) => changes.push(JsonChange {
+                op: JsonChangeOp::Added,
+                path: path.clone(),
+                old: None,
+                new: Some(n.clone()),
+            }),
+            (None, None) => {}
+        }
+        path.truncate(len);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn build_tree(items: &[(String, String)]) -> serde_json::Value {
+        build_tree_with_stats(items).0
+    }
+
+    #[test]
+    fn test_shape_merges_types_into_union() {
+        let mut shape = Shape::of(&serde_json::json!({ "name": "Alice", "age": 30 }));
+        shape.merge(Shape::of(
+            &serde_json::json!({ "name": "Bob", "age": "thirty", "tags": ["a", 1] }),
+        ));
+        shape.add(&serde_json::json!(null));
+
+        assert_eq!(
+            shape.to_schema(),
+            serde_json::json!({
+                "type": ["null", "object"],
+                "properties": {
+                    "age": { "type": ["number", "string"] },
+                    "name": { "type": "string" },
+                    "tags": { "type": "array", "items": { "type": ["number", "string"] } },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_infer_schema_from_raw_values() {
+        let schema = infer_schema(["{\"url\":\"ipfs://x\"}", "plain text", "[]"]);
+        assert_eq!(
+            schema["type"],
+            serde_json::json!(["array", "object", "string"])
+        );
+        assert_eq!(schema["properties"]["url"]["type"], "string");
+        assert_eq!(schema["items"], serde_json::json!({}));
+
+        assert_eq!(infer_schema([]), serde_json::json!({}));
+        assert_eq!(
+            Shape::of(&serde_json::json!(1)),
+            Shape::of(&serde_json::json!(2.5))
+        );
+    }
+
     #[test]
     fn test_build_tree_basic() {
         let items = vec![
@@ -89,6 +371,63 @@ mod tests {
         let items = vec![("a/b/c/d".to_string(), "\"deep\"".to_string())];
         let tree = build_tree(&items);
         assert_eq!(tree["a"]["b"]["c"]["d"], "deep");
+
+        let (_, stats) = build_tree_with_stats(&items);
+        assert_eq!(
+            stats,
+            TreeStats {
+                leaves: 1,
+                max_depth: 4,
+                nodes: 4
+            }
+        );
+    }
+
+    #[test]
+    fn test_build_tree_stats() {
+        let items = vec![
+            ("profile/name".to_string(), "\"Alice\"".to_string()),
+            (
+                "profile/image/url".to_string(),
+                "\"https://example.com\"".to_string(),
+            ),
+            // A JSON object value is a single leaf
+            (
+                "widget/app".to_string(),
+                "{\"src\":\"code\",\"v\":1}".to_string(),
+            ),
+        ];
+        let (_, stats) = build_tree_with_stats(&items);
+        assert_eq!(
+            stats,
+            TreeStats {
+                leaves: 3,
+                max_depth: 3,
+                nodes: 6
+            }
+        );
+
+        assert_eq!(build_tree_with_stats(&[]).1, TreeStats::default());
+
+        // Conflicts: a key under a leaf is dropped; a leaf over a subtree replaces it
+        let (tree, stats) = build_tree_with_stats(&[
+            ("a/b".to_string(), "\"leaf\"".to_string()),
+            ("a/b/c".to_string(), "\"nested\"".to_string()),
+            ("x/y/z".to_string(), "1".to_string()),
+            ("x/y".to_string(), "2".to_string()),
+        ]);
+        assert_eq!(
+            tree,
+            serde_json::json!({ "a": { "b": "leaf" }, "x": { "y": 2 } })
+        );
+        assert_eq!(
+            stats,
+            TreeStats {
+                leaves: 2,
+                max_depth: 2,
+                nodes: 4
+            }
+        );
     }
 
     #[test]
@@ -103,4 +442,198 @@ mod tests {
         // "a/b" was set first as a leaf, so "a/b/c" can't nest under it
         assert_eq!(tree["a"]["b"], "leaf");
     }
+
+    #[test]
+    fn test_build_tree_encrypted_leaf() {
+        let items = vec![(
+            "private/note".to_string(),
+            "\"enc:AES256:a1b2c3d4:c2VjcmV0\"".to_string(),
+        )];
+        let tree = build_tree(&items);
+        assert_eq!(
+            tree["private"]["note"],
+            serde_json::json!({ "$encrypted": true, "keyId": "a1b2c3d4" })
+        );
+    }
+
+    #[test]
+    fn test_build_tree_plain_string_not_marked() {
+        let items = vec![("note".to_string(), "\"enc:not-really\"".to_string())];
+        let tree = build_tree(&items);
+        assert_eq!(tree["note"], "enc:not-really");
+    }
+
+    #[test]
+    fn test_build_key_tree_leaves_are_true() {
+        let keys = vec![
+            "profile/name".to_string(),
+            "profile/image/ipfs_cid".to_string(),
+            "widget/app".to_string(),
+        ];
+        let (tree, stats) = build_key_tree(&keys);
+        assert_eq!(
+            tree,
+            serde_json::json!({
+                "profile": { "name": true, "image": { "ipfs_cid": true } },
+                "widget": { "app": true },
+            })
+        );
+        assert_eq!(
+            stats,
+            TreeStats {
+                leaves: 3,
+                max_depth: 3,
+                nodes: 6
+            }
+        );
+
+        // Same structure as the valued tree, with no value leaking through
+        let items: Vec<(String, String)> = keys
+            .iter()
+            .map(|k| (k.clone(), "\"secret\"".to_string()))
+            .collect();
+        let (valued, valued_stats) = build_tree_with_stats(&items);
+        assert_eq!(valued_stats, stats);
+        fn leaves(value: &serde_json::Value, out: &mut Vec<serde_json::Value>) {
+            match value.as_object() {
+                Some(obj) => obj.values().for_each(|v| leaves(v, out)),
+                None => out.push(value.clone()),
+            }
+        }
+        let mut found = Vec::new();
+        leaves(&tree, &mut found);
+        assert_eq!(found, vec![serde_json::Value::Bool(true); 3]);
+        assert!(!tree.to_string().contains("secret"));
+        assert!(valued.to_string().contains("secret"));
+    }
+
+    fn profile_tree() -> serde_json::Value {
+        build_tree(&[
+            ("profile/name".to_string(), "\"Alice\"".to_string()),
+            ("profile/image/ipfs_cid".to_string(), "\"bafy\"".to_string()),
+            ("profile/about".to_string(), "\"long bio\"".to_string()),
+            ("widget/app".to_string(), "\"code\"".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_prune_tree_subset() {
+        let fields = vec!["profile/name".to_string(), "profile/image".to_string()];
+        assert_eq!(
+            prune_tree(&profile_tree(), &fields),
+            serde_json::json!({ "profile": { "name": "Alice", "image": { "ipfs_cid": "bafy" } } })
+        );
+    }
+
+    #[test]
+    fn test_prune_tree_wildcards() {
+        let tree = serde_json::json!({ "alice.near": profile_tree(), "bob.near": profile_tree() });
+
+        let pruned = prune_tree(&tree, &["*/profile/name".to_string()]);
+        assert_eq!(
+            pruned,
+            serde_json::json!({
+                "alice.near": { "profile": { "name": "Alice" } },
+                "bob.near": { "profile": { "name": "Alice" } },
+            })
+        );
+
+        let pruned = prune_tree(&tree, &["bob.near/widget/**".to_string()]);
+        assert_eq!(
+            pruned,
+            serde_json::json!({ "bob.near": { "widget": { "app": "code" } } })
+        );
+    }
+
+    #[test]
+    fn test_prune_tree_no_match() {
+        let pruned = prune_tree(
+            &profile_tree(),
+            &["profile/name/first".to_string(), "missing".to_string()],
+        );
+        assert_eq!(pruned, serde_json::json!({}));
+    }
+
+    fn change(
+        op: JsonChangeOp,
+        path: &str,
+        old: Option<serde_json::Value>,
+        new: Option<serde_json::Value>,
+    ) -> JsonChange {
+        JsonChange {
+            op,
+            path: path.to_string(),
+            old,
+            new,
+        }
+    }
+
+    #[test]
+    fn test_json_diff_added_and_removed_keys() {
+        let old = serde_json::json!({ "name": "Alice", "links": { "x": "@a" } });
+        let new = serde_json::json!({ "name": "Alice", "links": { "gh": "a" }, "bio": "hi" });
+        assert_eq!(
+            json_diff(&old, &new),
+            vec![
+                change(
+                    JsonChangeOp::Added,
+                    "/bio",
+                    None,
+                    Some(serde_json::json!("hi"))
+                ),
+                change(
+                    JsonChangeOp::Added,
+                    "/links/gh",
+                    None,
+                    Some(serde_json::json!("a"))
+                ),
+                change(
+                    JsonChangeOp::Removed,
+                    "/links/x",
+                    Some(serde_json::json!("@a")),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_diff_changed_scalars() {
+        let old = serde_json::json!({ "age": 30, "tags": ["a"], "a/b": true });
+        let new = serde_json::json!({ "age": 31, "tags": ["a", "b"], "a/b": true });
+        assert_eq!(
+            json_diff(&old, &new),
+            vec![
+                change(
+                    JsonChangeOp::Changed,
+                    "/age",
+                    Some(serde_json::json!(30)),
+                    Some(serde_json::json!(31))
+                ),
+                change(
+                    JsonChangeOp::Changed,
+                    "/tags",
+                    Some(serde_json::json!(["a"])),
+                    Some(serde_json::json!(["a", "b"]))
+                ),
+            ]
+        );
+
+        // Whole-value and type changes are reported at the root / key path
+        assert_eq!(
+            json_diff(&serde_json::json!("Alice"), &serde_json::json!("Bob")),
+            vec![change(
+                JsonChangeOp::Changed,
+                "",
+                Some(serde_json::json!("Alice")),
+                Some(serde_json::json!("Bob"))
+            )]
+        );
+        let diff = json_diff(
+            &serde_json::json!({ "a/b": 1 }),
+            &serde_json::json!({ "a/b": { "c": 1 } }),
+        );
+        assert_eq!(diff[0].path, "/a~1b");
+        assert!(json_diff(&old, &old).is_empty());
+    }
 }
@@ -0,0 +1,124 @@
+//! Command-line options.
+
+use crate::near_tx::CallOptions;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const USAGE: &str = "usage: test-fastdata-tx [--batch-file FILE] [--gas GAS] [--deposit YOCTO] [--dry-run | --verify] [--server-url URL] [--verify-timeout-secs N]
+  --gas GAS  gas per KV call; by default 300 TGas is split across the calls, at most 100 TGas each";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// JSON array of payloads to write in one transaction instead of the
+    /// built-in test data.
+    pub batch_file: Option<PathBuf>,
+    /// Gas and deposit for each KV call. Without `--gas`, each call gets
+    /// 100 TGas or an even share of 300 TGas when there are more than three.
+    pub call: CallOptions,
+    /// Sign the transaction and print it instead of broadcasting.
+    pub dry_run: bool,
+    /// Poll fastkv-server after broadcasting until the data is indexed.
+    pub verify: bool,
+    pub server_url: String,
+    pub verify_timeout: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            batch_file: None,
+            call: CallOptions::default(),
+            dry_run: false,
+            verify: false,
+            server_url: "http://localhost:3001".to_string(),
+            verify_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+fn parse_number<T: FromStr>(name: &str, value: String) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{name}: not a number: {value}"))
+}
+
+/// Parse arguments (without the program name).
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{name} needs a value"));
+        match arg.as_str() {
+            "--batch-file" => options.batch_file = Some(value("--batch-file")?.into()),
+            "--gas" => options.call.gas = Some(parse_number("--gas", value("--gas")?)?),
+            "--deposit" => options.call.deposit = parse_number("--deposit", value("--deposit")?)?,
+            "--dry-run" => options.dry_run = true,
+            "--verify" => options.verify = true,
+            "--server-url" => options.server_url = value("--server-url")?,
+            "--verify-timeout-secs" => {
+                options.verify_timeout = Duration::from_secs(parse_number("--verify-timeout-secs", value("--verify-timeout-secs")?)?);
+            }
+            other => return Err(format!("unknown argument {other}\n{USAGE}")),
+        }
+    }
+    if options.dry_run && options.verify {
+        return Err("--dry-run and --verify cannot be combined".to_string());
+    }
+    options.call.validate()?;
+    Ok(options)
+}
+
+/// Payloads of a batch file: a JSON array of key/value objects.
+pub fn parse_batch(contents: &str) -> Result<Vec<Value>, String> {
+    let payloads: Vec<Value> = serde_json::from_str(contents).map_err(|e| format!("batch file: {e}"))?;
+    if payloads.is_empty() {
+        return Err("batch file has no payloads".to_string());
+    }
+    if let Some(i) = payloads.iter().position(|payload| !payload.is_object()) {
+        return Err(format!("batch file: payload {i} is not a JSON object"));
+    }
+    Ok(payloads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_verify_options() {
+        assert_eq!(parse(args(&[])).unwrap(), Options::default());
+        let options = parse(args(&["--verify", "--server-url", "http://kv:3001", "--verify-timeout-secs", "5"])).unwrap();
+        assert!(options.verify);
+        assert_eq!(options.server_url, "http://kv:3001");
+        assert_eq!(options.verify_timeout, Duration::from_secs(5));
+        assert!(parse(args(&["--verify-timeout-secs"])).is_err());
+        assert!(parse(args(&["--bogus"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_gas_and_deposit() {
+        let options = parse(args(&["--gas", "50000000000000", "--deposit", "1000000000000000000000000"])).unwrap();
+        assert_eq!(options.call, CallOptions { gas: Some(50_000_000_000_000), deposit: 10u128.pow(24) });
+        assert!(parse(args(&["--gas", "300000000000001"])).is_err());
+        assert!(parse(args(&["--deposit", "-1"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_batch() {
+        let options = parse(args(&["--batch-file", "payloads.json"])).unwrap();
+        assert_eq!(options.batch_file, Some(PathBuf::from("payloads.json")));
+        assert_eq!(parse_batch(r#"[{"a":1},{"b":2}]"#).unwrap().len(), 2);
+        assert!(parse_batch("[]").is_err());
+        assert!(parse_batch(r#"[{"a":1},2]"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_dry_run() {
+        assert!(parse(args(&["--dry-run"])).unwrap().dry_run);
+        assert!(parse(args(&["--dry-run", "--verify"])).is_err());
+    }
+}
@@ -0,0 +1,574 @@
+//! Build, sign and broadcast `__fastdata_kv` transactions.
+//!
+//! [`submit_kv`] does the whole round trip against a NEAR RPC node: read the
+//! signer's access key nonce and a recent block hash, build a self-call with
+//! the KV payload as args, sign it and `broadcast_tx_commit` it. The pieces
+//! are public so callers (and tests) can assemble a transaction offline;
+//! [`dry_run_kv`] stops after signing and returns the encoded transaction
+//! without broadcasting it. Several payloads can share one transaction, one
+//! `FunctionCall` action each, up to [`MAX_ACTIONS`].
+//!
+//! RPC access goes through the [`Rpc`] trait ([`HttpRpc`] in production), so
+//! the submission loop can be tested against a scripted node. A broadcast
+//! rejected with `InvalidNonce` (another transaction used the nonce first) is
+//! retried with a fresh nonce, up to [`MAX_NONCE_RETRIES`] times.
+
+use near_crypto::Signer;
+use near_primitives::hash::CryptoHash;
+use near_primitives::transaction::{Action, FunctionCallAction, SignedTransaction, Transaction, TransactionV0};
+use near_primitives::types::{AccountId, Balance, Gas};
+use reqwest::Client;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Method the kv-sub-indexer picks up (suffix `kv`).
+pub const KV_METHOD: &str = "__fastdata_kv";
+/// Default gas attached to each KV call (100 TGas).
+pub const KV_GAS: u64 = 100_000_000_000_000;
+/// Most gas a transaction may prepay (300 TGas).
+pub const MAX_GAS: u64 = 300_000_000_000_000;
+/// Most actions a transaction may carry (`max_actions_per_receipt`).
+pub const MAX_ACTIONS: usize = 100;
+/// Stale-nonce retries before a broadcast gives up.
+pub const MAX_NONCE_RETRIES: usize = 3;
+
+#[derive(Debug)]
+pub enum RpcError {
+    /// The node answered with a JSON-RPC `error` object.
+    Rpc { method: String, error: Value },
+    /// The request did not get a JSON-RPC answer.
+    Transport(String),
+}
+
+impl RpcError {
+    /// The node rejected the transaction's nonce as already used.
+    pub fn is_invalid_nonce(&self) -> bool {
+        matches!(self, RpcError::Rpc { error, .. } if error.to_string().contains("InvalidNonce"))
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpcError::Rpc { method, error } => write!(f, "{method} failed: {error}"),
+            RpcError::Transport(e) => write!(f, "RPC request failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Gas and deposit attached to each KV call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallOptions {
+    /// Gas per action. `None` splits [`MAX_GAS`] evenly across the actions,
+    /// capped at [`KV_GAS`] each.
+    pub gas: Option<u64>,
+    /// Attached deposit in yoctoNEAR.
+    pub deposit: u128,
+}
+
+impl CallOptions {
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        match self.gas {
+            Some(gas) if gas == 0 || gas > MAX_GAS => Err(format!("gas must be between 1 and {MAX_GAS} (300 TGas), got {gas}")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Gas each of `actions` actions gets.
+    pub fn gas_per_action(&self, actions: usize) -> u64 {
+        self.gas.unwrap_or_else(|| KV_GAS.min(MAX_GAS / actions.max(1) as u64))
+    }
+}
+
+/// A NEAR JSON-RPC endpoint.
+pub trait Rpc {
+    /// Send one request and return its `result`.
+    fn call(&self, method: &str, params: Value) -> impl Future<Output = std::result::Result<Value, RpcError>> + Send;
+}
+
+pub struct HttpRpc {
+    client: Client,
+    url: String,
+}
+
+impl HttpRpc {
+    pub fn new(url: &str) -> Self {
+        Self { client: Client::new(), url: url.to_string() }
+    }
+}
+
+impl Rpc for HttpRpc {
+    async fn call(&self, method: &str, params: Value) -> std::result::Result<Value, RpcError> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": "dontcare",
+            "method": method,
+            "params": params,
+        });
+        let mut resp = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| RpcError::Transport(e.to_string()))?
+            .json::<Value>()
+            .await
+            .map_err(|e| RpcError::Transport(e.to_string()))?;
+        if let Some(error) = resp.get_mut("error") {
+            return Err(RpcError::Rpc { method: method.to_string(), error: error.take() });
+        }
+        Ok(resp["result"].take())
+    }
+}
+
+/// Current nonce of the signer's access key.
+pub async fn fetch_access_key_nonce<R: Rpc>(rpc: &R, signer: &Signer) -> Result<u64> {
+    let params = json!({
+        "request_type": "view_access_key",
+        "finality": "final",
+        "account_id": signer.get_account_id(),
+        "public_key": signer.public_key().to_string(),
+    });
+    let result = rpc.call("query", params).await?;
+    result["nonce"]
+        .as_u64()
+        .ok_or_else(|| format!("access key response has no nonce: {result}").into())
+}
+
+/// Hash of the latest final block, which the transaction must reference.
+pub async fn fetch_recent_block_hash<R: Rpc>(rpc: &R) -> Result<CryptoHash> {
+    let result = rpc.call("block", json!({ "finality": "final" })).await?;
+    let hash = result["header"]["hash"]
+        .as_str()
+        .ok_or_else(|| format!("block response has no hash: {result}"))?;
+    CryptoHash::from_str(hash)
+}
+
+/// One `FunctionCall` action: method name and args.
+pub type MethodCall = (String, Vec<u8>);
+
+fn function_call((method_name, args): MethodCall, gas: u64, call: CallOptions) -> Action {
+    Action::from(FunctionCallAction {
+        method_name,
+        args,
+        gas: Gas::from_gas(gas),
+        deposit: Balance::from_yoctonear(call.deposit),
+    })
+}
+
+fn self_call(signer: &Signer, access_key_nonce: u64, block_hash: CryptoHash, actions: Vec<Action>) -> Transaction {
+    Transaction::V0(TransactionV0 {
+        signer_id: signer.get_account_id(),
+        public_key: signer.public_key(),
+        nonce: access_key_nonce + 1,
+        receiver_id: signer.get_account_id(), // Call self
+        block_hash,
+        actions,
+    })
+}
+
+/// Build the KV self-call. `access_key_nonce` is the key's current nonce; the
+/// transaction uses the next one.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn build_kv_transaction(
+    signer: &Signer,
+    access_key_nonce: u64,
+    block_hash: CryptoHash,
+    args: Vec<u8>,
+    call: CallOptions,
+) -> Transaction {
+    let gas = call.gas_per_action(1);
+    self_call(signer, access_key_nonce, block_hash, vec![function_call((KV_METHOD.to_string(), args), gas, call)])
+}
+
+/// Build a self-call carrying one `FunctionCall` action per entry of `calls`,
+/// in order, each with `call`'s deposit and [`CallOptions::gas_per_action`].
+/// Fails when there are no calls, more than [`MAX_ACTIONS`], or an explicit
+/// per-action gas adds up to more than [`MAX_GAS`].
+pub fn build_batch_transaction(
+    signer: &Signer,
+    access_key_nonce: u64,
+    block_hash: CryptoHash,
+    calls: Vec<MethodCall>,
+    call: CallOptions,
+) -> Result<Transaction> {
+    if calls.is_empty() {
+        return Err("a transaction needs at least one action".into());
+    }
+    if calls.len() > MAX_ACTIONS {
+        return Err(format!("{} actions exceed the per-transaction limit of {MAX_ACTIONS}", calls.len()).into());
+    }
+    let gas = call.gas_per_action(calls.len());
+    if gas.saturating_mul(calls.len() as u64) > MAX_GAS {
+        return Err(format!(
+            "{} actions with {gas} gas each exceed {MAX_GAS} gas per transaction; lower --gas or omit it to split the gas across actions",
+            calls.len()
+        )
+        .into());
+    }
+    let actions = calls.into_iter().map(|method_call| function_call(method_call, gas, call)).collect();
+    Ok(self_call(signer, access_key_nonce, block_hash, actions))
+}
+
+/// Sign the SHA-256 of the borsh-encoded transaction.
+pub fn sign_transaction(signer: &Signer, tx: Transaction) -> Result<SignedTransaction> {
+    let hash = Sha256::digest(borsh::to_vec(&tx)?);
+    let signature = signer.sign(&hash);
+    Ok(SignedTransaction::new(signature, tx))
+}
+
+/// Borsh-encode and base64 a signed transaction for `broadcast_tx_commit`.
+pub fn encode_signed_transaction(signed_tx: &SignedTransaction) -> Result<String> {
+    let bytes = borsh::to_vec(signed_tx)?;
+    Ok(base64::Engine::encode(&base64::engine::general_purpose::STANDARD, bytes))
+}
+
+/// Transaction hash from a `broadcast_tx_commit` result.
+fn outcome_tx_hash(result: &Value) -> Result<String> {
+    result["transaction"]["hash"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| format!("broadcast response has no transaction hash: {result}").into())
+}
+
+/// A signed KV transaction, ready for `broadcast_tx_commit`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedKvTx {
+    /// Nonce the transaction uses.
+    pub nonce: u64,
+    /// Transaction hash the chain will report once it is included.
+    pub hash: CryptoHash,
+    /// Borsh-encoded, base64 signed transaction.
+    pub tx_base64: String,
+}
+
+/// Read the signer's current nonce and sign `calls` as one transaction on top
+/// of `block_hash`.
+pub async fn build_signed_tx<R: Rpc>(
+    rpc: &R,
+    signer: &Signer,
+    block_hash: CryptoHash,
+    calls: &[MethodCall],
+    call: CallOptions,
+) -> Result<SignedKvTx> {
+    let access_key_nonce = fetch_access_key_nonce(rpc, signer).await?;
+    let tx = build_batch_transaction(signer, access_key_nonce, block_hash, calls.to_vec(), call)?;
+    let signed_tx = sign_transaction(signer, tx)?;
+    Ok(SignedKvTx {
+        nonce: signed_tx.transaction.nonce(),
+        hash: signed_tx.get_hash(),
+        tx_base64: encode_signed_transaction(&signed_tx)?,
+    })
+}
+
+/// Send an encoded signed transaction with `broadcast_tx_commit` and return
+/// the node's raw result.
+pub async fn broadcast<R: Rpc>(rpc: &R, tx_base64: &str) -> std::result::Result<Value, RpcError> {
+    rpc.call("broadcast_tx_commit", json!([tx_base64])).await
+}
+
+/// Sign `calls` against the latest block without broadcasting them.
+pub async fn sign_only<R: Rpc>(rpc: &R, signer: &Signer, calls: &[MethodCall], call: CallOptions) -> Result<SignedKvTx> {
+    call.validate()?;
+    let block_hash = fetch_recent_block_hash(rpc).await?;
+    build_signed_tx(rpc, signer, block_hash, calls, call).await
+}
+
+/// A broadcast transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submitted {
+    /// Hash reported by `broadcast_tx_commit`.
+    pub tx_hash: String,
+    /// Nonces the node rejected as stale before one went through.
+    pub stale_nonces: Vec<u64>,
+}
+
+/// Sign and broadcast `calls` as one transaction. When the node rejects the nonce as
+/// stale, re-read the access key, re-sign with the next nonce and retry, at
+/// most `max_retries` times. Other errors are returned as is.
+pub async fn sign_and_broadcast<R: Rpc>(
+    rpc: &R,
+    signer: &Signer,
+    calls: &[MethodCall],
+    call: CallOptions,
+    max_retries: usize,
+) -> Result<Submitted> {
+    call.validate()?;
+    let block_hash = fetch_recent_block_hash(rpc).await?;
+    let mut stale_nonces = Vec::new();
+    loop {
+        let signed_tx = build_signed_tx(rpc, signer, block_hash, calls, call).await?;
+        match broadcast(rpc, &signed_tx.tx_base64).await {
+            Err(e) if e.is_invalid_nonce() && stale_nonces.len() < max_retries => stale_nonces.push(signed_tx.nonce),
+            Err(e) => return Err(e.into()),
+            Ok(result) => return Ok(Submitted { tx_hash: outcome_tx_hash(&result)?, stale_nonces }),
+        }
+    }
+}
+
+/// KV calls writing each of `payloads` to `account`'s partition as `signer`.
+fn kv_calls(account: &AccountId, signer: &Signer, payloads: &[Value]) -> Result<Vec<MethodCall>> {
+    if &signer.get_account_id() != account {
+        return Err(format!("signer {} cannot write for {account}", signer.get_account_id()).into());
+    }
+    payloads
+        .iter()
+        .map(|data| {
+            if !data.is_object() {
+                return Err("data must be a JSON object of key/value pairs".into());
+            }
+            Ok((KV_METHOD.to_string(), serde_json::to_vec(data)?))
+        })
+        .collect()
+}
+
+/// Write `payloads` (JSON objects of key/value pairs) to `account`'s FastKV
+/// partition in one transaction, one KV call per payload, signed by `signer`
+/// with `call`'s deposit on each and the gas split per [`CallOptions`].
+pub async fn submit_kv(
+    account: &AccountId,
+    signer: &Signer,
+    payloads: &[Value],
+    rpc_url: &str,
+    call: CallOptions,
+) -> Result<Submitted> {
+    let calls = kv_calls(account, signer, payloads)?;
+    sign_and_broadcast(&HttpRpc::new(rpc_url), signer, &calls, call, MAX_NONCE_RETRIES).await
+}
+
+/// Like [`submit_kv`], but stop after signing: the RPC node is only asked for
+/// the nonce and a block hash, and the signed transaction is returned instead
+/// of broadcast.
+pub async fn dry_run_kv(
+    account: &AccountId,
+    signer: &Signer,
+    payloads: &[Value],
+    rpc_url: &str,
+    call: CallOptions,
+) -> Result<SignedKvTx> {
+    let calls = kv_calls(account, signer, payloads)?;
+    sign_only(&HttpRpc::new(rpc_url), signer, &calls, call).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshDeserialize;
+    use near_crypto::{InMemorySigner, KeyType, SecretKey};
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Answers calls from a script of `(method, response)` in order, recording
+    /// each call's params.
+    struct MockRpc {
+        script: Mutex<VecDeque<(&'static str, std::result::Result<Value, Value>)>>,
+        calls: Mutex<Vec<(String, Value)>>,
+    }
+
+    impl MockRpc {
+        fn new(script: Vec<(&'static str, std::result::Result<Value, Value>)>) -> Self {
+            Self { script: Mutex::new(script.into()), calls: Mutex::default() }
+        }
+
+        fn methods(&self) -> Vec<String> {
+            self.calls.lock().unwrap().iter().map(|(method, _)| method.clone()).collect()
+        }
+    }
+
+    impl Rpc for MockRpc {
+        async fn call(&self, method: &str, params: Value) -> std::result::Result<Value, RpcError> {
+            self.calls.lock().unwrap().push((method.to_string(), params));
+            let (expected, response) = self.script.lock().unwrap().pop_front().expect("unexpected RPC call");
+            assert_eq!(method, expected);
+            response.map_err(|error| RpcError::Rpc { method: method.to_string(), error })
+        }
+    }
+
+    fn access_key(nonce: u64) -> (&'static str, std::result::Result<Value, Value>) {
+        ("query", Ok(json!({ "nonce": nonce })))
+    }
+
+    fn block() -> (&'static str, std::result::Result<Value, Value>) {
+        ("block", Ok(json!({ "header": { "hash": CryptoHash::default().to_string() } })))
+    }
+
+    /// Nonce of the transaction sent in a recorded `broadcast_tx_commit` call.
+    fn broadcast_nonce(params: &Value) -> u64 {
+        let bytes = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, params[0].as_str().unwrap()).unwrap();
+        SignedTransaction::try_from_slice(&bytes).unwrap().transaction.nonce()
+    }
+
+    fn kv_call(args: &[u8]) -> Vec<MethodCall> {
+        vec![(KV_METHOD.to_string(), args.to_vec())]
+    }
+
+    fn signer() -> Signer {
+        InMemorySigner::from_secret_key(
+            AccountId::from_str("alice.near").unwrap(),
+            SecretKey::from_seed(KeyType::ED25519, "alice.near"),
+        )
+    }
+
+    #[test]
+    fn test_build_kv_transaction_uses_next_nonce_and_kv_call() {
+        let signer = signer();
+        let args = br#"{"test/name":"Alice"}"#.to_vec();
+        let tx = build_kv_transaction(&signer, 41, CryptoHash::default(), args.clone(), CallOptions::default());
+
+        assert_eq!(tx.nonce(), 42);
+        assert_eq!(tx.signer_id(), &signer.get_account_id());
+        assert_eq!(tx.receiver_id(), &signer.get_account_id());
+        assert_eq!(tx.public_key(), &signer.public_key());
+        assert_eq!(tx.block_hash(), &CryptoHash::default());
+        match tx.actions() {
+            [Action::FunctionCall(call)] => {
+                assert_eq!(call.method_name, KV_METHOD);
+                assert_eq!(call.args, args);
+                assert_eq!(call.gas, Gas::from_gas(KV_GAS));
+                assert_eq!(call.deposit, Balance::ZERO);
+            }
+            actions => panic!("expected one FunctionCall, got {actions:?}"),
+        }
+    }
+
+    #[test]
+    fn test_call_options_set_gas_and_deposit() {
+        let call = CallOptions { gas: Some(30_000_000_000_000), deposit: 1 };
+        let tx = build_kv_transaction(&signer(), 0, CryptoHash::default(), b"{}".to_vec(), call);
+        match tx.actions() {
+            [Action::FunctionCall(action)] => {
+                assert_eq!(action.gas, Gas::from_teragas(30));
+                assert_eq!(action.deposit, Balance::from_yoctonear(1));
+            }
+            actions => panic!("expected one FunctionCall, got {actions:?}"),
+        }
+
+        assert!(CallOptions::default().validate().is_ok());
+        assert!(CallOptions { gas: Some(MAX_GAS), deposit: 0 }.validate().is_ok());
+        assert!(CallOptions { gas: Some(MAX_GAS + 1), deposit: 0 }.validate().is_err());
+        assert!(CallOptions { gas: Some(0), deposit: 0 }.validate().is_err());
+    }
+
+    #[test]
+    fn test_batch_transaction_has_one_action_per_payload_in_order() {
+        let payloads = [json!({ "a": 1 }), json!({ "b": 2 }), json!({ "c": 3 })];
+        let signer = signer();
+        let calls = kv_calls(&signer.get_account_id(), &signer, &payloads).unwrap();
+        let call = CallOptions { gas: Some(50_000_000_000_000), deposit: 0 };
+        let tx = build_batch_transaction(&signer, 0, CryptoHash::default(), calls, call).unwrap();
+
+        assert_eq!(tx.actions().len(), payloads.len());
+        for (action, payload) in tx.actions().iter().zip(&payloads) {
+            match action {
+                Action::FunctionCall(action) => {
+                    assert_eq!(action.method_name, KV_METHOD);
+                    assert_eq!(serde_json::from_slice::<Value>(&action.args).unwrap(), *payload);
+                    assert_eq!(action.gas, Gas::from_teragas(50));
+                }
+                other => panic!("expected a FunctionCall, got {other:?}"),
+            }
+        }
+
+        let too_many = vec![(KV_METHOD.to_string(), b"{}".to_vec()); MAX_ACTIONS + 1];
+        let small = CallOptions { gas: Some(1), deposit: 0 };
+        assert!(build_batch_transaction(&signer, 0, CryptoHash::default(), too_many, small).is_err());
+        let four = vec![(KV_METHOD.to_string(), b"{}".to_vec()); 4];
+        let explicit = CallOptions { gas: Some(KV_GAS), deposit: 0 };
+        assert!(build_batch_transaction(&signer, 0, CryptoHash::default(), four, explicit).is_err());
+        assert!(build_batch_transaction(&signer, 0, CryptoHash::default(), vec![], small).is_err());
+    }
+
+    #[test]
+    fn test_default_gas_is_split_across_actions() {
+        assert_eq!(CallOptions::default().gas_per_action(1), KV_GAS);
+        assert_eq!(CallOptions::default().gas_per_action(3), KV_GAS);
+        assert_eq!(CallOptions::default().gas_per_action(4), MAX_GAS / 4);
+
+        let signer = signer();
+        for n in [4, MAX_ACTIONS] {
+            let calls = vec![(KV_METHOD.to_string(), b"{}".to_vec()); n];
+            let tx = build_batch_transaction(&signer, 0, CryptoHash::default(), calls, CallOptions::default()).unwrap();
+            let total: u64 = tx
+                .actions()
+                .iter()
+                .map(|action| match action {
+                    Action::FunctionCall(action) => action.gas.as_gas(),
+                    other => panic!("expected a FunctionCall, got {other:?}"),
+                })
+                .sum();
+            assert!(total <= MAX_GAS, "{n} actions prepay {total} gas");
+        }
+    }
+
+    #[test]
+    fn test_signed_transaction_round_trips_and_verifies() {
+        let signer = signer();
+        let tx = build_kv_transaction(&signer, 0, CryptoHash::default(), b"{}".to_vec(), CallOptions::default());
+        let encoded = encode_signed_transaction(&sign_transaction(&signer, tx.clone()).unwrap()).unwrap();
+
+        let bytes = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, encoded).unwrap();
+        let decoded = SignedTransaction::try_from_slice(&bytes).unwrap();
+        assert_eq!(decoded.transaction, tx);
+        let hash = Sha256::digest(borsh::to_vec(&tx).unwrap());
+        assert!(decoded.signature.verify(&hash, &signer.public_key()));
+    }
+
+    #[tokio::test]
+    async fn test_stale_nonce_refreshes_once_then_succeeds() {
+        let stale = json!({
+            "name": "HANDLER_ERROR",
+            "cause": { "name": "INVALID_TRANSACTION" },
+            "data": { "TxExecutionError": { "InvalidTxError": { "InvalidNonce": { "tx_nonce": 6, "ak_nonce": 6 } } } }
+        });
+        let rpc = MockRpc::new(vec![
+            block(),
+            access_key(5),
+            ("broadcast_tx_commit", Err(stale)),
+            access_key(6),
+            ("broadcast_tx_commit", Ok(json!({ "transaction": { "hash": "tx1" } }))),
+        ]);
+
+        let submitted = sign_and_broadcast(&rpc, &signer(), &kv_call(b"{}"), CallOptions::default(), MAX_NONCE_RETRIES).await.unwrap();
+        assert_eq!(submitted, Submitted { tx_hash: "tx1".to_string(), stale_nonces: vec![6] });
+        assert_eq!(rpc.methods(), vec!["block", "query", "broadcast_tx_commit", "query", "broadcast_tx_commit"]);
+        let calls = rpc.calls.lock().unwrap();
+        assert_eq!(broadcast_nonce(&calls[2].1), 6);
+        assert_eq!(broadcast_nonce(&calls[4].1), 7);
+    }
+
+    #[tokio::test]
+    async fn test_other_broadcast_errors_are_not_retried() {
+        let rpc = MockRpc::new(vec![
+            block(),
+            access_key(5),
+            ("broadcast_tx_commit", Err(json!({ "name": "HANDLER_ERROR", "cause": { "name": "NotEnoughBalance" } }))),
+        ]);
+        let err = sign_and_broadcast(&rpc, &signer(), &kv_call(b"{}"), CallOptions::default(), MAX_NONCE_RETRIES).await.unwrap_err();
+        assert!(err.to_string().contains("NotEnoughBalance"), "{err}");
+        assert_eq!(rpc.methods().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_sign_only_returns_signed_tx_without_broadcasting() {
+        let rpc = MockRpc::new(vec![block(), access_key(9)]);
+        let signer = signer();
+        let signed = sign_only(&rpc, &signer, &kv_call(br#"{"test/name":"Alice"}"#), CallOptions::default()).await.unwrap();
+        assert_eq!(rpc.methods(), vec!["block", "query"]);
+
+        let bytes = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &signed.tx_base64).unwrap();
+        let decoded = SignedTransaction::try_from_slice(&bytes).unwrap();
+        assert_eq!(decoded.transaction.nonce(), 10);
+        assert_eq!(signed.nonce, 10);
+        assert_eq!(signed.hash, decoded.get_hash());
+        let hash = Sha256::digest(borsh::to_vec(&decoded.transaction).unwrap());
+        assert!(decoded.signature.verify(&hash, &signer.public_key()));
+    }
+}
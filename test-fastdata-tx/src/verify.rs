@@ -0,0 +1,186 @@
+//! Check that a broadcast write was indexed.
+//!
+//! [`wait_for_keys`] polls fastkv-server's `/v1/kv/query` until every written
+//! key reads back with the value that was sent, or the timeout passes. Query
+//! failures (server starting, indexer lagging) count as "not yet" rather than
+//! ending the wait.
+
+use crate::near_tx::Result;
+use reqwest::Client;
+use serde_json::{Map, Value};
+use std::future::Future;
+use std::time::Duration;
+
+/// Delay between polls.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reads a partition's current entries.
+pub trait KvQuery {
+    /// `data` array of `/v1/kv/query` for the partition.
+    fn query(&self, account_id: &str, contract_id: &str) -> impl Future<Output = Result<Vec<Value>>> + Send;
+}
+
+pub struct HttpKvQuery {
+    client: Client,
+    server_url: String,
+}
+
+impl HttpKvQuery {
+    pub fn new(server_url: &str) -> Self {
+        Self { client: Client::new(), server_url: server_url.trim_end_matches('/').to_string() }
+    }
+}
+
+impl KvQuery for HttpKvQuery {
+    async fn query(&self, account_id: &str, contract_id: &str) -> Result<Vec<Value>> {
+        let mut resp = self
+            .client
+            .get(format!("{}/v1/kv/query", self.server_url))
+            .query(&[("accountId", account_id), ("contractId", contract_id), ("limit", "1000")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+        match resp["data"].take() {
+            Value::Array(entries) => Ok(entries),
+            other => Err(format!("unexpected /v1/kv/query response: {other}").into()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VerifyOutcome {
+    /// Every key was found with its value.
+    Indexed { polls: usize },
+    /// The timeout passed with these keys still missing or stale.
+    /// `last_error` is the last poll's query error, if it failed.
+    TimedOut { missing: Vec<String>, last_error: Option<String> },
+}
+
+/// Keys of `expected` that `entries` does not hold with the same value.
+/// Stored values are JSON text, so they are parsed before comparing.
+fn missing_keys(expected: &Map<String, Value>, entries: &[Value]) -> Vec<String> {
+    expected
+        .iter()
+        .filter(|(key, value)| {
+            !entries.iter().any(|entry| {
+                entry["key"].as_str() == Some(key.as_str())
+                    && entry["value"]
+                        .as_str()
+                        .and_then(|stored| serde_json::from_str::<Value>(stored).ok())
+                        .is_some_and(|stored| &stored == *value)
+            })
+        })
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+/// Poll until `expected` is readable from `account_id`'s partition on
+/// `contract_id`, checking every `interval` for at most `timeout`.
+pub async fn wait_for_keys<Q: KvQuery>(
+    query: &Q,
+    account_id: &str,
+    contract_id: &str,
+    expected: &Map<String, Value>,
+    timeout: Duration,
+    interval: Duration,
+) -> VerifyOutcome {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut polls = 0;
+    loop {
+        polls += 1;
+        let (missing, last_error) = match query.query(account_id, contract_id).await {
+            Ok(entries) => (missing_keys(expected, &entries), None),
+            Err(e) => (expected.keys().cloned().collect(), Some(e.to_string())),
+        };
+        if missing.is_empty() {
+            return VerifyOutcome::Indexed { polls };
+        }
+        if tokio::time::Instant::now() + interval > deadline {
+            return VerifyOutcome::TimedOut { missing, last_error };
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Returns nothing until `ready_after` polls have been made, then the entries.
+    struct DelayedQuery {
+        ready_after: usize,
+        entries: Vec<Value>,
+        polls: AtomicUsize,
+    }
+
+    impl KvQuery for DelayedQuery {
+        async fn query(&self, _account_id: &str, _contract_id: &str) -> Result<Vec<Value>> {
+            if self.polls.fetch_add(1, Ordering::SeqCst) < self.ready_after {
+                return Ok(vec![]);
+            }
+            Ok(self.entries.clone())
+        }
+    }
+
+    fn expected() -> Map<String, Value> {
+        json!({ "test/name": "Alice", "test/n": 1 }).as_object().unwrap().clone()
+    }
+
+    fn entries() -> Vec<Value> {
+        vec![
+            json!({ "key": "test/name", "value": "\"Alice\"" }),
+            json!({ "key": "test/n", "value": "1" }),
+            json!({ "key": "other", "value": "true" }),
+        ]
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waits_until_data_is_indexed() {
+        let query = DelayedQuery { ready_after: 3, entries: entries(), polls: AtomicUsize::new(0) };
+        let outcome = wait_for_keys(&query, "alice.near", "alice.near", &expected(), Duration::from_secs(10), POLL_INTERVAL).await;
+        assert_eq!(outcome, VerifyOutcome::Indexed { polls: 4 });
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_times_out_with_missing_keys() {
+        let query = DelayedQuery { ready_after: 100, entries: entries(), polls: AtomicUsize::new(0) };
+        let outcome = wait_for_keys(&query, "alice.near", "alice.near", &expected(), Duration::from_secs(5), POLL_INTERVAL).await;
+        let mut missing = match outcome {
+            VerifyOutcome::TimedOut { missing, last_error: None } => missing,
+            other => panic!("expected a timeout, got {other:?}"),
+        };
+        missing.sort();
+        assert_eq!(missing, vec!["test/n", "test/name"]);
+        assert_eq!(query.polls.load(Ordering::SeqCst), 6);
+    }
+
+    struct FailingQuery;
+
+    impl KvQuery for FailingQuery {
+        async fn query(&self, _account_id: &str, _contract_id: &str) -> Result<Vec<Value>> {
+            Err("connection refused".into())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_reports_last_query_error() {
+        let outcome = wait_for_keys(&FailingQuery, "alice.near", "alice.near", &expected(), Duration::from_secs(2), POLL_INTERVAL).await;
+        match outcome {
+            VerifyOutcome::TimedOut { missing, last_error } => {
+                assert_eq!(missing.len(), 2);
+                assert_eq!(last_error.as_deref(), Some("connection refused"));
+            }
+            other => panic!("expected a timeout, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_stale_value_counts_as_missing() {
+        let stale = vec![json!({ "key": "test/name", "value": "\"Bob\"" }), json!({ "key": "test/n", "value": "1" })];
+        assert_eq!(missing_keys(&expected(), &stale), vec!["test/name"]);
+    }
+}